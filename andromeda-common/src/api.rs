@@ -1,18 +1,18 @@
 pub mod game_versions;
 
-use std::str::FromStr;
+use std::{fs, path::Path, str::FromStr};
 
 use game_versions::{FFXIV_3_30_VER, FFXIV_7_30H_VER};
 
 #[repr(C)]
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Game {
   Ffxiv,
   Unknown
 }
 
 #[repr(C)]
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum GameVersion {
   Ffxiv7_30h,
   Ffxiv3_30,
@@ -94,3 +94,11 @@ pub fn get_game_version(game: &Game, version: &str) -> GameVersion {
     (_, _) => GameVersion::Unknown
   }
 }
+
+/// The version of `game` installed at `exe`, as the game reports it
+pub fn read_game_version(game: &Game, exe: &Path) -> Option<String> {
+  match game {
+    Game::Ffxiv => fs::read_to_string(exe.parent()?.join("ffxivgame.ver")).ok(),
+    Game::Unknown => None
+  }
+}
@@ -1,7 +1,13 @@
 pub mod andromeda_config;
+pub mod logging_config;
+pub mod overlay_config;
 pub mod startup_config;
 
 pub use andromeda_config::{
-  AndromedaConfig, create_andromeda_config, get_andromeda_config, get_andromeda_loader_path, get_andromeda_log_path
+  AndromedaConfig, CONFIG_FILE, Channel, andromeda_loader_path, create_andromeda_config, get_andromeda_config,
+  get_andromeda_crash_path, get_andromeda_loader_path, get_andromeda_log_path, get_andromeda_plugins_path,
+  read_andromeda_config, save_andromeda_config, update_andromeda_config, write_andromeda_config
 };
+pub use logging_config::{LogFormat, LoggingConfig};
+pub use overlay_config::{MainSwapchainRule, OverlayConfig};
 pub use startup_config::StartupConfig;
@@ -1,49 +1,231 @@
-use std::{fs, io::Write};
+use std::{
+  collections::BTreeMap,
+  fs,
+  io::{self, Write},
+  path::{Path, PathBuf}
+};
 
-use log::info;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use crate::errors::AndromedaError;
+use crate::{
+  config::{LoggingConfig, OverlayConfig},
+  errors::{AndromedaError, ErrorContext},
+  launch::{current_launch_session, merge_overrides, restore_overridden},
+  loader_store::DEV_VERSION
+};
+
+pub const CONFIG_FILE: &str = "andromeda_config.json";
 
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct AndromedaPlugin {
-  enabled: bool,
-  name: String,
-  id: String
+  pub enabled: bool,
+  pub name: String,
+  pub id: String
+}
+
+/// Which payload builds the proxy loads and updates to
+#[derive(Default, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Channel {
+  /// Releases, updated from the feed
+  #[default]
+  Stable,
+  /// Releases and betas, updated from the feed
+  Beta,
+  /// A developer's build in `loader/dev`
+  Dev,
+  /// A build directory anywhere, e.g. `{ "custom": "C:/src/andromeda/target/debug" }`
+  Custom(PathBuf)
+}
+
+impl Channel {
+  /// The feed's name of the channel, `None` for local builds that aren't updated
+  pub fn feed_name(&self) -> Option<&'static str> {
+    match self {
+      Channel::Stable => Some("stable"),
+      Channel::Beta => Some("beta"),
+      Channel::Dev | Channel::Custom(_) => None
+    }
+  }
+
+  /// A build on this machine rather than an installed release, loaded without a manifest if it has none
+  pub fn is_local_build(&self) -> bool {
+    self.feed_name().is_none()
+  }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AndromedaConfig {
-  #[serde(rename = "devBuild")]
-  dev_build: bool,
+  /// Replaced by `channel`, only read to migrate older configs
+  #[serde(rename = "devBuild", default, skip_serializing_if = "Option::is_none")]
+  dev_build: Option<bool>,
+  #[serde(rename = "channel", default, skip_serializing_if = "Option::is_none")]
+  channel: Option<Channel>,
   #[serde(rename = "latestVersion")]
   latest_version: String,
+  /// The version `latestVersion` replaced, rolled back to if the new one fails to start
+  #[serde(rename = "previousVersion", default, skip_serializing_if = "Option::is_none")]
+  previous_version: Option<String>,
+  /// Versions rolled back from after they failed to start, updates skip them
+  #[serde(rename = "failedVersions", default, skip_serializing_if = "Vec::is_empty")]
+  failed_versions: Vec<String>,
+  /// Installed loader versions kept besides the current and previous one
+  #[serde(rename = "keepLoaderVersions", default = "default_keep_loader_versions")]
+  pub keep_loader_versions: usize,
   #[serde(rename = "checkForUpdates")]
   check_for_updates: bool,
+  /// Where updates come from, a release feed's `https://` or `file://` URL or a directory. No updates without one.
+  #[serde(rename = "updateFeed", default, skip_serializing_if = "Option::is_none")]
+  pub update_feed: Option<String>,
   #[serde(rename = "plugins")]
   plugins: Vec<AndromedaPlugin>,
   #[serde(rename = "seenPlugins")]
-  seen_plugins: Vec<String>
+  seen_plugins: Vec<String>,
+  #[serde(rename = "overlay", default)]
+  pub overlay: OverlayConfig,
+  /// Chords keyed by action id, e.g. `"andromeda.toggleOverlay": "Shift+F12"`. An empty chord unbinds the action.
+  #[serde(rename = "keybindings", default)]
+  pub keybindings: BTreeMap<String, String>,
+  #[serde(rename = "logging", default)]
+  pub logging: LoggingConfig,
+  /// Unclean exits in a row after which Andromeda starts in safe mode, 0 never does
+  #[serde(rename = "safeModeAfterCrashes", default = "default_safe_mode_after_crashes")]
+  pub safe_mode_after_crashes: u32,
+  /// Hex encoded ed25519 keys, the payload's manifest has to be signed by one of them if there are any
+  #[serde(rename = "trustedKeys", default)]
+  pub trusted_keys: Vec<String>
+}
+
+fn default_safe_mode_after_crashes() -> u32 {
+  3
+}
+
+fn default_keep_loader_versions() -> usize {
+  3
 }
 
 impl Default for AndromedaConfig {
   fn default() -> Self {
     Self {
-      dev_build: true,
+      dev_build: None,
+      channel: Some(Channel::Stable),
       latest_version: "0.0.1".to_string(),
+      previous_version: None,
+      failed_versions: Default::default(),
+      keep_loader_versions: default_keep_loader_versions(),
       check_for_updates: true,
+      update_feed: None,
       plugins: Default::default(),
-      seen_plugins: Default::default()
+      seen_plugins: Default::default(),
+      overlay: Default::default(),
+      keybindings: Default::default(),
+      logging: Default::default(),
+      safe_mode_after_crashes: default_safe_mode_after_crashes(),
+      trusted_keys: Default::default()
     }
   }
 }
 
-pub fn get_andromeda_loader_path(config: AndromedaConfig) -> Option<std::path::PathBuf> {
-  get_andromeda_config_path().map(|path| {
-    path
-      .join("loader")
-      .join(if config.dev_build { "dev".into() } else { config.latest_version })
-  })
+impl AndromedaConfig {
+  /// The channel, or what `devBuild` meant in a config from before there were channels
+  pub fn channel(&self) -> Channel {
+    match (&self.channel, self.dev_build) {
+      (Some(channel), _) => channel.clone(),
+      (None, Some(true)) => Channel::Dev,
+      (None, _) => Channel::Stable
+    }
+  }
+
+  pub fn set_channel(&mut self, channel: Channel) {
+    self.channel = Some(channel);
+    self.dev_build = None;
+  }
+
+  /// Replaces `devBuild` with the channel it stands for, returns whether there was anything to migrate
+  pub fn migrate(&mut self) -> bool {
+    if self.channel.is_some() && self.dev_build.is_none() {
+      return false;
+    }
+    self.set_channel(self.channel());
+    true
+  }
+
+  pub fn check_for_updates(&self) -> bool {
+    self.check_for_updates
+  }
+
+  pub fn latest_version(&self) -> &str {
+    &self.latest_version
+  }
+
+  pub fn previous_version(&self) -> Option<&str> {
+    self.previous_version.as_deref()
+  }
+
+  /// See `LoaderStore::make_current` and `LoaderStore::roll_back`, which also check the versions are installed
+  pub(crate) fn set_loader_versions(&mut self, latest: String, previous: Option<String>) {
+    self.latest_version = latest;
+    self.previous_version = previous;
+  }
+
+  pub fn failed_versions(&self) -> &[String] {
+    &self.failed_versions
+  }
+
+  /// See `LoaderStore::roll_back`, which marks the version it goes back from, and `LoaderStore::make_current`
+  pub(crate) fn set_version_failed(&mut self, version: &str, failed: bool) {
+    self.failed_versions.retain(|failed_version| failed_version != version);
+    if failed {
+      self.failed_versions.push(version.to_string());
+    }
+  }
+
+  /// Plugins start out disabled until they are enabled in the plugin manager
+  pub fn is_plugin_enabled(&self, id: &str) -> bool {
+    self.plugins.iter().any(|plugin| plugin.id == id && plugin.enabled)
+  }
+
+  pub fn set_plugin_enabled(&mut self, id: &str, name: &str, enabled: bool) {
+    match self.plugins.iter_mut().find(|plugin| plugin.id == id) {
+      Some(plugin) => {
+        plugin.enabled = enabled;
+        plugin.name = name.to_string();
+      }
+      None => self.plugins.push(AndromedaPlugin {
+        enabled,
+        name: name.to_string(),
+        id: id.to_string()
+      })
+    }
+  }
+
+  /// Remembers `ids` as seen, returning the ones that weren't seen before
+  pub fn mark_plugins_seen<'a>(&mut self, ids: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut new = Vec::new();
+    for id in ids {
+      if !self.seen_plugins.iter().any(|seen| seen == id) {
+        self.seen_plugins.push(id.to_string());
+        new.push(id.to_string());
+      }
+    }
+    new
+  }
+}
+
+pub fn get_andromeda_loader_path(config: &AndromedaConfig) -> Option<std::path::PathBuf> {
+  Some(andromeda_loader_path(&get_andromeda_config_path()?, config))
+}
+
+/// The payload directory `config` loads from, with the installed versions in `andromeda_path`
+pub fn andromeda_loader_path(andromeda_path: &Path, config: &AndromedaConfig) -> PathBuf {
+  let loader_path = andromeda_path.join("loader");
+  match config.channel() {
+    Channel::Stable | Channel::Beta => loader_path.join(&config.latest_version),
+    Channel::Dev => loader_path.join(DEV_VERSION),
+    Channel::Custom(path) => path
+  }
 }
 
 pub fn get_andromeda_log_path() -> Option<std::path::PathBuf> {
@@ -52,6 +234,18 @@ pub fn get_andromeda_log_path() -> Option<std::path::PathBuf> {
   Some(path)
 }
 
+pub fn get_andromeda_crash_path() -> Option<std::path::PathBuf> {
+  let path = get_andromeda_config_path().map(|c| c.join("crashes"))?;
+  fs::create_dir_all(&path).ok()?;
+  Some(path)
+}
+
+pub fn get_andromeda_plugins_path() -> Option<std::path::PathBuf> {
+  let path = get_andromeda_config_path().map(|c| c.join("plugins"))?;
+  fs::create_dir_all(&path).ok()?;
+  Some(path)
+}
+
 pub fn get_andromeda_config_path() -> Option<std::path::PathBuf> {
   let path = dirs::config_dir().map(|dir| dir.join("Andromeda").to_path_buf());
   if let Some(ref andromeda_path) = path {
@@ -61,18 +255,75 @@ pub fn get_andromeda_config_path() -> Option<std::path::PathBuf> {
 }
 
 pub fn get_andromeda_config() -> Option<AndromedaConfig> {
-  if let Some(andromeda_path) = get_andromeda_config_path() &&
-    let Ok(true) = fs::exists(andromeda_path.join("andromeda_config.json"))
-  {
-    let file = fs::File::open(andromeda_path.join("andromeda_config.json")).ok()?;
-    serde_json::from_reader(file).ok()?
+  let andromeda_path = get_andromeda_config_path()?;
+  let mut config = read_andromeda_config(&andromeda_path).ok()??;
+  // Left for a session without overrides, the overrides could be what decides the channel
+  if config.migrate() && current_launch_session().is_none() {
+    match write_andromeda_config(&andromeda_path, &config) {
+      Ok(()) => info!("Migrated devBuild to the {:?} channel", config.channel()),
+      Err(e) => warn!("Failed to save the migrated config: {:#}", e)
+    }
+  }
+  Some(config)
+}
+
+/// Reads `andromeda_config.json` in `andromeda_path` with this process's launch overrides over it, `None` if there is
+/// no config yet
+pub fn read_andromeda_config(andromeda_path: &Path) -> Result<Option<AndromedaConfig>, AndromedaError> {
+  let file_path = andromeda_path.join(CONFIG_FILE);
+  let contents = match fs::read(&file_path) {
+    Ok(contents) => contents,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+    Err(e) => return Err(AndromedaError::config(format!("Can't read {}", file_path.display())).with_source(e))
+  };
+  let mut value: Value = serde_json::from_slice(&contents)
+    .context(|| AndromedaError::config(format!("{} is not valid JSON", file_path.display())))?;
+  if let Some(session) = current_launch_session() {
+    merge_overrides(&mut value, &session.overrides);
+  }
+  let config = serde_json::from_value(value)
+    .context(|| AndromedaError::config(format!("{} is not a valid config", file_path.display())))?;
+  Ok(Some(config))
+}
+
+pub fn save_andromeda_config(config: &AndromedaConfig) -> Result<(), AndromedaError> {
+  let andromeda_path = get_andromeda_config_path().ok_or_else(|| AndromedaError::config("No config directory"))?;
+  write_andromeda_config(&andromeda_path, config)
+}
+
+/// Changes the config on disk with `update`, starting from the defaults if there is none yet. A config that can't be
+/// read is left as it is rather than replaced by the defaults.
+pub fn update_andromeda_config<T>(update: impl FnOnce(&mut AndromedaConfig) -> T) -> Result<T, AndromedaError> {
+  let andromeda_path = get_andromeda_config_path().ok_or_else(|| AndromedaError::config("No config directory"))?;
+  let mut config = read_andromeda_config(&andromeda_path)?.unwrap_or_default();
+  let result = update(&mut config);
+  write_andromeda_config(&andromeda_path, &config)?;
+  Ok(result)
+}
+
+/// Writes `config` to `andromeda_config.json` in `andromeda_path`
+pub fn write_andromeda_config(andromeda_path: &Path, config: &AndromedaConfig) -> Result<(), AndromedaError> {
+  let file_path = andromeda_path.join(CONFIG_FILE);
+  // Write next to the config and swap it in, a crash halfway through must not leave a truncated config behind
+  let temp_path = andromeda_path.join(format!("{CONFIG_FILE}.tmp"));
+  let mut value = serde_json::to_value(config)?;
+  if let Some(session) = current_launch_session() {
+    let original = fs::read(&file_path)
+      .ok()
+      .and_then(|original| serde_json::from_slice(&original).ok())
+      .unwrap_or_default();
+    restore_overridden(&mut value, &session.overrides, &original);
   }
-  None
+  fs::write(&temp_path, serde_json::to_string_pretty(&value)?)
+    .context(|| AndromedaError::config(format!("Can't write {}", temp_path.display())))?;
+  fs::rename(&temp_path, &file_path)
+    .context(|| AndromedaError::config(format!("Can't replace {}", file_path.display())))?;
+  Ok(())
 }
 
 pub fn create_andromeda_config() -> Result<AndromedaConfig, AndromedaError> {
   if let Some(andromeda_path) = get_andromeda_config_path() {
-    let file_path = andromeda_path.join("andromeda_config.json");
+    let file_path = andromeda_path.join(CONFIG_FILE);
     fs::create_dir_all(&andromeda_path)?;
     if let Ok(false) = fs::exists(&file_path) {
       info!("File path: {}", file_path.to_str().unwrap());
@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// How records are written to the log files
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LogFormat {
+  /// `[Andromeda] <time> [<level>] [<module>] [<target>] <message>`
+  #[default]
+  Text,
+  /// One JSON object per line
+  Json
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoggingConfig {
+  /// Level of targets without one of their own: `off`, `error`, `warn`, `info`, `debug` or `trace`
+  #[serde(rename = "level", default = "default_level")]
+  pub level: String,
+  /// Levels by target prefix, e.g. `"andromeda::hooks": "trace"` or `"plugin.foo": "warn"`
+  #[serde(rename = "targets", default)]
+  pub targets: BTreeMap<String, String>,
+  /// Size at which a log file is continued in a new one, 0 for no limit
+  #[serde(rename = "maxFileSizeMb", default = "default_max_file_size_mb")]
+  pub max_file_size_mb: u64,
+  /// Continue in a new log file when the day changes
+  #[serde(rename = "rotateDaily", default = "default_rotate_daily")]
+  pub rotate_daily: bool,
+  /// Log files kept per DLL, 0 for no limit
+  #[serde(rename = "keepFiles", default = "default_keep_files")]
+  pub keep_files: usize,
+  /// Log files older than this are removed at startup, 0 for no limit
+  #[serde(rename = "maxAgeDays", default = "default_max_age_days")]
+  pub max_age_days: u64,
+  #[serde(rename = "format", default)]
+  pub format: LogFormat
+}
+
+fn default_level() -> String {
+  "debug".to_string()
+}
+
+fn default_max_file_size_mb() -> u64 {
+  10
+}
+
+fn default_rotate_daily() -> bool {
+  true
+}
+
+fn default_keep_files() -> usize {
+  20
+}
+
+fn default_max_age_days() -> u64 {
+  14
+}
+
+impl Default for LoggingConfig {
+  fn default() -> Self {
+    Self {
+      level: default_level(),
+      targets: Default::default(),
+      max_file_size_mb: default_max_file_size_mb(),
+      rotate_daily: default_rotate_daily(),
+      keep_files: default_keep_files(),
+      max_age_days: default_max_age_days(),
+      format: Default::default()
+    }
+  }
+}
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// How the overlay picks the game's main swapchain when several are alive
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MainSwapchainRule {
+  /// The swapchain with the largest back buffer
+  #[default]
+  Largest,
+  /// The first swapchain that presents into a window
+  HasHwnd,
+  /// The swapchain that presented the most frames
+  MostPresents,
+  /// The first swapchain that was created
+  First
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct OverlayConfig {
+  #[serde(rename = "mainSwapchain", default)]
+  pub main_swapchain: MainSwapchainRule,
+  /// Also draw the overlay onto every other swapchain
+  #[serde(rename = "mirrorOverlay", default)]
+  pub mirror_overlay: bool,
+  /// Per-game rule overrides keyed by process name, e.g. `ffxiv_dx11.exe`
+  #[serde(rename = "gameOverrides", default)]
+  pub game_overrides: HashMap<String, MainSwapchainRule>
+}
+
+impl OverlayConfig {
+  pub fn main_swapchain_rule(&self, process_name: &str) -> MainSwapchainRule {
+    self
+      .game_overrides
+      .iter()
+      .find(|(name, _)| name.eq_ignore_ascii_case(process_name))
+      .map_or(self.main_swapchain, |(_, rule)| *rule)
+  }
+}
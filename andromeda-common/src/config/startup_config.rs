@@ -1,6 +1,18 @@
+use crate::{crash::CrashApi, input::InputApi, logging::sink::LogApi};
+
 #[repr(C)]
 #[derive(Default)]
 pub struct StartupConfig {
   pub process_name: *mut i8,
-  pub version: *mut i8
+  pub version: *mut i8,
+  /// DirectInput hooks of the proxy, null when the payload was loaded some other way
+  pub input: *const InputApi,
+  /// The proxy's logger, null when the payload was loaded some other way
+  pub logs: *const LogApi,
+  /// The proxy's crash reporter, null when the payload was loaded some other way
+  pub crash: *const CrashApi,
+  /// Start without plugins, see `safe_mode`. Only the proxy keeps track of crashes.
+  pub safe_mode: bool,
+  /// Sessions in a row that didn't exit cleanly before this one
+  pub unclean_exits: u32
 }
@@ -0,0 +1,476 @@
+//! Crash reports, one folder per crash under `<config>/crashes`.
+//!
+//! A report holds what went wrong (a panic or a native exception), the last lines of the log, the game and its
+//! version, the loaded plugins, the applied patches and installed hooks, and the config as it was on disk. Assembling
+//! and writing it is plain Rust, the exception handler in [`exception`] only feeds it the details.
+//!
+//! Like the logger the entry DLL owns the reporter. The payload gets [`CRASH_API`] in `StartupConfig` and sends its
+//! notes and panics there, so a report knows about everything loaded into the game.
+
+#[cfg(windows)]
+pub mod exception;
+
+use std::{
+  fs,
+  io::{self, Write},
+  panic::{self, PanicHookInfo},
+  path::{Path, PathBuf},
+  sync::{
+    Mutex, OnceLock,
+    atomic::{AtomicBool, Ordering}
+  }
+};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  config::{andromeda_config::get_andromeda_config_path, get_andromeda_crash_path},
+  guard::{catch_panic, in_guard, payload_message},
+  logging::{
+    buffer::{LOG_BUFFER, format_log_entry},
+    sink::str_from_raw
+  }
+};
+
+/// Log lines a report keeps
+pub const CRASH_LOG_LINES: usize = 500;
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+/// What took the game down
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum CrashDetails {
+  Panic {
+    message: String,
+    /// `file:line:column`
+    location: Option<String>,
+    thread: Option<String>,
+    backtrace: String
+  },
+  /// A structured exception nobody handled, e.g. an access violation
+  #[serde(rename_all = "camelCase")]
+  Exception {
+    code: u32,
+    /// `EXCEPTION_ACCESS_VIOLATION` and so on, empty for codes we don't know
+    name: String,
+    address: usize,
+    /// The module `address` is in and the offset into it, e.g. `andromeda.dll+0x1a2b`
+    module: Option<String>,
+    /// `EXCEPTION_RECORD::ExceptionInformation`, for an access violation the kind of access and the address
+    information: Vec<usize>
+  }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct LoadedPlugin {
+  pub id: String,
+  pub version: String
+}
+
+/// What Andromeda did to the game so far, noted as it happens
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrashContext {
+  pub game: Option<String>,
+  pub game_version: Option<String>,
+  pub plugins: Vec<LoadedPlugin>,
+  pub patches: Vec<String>,
+  pub hooks: Vec<String>
+}
+
+impl CrashContext {
+  pub const fn new() -> Self {
+    Self {
+      game: None,
+      game_version: None,
+      plugins: Vec::new(),
+      patches: Vec::new(),
+      hooks: Vec::new()
+    }
+  }
+
+  pub fn note(&mut self, note: CrashNote, text: &str) {
+    match note {
+      CrashNote::Game => self.game = Some(text.to_string()),
+      CrashNote::GameVersion => self.game_version = Some(text.to_string()),
+      CrashNote::Plugin => {
+        // `id version`, see `note_plugin`
+        let (id, version) = text.split_once(' ').unwrap_or((text, ""));
+        self.plugins.retain(|plugin| plugin.id != id);
+        self.plugins.push(LoadedPlugin {
+          id: id.to_string(),
+          version: version.to_string()
+        });
+      }
+      CrashNote::Patch => push_unique(&mut self.patches, text),
+      CrashNote::Hook => push_unique(&mut self.hooks, text)
+    }
+  }
+}
+
+fn push_unique(list: &mut Vec<String>, text: &str) {
+  if !list.iter().any(|entry| entry == text) {
+    list.push(text.to_string());
+  }
+}
+
+/// Everything written to a crash folder
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrashReport {
+  pub time: DateTime<Local>,
+  pub andromeda_version: String,
+  pub crash: CrashDetails,
+  #[serde(flatten)]
+  pub context: CrashContext,
+  /// Written to `log.txt`
+  #[serde(skip)]
+  pub log_lines: Vec<String>,
+  /// `andromeda_config.json` as it was, written to `config.json`. Not necessarily what the game started with.
+  #[serde(skip)]
+  pub config: Option<String>
+}
+
+impl CrashReport {
+  pub fn new(time: DateTime<Local>, crash: CrashDetails, context: CrashContext) -> Self {
+    Self {
+      time,
+      andromeda_version: env!("CARGO_PKG_VERSION").to_string(),
+      crash,
+      context,
+      log_lines: Vec::new(),
+      config: None
+    }
+  }
+
+  /// Writes `report.json`, `log.txt` and `config.json` to a new folder in `dir` named after the time of the crash.
+  /// Returns the folder.
+  pub fn write(&self, dir: &Path) -> io::Result<PathBuf> {
+    let folder = create_report_folder(dir, &self.time)?;
+    fs::write(folder.join("report.json"), serde_json::to_string_pretty(self)?)?;
+
+    let mut log = fs::File::create(folder.join("log.txt"))?;
+    for line in &self.log_lines {
+      writeln!(log, "{line}")?;
+    }
+
+    if let Some(config) = &self.config {
+      fs::write(folder.join("config.json"), config)?;
+    }
+    Ok(folder)
+  }
+}
+
+/// Creates `dir/<timestamp>`, adding a counter if a crash of the same second has a folder already
+fn create_report_folder(dir: &Path, time: &DateTime<Local>) -> io::Result<PathBuf> {
+  fs::create_dir_all(dir)?;
+  let timestamp = time.format(TIMESTAMP_FORMAT).to_string();
+  let mut folder = dir.join(&timestamp);
+  let mut counter = 1;
+  loop {
+    match fs::create_dir(&folder) {
+      Ok(()) => return Ok(folder),
+      Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+        folder = dir.join(format!("{timestamp}_{counter}"));
+        counter += 1;
+      }
+      Err(e) => return Err(e)
+    }
+  }
+}
+
+/// Crash reports in `dir`, oldest first
+pub fn crash_reports(dir: &Path) -> io::Result<Vec<PathBuf>> {
+  let mut reports: Vec<PathBuf> = fs::read_dir(dir)?
+    .filter_map(|entry| entry.ok())
+    .map(|entry| entry.path())
+    .filter(|path| path.join("report.json").is_file())
+    .collect();
+  reports.sort_by_cached_key(|path| report_order(path));
+  Ok(reports)
+}
+
+/// Report folders sort by time, then by the counter of crashes in the same second, so `_10` comes after `_9`
+fn report_order(folder: &Path) -> (String, u32) {
+  let name = folder
+    .file_name()
+    .map(|name| name.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  match name
+    .rsplit_once('_')
+    .and_then(|(timestamp, counter)| Some((timestamp, counter.parse().ok()?)))
+  {
+    Some((timestamp, counter)) => (timestamp.to_string(), counter),
+    None => (name, 0)
+  }
+}
+
+/// Kind of a fact noted for crash reports
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CrashNote {
+  Game,
+  GameVersion,
+  /// `id version`
+  Plugin,
+  Patch,
+  Hook
+}
+
+/// What the DLL owning the crash reporter offers the others, handed over in `StartupConfig`
+#[repr(C)]
+pub struct CrashApi {
+  /// Notes a fact for the next report, `text` is UTF-8 and only valid during the call
+  pub note: unsafe extern "system" fn(note: CrashNote, text: *const u8, text_len: usize),
+  /// Writes a report for a crash of another DLL, `details` is a [`CrashDetails`] as JSON
+  pub report: unsafe extern "system" fn(details: *const u8, details_len: usize)
+}
+
+pub static CRASH_API: CrashApi = CrashApi {
+  note: note_from_raw,
+  report: report_from_raw
+};
+
+static CRASH_CONTEXT: Mutex<CrashContext> = Mutex::new(CrashContext::new());
+
+/// The API notes and reports are forwarded to, if this DLL doesn't own the crash reporter
+static FORWARD_API: OnceLock<&'static CrashApi> = OnceLock::new();
+
+/// Set while a report is written, a crash in the reporter itself must not start another
+static REPORTING: AtomicBool = AtomicBool::new(false);
+
+/// Notes a fact for crash reports, here or in the DLL owning the reporter
+pub fn note_crash_context(note: CrashNote, text: &str) {
+  if let Some(api) = FORWARD_API.get() {
+    unsafe { (api.note)(note, text.as_ptr(), text.len()) };
+    return;
+  }
+  // The crashing thread may hold the lock already, losing a note beats a deadlock
+  if let Ok(mut context) = CRASH_CONTEXT.try_lock() {
+    context.note(note, text);
+  }
+}
+
+/// The hooks noted so far, by this DLL or the ones forwarding to it
+pub fn noted_hooks() -> Vec<String> {
+  CRASH_CONTEXT
+    .lock()
+    .map(|context| context.hooks.clone())
+    .unwrap_or_default()
+}
+
+pub fn note_plugin(id: &str, version: &str) {
+  note_crash_context(CrashNote::Plugin, &format!("{id} {version}"));
+}
+
+/// Writes a report for `crash` to the crash directory, or hands it to the DLL owning the reporter. Returns the report
+/// folder if it was written here.
+pub fn report_crash(crash: CrashDetails) -> Option<PathBuf> {
+  if let Some(api) = FORWARD_API.get() {
+    let details = serde_json::to_string(&crash).ok()?;
+    unsafe { (api.report)(details.as_ptr(), details.len()) };
+    return None;
+  }
+  if REPORTING.swap(true, Ordering::AcqRel) {
+    return None;
+  }
+
+  let context = CRASH_CONTEXT
+    .try_lock()
+    .map(|context| context.clone())
+    .unwrap_or_default();
+  let mut report = CrashReport::new(Local::now(), crash, context);
+  if let Ok(buffer) = LOG_BUFFER.try_lock() {
+    report.log_lines = buffer.last(CRASH_LOG_LINES).map(format_log_entry).collect();
+  }
+  report.config =
+    get_andromeda_config_path().and_then(|dir| fs::read_to_string(dir.join("andromeda_config.json")).ok());
+
+  let folder = get_andromeda_crash_path().and_then(|dir| match report.write(&dir) {
+    Ok(folder) => Some(folder),
+    Err(e) => {
+      eprintln!("Failed to write crash report: {e}");
+      None
+    }
+  });
+  if let Some(folder) = &folder {
+    log::error!("Wrote a crash report to {}", folder.display());
+  }
+  REPORTING.store(false, Ordering::Release);
+  folder
+}
+
+/// Sends notes and reports to another DLL's [`CrashApi`] from now on
+pub fn init_forwarding_crash_reporter(api: &'static CrashApi) {
+  let _ = FORWARD_API.set(api);
+}
+
+/// Reports panics that no guard catches, they are about to take down the thread or the game
+pub fn install_panic_reporter() {
+  static INSTALLED: AtomicBool = AtomicBool::new(false);
+  if INSTALLED.swap(true, Ordering::AcqRel) {
+    return;
+  }
+  let previous = panic::take_hook();
+  panic::set_hook(Box::new(move |info| {
+    if !in_guard() {
+      report_crash(panic_details(info));
+    }
+    previous(info);
+  }));
+}
+
+fn panic_details(info: &PanicHookInfo) -> CrashDetails {
+  CrashDetails::Panic {
+    message: payload_message(info.payload()).to_string(),
+    location: info.location().map(|location| location.to_string()),
+    thread: std::thread::current().name().map(str::to_string),
+    backtrace: std::backtrace::Backtrace::force_capture().to_string()
+  }
+}
+
+unsafe extern "system" fn note_from_raw(note: CrashNote, text: *const u8, text_len: usize) {
+  catch_panic("CrashApi::note", || {
+    let text = unsafe { str_from_raw(text, text_len) };
+    note_crash_context(note, &text);
+  });
+}
+
+unsafe extern "system" fn report_from_raw(details: *const u8, details_len: usize) {
+  catch_panic("CrashApi::report", || {
+    let details = unsafe { str_from_raw(details, details_len) };
+    if let Ok(crash) = serde_json::from_str(&details) {
+      report_crash(crash);
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::TimeZone;
+
+  use super::*;
+
+  fn report(config: Option<&str>) -> CrashReport {
+    let mut context = CrashContext::new();
+    context.note(CrashNote::Game, "Ffxiv");
+    context.note(CrashNote::Plugin, "hello 1.0.0");
+    let crash = CrashDetails::Exception {
+      code: 0xC0000005,
+      name: "EXCEPTION_ACCESS_VIOLATION".to_string(),
+      address: 0x1000,
+      module: Some("game.exe+0x1000".to_string()),
+      information: vec![0, 0]
+    };
+    let mut report = CrashReport::new(Local.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap(), crash, context);
+    report.log_lines = vec!["first".to_string(), "second".to_string()];
+    report.config = config.map(str::to_string);
+    report
+  }
+
+  fn folder_names(folders: &[PathBuf]) -> Vec<String> {
+    folders
+      .iter()
+      .map(|folder| folder.file_name().unwrap().to_string_lossy().into_owned())
+      .collect()
+  }
+
+  #[test]
+  fn note_keeps_one_entry_per_plugin_hook_and_patch() {
+    let mut context = CrashContext::new();
+    context.note(CrashNote::Plugin, "hello 1.0.0");
+    context.note(CrashNote::Plugin, "other 2.0.0");
+    context.note(CrashNote::Plugin, "hello 1.1.0");
+    context.note(CrashNote::Plugin, "bare");
+    context.note(CrashNote::Hook, "Present");
+    context.note(CrashNote::Hook, "Present");
+    context.note(CrashNote::Patch, "anti-debug");
+    context.note(CrashNote::Patch, "anti-debug");
+    context.note(CrashNote::Game, "Ffxiv");
+    context.note(CrashNote::GameVersion, "2025.08.07.0000.0000");
+
+    let plugin = |id: &str, version: &str| LoadedPlugin {
+      id: id.to_string(),
+      version: version.to_string()
+    };
+    assert_eq!(
+      context,
+      CrashContext {
+        game: Some("Ffxiv".to_string()),
+        game_version: Some("2025.08.07.0000.0000".to_string()),
+        // A plugin noted again is moved to the end with its new version
+        plugins: vec![plugin("other", "2.0.0"), plugin("hello", "1.1.0"), plugin("bare", "")],
+        patches: vec!["anti-debug".to_string()],
+        hooks: vec!["Present".to_string()]
+      }
+    );
+  }
+
+  #[test]
+  fn write_puts_the_report_in_a_folder_named_after_the_time() {
+    let dir = tempfile::tempdir().unwrap();
+    let report = report(Some(r#"{ "channel": "beta" }"#));
+    let folder = report.write(dir.path()).unwrap();
+    assert_eq!(folder, dir.path().join("2026-03-01_12-00-00"));
+
+    let written: CrashReport = serde_json::from_str(&fs::read_to_string(folder.join("report.json")).unwrap()).unwrap();
+    assert_eq!(written.time, report.time);
+    assert_eq!(written.crash, report.crash);
+    assert_eq!(written.context, report.context);
+    let json: serde_json::Value =
+      serde_json::from_str(&fs::read_to_string(folder.join("report.json")).unwrap()).unwrap();
+    assert_eq!(json["crash"]["kind"], "exception");
+    assert_eq!(json["plugins"][0]["id"], "hello");
+    assert!(json.get("logLines").is_none());
+
+    assert_eq!(fs::read_to_string(folder.join("log.txt")).unwrap(), "first\nsecond\n");
+    assert_eq!(
+      fs::read_to_string(folder.join("config.json")).unwrap(),
+      r#"{ "channel": "beta" }"#
+    );
+  }
+
+  #[test]
+  fn write_counts_up_crashes_of_the_same_second() {
+    let dir = tempfile::tempdir().unwrap();
+    let report = report(None);
+    let folders: Vec<PathBuf> = (0..3).map(|_| report.write(dir.path()).unwrap()).collect();
+    assert_eq!(
+      folder_names(&folders),
+      ["2026-03-01_12-00-00", "2026-03-01_12-00-00_1", "2026-03-01_12-00-00_2"]
+    );
+    // Without a config on disk there is nothing to copy
+    assert!(!folders[0].join("config.json").exists());
+    assert!(folders[2].join("log.txt").is_file());
+  }
+
+  #[test]
+  fn crash_reports_are_listed_oldest_first() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in [
+      "2026-03-02_08-00-00",
+      "2026-03-01_12-00-00_10",
+      "2026-03-01_12-00-00_2",
+      "2026-03-01_12-00-00",
+      "2026-03-01_12-00-00_1"
+    ] {
+      fs::create_dir(dir.path().join(name)).unwrap();
+      fs::write(dir.path().join(name).join("report.json"), "{}").unwrap();
+    }
+    // Without a report.json it's not a report, e.g. one still being written
+    fs::create_dir(dir.path().join("2026-03-03_08-00-00")).unwrap();
+
+    assert_eq!(
+      folder_names(&crash_reports(dir.path()).unwrap()),
+      [
+        "2026-03-01_12-00-00",
+        "2026-03-01_12-00-00_1",
+        "2026-03-01_12-00-00_2",
+        "2026-03-01_12-00-00_10",
+        "2026-03-02_08-00-00"
+      ]
+    );
+  }
+}
@@ -0,0 +1,85 @@
+//! Turns exceptions nobody handled into crash reports, see [`report_crash`].
+
+use std::{ffi::c_void, path::Path, sync::OnceLock};
+
+use windows::{
+  Win32::{
+    Foundation::{
+      EXCEPTION_ACCESS_VIOLATION, EXCEPTION_BREAKPOINT, EXCEPTION_ILLEGAL_INSTRUCTION, EXCEPTION_IN_PAGE_ERROR,
+      EXCEPTION_INT_DIVIDE_BY_ZERO, EXCEPTION_PRIV_INSTRUCTION, EXCEPTION_STACK_OVERFLOW, HMODULE, NTSTATUS,
+      STATUS_HEAP_CORRUPTION, STATUS_STACK_BUFFER_OVERRUN
+    },
+    System::{
+      Diagnostics::Debug::{
+        EXCEPTION_CONTINUE_SEARCH, EXCEPTION_POINTERS, LPTOP_LEVEL_EXCEPTION_FILTER, SetUnhandledExceptionFilter
+      },
+      LibraryLoader::{
+        GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT, GetModuleFileNameW,
+        GetModuleHandleExW
+      }
+    }
+  },
+  core::PCWSTR
+};
+
+use crate::crash::{CrashDetails, report_crash};
+
+/// The filter that was installed before ours, called after the report is written
+static PREVIOUS_FILTER: OnceLock<LPTOP_LEVEL_EXCEPTION_FILTER> = OnceLock::new();
+
+/// Writes a crash report when the game is about to die of an exception. Only the first call installs the filter.
+pub fn install_exception_handler() {
+  PREVIOUS_FILTER.get_or_init(|| unsafe { SetUnhandledExceptionFilter(Some(unhandled_exception_filter)) });
+}
+
+unsafe extern "system" fn unhandled_exception_filter(pointers: *const EXCEPTION_POINTERS) -> i32 {
+  if let Some(record) = unsafe { pointers.as_ref().and_then(|pointers| pointers.ExceptionRecord.as_ref()) } {
+    let address = record.ExceptionAddress as usize;
+    let count = (record.NumberParameters as usize).min(record.ExceptionInformation.len());
+    report_crash(CrashDetails::Exception {
+      code: record.ExceptionCode.0 as u32,
+      name: exception_name(record.ExceptionCode).to_string(),
+      address,
+      module: module_offset(address),
+      information: record.ExceptionInformation[..count].to_vec()
+    });
+  }
+
+  match PREVIOUS_FILTER.get().copied().flatten() {
+    Some(previous) => unsafe { previous(pointers) },
+    None => EXCEPTION_CONTINUE_SEARCH
+  }
+}
+
+fn exception_name(code: NTSTATUS) -> &'static str {
+  match code {
+    EXCEPTION_ACCESS_VIOLATION => "EXCEPTION_ACCESS_VIOLATION",
+    EXCEPTION_IN_PAGE_ERROR => "EXCEPTION_IN_PAGE_ERROR",
+    EXCEPTION_STACK_OVERFLOW => "EXCEPTION_STACK_OVERFLOW",
+    EXCEPTION_ILLEGAL_INSTRUCTION => "EXCEPTION_ILLEGAL_INSTRUCTION",
+    EXCEPTION_PRIV_INSTRUCTION => "EXCEPTION_PRIV_INSTRUCTION",
+    EXCEPTION_INT_DIVIDE_BY_ZERO => "EXCEPTION_INT_DIVIDE_BY_ZERO",
+    EXCEPTION_BREAKPOINT => "EXCEPTION_BREAKPOINT",
+    STATUS_HEAP_CORRUPTION => "STATUS_HEAP_CORRUPTION",
+    STATUS_STACK_BUFFER_OVERRUN => "STATUS_STACK_BUFFER_OVERRUN",
+    _ => ""
+  }
+}
+
+/// `module.dll+0x1a2b` for an address inside a loaded module
+fn module_offset(address: usize) -> Option<String> {
+  let mut module = HMODULE::default();
+  unsafe {
+    GetModuleHandleExW(
+      GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+      PCWSTR(address as *const c_void as *const u16),
+      &mut module
+    )
+    .ok()?;
+  }
+  let mut path = [0u16; 260];
+  let len = unsafe { GetModuleFileNameW(Some(module), &mut path) } as usize;
+  let path = String::from_utf16_lossy(&path[..len]);
+  let name = Path::new(&path).file_name()?.to_string_lossy().into_owned();
+  Some(format!("{}+{:#x}", name, address - module.0 as usize))
+}
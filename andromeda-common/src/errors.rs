@@ -1,47 +1,303 @@
-use std::{fmt, io};
+use std::{error::Error, fmt, io};
 
+#[cfg(windows)]
+use windows::core::HRESULT;
+
+#[cfg(windows)]
+use crate::utils::win32::hresult_to_string;
+
+pub type BoxError = Box<dyn Error + Send + Sync + 'static>;
+
+#[derive(Debug)]
 pub enum AndromedaError {
-  Hooking(String),
-  MinHook(String),
-  IO(String),
-  JSON(String),
-  Logger(String),
-  Path(String)
+  /// Reading, writing or interpreting the config and the paths it points to
+  Config {
+    message: String,
+    source: Option<BoxError>
+  },
+  Plugin {
+    message: String,
+    source: Option<BoxError>
+  },
+  /// Installing or calling a function hook
+  Hook {
+    message: String,
+    source: Option<BoxError>
+  },
+  /// Patching game or system memory
+  Patch {
+    message: String,
+    source: Option<BoxError>
+  },
+  /// Reading a PE image, e.g. its exports or version resources
+  PeParse {
+    message: String,
+    source: Option<BoxError>
+  },
+  /// Data passed across the C ABI, e.g. a null pointer or a string that isn't UTF-8
+  Ffi {
+    message: String,
+    source: Option<BoxError>
+  },
+  /// A file that doesn't match its manifest or a manifest without a valid signature
+  Integrity {
+    message: String,
+    source: Option<BoxError>
+  },
+  /// Checking for, downloading or installing an update
+  Update {
+    message: String,
+    source: Option<BoxError>
+  },
+  /// A failed Windows API call
+  #[cfg(windows)]
+  Hresult {
+    message: String,
+    hresult: HRESULT
+  },
+  Io(io::Error),
+  Json(serde_json::Error),
+  Logger(log::SetLoggerError)
+}
+
+/// Numbers for [`AndromedaError`] that stay the same across versions, for returning errors across the C ABI
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorCode {
+  Success = 0,
+  Config = 1,
+  Plugin = 2,
+  Hook = 3,
+  Patch = 4,
+  PeParse = 5,
+  Ffi = 6,
+  Hresult = 7,
+  Io = 8,
+  Json = 9,
+  Logger = 10,
+  Integrity = 11,
+  Update = 12,
+  /// A code from a newer version
+  Unknown = u32::MAX
 }
 
+impl From<u32> for ErrorCode {
+  fn from(code: u32) -> Self {
+    match code {
+      0 => ErrorCode::Success,
+      1 => ErrorCode::Config,
+      2 => ErrorCode::Plugin,
+      3 => ErrorCode::Hook,
+      4 => ErrorCode::Patch,
+      5 => ErrorCode::PeParse,
+      6 => ErrorCode::Ffi,
+      7 => ErrorCode::Hresult,
+      8 => ErrorCode::Io,
+      9 => ErrorCode::Json,
+      10 => ErrorCode::Logger,
+      11 => ErrorCode::Integrity,
+      12 => ErrorCode::Update,
+      _ => ErrorCode::Unknown
+    }
+  }
+}
+
+impl AndromedaError {
+  pub fn config(message: impl Into<String>) -> Self {
+    AndromedaError::Config {
+      message: message.into(),
+      source: None
+    }
+  }
+
+  pub fn plugin(message: impl Into<String>) -> Self {
+    AndromedaError::Plugin {
+      message: message.into(),
+      source: None
+    }
+  }
+
+  pub fn hook(message: impl Into<String>) -> Self {
+    AndromedaError::Hook {
+      message: message.into(),
+      source: None
+    }
+  }
+
+  pub fn patch(message: impl Into<String>) -> Self {
+    AndromedaError::Patch {
+      message: message.into(),
+      source: None
+    }
+  }
+
+  pub fn pe_parse(message: impl Into<String>) -> Self {
+    AndromedaError::PeParse {
+      message: message.into(),
+      source: None
+    }
+  }
+
+  pub fn ffi(message: impl Into<String>) -> Self {
+    AndromedaError::Ffi {
+      message: message.into(),
+      source: None
+    }
+  }
+
+  pub fn integrity(message: impl Into<String>) -> Self {
+    AndromedaError::Integrity {
+      message: message.into(),
+      source: None
+    }
+  }
+
+  pub fn update(message: impl Into<String>) -> Self {
+    AndromedaError::Update {
+      message: message.into(),
+      source: None
+    }
+  }
+
+  #[cfg(windows)]
+  pub fn hresult(message: impl Into<String>, hresult: HRESULT) -> Self {
+    AndromedaError::Hresult {
+      message: message.into(),
+      hresult
+    }
+  }
+
+  /// Sets the error that caused this one, errors wrapping a library error keep theirs
+  pub fn with_source(mut self, error: impl Into<BoxError>) -> Self {
+    match &mut self {
+      AndromedaError::Config { source, .. } |
+      AndromedaError::Plugin { source, .. } |
+      AndromedaError::Hook { source, .. } |
+      AndromedaError::Patch { source, .. } |
+      AndromedaError::PeParse { source, .. } |
+      AndromedaError::Ffi { source, .. } |
+      AndromedaError::Integrity { source, .. } |
+      AndromedaError::Update { source, .. } => *source = Some(error.into()),
+      #[cfg(windows)]
+      AndromedaError::Hresult { .. } => {}
+      AndromedaError::Io(_) | AndromedaError::Json(_) | AndromedaError::Logger(_) => {}
+    }
+    self
+  }
+
+  pub fn code(&self) -> ErrorCode {
+    match self {
+      AndromedaError::Config { .. } => ErrorCode::Config,
+      AndromedaError::Plugin { .. } => ErrorCode::Plugin,
+      AndromedaError::Hook { .. } => ErrorCode::Hook,
+      AndromedaError::Patch { .. } => ErrorCode::Patch,
+      AndromedaError::PeParse { .. } => ErrorCode::PeParse,
+      AndromedaError::Ffi { .. } => ErrorCode::Ffi,
+      AndromedaError::Integrity { .. } => ErrorCode::Integrity,
+      AndromedaError::Update { .. } => ErrorCode::Update,
+      #[cfg(windows)]
+      AndromedaError::Hresult { .. } => ErrorCode::Hresult,
+      AndromedaError::Io(_) => ErrorCode::Io,
+      AndromedaError::Json(_) => ErrorCode::Json,
+      AndromedaError::Logger(_) => ErrorCode::Logger
+    }
+  }
+}
+
+/// Shows the message of this error only, `{:#}` appends the messages of its sources
 impl fmt::Display for AndromedaError {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
-      AndromedaError::Hooking(msg) => write!(f, "An error occurred while attempting to hook a function: {}", msg),
-      AndromedaError::MinHook(msg) => write!(f, "A MinHook error occurred: {}", msg),
-      AndromedaError::IO(msg) => write!(f, "An error occurred: {}", msg),
-      AndromedaError::JSON(msg) => write!(f, "A JSON error occurred: {}", msg),
-      AndromedaError::Logger(msg) => write!(f, "A logger error occurred: {}", msg),
-      AndromedaError::Path(msg) => write!(f, "A path error has occurred: {}", msg)
+      AndromedaError::Config { message, .. } |
+      AndromedaError::Plugin { message, .. } |
+      AndromedaError::Hook { message, .. } |
+      AndromedaError::Patch { message, .. } |
+      AndromedaError::PeParse { message, .. } |
+      AndromedaError::Ffi { message, .. } |
+      AndromedaError::Integrity { message, .. } |
+      AndromedaError::Update { message, .. } => write!(f, "{}", message)?,
+      #[cfg(windows)]
+      AndromedaError::Hresult { message, hresult } if message.is_empty() => {
+        write!(f, "{}", hresult_to_string(*hresult))?
+      }
+      #[cfg(windows)]
+      AndromedaError::Hresult { message, hresult } => write!(f, "{}: {}", message, hresult_to_string(*hresult))?,
+      AndromedaError::Io(error) => write!(f, "{}", error)?,
+      AndromedaError::Json(error) => write!(f, "{}", error)?,
+      AndromedaError::Logger(error) => write!(f, "{}", error)?
     }
+
+    if f.alternate() {
+      let mut source = self.source();
+      while let Some(error) = source {
+        write!(f, ": {}", error)?;
+        source = error.source();
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Error for AndromedaError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      AndromedaError::Config { source, .. } |
+      AndromedaError::Plugin { source, .. } |
+      AndromedaError::Hook { source, .. } |
+      AndromedaError::Patch { source, .. } |
+      AndromedaError::PeParse { source, .. } |
+      AndromedaError::Ffi { source, .. } |
+      AndromedaError::Integrity { source, .. } |
+      AndromedaError::Update { source, .. } => source.as_ref().map(|source| source.as_ref() as &(dyn Error + 'static)),
+      #[cfg(windows)]
+      AndromedaError::Hresult { .. } => None,
+      // These show the wrapped error's message, so its source is ours
+      AndromedaError::Io(error) => error.source(),
+      AndromedaError::Json(error) => error.source(),
+      AndromedaError::Logger(error) => error.source()
+    }
+  }
+}
+
+/// Turns any error into the source of an [`AndromedaError`] describing what failed
+pub trait ErrorContext<T> {
+  fn context(self, error: impl FnOnce() -> AndromedaError) -> Result<T, AndromedaError>;
+}
+
+impl<T, E: Into<BoxError>> ErrorContext<T> for Result<T, E> {
+  fn context(self, error: impl FnOnce() -> AndromedaError) -> Result<T, AndromedaError> {
+    self.map_err(|source| error().with_source(source))
   }
 }
 
 impl From<io::Error> for AndromedaError {
   fn from(error: io::Error) -> Self {
-    AndromedaError::IO(error.to_string())
+    AndromedaError::Io(error)
   }
 }
 
+#[cfg(windows)]
 impl From<min_hook_rs::HookError> for AndromedaError {
   fn from(error: min_hook_rs::HookError) -> Self {
-    AndromedaError::MinHook(error.to_string())
+    AndromedaError::hook("MinHook failed").with_source(error)
   }
 }
 
 impl From<serde_json::Error> for AndromedaError {
   fn from(error: serde_json::Error) -> Self {
-    AndromedaError::JSON(error.to_string())
+    AndromedaError::Json(error)
   }
 }
 
 impl From<log::SetLoggerError> for AndromedaError {
   fn from(error: log::SetLoggerError) -> Self {
-    AndromedaError::Logger(error.to_string())
+    AndromedaError::Logger(error)
+  }
+}
+
+#[cfg(windows)]
+impl From<windows::core::Error> for AndromedaError {
+  fn from(error: windows::core::Error) -> Self {
+    AndromedaError::hresult("", error.code())
   }
 }
@@ -0,0 +1,110 @@
+//! Keeps panics in Andromeda code from unwinding into the game.
+//!
+//! Unwinding out of an `extern "system"` function aborts the process, so every export and detour runs its own code
+//! through [`catch_panic`] or a [`HookGuard`]. Panics are logged with a backtrace, and a hook that panicked steps
+//! aside so the game's function runs on its own from then on.
+
+use std::{
+  any::Any,
+  backtrace::Backtrace,
+  cell::{Cell, RefCell},
+  panic::{self, AssertUnwindSafe},
+  sync::{
+    Once,
+    atomic::{AtomicBool, Ordering}
+  }
+};
+
+use log::error;
+
+thread_local! {
+  /// How many guards this thread is in, panics outside of them go to the previous panic hook
+  static GUARD_DEPTH: Cell<usize> = const { Cell::new(0) };
+  /// Message, location and backtrace of the last panic caught on this thread
+  static CAUGHT_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// The backtrace has to be captured while the panicking frames still exist, `catch_unwind` is too late for that
+fn install_panic_hook() {
+  static INSTALLED: Once = Once::new();
+  INSTALLED.call_once(|| {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+      if !in_guard() {
+        previous(info);
+        return;
+      }
+      let report = format!("{}\n{}", info, Backtrace::force_capture());
+      CAUGHT_PANIC.with_borrow_mut(|caught| *caught = Some(report));
+    }));
+  });
+}
+
+/// Whether this thread runs inside [`catch_panic`] or a [`HookGuard`], a panic here won't take the game down
+pub fn in_guard() -> bool {
+  GUARD_DEPTH.get() > 0
+}
+
+pub(crate) fn payload_message(payload: &(dyn Any + Send)) -> &str {
+  payload
+    .downcast_ref::<&str>()
+    .copied()
+    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+    .unwrap_or("Box<dyn Any>")
+}
+
+/// Runs `f`, logging a panic with its backtrace instead of letting it unwind. For exports and entrypoints, detours
+/// use a [`HookGuard`].
+pub fn catch_panic<R>(name: &str, f: impl FnOnce() -> R) -> Option<R> {
+  install_panic_hook();
+  GUARD_DEPTH.set(GUARD_DEPTH.get() + 1);
+  let result = panic::catch_unwind(AssertUnwindSafe(f));
+  GUARD_DEPTH.set(GUARD_DEPTH.get() - 1);
+
+  match result {
+    Ok(value) => Some(value),
+    Err(payload) => {
+      let report = CAUGHT_PANIC
+        .take()
+        .unwrap_or_else(|| payload_message(payload.as_ref()).to_string());
+      error!("{} panicked: {}", name, report);
+      None
+    }
+  }
+}
+
+/// Andromeda's part of a detour, disabled for good once it panics
+pub struct HookGuard {
+  name: &'static str,
+  disabled: AtomicBool
+}
+
+impl HookGuard {
+  pub const fn new(name: &'static str) -> Self {
+    Self {
+      name,
+      disabled: AtomicBool::new(false)
+    }
+  }
+
+  pub fn is_disabled(&self) -> bool {
+    self.disabled.load(Ordering::Relaxed)
+  }
+
+  /// Runs `f` unless the hook is disabled. `None` means it didn't run or panicked, the detour should then do what the
+  /// game's function would have done without Andromeda.
+  pub fn run<R>(&self, f: impl FnOnce() -> R) -> Option<R> {
+    if self.is_disabled() {
+      return None;
+    }
+    let result = catch_panic(self.name, f);
+    if result.is_none() {
+      self.disabled.store(true, Ordering::Relaxed);
+      error!(
+        "Disabled the {} hook, the game's function runs on its own from now on",
+        self.name
+      );
+    }
+    result
+  }
+}
@@ -0,0 +1,312 @@
+//! Keyboard and mouse input as the game reads it through DirectInput.
+//!
+//! The proxy `dinput8.dll` turns what the game's devices return into [`InputEvent`]s, asks the payload's
+//! [`InputFilterFn`] whether the game may see each of them and mixes in events injected through [`InputApi`].
+//! Everything crossing the DLL boundary is `repr(C)`, the rest of this module is plain bookkeeping the proxy runs on
+//! the raw device data.
+
+use std::collections::VecDeque;
+
+/// Bytes in the keyboard state `GetDeviceState` fills, one per `DIK_*` scan code
+pub const KEYBOARD_STATE_SIZE: usize = 256;
+/// Offset of the first button in `DIMOUSESTATE` and `DIMOUSESTATE2`, after the three axes
+const MOUSE_BUTTONS_OFFSET: usize = 12;
+/// `DIMOUSESTATE2` has eight buttons, `DIMOUSESTATE` the first four of them
+pub const MOUSE_BUTTONS: usize = 8;
+
+/// `DIMOFS_*` offsets buffered mouse data is reported under
+const MOUSE_OFFSET_X: u32 = 0;
+const MOUSE_OFFSET_Y: u32 = 4;
+const MOUSE_OFFSET_Z: u32 = 8;
+const MOUSE_OFFSET_BUTTON0: u32 = 12;
+
+/// Injected events waiting for a buffered read, older ones are dropped once a device isn't read that way
+const MAX_QUEUED_EVENTS: usize = 128;
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum InputDevice {
+  Keyboard,
+  Mouse
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputEvent {
+  /// `scan_code` is a DirectInput `DIK_*` code
+  Key {
+    scan_code: u8,
+    pressed: bool
+  },
+  MouseButton {
+    button: u8,
+    pressed: bool
+  },
+  /// Relative motion in mickeys
+  MouseMove {
+    dx: i32,
+    dy: i32
+  },
+  MouseWheel {
+    delta: i32
+  }
+}
+
+impl InputEvent {
+  pub fn device(&self) -> InputDevice {
+    match self {
+      InputEvent::Key { .. } => InputDevice::Keyboard,
+      _ => InputDevice::Mouse
+    }
+  }
+
+  /// Decodes one record of buffered device data
+  pub fn from_buffered(device: InputDevice, offset: u32, data: u32) -> Option<Self> {
+    let pressed = data & 0x80 != 0;
+    match (device, offset) {
+      (InputDevice::Keyboard, 0..=255) => Some(InputEvent::Key {
+        scan_code: offset as u8,
+        pressed
+      }),
+      (InputDevice::Mouse, MOUSE_OFFSET_X) => Some(InputEvent::MouseMove { dx: data as i32, dy: 0 }),
+      (InputDevice::Mouse, MOUSE_OFFSET_Y) => Some(InputEvent::MouseMove { dx: 0, dy: data as i32 }),
+      (InputDevice::Mouse, MOUSE_OFFSET_Z) => Some(InputEvent::MouseWheel { delta: data as i32 }),
+      (InputDevice::Mouse, offset)
+        if (MOUSE_OFFSET_BUTTON0..MOUSE_OFFSET_BUTTON0 + MOUSE_BUTTONS as u32).contains(&offset) =>
+      {
+        Some(InputEvent::MouseButton {
+          button: (offset - MOUSE_OFFSET_BUTTON0) as u8,
+          pressed
+        })
+      }
+      _ => None
+    }
+  }
+
+  /// The `(offset, data)` records that report this event as buffered device data
+  pub fn to_buffered(&self) -> Vec<(u32, u32)> {
+    let state = |pressed: bool| if pressed { 0x80 } else { 0 };
+    match *self {
+      InputEvent::Key { scan_code, pressed } => vec![(scan_code as u32, state(pressed))],
+      InputEvent::MouseButton { button, pressed } => vec![(MOUSE_OFFSET_BUTTON0 + button as u32, state(pressed))],
+      InputEvent::MouseMove { dx, dy } => [(MOUSE_OFFSET_X, dx), (MOUSE_OFFSET_Y, dy)]
+        .into_iter()
+        .filter(|(_, delta)| *delta != 0)
+        .map(|(offset, delta)| (offset, delta as u32))
+        .collect(),
+      InputEvent::MouseWheel { delta } => vec![(MOUSE_OFFSET_Z, delta as u32)]
+    }
+  }
+}
+
+/// Decides whether the game gets to see an event, `true` blocks it
+pub type InputFilterFn = unsafe extern "system" fn(event: *const InputEvent) -> bool;
+
+/// What the proxy offers the payload for DirectInput, handed over in `StartupConfig`
+#[repr(C)]
+pub struct InputApi {
+  /// Replaces the filter every event the game reads goes through, `None` lets everything through
+  pub set_filter: unsafe extern "system" fn(filter: Option<InputFilterFn>),
+  /// Makes the game read an event as if it came from its device
+  pub inject: unsafe extern "system" fn(event: *const InputEvent)
+}
+
+/// Filter state of one keyboard or mouse the game created.
+///
+/// A blocked press keeps its key or button released for the game until it is physically released, releases of keys
+/// the game saw going down always get through. That way blocking input never leaves a key stuck in the game.
+pub struct DeviceInput {
+  device: InputDevice,
+  /// Last keyboard state the device itself reported
+  last_keys: [u8; KEYBOARD_STATE_SIZE],
+  last_buttons: [bool; MOUSE_BUTTONS],
+  blocked_keys: [bool; KEYBOARD_STATE_SIZE],
+  blocked_buttons: [bool; MOUSE_BUTTONS]
+}
+
+impl DeviceInput {
+  pub fn new(device: InputDevice) -> Self {
+    Self {
+      device,
+      last_keys: [0; KEYBOARD_STATE_SIZE],
+      last_buttons: [false; MOUSE_BUTTONS],
+      blocked_keys: [false; KEYBOARD_STATE_SIZE],
+      blocked_buttons: [false; MOUSE_BUTTONS]
+    }
+  }
+
+  pub fn device(&self) -> InputDevice {
+    self.device
+  }
+
+  /// Whether the game sees `event`. `filter` is asked about every event but only decides presses, motion and the
+  /// wheel.
+  fn admit(&mut self, event: &InputEvent, filter: &mut dyn FnMut(&InputEvent) -> bool) -> bool {
+    let blocked = filter(event);
+    let held = match *event {
+      InputEvent::Key { scan_code, pressed } => Some((&mut self.blocked_keys[scan_code as usize], pressed)),
+      InputEvent::MouseButton { button, pressed } => self
+        .blocked_buttons
+        .get_mut(button as usize)
+        .map(|blocked| (blocked, pressed)),
+      _ => None
+    };
+    match held {
+      Some((held_blocked, true)) => {
+        *held_blocked = blocked;
+        !blocked
+      }
+      Some((held_blocked, false)) => !std::mem::take(held_blocked),
+      None => !blocked
+    }
+  }
+
+  /// Filters what `GetDeviceState` wrote to `state`, a keyboard state or a `DIMOUSESTATE(2)`
+  pub fn filter_state(&mut self, state: &mut [u8], filter: &mut dyn FnMut(&InputEvent) -> bool) {
+    match self.device {
+      InputDevice::Keyboard => {
+        for (scan_code, byte) in state.iter_mut().take(KEYBOARD_STATE_SIZE).enumerate() {
+          let pressed = *byte & 0x80 != 0;
+          if pressed != (self.last_keys[scan_code] & 0x80 != 0) {
+            self.admit(
+              &InputEvent::Key {
+                scan_code: scan_code as u8,
+                pressed
+              },
+              filter
+            );
+          }
+          self.last_keys[scan_code] = *byte;
+          if self.blocked_keys[scan_code] {
+            *byte = 0;
+          }
+        }
+      }
+      InputDevice::Mouse => {
+        if state.len() < MOUSE_BUTTONS_OFFSET {
+          return;
+        }
+        let axis = |state: &[u8], index: usize| i32::from_le_bytes(state[index * 4..index * 4 + 4].try_into().unwrap());
+        let (dx, dy, wheel) = (axis(state, 0), axis(state, 1), axis(state, 2));
+        if (dx, dy) != (0, 0) && !self.admit(&InputEvent::MouseMove { dx, dy }, filter) {
+          state[0..8].fill(0);
+        }
+        if wheel != 0 && !self.admit(&InputEvent::MouseWheel { delta: wheel }, filter) {
+          state[8..12].fill(0);
+        }
+
+        let buttons = (state.len() - MOUSE_BUTTONS_OFFSET).min(MOUSE_BUTTONS);
+        for button in 0..buttons {
+          let byte = &mut state[MOUSE_BUTTONS_OFFSET + button];
+          let pressed = *byte & 0x80 != 0;
+          if pressed != self.last_buttons[button] {
+            self.last_buttons[button] = pressed;
+            self.admit(
+              &InputEvent::MouseButton {
+                button: button as u8,
+                pressed
+              },
+              filter
+            );
+          }
+          if self.blocked_buttons[button] {
+            *byte = 0;
+          }
+        }
+      }
+    }
+  }
+
+  /// Whether the game gets to see one record `GetDeviceData` returned
+  pub fn filter_buffered(&mut self, offset: u32, data: u32, filter: &mut dyn FnMut(&InputEvent) -> bool) -> bool {
+    match InputEvent::from_buffered(self.device, offset, data) {
+      Some(event) => self.admit(&event, filter),
+      None => true
+    }
+  }
+}
+
+/// Events plugins injected that the game hasn't read yet.
+///
+/// A device read through `GetDeviceState` sees injected keys and buttons as held from their press until their
+/// release, so a press and release injected between two reads is lost there. Buffered reads get every event.
+#[derive(Default)]
+pub struct InjectedInput {
+  held_keys: Vec<u8>,
+  held_buttons: [bool; MOUSE_BUTTONS],
+  motion: (i32, i32),
+  wheel: i32,
+  keyboard_queue: VecDeque<(u32, u32)>,
+  mouse_queue: VecDeque<(u32, u32)>
+}
+
+impl InjectedInput {
+  pub fn push(&mut self, event: InputEvent) {
+    match event {
+      InputEvent::Key { scan_code, pressed } => {
+        self.held_keys.retain(|key| *key != scan_code);
+        if pressed {
+          self.held_keys.push(scan_code);
+        }
+      }
+      InputEvent::MouseButton { button, pressed } => {
+        if let Some(held) = self.held_buttons.get_mut(button as usize) {
+          *held = pressed;
+        }
+      }
+      InputEvent::MouseMove { dx, dy } => {
+        self.motion = (self.motion.0.saturating_add(dx), self.motion.1.saturating_add(dy));
+      }
+      InputEvent::MouseWheel { delta } => self.wheel = self.wheel.saturating_add(delta)
+    }
+
+    let queue = match event.device() {
+      InputDevice::Keyboard => &mut self.keyboard_queue,
+      InputDevice::Mouse => &mut self.mouse_queue
+    };
+    queue.extend(event.to_buffered());
+    while queue.len() > MAX_QUEUED_EVENTS {
+      queue.pop_front();
+    }
+  }
+
+  /// Adds the injected input to a state `GetDeviceState` returned, motion and wheel are only reported once
+  pub fn apply_state(&mut self, device: InputDevice, state: &mut [u8]) {
+    match device {
+      InputDevice::Keyboard => {
+        for key in &self.held_keys {
+          if let Some(byte) = state.get_mut(*key as usize) {
+            *byte = 0x80;
+          }
+        }
+      }
+      InputDevice::Mouse => {
+        if state.len() < MOUSE_BUTTONS_OFFSET {
+          return;
+        }
+        let (dx, dy) = std::mem::take(&mut self.motion);
+        let wheel = std::mem::take(&mut self.wheel);
+        for (index, delta) in [dx, dy, wheel].into_iter().enumerate() {
+          let axis = &mut state[index * 4..index * 4 + 4];
+          let value = i32::from_le_bytes((&*axis).try_into().unwrap()).saturating_add(delta);
+          axis.copy_from_slice(&value.to_le_bytes());
+        }
+        for (button, byte) in state[MOUSE_BUTTONS_OFFSET..].iter_mut().take(MOUSE_BUTTONS).enumerate() {
+          if self.held_buttons[button] {
+            *byte = 0x80;
+          }
+        }
+      }
+    }
+  }
+
+  /// Takes up to `max` `(offset, data)` records for a buffered read of `device`
+  pub fn take_buffered(&mut self, device: InputDevice, max: usize) -> Vec<(u32, u32)> {
+    let queue = match device {
+      InputDevice::Keyboard => &mut self.keyboard_queue,
+      InputDevice::Mouse => &mut self.mouse_queue
+    };
+    let count = queue.len().min(max);
+    queue.drain(..count).collect()
+  }
+}
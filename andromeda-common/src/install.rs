@@ -0,0 +1,479 @@
+//! Installing the entry DLL into a game folder as a proxy DLL, and taking it out again.
+//!
+//! The install leaves `andromeda_proxy.json` next to the proxy, recording which name it took and which DLL of another
+//! mod it moved aside to `<name>.andromeda-displaced` for it, so the uninstall can put that back. Everything here is
+//! plain file operations, so a game folder mounted from another system works too.
+
+use std::{
+  fs, io,
+  path::{Path, PathBuf}
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  api::{Game, get_game},
+  errors::{AndromedaError, ErrorContext},
+  integrity::{sha256_file, to_hex},
+  proxy::{PROXY_DLL_NAMES, find_proxy_dlls, is_entry_dll}
+};
+
+pub const MARKER_FILE: &str = "andromeda_proxy.json";
+/// Appended to the name of a DLL moved aside for the proxy
+pub const DISPLACED_SUFFIX: &str = ".andromeda-displaced";
+
+/// Where games are installed, relative to a drive
+const INSTALL_DIRS: [&str; 3] = [
+  "Program Files (x86)/SquareEnix/FINAL FANTASY XIV - A Realm Reborn/game",
+  "Program Files/SquareEnix/FINAL FANTASY XIV - A Realm Reborn/game",
+  "SquareEnix/FINAL FANTASY XIV - A Realm Reborn/game"
+];
+/// Where Steam libraries are, relative to a drive or, for Proton, a home directory's `.local/share` and `.steam`
+const STEAM_LIBRARIES: [&str; 5] = [
+  "Program Files (x86)/Steam",
+  "Program Files/Steam",
+  "Steam",
+  "steam",
+  "SteamLibrary"
+];
+/// Where games are installed, relative to a Steam library
+const STEAM_INSTALL_DIRS: [&str; 1] = ["steamapps/common/FINAL FANTASY XIV Online/game"];
+
+/// What [`install_proxy`] did to a game folder
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstallMarker {
+  /// The proxy's file name
+  pub proxy: String,
+  /// Hex encoded SHA-256 of the installed DLL
+  pub sha256: String,
+  /// File name of the DLL that had the proxy's name before
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub displaced: Option<String>
+}
+
+/// The game's executable at `path`, which is the executable, its folder, or the install folder above that
+pub fn find_game_exe(path: &Path) -> Option<PathBuf> {
+  if path.is_file() {
+    return is_game_exe(path).then(|| path.to_path_buf());
+  }
+  [path.to_path_buf(), path.join("game")].into_iter().find_map(|dir| {
+    fs::read_dir(dir)
+      .ok()?
+      .filter_map(|entry| entry.ok())
+      .map(|entry| entry.path())
+      .find(|path| path.is_file() && is_game_exe(path))
+  })
+}
+
+fn is_game_exe(path: &Path) -> bool {
+  path
+    .file_name()
+    .is_some_and(|name| get_game(&name.to_string_lossy()) != Game::Unknown)
+}
+
+/// Game executables in the usual install folders under `roots`, including every Steam library Steam knows of
+pub fn search_game_exes(roots: &[PathBuf]) -> Vec<PathBuf> {
+  let mut libraries: Vec<PathBuf> = roots
+    .iter()
+    .flat_map(|root| STEAM_LIBRARIES.iter().map(move |library| root.join(library)))
+    .filter(|library| library.is_dir())
+    .collect();
+  let listed: Vec<PathBuf> = libraries
+    .iter()
+    .flat_map(|steam| steam_library_folders(steam))
+    .collect();
+  libraries.extend(listed);
+
+  let install_dirs = roots
+    .iter()
+    .flat_map(|root| INSTALL_DIRS.iter().map(move |dir| root.join(dir)))
+    .chain(
+      libraries
+        .iter()
+        .flat_map(|library| STEAM_INSTALL_DIRS.iter().map(move |dir| library.join(dir)))
+    );
+  // Case-insensitive file systems find the same game through differently cased paths
+  let mut exes: Vec<PathBuf> = install_dirs
+    .filter_map(|dir| find_game_exe(&dir))
+    .filter_map(|exe| exe.canonicalize().ok())
+    .collect();
+  exes.sort();
+  exes.dedup();
+  exes
+}
+
+/// The libraries listed in Steam's `libraryfolders.vdf`, as `"path"  "D:\\SteamLibrary"` lines
+fn steam_library_folders(steam: &Path) -> Vec<PathBuf> {
+  let Ok(vdf) = fs::read_to_string(steam.join("steamapps/libraryfolders.vdf")) else {
+    return Vec::new();
+  };
+  vdf
+    .lines()
+    .filter_map(|line| {
+      let mut quoted = line.split('"').skip(1).step_by(2);
+      let (key, value) = (quoted.next()?, quoted.next()?);
+      (key == "path").then(|| PathBuf::from(value.replace("\\\\", "\\")))
+    })
+    .collect()
+}
+
+/// Where to search for games without being told: every drive on Windows, the home directory's Steam folders elsewhere
+pub fn default_search_roots() -> Vec<PathBuf> {
+  #[cfg(windows)]
+  {
+    ('C'..='Z')
+      .map(|drive| PathBuf::from(format!("{drive}:\\")))
+      .filter(|root| root.is_dir())
+      .collect()
+  }
+  #[cfg(not(windows))]
+  {
+    dirs::home_dir()
+      .map(|home| vec![home.join(".local/share"), home.join(".steam")])
+      .unwrap_or_default()
+  }
+}
+
+/// The install recorded in `game_dir`, `None` if there is none
+pub fn read_marker(game_dir: &Path) -> Result<Option<InstallMarker>, AndromedaError> {
+  let path = game_dir.join(MARKER_FILE);
+  let contents = match fs::read(&path) {
+    Ok(contents) => contents,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+    Err(e) => return Err(AndromedaError::config(format!("Can't read {}", path.display())).with_source(e))
+  };
+  serde_json::from_slice(&contents)
+    .context(|| AndromedaError::config(format!("{} is not an install marker", path.display())))
+    .map(Some)
+}
+
+/// Copies `entry` into `game_dir` as proxy DLL `name`, by default the name of an earlier install or else the first
+/// of [`PROXY_DLL_NAMES`] that is free. Another mod's DLL with that name is moved aside until [`uninstall_proxy`].
+pub fn install_proxy(game_dir: &Path, entry: &Path, name: Option<&str>) -> Result<InstallMarker, AndromedaError> {
+  if !is_entry_dll(entry).context(|| AndromedaError::config(format!("Can't read {}", entry.display())))? {
+    return Err(AndromedaError::config(format!(
+      "{} is not the entry DLL",
+      entry.display()
+    )));
+  }
+  let name = name.map(proxy_name).transpose()?;
+  // Reinstalling starts from the folder as it was before, with whatever the last install displaced back in place
+  let previous = match read_marker(game_dir)? {
+    Some(_) => Some(uninstall_proxy(game_dir)?.proxy),
+    None => None
+  };
+
+  let proxies =
+    find_proxy_dlls(game_dir).context(|| AndromedaError::config(format!("Can't read {}", game_dir.display())))?;
+  let file_name = |proxy: &PathBuf| proxy.file_name().unwrap_or_default().to_string_lossy().into_owned();
+  let copies: Vec<String> = proxies
+    .iter()
+    .filter(|proxy| proxy.andromeda)
+    .map(|proxy| file_name(&proxy.path))
+    .collect();
+  let name = match name.or(previous).or_else(|| copies.first().cloned()) {
+    Some(name) => name,
+    None => PROXY_DLL_NAMES
+      .iter()
+      .find(|candidate| {
+        !proxies
+          .iter()
+          .any(|proxy| file_name(&proxy.path).eq_ignore_ascii_case(candidate))
+      })
+      .map(|name| name.to_string())
+      .ok_or_else(|| {
+        AndromedaError::config(format!(
+          "Other mods use every proxy name in {}, pick one to move its DLL aside",
+          game_dir.display()
+        ))
+      })?
+  };
+  // A copy put there by hand under another name would start Andromeda twice
+  if let Some(copy) = copies.iter().find(|copy| !copy.eq_ignore_ascii_case(&name)) {
+    return Err(AndromedaError::config(format!(
+      "{} already has Andromeda as {copy}, remove it first",
+      game_dir.display()
+    )));
+  }
+
+  let existing = proxies
+    .iter()
+    .find(|proxy| file_name(&proxy.path).eq_ignore_ascii_case(&name));
+  let displaced = match existing {
+    Some(proxy) if !proxy.andromeda => {
+      let displaced = file_name(&proxy.path);
+      let aside = game_dir.join(format!("{displaced}{DISPLACED_SUFFIX}"));
+      if aside.exists() {
+        return Err(AndromedaError::config(format!(
+          "{} is in the way of moving {displaced} aside",
+          aside.display()
+        )));
+      }
+      fs::rename(&proxy.path, &aside)
+        .context(|| AndromedaError::config(format!("Can't move {} aside", proxy.path.display())))?;
+      Some(displaced)
+    }
+    _ => None
+  };
+  // Keep the case of a copy that is replaced, a case-sensitive file system would keep both otherwise
+  let target = existing.map_or_else(|| game_dir.join(&name), |proxy| proxy.path.clone());
+
+  let marker = copy_proxy(entry, &target).and_then(|()| {
+    let marker = InstallMarker {
+      proxy: file_name(&target),
+      sha256: to_hex(&sha256_file(&target)?),
+      displaced: displaced.clone()
+    };
+    fs::write(game_dir.join(MARKER_FILE), serde_json::to_string_pretty(&marker)?)
+      .context(|| AndromedaError::config(format!("Can't write {MARKER_FILE} in {}", game_dir.display())))?;
+    Ok(marker)
+  });
+  if marker.is_err() {
+    let _ = fs::remove_file(&target);
+    if let Some(displaced) = &displaced {
+      let _ = fs::rename(
+        game_dir.join(format!("{displaced}{DISPLACED_SUFFIX}")),
+        game_dir.join(displaced)
+      );
+    }
+  }
+  marker
+}
+
+/// `name` as a proxy DLL file name, `dxgi` and `dxgi.dll` both work
+fn proxy_name(name: &str) -> Result<String, AndromedaError> {
+  PROXY_DLL_NAMES
+    .iter()
+    .find(|proxy| {
+      proxy.eq_ignore_ascii_case(name) ||
+        proxy
+          .strip_suffix(".dll")
+          .is_some_and(|stem| stem.eq_ignore_ascii_case(name))
+    })
+    .map(|proxy| proxy.to_string())
+    .ok_or_else(|| {
+      AndromedaError::config(format!(
+        "The entry DLL can't be loaded as {name}, only as {}",
+        PROXY_DLL_NAMES.join(", ")
+      ))
+    })
+}
+
+/// Copies next to `target` first, so a failed copy never leaves half a DLL for the game to load
+fn copy_proxy(entry: &Path, target: &Path) -> Result<(), AndromedaError> {
+  let temp_path = target.with_extension("dll.tmp");
+  fs::copy(entry, &temp_path).context(|| AndromedaError::config(format!("Can't write {}", temp_path.display())))?;
+  fs::rename(&temp_path, target)
+    .context(|| AndromedaError::config(format!("Can't replace {}, is the game running?", target.display())))
+}
+
+/// Removes the proxy installed in `game_dir` and puts back the DLL it displaced, returning what was uninstalled
+pub fn uninstall_proxy(game_dir: &Path) -> Result<InstallMarker, AndromedaError> {
+  let marker = read_marker(game_dir)?
+    .ok_or_else(|| AndromedaError::config(format!("Andromeda is not installed in {}", game_dir.display())))?;
+  let proxy = game_dir.join(&marker.proxy);
+  let installed = match sha256_file(&proxy) {
+    Ok(sha256) => Some(to_hex(&sha256).eq_ignore_ascii_case(&marker.sha256)),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+    Err(e) => return Err(AndromedaError::config(format!("Can't read {}", proxy.display())).with_source(e))
+  };
+  // Not the DLL the install put there, but maybe another build of the entry DLL copied over it since
+  let andromeda = match installed {
+    Some(false) => {
+      Some(is_entry_dll(&proxy).context(|| AndromedaError::config(format!("Can't read {}", proxy.display())))?)
+    }
+    installed => installed
+  };
+  match andromeda {
+    Some(true) => fs::remove_file(&proxy)
+      .context(|| AndromedaError::config(format!("Can't remove {}, is the game running?", proxy.display())))?,
+    // Another mod took the name since, removing it would break that mod
+    Some(false) => {
+      return Err(AndromedaError::config(format!(
+        "{} is no longer Andromeda's, remove it or {MARKER_FILE} by hand",
+        proxy.display()
+      )));
+    }
+    None => {}
+  }
+  if let Some(displaced) = &marker.displaced {
+    let aside = game_dir.join(format!("{displaced}{DISPLACED_SUFFIX}"));
+    fs::rename(&aside, game_dir.join(displaced))
+      .context(|| AndromedaError::config(format!("Can't put {} back", aside.display())))?;
+  }
+  fs::remove_file(game_dir.join(MARKER_FILE))
+    .context(|| AndromedaError::config(format!("Can't remove {MARKER_FILE} in {}", game_dir.display())))?;
+  Ok(marker)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::logging::files::ENTRY_LOG_BASENAME;
+
+  /// A build of the entry DLL, told apart from other DLLs by naming its log file
+  fn entry_dll(dir: &Path, build: &str) -> PathBuf {
+    let path = dir.join(format!("andromeda_entry_{build}.dll"));
+    fs::write(&path, format!("MZ {ENTRY_LOG_BASENAME} {build}")).unwrap();
+    path
+  }
+
+  fn game_dir(dir: &Path) -> PathBuf {
+    let game_dir = dir.join("game");
+    fs::create_dir_all(&game_dir).unwrap();
+    game_dir
+  }
+
+  fn read(path: PathBuf) -> String {
+    fs::read_to_string(path).unwrap()
+  }
+
+  fn file_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+      .collect();
+    names.sort();
+    names
+  }
+
+  #[test]
+  fn install_into_an_empty_folder_takes_the_first_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let game_dir = game_dir(dir.path());
+    let entry = entry_dll(dir.path(), "1");
+
+    let marker = install_proxy(&game_dir, &entry, None).unwrap();
+    assert_eq!(marker.proxy, "dinput8.dll");
+    assert_eq!(marker.displaced, None);
+    assert_eq!(marker.sha256, to_hex(&sha256_file(&entry).unwrap()));
+    assert_eq!(read(game_dir.join("dinput8.dll")), read(entry));
+    assert_eq!(read_marker(&game_dir).unwrap().unwrap().proxy, "dinput8.dll");
+    assert_eq!(file_names(&game_dir), ["andromeda_proxy.json", "dinput8.dll"]);
+  }
+
+  #[test]
+  fn install_moves_another_mods_dll_aside() {
+    let dir = tempfile::tempdir().unwrap();
+    let game_dir = game_dir(dir.path());
+    let entry = entry_dll(dir.path(), "1");
+    fs::write(game_dir.join("dinput8.dll"), "other mod").unwrap();
+
+    // Without a name the first free one is taken
+    assert_eq!(install_proxy(&game_dir, &entry, None).unwrap().proxy, "dxgi.dll");
+    uninstall_proxy(&game_dir).unwrap();
+
+    let marker = install_proxy(&game_dir, &entry, Some("dinput8")).unwrap();
+    assert_eq!(marker.proxy, "dinput8.dll");
+    assert_eq!(marker.displaced.as_deref(), Some("dinput8.dll"));
+    assert_eq!(read(game_dir.join("dinput8.dll.andromeda-displaced")), "other mod");
+    assert_eq!(read(game_dir.join("dinput8.dll")), read(entry));
+  }
+
+  #[test]
+  fn reinstall_replaces_the_proxy_and_keeps_the_displaced_dll_aside() {
+    let dir = tempfile::tempdir().unwrap();
+    let game_dir = game_dir(dir.path());
+    fs::write(game_dir.join("dxgi.dll"), "other mod").unwrap();
+    install_proxy(&game_dir, &entry_dll(dir.path(), "1"), Some("dxgi.dll")).unwrap();
+
+    // The name of the earlier install is kept
+    let entry = entry_dll(dir.path(), "2");
+    let marker = install_proxy(&game_dir, &entry, None).unwrap();
+    assert_eq!(marker.proxy, "dxgi.dll");
+    assert_eq!(marker.displaced.as_deref(), Some("dxgi.dll"));
+    assert_eq!(marker.sha256, to_hex(&sha256_file(&entry).unwrap()));
+    assert_eq!(read(game_dir.join("dxgi.dll")), read(entry));
+    assert_eq!(
+      file_names(&game_dir),
+      ["andromeda_proxy.json", "dxgi.dll", "dxgi.dll.andromeda-displaced"]
+    );
+
+    // Another name puts the displaced DLL back
+    install_proxy(&game_dir, &entry_dll(dir.path(), "3"), Some("d3d11")).unwrap();
+    assert_eq!(read(game_dir.join("dxgi.dll")), "other mod");
+    assert_eq!(file_names(&game_dir), ["andromeda_proxy.json", "d3d11.dll", "dxgi.dll"]);
+  }
+
+  #[test]
+  fn uninstall_puts_the_displaced_dll_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let game_dir = game_dir(dir.path());
+    fs::write(game_dir.join("dinput8.dll"), "other mod").unwrap();
+    install_proxy(&game_dir, &entry_dll(dir.path(), "1"), Some("dinput8")).unwrap();
+
+    let marker = uninstall_proxy(&game_dir).unwrap();
+    assert_eq!(marker.proxy, "dinput8.dll");
+    assert_eq!(read(game_dir.join("dinput8.dll")), "other mod");
+    assert_eq!(file_names(&game_dir), ["dinput8.dll"]);
+    assert!(uninstall_proxy(&game_dir).is_err());
+  }
+
+  #[test]
+  fn uninstall_leaves_another_mods_dll_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let game_dir = game_dir(dir.path());
+    install_proxy(&game_dir, &entry_dll(dir.path(), "1"), None).unwrap();
+
+    // Another build of the entry DLL copied over the installed one is still Andromeda's
+    fs::copy(entry_dll(dir.path(), "2"), game_dir.join("dinput8.dll")).unwrap();
+    uninstall_proxy(&game_dir).unwrap();
+    assert_eq!(file_names(&game_dir), Vec::<String>::new());
+
+    install_proxy(&game_dir, &entry_dll(dir.path(), "1"), None).unwrap();
+    fs::write(game_dir.join("dinput8.dll"), "other mod").unwrap();
+    let error = uninstall_proxy(&game_dir).unwrap_err().to_string();
+    assert!(error.contains("is no longer Andromeda's"), "{error}");
+    assert_eq!(read(game_dir.join("dinput8.dll")), "other mod");
+    assert!(game_dir.join(MARKER_FILE).is_file());
+  }
+
+  #[test]
+  fn uninstall_takes_a_proxy_removed_by_hand() {
+    let dir = tempfile::tempdir().unwrap();
+    let game_dir = game_dir(dir.path());
+    fs::write(game_dir.join("dinput8.dll"), "other mod").unwrap();
+    install_proxy(&game_dir, &entry_dll(dir.path(), "1"), Some("dinput8")).unwrap();
+    fs::remove_file(game_dir.join("dinput8.dll")).unwrap();
+
+    uninstall_proxy(&game_dir).unwrap();
+    assert_eq!(read(game_dir.join("dinput8.dll")), "other mod");
+    assert_eq!(file_names(&game_dir), ["dinput8.dll"]);
+  }
+
+  #[test]
+  fn install_needs_the_entry_dll_and_a_proxy_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let game_dir = game_dir(dir.path());
+    let other = dir.path().join("other.dll");
+    fs::write(&other, "other mod").unwrap();
+    assert!(install_proxy(&game_dir, &other, None).is_err());
+
+    let entry = entry_dll(dir.path(), "1");
+    let error = install_proxy(&game_dir, &entry, Some("version"))
+      .unwrap_err()
+      .to_string();
+    assert!(error.contains("only as dinput8.dll, dxgi.dll, d3d11.dll"), "{error}");
+    assert_eq!(file_names(&game_dir), Vec::<String>::new());
+  }
+
+  #[test]
+  fn install_refuses_a_second_copy_under_another_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let game_dir = game_dir(dir.path());
+    let entry = entry_dll(dir.path(), "1");
+    fs::copy(&entry, game_dir.join("dxgi.dll")).unwrap();
+
+    // Without a name the copy is taken over
+    assert_eq!(install_proxy(&game_dir, &entry, None).unwrap().proxy, "dxgi.dll");
+    uninstall_proxy(&game_dir).unwrap();
+
+    fs::copy(&entry, game_dir.join("dxgi.dll")).unwrap();
+    let error = install_proxy(&game_dir, &entry, Some("dinput8"))
+      .unwrap_err()
+      .to_string();
+    assert!(error.contains("already has Andromeda as dxgi.dll"), "{error}");
+    assert_eq!(file_names(&game_dir), ["dxgi.dll"]);
+  }
+}
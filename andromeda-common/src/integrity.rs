@@ -0,0 +1,307 @@
+//! Checks that a payload is the one that was installed before it gets loaded.
+//!
+//! Every loader version directory has a `manifest.json` listing the SHA-256 of its files, written when the version is
+//! installed. If trusted keys are configured, the manifest also needs a `manifest.json.sig` next to it: a hex encoded
+//! ed25519 signature over the manifest's bytes by one of those keys.
+
+use std::{
+  collections::BTreeMap,
+  fs::{self, File},
+  io::{self, Read},
+  path::Path
+};
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::errors::{AndromedaError, ErrorContext};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const SIGNATURE_FILE: &str = "manifest.json.sig";
+
+/// The files of a loader version and their SHA-256, hex encoded
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct PayloadManifest {
+  pub version: String,
+  pub files: BTreeMap<String, String>
+}
+
+impl PayloadManifest {
+  /// Hashes `files` in `dir`
+  pub fn from_files<'a>(version: &str, dir: &Path, files: impl IntoIterator<Item = &'a str>) -> io::Result<Self> {
+    let mut manifest = PayloadManifest {
+      version: version.to_string(),
+      files: BTreeMap::new()
+    };
+    for file in files {
+      manifest
+        .files
+        .insert(file.to_string(), to_hex(&sha256_file(&dir.join(file))?));
+    }
+    Ok(manifest)
+  }
+
+  pub fn read(dir: &Path) -> Result<(Self, Vec<u8>), AndromedaError> {
+    let path = dir.join(MANIFEST_FILE);
+    let bytes = fs::read(&path).context(|| AndromedaError::integrity(format!("Can't read {}", path.display())))?;
+    let manifest = serde_json::from_slice(&bytes)
+      .context(|| AndromedaError::integrity(format!("{} is not a manifest", path.display())))?;
+    Ok((manifest, bytes))
+  }
+
+  pub fn write(&self, dir: &Path) -> Result<(), AndromedaError> {
+    let path = dir.join(MANIFEST_FILE);
+    fs::write(&path, serde_json::to_string_pretty(self)?)
+      .context(|| AndromedaError::integrity(format!("Can't write {}", path.display())))
+  }
+
+  /// Checks `file` in `dir` against its hash in this manifest
+  pub fn verify_file(&self, dir: &Path, file: &str) -> Result<(), AndromedaError> {
+    let expected = self
+      .files
+      .get(file)
+      .ok_or_else(|| AndromedaError::integrity(format!("{file} is not in the manifest")))?;
+    let path = dir.join(file);
+    let actual =
+      to_hex(&sha256_file(&path).context(|| AndromedaError::integrity(format!("Can't hash {}", path.display())))?);
+    if !actual.eq_ignore_ascii_case(expected) {
+      return Err(AndromedaError::integrity(format!(
+        "{} has SHA-256 {}, the manifest expects {}",
+        path.display(),
+        actual,
+        expected
+      )));
+    }
+    Ok(())
+  }
+}
+
+pub fn sha256_file(path: &Path) -> io::Result<[u8; 32]> {
+  let mut file = File::open(path)?;
+  let mut hasher = Sha256::new();
+  let mut buffer = vec![0; 64 * 1024];
+  loop {
+    match file.read(&mut buffer)? {
+      0 => break,
+      read => hasher.update(&buffer[..read])
+    }
+  }
+  Ok(hasher.finalize().into())
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+  let hex = hex.trim();
+  // from_str_radix alone would take a sign, e.g. "+1"
+  if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+    return None;
+  }
+  (0..hex.len())
+    .step_by(2)
+    .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+    .collect()
+}
+
+/// An ed25519 public key from its hex encoding
+pub fn parse_public_key(hex: &str) -> Result<VerifyingKey, AndromedaError> {
+  let bytes: [u8; 32] = from_hex(hex)
+    .and_then(|bytes| bytes.try_into().ok())
+    .ok_or_else(|| AndromedaError::integrity(format!("'{hex}' is not a hex encoded ed25519 public key")))?;
+  VerifyingKey::from_bytes(&bytes)
+    .context(|| AndromedaError::integrity(format!("'{hex}' is not a valid ed25519 public key")))
+}
+
+/// Checks that `signature`, hex encoded, is a signature over `manifest` by one of `keys`
+pub fn verify_manifest_signature(
+  manifest: &[u8],
+  signature: &str,
+  keys: &[VerifyingKey]
+) -> Result<(), AndromedaError> {
+  let signature: [u8; 64] = from_hex(signature)
+    .and_then(|bytes| bytes.try_into().ok())
+    .ok_or_else(|| AndromedaError::integrity("The manifest signature is not a hex encoded ed25519 signature"))?;
+  let signature = Signature::from_bytes(&signature);
+  if keys.iter().any(|key| key.verify_strict(manifest, &signature).is_ok()) {
+    return Ok(());
+  }
+  Err(AndromedaError::integrity("The manifest is not signed by a trusted key"))
+}
+
+/// Checks `file` in the loader version `dir` against the manifest there, and the manifest's signature if there are
+/// trusted `keys`. Returns the manifest.
+pub fn verify_payload(dir: &Path, file: &str, keys: &[VerifyingKey]) -> Result<PayloadManifest, AndromedaError> {
+  let (manifest, bytes) = PayloadManifest::read(dir)?;
+  if !keys.is_empty() {
+    let path = dir.join(SIGNATURE_FILE);
+    let signature =
+      fs::read_to_string(&path).context(|| AndromedaError::integrity(format!("Can't read {}", path.display())))?;
+    verify_manifest_signature(&bytes, &signature, keys)?;
+  }
+  manifest.verify_file(dir, file)?;
+  Ok(manifest)
+}
+
+/// Whether the payload in `dir` may load without being verified: only a local build without a manifest, and never
+/// once there are trusted keys, every payload has to be signed by one of them then
+pub fn allows_unverified(dir: &Path, local_build: bool, keys: &[VerifyingKey]) -> bool {
+  local_build && keys.is_empty() && !dir.join(MANIFEST_FILE).exists()
+}
+
+#[cfg(test)]
+mod tests {
+  use ed25519_dalek::{Signer, SigningKey};
+
+  use super::*;
+
+  const PAYLOAD: &str = "andromeda.dll";
+
+  /// A loader version directory with a payload and its manifest
+  fn version_dir() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join(PAYLOAD), b"abc").unwrap();
+    PayloadManifest::from_files("1.0.0", dir.path(), [PAYLOAD])
+      .unwrap()
+      .write(dir.path())
+      .unwrap();
+    dir
+  }
+
+  fn sign(dir: &Path, key: &SigningKey) {
+    let manifest = fs::read(dir.join(MANIFEST_FILE)).unwrap();
+    fs::write(dir.join(SIGNATURE_FILE), to_hex(&key.sign(&manifest).to_bytes())).unwrap();
+  }
+
+  fn error(result: Result<impl std::fmt::Debug, AndromedaError>) -> String {
+    result.unwrap_err().to_string()
+  }
+
+  #[test]
+  fn manifest_has_the_sha256_of_each_file() {
+    let dir = version_dir();
+    let (manifest, _) = PayloadManifest::read(dir.path()).unwrap();
+    assert_eq!(manifest.version, "1.0.0");
+    assert_eq!(
+      manifest.files[PAYLOAD],
+      "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+  }
+
+  #[test]
+  fn verify_file_checks_the_hash() {
+    let dir = version_dir();
+    let (mut manifest, _) = PayloadManifest::read(dir.path()).unwrap();
+    manifest.verify_file(dir.path(), PAYLOAD).unwrap();
+    // Hashes are compared case-insensitively
+    manifest
+      .files
+      .insert(PAYLOAD.to_string(), manifest.files[PAYLOAD].to_uppercase());
+    manifest.verify_file(dir.path(), PAYLOAD).unwrap();
+
+    fs::write(dir.path().join(PAYLOAD), b"abd").unwrap();
+    assert!(error(manifest.verify_file(dir.path(), PAYLOAD)).contains("the manifest expects BA7816BF"));
+    assert!(error(verify_payload(dir.path(), PAYLOAD, &[])).contains("the manifest expects"));
+  }
+
+  #[test]
+  fn files_missing_from_the_manifest_are_rejected() {
+    let dir = version_dir();
+    fs::write(dir.path().join("other.dll"), b"abc").unwrap();
+    assert!(error(verify_payload(dir.path(), "other.dll", &[])).contains("other.dll is not in the manifest"));
+
+    fs::remove_file(dir.path().join(MANIFEST_FILE)).unwrap();
+    assert!(error(verify_payload(dir.path(), PAYLOAD, &[])).contains("Can't read"));
+  }
+
+  #[test]
+  fn manifest_signed_by_a_trusted_key_verifies() {
+    let dir = version_dir();
+    let trusted = SigningKey::from_bytes(&[1; 32]);
+    let other = SigningKey::from_bytes(&[2; 32]);
+    sign(dir.path(), &trusted);
+
+    let manifest = verify_payload(dir.path(), PAYLOAD, &[other.verifying_key(), trusted.verifying_key()]).unwrap();
+    assert_eq!(manifest.version, "1.0.0");
+  }
+
+  #[test]
+  fn untrusted_or_missing_signatures_are_rejected() {
+    let dir = version_dir();
+    let trusted = SigningKey::from_bytes(&[1; 32]);
+    let keys = [trusted.verifying_key()];
+    // Without trusted keys the signature isn't needed
+    verify_payload(dir.path(), PAYLOAD, &[]).unwrap();
+    assert!(error(verify_payload(dir.path(), PAYLOAD, &keys)).contains(SIGNATURE_FILE));
+
+    sign(dir.path(), &SigningKey::from_bytes(&[2; 32]));
+    assert_eq!(
+      error(verify_payload(dir.path(), PAYLOAD, &keys)),
+      AndromedaError::integrity("The manifest is not signed by a trusted key").to_string()
+    );
+
+    // A signature over another manifest doesn't carry over
+    sign(dir.path(), &trusted);
+    PayloadManifest::from_files("1.0.1", dir.path(), [PAYLOAD])
+      .unwrap()
+      .write(dir.path())
+      .unwrap();
+    assert!(error(verify_payload(dir.path(), PAYLOAD, &keys)).contains("not signed by a trusted key"));
+  }
+
+  #[test]
+  fn malformed_signatures_are_rejected() {
+    let keys = [SigningKey::from_bytes(&[1; 32]).verifying_key()];
+    let signature = to_hex(&keys[0].to_bytes());
+    for signature in ["", "not hex", &signature, &format!("{signature}{signature}zz")] {
+      assert!(
+        error(verify_manifest_signature(b"{}", signature, &keys)).contains("not a hex encoded ed25519 signature"),
+        "{signature}"
+      );
+    }
+  }
+
+  #[test]
+  fn hex_round_trips() {
+    assert_eq!(to_hex(&[0x00, 0xab, 0x10, 0xff]), "00ab10ff");
+    assert_eq!(from_hex("00ab10ff"), Some(vec![0x00, 0xab, 0x10, 0xff]));
+    assert_eq!(from_hex(" 00AB10FF\n"), Some(vec![0x00, 0xab, 0x10, 0xff]));
+    assert_eq!(from_hex(""), Some(vec![]));
+  }
+
+  #[test]
+  fn invalid_hex_is_rejected() {
+    for hex in ["abc", "zz", "0g", "+1", "éé", "00 11"] {
+      assert_eq!(from_hex(hex), None, "{hex}");
+    }
+  }
+
+  #[test]
+  fn public_keys_parse_from_hex() {
+    let key = SigningKey::from_bytes(&[1; 32]).verifying_key();
+    assert_eq!(parse_public_key(&to_hex(key.as_bytes())).unwrap(), key);
+    assert_eq!(parse_public_key(&to_hex(key.as_bytes()).to_uppercase()).unwrap(), key);
+
+    let short = to_hex(&key.as_bytes()[..31]);
+    for hex in ["", "xyz", &short, &format!("{short}zz")] {
+      assert!(
+        error(parse_public_key(hex)).contains("is not a hex encoded ed25519 public key"),
+        "{hex}"
+      );
+    }
+  }
+
+  #[test]
+  fn only_unsigned_local_builds_without_a_manifest_skip_verification() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = SigningKey::from_bytes(&[7; 32]).verifying_key();
+    assert!(allows_unverified(dir.path(), true, &[]));
+    assert!(!allows_unverified(dir.path(), false, &[]));
+    assert!(!allows_unverified(dir.path(), true, &[key]));
+
+    let dir = version_dir();
+    assert!(!allows_unverified(dir.path(), true, &[]));
+  }
+}
@@ -0,0 +1,250 @@
+//! Key chords like `Ctrl+Shift+F12` and conflict detection between bindings.
+//!
+//! Key names are the ones egui uses (`A`, `0`, `F12`, `Escape`, `PageUp`, ...), so the overlay can turn a parsed chord
+//! straight into an `egui::Key`.
+
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+/// Names accepted for keys, canonical name first and aliases after it
+const KEY_NAMES: &[&[&str]] = &[
+  &["Escape", "Esc"],
+  &["Tab"],
+  &["Backspace"],
+  &["Enter", "Return"],
+  &["Space"],
+  &["Insert", "Ins"],
+  &["Delete", "Del"],
+  &["Home"],
+  &["End"],
+  &["PageUp", "PgUp"],
+  &["PageDown", "PgDn"],
+  &["Up", "ArrowUp"],
+  &["Down", "ArrowDown"],
+  &["Left", "ArrowLeft"],
+  &["Right", "ArrowRight"],
+  &["Minus", "-"],
+  &["Plus"],
+  &["Equals", "="],
+  &["Comma", ","],
+  &["Period", "."],
+  &["Slash", "/"],
+  &["Backslash", "\\"],
+  &["Semicolon", ";"],
+  &["Quote", "'"],
+  &["Backtick", "`"],
+  &["OpenBracket", "["],
+  &["CloseBracket", "]"]
+];
+
+/// A key together with the modifiers that have to be held for it
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct Chord {
+  pub ctrl: bool,
+  pub shift: bool,
+  pub alt: bool,
+  /// Canonical key name, see the module docs
+  pub key: String
+}
+
+impl Chord {
+  /// A chord without modifiers, `None` if `key` isn't a known key name
+  pub fn from_key(key: &str) -> Option<Self> {
+    Some(Self {
+      ctrl: false,
+      shift: false,
+      alt: false,
+      key: canonical_key_name(key)?
+    })
+  }
+}
+
+impl fmt::Display for Chord {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.ctrl {
+      write!(f, "Ctrl+")?;
+    }
+    if self.shift {
+      write!(f, "Shift+")?;
+    }
+    if self.alt {
+      write!(f, "Alt+")?;
+    }
+    write!(f, "{}", self.key)
+  }
+}
+
+impl FromStr for Chord {
+  type Err = String;
+
+  fn from_str(chord: &str) -> Result<Self, Self::Err> {
+    let mut parts: Vec<&str> = chord.split('+').map(str::trim).collect();
+    // `Ctrl++` binds the plus key, splitting leaves two empty parts at the end
+    if parts.len() >= 2 && parts[parts.len() - 2..] == ["", ""] {
+      parts.truncate(parts.len() - 2);
+      parts.push("Plus");
+    }
+
+    let mut result = Chord {
+      ctrl: false,
+      shift: false,
+      alt: false,
+      key: String::new()
+    };
+    for part in parts {
+      let modifier = match part.to_ascii_lowercase().as_str() {
+        "ctrl" | "control" => &mut result.ctrl,
+        "shift" => &mut result.shift,
+        "alt" => &mut result.alt,
+        "" => return Err(format!("Empty key in '{chord}'")),
+        _ => {
+          if !result.key.is_empty() {
+            return Err(format!("More than one key in '{chord}'"));
+          }
+          result.key = canonical_key_name(part).ok_or_else(|| format!("Unknown key '{part}' in '{chord}'"))?;
+          continue;
+        }
+      };
+      if *modifier {
+        return Err(format!("Modifier '{part}' repeated in '{chord}'"));
+      }
+      *modifier = true;
+    }
+
+    if result.key.is_empty() {
+      return Err(format!("No key in '{chord}'"));
+    }
+    Ok(result)
+  }
+}
+
+/// The canonical spelling of a key name, case insensitive
+pub fn canonical_key_name(name: &str) -> Option<String> {
+  let mut chars = name.chars();
+  if let (Some(c), None) = (chars.next(), chars.next()) &&
+    c.is_ascii_alphanumeric()
+  {
+    return Some(c.to_ascii_uppercase().to_string());
+  }
+
+  if let Some(number) = name
+    .strip_prefix(['F', 'f'])
+    .and_then(|number| number.parse::<u8>().ok()) &&
+    (1..=24).contains(&number)
+  {
+    return Some(format!("F{number}"));
+  }
+
+  KEY_NAMES
+    .iter()
+    .find(|names| names.iter().any(|alias| alias.eq_ignore_ascii_case(name)))
+    .map(|names| names[0].to_string())
+}
+
+/// Several actions bound to the same chord
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct KeybindingConflict {
+  pub chord: Chord,
+  /// Sorted action ids
+  pub actions: Vec<String>
+}
+
+/// Every chord that more than one action is bound to, ordered by chord
+pub fn find_conflicts<'a>(bindings: impl IntoIterator<Item = (&'a str, &'a Chord)>) -> Vec<KeybindingConflict> {
+  let mut by_chord: BTreeMap<&Chord, Vec<String>> = BTreeMap::new();
+  for (action, chord) in bindings {
+    by_chord.entry(chord).or_default().push(action.to_string());
+  }
+
+  by_chord
+    .into_iter()
+    .filter(|(_, actions)| actions.len() > 1)
+    .map(|(chord, mut actions)| {
+      actions.sort();
+      KeybindingConflict {
+        chord: chord.clone(),
+        actions
+      }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn chord(chord: &str) -> Chord {
+    chord.parse().unwrap()
+  }
+
+  #[test]
+  fn formats_what_it_parses() {
+    for text in [
+      "F12",
+      "Shift+F12",
+      "Ctrl+Shift+Alt+A",
+      "Ctrl+Plus",
+      "Alt+PageDown",
+      "Ctrl+0",
+      "Shift+Backtick"
+    ] {
+      assert_eq!(chord(text).to_string(), text);
+    }
+  }
+
+  #[test]
+  fn parses_aliases_into_the_canonical_chord() {
+    assert_eq!(chord("shift + f12"), chord("Shift+F12"));
+    assert_eq!(chord("F12+Control+Shift").to_string(), "Ctrl+Shift+F12");
+    assert_eq!(chord("ctrl+esc").to_string(), "Ctrl+Escape");
+    assert_eq!(chord("Ctrl++").to_string(), "Ctrl+Plus");
+    assert_eq!(chord("Ctrl+-").to_string(), "Ctrl+Minus");
+    assert_eq!(chord("alt+pgdn").to_string(), "Alt+PageDown");
+    assert_eq!(chord("a").to_string(), "A");
+  }
+
+  #[test]
+  fn rejects_invalid_chords() {
+    for text in [
+      "",
+      "Ctrl",
+      "Ctrl+Shift",
+      "Ctrl+A+B",
+      "Ctrl+Ctrl+A",
+      "Ctrl++A",
+      "Ctrl+",
+      "F0",
+      "F25",
+      "Hyper+A",
+      "Ctrl+Nothing"
+    ] {
+      assert!(text.parse::<Chord>().is_err(), "{text} parsed");
+    }
+  }
+
+  #[test]
+  fn finds_chords_bound_more_than_once() {
+    let (f12, shift_f12, esc) = (chord("F12"), chord("Shift+F12"), chord("Escape"));
+    let conflicts = find_conflicts([
+      ("overlay", &shift_f12),
+      ("console", &f12),
+      ("menu", &esc),
+      ("screenshot", &f12),
+      ("close", &esc),
+      ("back", &esc)
+    ]);
+    assert_eq!(
+      conflicts,
+      vec![
+        KeybindingConflict {
+          chord: esc,
+          actions: vec!["back".to_string(), "close".to_string(), "menu".to_string()]
+        },
+        KeybindingConflict {
+          chord: f12.clone(),
+          actions: vec!["console".to_string(), "screenshot".to_string()]
+        }
+      ]
+    );
+    assert!(find_conflicts([("overlay", &shift_f12), ("console", &f12)]).is_empty());
+  }
+}
@@ -0,0 +1,239 @@
+//! Sessions started by `andromeda-runner` rather than through a proxy DLL in the game folder.
+//!
+//! Before injecting the proxy the runner leaves `<config>/launch/<pid>.json` for the game's process: config values
+//! that only hold for this session, merged over `andromeda_config.json` whenever the config is read in that process and
+//! kept out of it when it is saved. Once the payload started, or failed to, the proxy answers with `<pid>.status.json`.
+//! Process ids are reused, so a session is stamped with its process's creation time and ignored by any other process.
+
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  sync::OnceLock,
+  time::{Duration, SystemTime}
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+  config::andromeda_config::get_andromeda_config_path,
+  errors::{AndromedaError, ErrorContext},
+  utils::win32::current_process_creation_time
+};
+
+pub const LAUNCH_DIR: &str = "launch";
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LaunchSession {
+  /// Creation time of the game's process, see `process_creation_time`
+  pub process_created: u64,
+  /// Config values for this session, keyed like `andromeda_config.json`
+  pub overrides: Map<String, Value>
+}
+
+/// How the session's start went, as far as the proxy can tell
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartupStatus {
+  pub started: bool,
+  /// The payload's version or the local build it was loaded from
+  pub payload: Option<String>,
+  pub error: Option<String>,
+  pub safe_mode: bool,
+  /// Graphics APIs the payload hooked, see [`crate::crash::CrashNote::Hook`]
+  #[serde(default)]
+  pub hooks: Vec<String>
+}
+
+impl StartupStatus {
+  /// How loading the payload went, with the `hooks` it noted. A payload that installed no hooks never draws the
+  /// overlay, so it didn't start whatever its entrypoint said.
+  pub fn new(payload: Result<Option<String>, String>, hooks: Vec<String>, safe_mode: bool) -> Self {
+    let (started, error) = match &payload {
+      Ok(_) if hooks.is_empty() => (false, Some("The payload installed no hooks".to_string())),
+      Ok(_) => (true, None),
+      Err(error) => (false, Some(error.clone()))
+    };
+    Self {
+      started,
+      payload: payload.ok().flatten(),
+      error,
+      safe_mode,
+      hooks
+    }
+  }
+}
+
+pub fn launch_dir(config_dir: &Path) -> PathBuf {
+  config_dir.join(LAUNCH_DIR)
+}
+
+fn session_path(config_dir: &Path, pid: u32) -> PathBuf {
+  launch_dir(config_dir).join(format!("{pid}.json"))
+}
+
+fn status_path(config_dir: &Path, pid: u32) -> PathBuf {
+  launch_dir(config_dir).join(format!("{pid}.status.json"))
+}
+
+pub fn write_launch_session(config_dir: &Path, pid: u32, session: &LaunchSession) -> Result<(), AndromedaError> {
+  write_json(&session_path(config_dir, pid), session)
+}
+
+/// The session of process `pid` created at `process_created`
+pub fn read_launch_session(config_dir: &Path, pid: u32, process_created: u64) -> Option<LaunchSession> {
+  let session = fs::read_to_string(session_path(config_dir, pid)).ok()?;
+  serde_json::from_str::<LaunchSession>(&session)
+    .ok()
+    .filter(|session| session.process_created == process_created)
+}
+
+pub fn remove_launch_session(config_dir: &Path, pid: u32) {
+  let _ = fs::remove_file(session_path(config_dir, pid));
+}
+
+/// The session of this process, read once. `None` if it wasn't started by the runner.
+pub fn current_launch_session() -> Option<&'static LaunchSession> {
+  static SESSION: OnceLock<Option<LaunchSession>> = OnceLock::new();
+  SESSION
+    .get_or_init(|| {
+      let config_dir = get_andromeda_config_path()?;
+      let pid = std::process::id();
+      // Most processes weren't started by the runner, no need to ask Windows when they were created
+      if !session_path(&config_dir, pid).is_file() {
+        return None;
+      }
+      read_launch_session(&config_dir, pid, current_process_creation_time()?)
+    })
+    .as_ref()
+}
+
+/// Ends this process's session, the status stays for the runner to pick up
+pub fn end_current_launch_session() {
+  if current_launch_session().is_some() &&
+    let Some(config_dir) = get_andromeda_config_path()
+  {
+    remove_launch_session(&config_dir, std::process::id());
+  }
+}
+
+pub fn write_startup_status(config_dir: &Path, pid: u32, status: &StartupStatus) -> Result<(), AndromedaError> {
+  write_json(&status_path(config_dir, pid), status)
+}
+
+/// The status of process `pid` once the proxy wrote it, removing it
+pub fn take_startup_status(config_dir: &Path, pid: u32) -> Option<StartupStatus> {
+  let path = status_path(config_dir, pid);
+  let status = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+  let _ = fs::remove_file(path);
+  Some(status)
+}
+
+/// Removes sessions and statuses older than `max_age`, left behind by games that crashed or runners that gave up
+pub fn clean_up_launch_dir(config_dir: &Path, max_age: Duration) {
+  let Ok(entries) = fs::read_dir(launch_dir(config_dir)) else {
+    return;
+  };
+  let now = SystemTime::now();
+  for entry in entries.filter_map(|entry| entry.ok()) {
+    let stale = entry
+      .metadata()
+      .and_then(|metadata| metadata.modified())
+      .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() > max_age);
+    if stale {
+      let _ = fs::remove_file(entry.path());
+    }
+  }
+}
+
+/// Merges `overrides` into `value`, objects key by key and anything else replacing what was there
+pub fn merge_overrides(value: &mut Value, overrides: &Map<String, Value>) {
+  if !value.is_object() {
+    *value = Value::Object(Map::new());
+  }
+  let Some(object) = value.as_object_mut() else {
+    return;
+  };
+  for (key, override_value) in overrides {
+    match (object.get_mut(key), override_value) {
+      (Some(existing), Value::Object(nested)) if existing.is_object() => merge_overrides(existing, nested),
+      _ => {
+        object.insert(key.clone(), override_value.clone());
+      }
+    }
+  }
+}
+
+/// Puts the values of `original` back wherever `overrides` set one in `value`, so a config saved during a session
+/// doesn't keep its overrides
+pub fn restore_overridden(value: &mut Value, overrides: &Map<String, Value>, original: &Value) {
+  let Some(object) = value.as_object_mut() else {
+    return;
+  };
+  for (key, override_value) in overrides {
+    let original_value = original.get(key);
+    match (object.get_mut(key), override_value) {
+      (Some(existing), Value::Object(nested))
+        if existing.is_object() && original_value.is_none_or(Value::is_object) =>
+      {
+        restore_overridden(existing, nested, original_value.unwrap_or(&Value::Null))
+      }
+      _ => match original_value {
+        Some(original_value) => {
+          object.insert(key.clone(), original_value.clone());
+        }
+        None => {
+          object.remove(key);
+        }
+      }
+    }
+  }
+}
+
+fn write_json(path: &Path, value: &impl Serialize) -> Result<(), AndromedaError> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent).context(|| AndromedaError::config(format!("Can't create {}", parent.display())))?;
+  }
+  // The other side polls for the file, it must never see half of it
+  let temp_path = path.with_extension("json.tmp");
+  fs::write(&temp_path, serde_json::to_string_pretty(value)?)
+    .context(|| AndromedaError::config(format!("Can't write {}", temp_path.display())))?;
+  fs::rename(&temp_path, path).context(|| AndromedaError::config(format!("Can't replace {}", path.display())))?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn startup_status_needs_a_hook_to_count_as_started() {
+    let hooks = vec!["DX11".to_string()];
+    let status = StartupStatus::new(Ok(Some("1.0.0".to_string())), hooks.clone(), false);
+    assert!(status.started);
+    assert_eq!((status.payload.as_deref(), status.error), (Some("1.0.0"), None));
+
+    let status = StartupStatus::new(Ok(Some("1.0.0".to_string())), Vec::new(), false);
+    assert!(!status.started);
+    assert_eq!(status.payload.as_deref(), Some("1.0.0"));
+    assert_eq!(status.error.as_deref(), Some("The payload installed no hooks"));
+
+    let status = StartupStatus::new(Err("Payload 1.0.0 failed to initialize".to_string()), hooks, true);
+    assert!(!status.started && status.safe_mode);
+    assert_eq!(status.error.as_deref(), Some("Payload 1.0.0 failed to initialize"));
+  }
+
+  #[test]
+  fn startup_status_round_trips_through_the_launch_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let status = StartupStatus::new(Ok(None), vec!["Vulkan".to_string(), "DX11".to_string()], false);
+    write_startup_status(dir.path(), 42, &status).unwrap();
+
+    let read = take_startup_status(dir.path(), 42).unwrap();
+    assert!(read.started);
+    assert_eq!(read.hooks, ["Vulkan", "DX11"]);
+    // Taken once
+    assert!(take_startup_status(dir.path(), 42).is_none());
+  }
+}
@@ -4,32 +4,20 @@ use log::info;
 
 pub mod api;
 pub mod config;
+pub mod crash;
 pub mod errors;
+#[cfg(windows)]
 pub mod exports;
+pub mod guard;
+pub mod input;
+pub mod install;
+pub mod integrity;
+pub mod keybindings;
+pub mod launch;
+pub mod loader_store;
+pub mod logging;
+pub mod plugins;
+pub mod proxy;
+pub mod safe_mode;
+pub mod updates;
 pub mod utils;
-
-pub mod logging {
-  use std::fmt;
-
-  use chrono::Local;
-  use fern::FormatCallback;
-
-  pub fn andromeda_stdout_logging_format(out: FormatCallback, message: &fmt::Arguments, record: &log::Record) {
-    out.finish(format_args!(
-      "[Andromeda] [{}] [{}] {}",
-      record.level(),
-      record.target(),
-      message
-    ))
-  }
-
-  pub fn andromeda_file_logging_format(out: FormatCallback, message: &fmt::Arguments, record: &log::Record) {
-    out.finish(format_args!(
-      "[Andromeda] {} [{}] [{}] {}",
-      Local::now().format("%Y-%m-%d %H:%M:%S %:z"),
-      record.level(),
-      record.target(),
-      message
-    ))
-  }
-}
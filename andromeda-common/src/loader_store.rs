@@ -0,0 +1,602 @@
+//! Installed payload versions, one directory per version under `<config>/loader`.
+//!
+//! A version is installed by copying its bundle into a hidden directory next to the others and renaming it into place
+//! once it is complete, with a `manifest.json` (see [`crate::integrity`]) listing its files. Which version the proxy
+//! loads is `latestVersion` in the config, the version it replaced stays as `previousVersion` to roll back to.
+//! `loader/dev` is a developer's build and left alone.
+
+use std::{
+  cmp::Ordering,
+  fs, io,
+  path::{Path, PathBuf}
+};
+
+use log::{error, warn};
+
+use crate::{
+  config::{AndromedaConfig, andromeda_config::get_andromeda_config_path, write_andromeda_config},
+  errors::{AndromedaError, ErrorContext},
+  integrity::{MANIFEST_FILE, PayloadManifest, SIGNATURE_FILE}
+};
+
+pub const DEV_VERSION: &str = "dev";
+/// The DLL of a loader version the proxy loads
+pub const PAYLOAD_FILE: &str = "andromeda.dll";
+
+/// How far a payload got before it failed
+#[derive(Debug)]
+pub enum PayloadFailure {
+  /// It never ran, another version can be loaded in its place
+  NotLoaded(AndromedaError),
+  /// Its entrypoint ran and reported failure, it may have hooked things already
+  Initialization
+}
+
+/// Loader versions in a config directory
+pub struct LoaderStore {
+  config_dir: PathBuf
+}
+
+impl LoaderStore {
+  /// The store in `config_dir`, which also holds the `andromeda_config.json` pointing at the current version
+  pub fn new(config_dir: impl Into<PathBuf>) -> Self {
+    Self {
+      config_dir: config_dir.into()
+    }
+  }
+
+  /// The store in Andromeda's config directory
+  pub fn open() -> Option<Self> {
+    get_andromeda_config_path().map(Self::new)
+  }
+
+  pub fn loader_dir(&self) -> PathBuf {
+    self.config_dir.join("loader")
+  }
+
+  pub fn version_dir(&self, version: &str) -> PathBuf {
+    self.loader_dir().join(version)
+  }
+
+  /// A version is installed once its directory has a manifest
+  pub fn is_installed(&self, version: &str) -> bool {
+    is_version_name(version) && self.version_dir(version).join(MANIFEST_FILE).is_file()
+  }
+
+  /// Installed versions, oldest first
+  pub fn versions(&self) -> io::Result<Vec<String>> {
+    let mut versions: Vec<String> = match fs::read_dir(self.loader_dir()) {
+      Ok(entries) => entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|version| self.is_installed(version))
+        .collect(),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+      Err(e) => return Err(e)
+    };
+    versions.sort_by(|a, b| compare_versions(a, b));
+    Ok(versions)
+  }
+
+  /// Copies the payload bundle in `bundle` into `loader/<version>`, replacing an earlier install of the same version.
+  /// A bundle with a manifest (and maybe its signature) has its files checked against it and only those copied,
+  /// otherwise all its files are and the manifest is written here. Returns the version directory.
+  pub fn install(&self, version: &str, bundle: &Path) -> Result<PathBuf, AndromedaError> {
+    if !is_version_name(version) {
+      return Err(AndromedaError::config(format!(
+        "'{version}' can't be installed as a loader version"
+      )));
+    }
+    let loader_dir = self.loader_dir();
+    fs::create_dir_all(&loader_dir)
+      .context(|| AndromedaError::config(format!("Can't create {}", loader_dir.display())))?;
+
+    let staging = loader_dir.join(format!(".{version}.installing"));
+    remove_dir_if_exists(&staging)?;
+    let result = self.stage(version, bundle, &staging);
+    if result.is_err() {
+      let _ = fs::remove_dir_all(&staging);
+    }
+    result?;
+
+    // A directory is renamed over as a whole, so the old install moves aside first
+    let target = self.version_dir(version);
+    let replaced = loader_dir.join(format!(".{version}.replaced"));
+    if target.exists() {
+      remove_dir_if_exists(&replaced)?;
+      fs::rename(&target, &replaced)
+        .context(|| AndromedaError::config(format!("Can't move {} aside", target.display())))?;
+    }
+    fs::rename(&staging, &target).context(|| AndromedaError::config(format!("Can't create {}", target.display())))?;
+    let _ = fs::remove_dir_all(&replaced);
+    Ok(target)
+  }
+
+  fn stage(&self, version: &str, bundle: &Path, staging: &Path) -> Result<(), AndromedaError> {
+    fs::create_dir_all(staging).context(|| AndromedaError::config(format!("Can't create {}", staging.display())))?;
+    if bundle.join(MANIFEST_FILE).is_file() {
+      let (manifest, _) = PayloadManifest::read(bundle)?;
+      if manifest.version != version {
+        return Err(AndromedaError::integrity(format!(
+          "The bundle's manifest is for {}, not {}",
+          manifest.version, version
+        )));
+      }
+      for file in manifest.files.keys() {
+        if !is_bundle_path(file) {
+          return Err(AndromedaError::integrity(format!(
+            "The bundle's manifest lists {file} outside the bundle"
+          )));
+        }
+        manifest.verify_file(bundle, file)?;
+        copy_file(&bundle.join(file), &staging.join(file))?;
+      }
+      // Copied as is, a signature is over the manifest's exact bytes
+      copy_file(&bundle.join(MANIFEST_FILE), &staging.join(MANIFEST_FILE))?;
+      if bundle.join(SIGNATURE_FILE).is_file() {
+        copy_file(&bundle.join(SIGNATURE_FILE), &staging.join(SIGNATURE_FILE))?;
+      }
+    } else {
+      let files = bundle_files(bundle)?;
+      for file in &files {
+        copy_file(&bundle.join(file), &staging.join(file))?;
+      }
+      PayloadManifest::from_files(version, staging, files.iter().map(String::as_str))
+        .context(|| AndromedaError::integrity(format!("Can't hash the files of {version}")))?
+        .write(staging)?;
+    }
+    Ok(())
+  }
+
+  /// Makes the installed `version` the one the proxy loads next, remembering the current one to roll back to. A
+  /// version that failed before gets another chance.
+  pub fn make_current(&self, config: &mut AndromedaConfig, version: &str) -> Result<(), AndromedaError> {
+    if !self.is_installed(version) {
+      return Err(AndromedaError::config(format!(
+        "Loader version {version} is not installed"
+      )));
+    }
+    if config.latest_version() != version {
+      let previous = Some(config.latest_version().to_string()).filter(|previous| self.is_installed(previous));
+      config.set_loader_versions(version.to_string(), previous);
+    } else if !config.failed_versions().iter().any(|failed| failed == version) {
+      return Ok(());
+    }
+    config.set_version_failed(version, false);
+    write_andromeda_config(&self.config_dir, config)
+  }
+
+  /// Goes back from the current version, which failed to start, to the previous one or else the newest other
+  /// installed one. The failed version is remembered so updates don't pick it again. Returns the version now current,
+  /// `None` if there is nothing to go back to.
+  pub fn roll_back(&self, config: &mut AndromedaConfig) -> Result<Option<String>, AndromedaError> {
+    let failed = config.latest_version().to_string();
+    let fallback = match config.previous_version() {
+      Some(previous) if previous != failed && self.is_installed(previous) => Some(previous.to_string()),
+      _ => self.versions()?.into_iter().rev().find(|version| *version != failed)
+    };
+    let Some(fallback) = fallback else {
+      return Ok(None);
+    };
+    config.set_loader_versions(fallback.clone(), None);
+    config.set_version_failed(&failed, true);
+    write_andromeda_config(&self.config_dir, config)?;
+    Ok(Some(fallback))
+  }
+
+  /// Loads the current version with `load`, rolling back if it fails. A version that never ran is replaced right away,
+  /// one that failed to initialize may have hooked the game already, so the version rolled back to loads with the
+  /// next start. Local builds are never rolled back.
+  pub fn load_or_roll_back<T>(
+    &self,
+    config: &mut AndromedaConfig,
+    mut load: impl FnMut(&AndromedaConfig) -> Result<T, PayloadFailure>
+  ) -> Result<T, AndromedaError> {
+    let failure = match load(config) {
+      Ok(loaded) => return Ok(loaded),
+      Err(failure) => failure
+    };
+    if config.channel().is_local_build() {
+      return match failure {
+        PayloadFailure::NotLoaded(e) => Err(e),
+        PayloadFailure::Initialization => Err(AndromedaError::config("The local payload build failed to initialize"))
+      };
+    }
+    let failed_version = config.latest_version().to_string();
+    match &failure {
+      PayloadFailure::NotLoaded(e) => error!("Failed to load payload {}: {:#}", failed_version, e),
+      PayloadFailure::Initialization => error!("Payload {} failed to initialize", failed_version)
+    }
+
+    let version = self
+      .roll_back(config)?
+      .ok_or_else(|| AndromedaError::config("No other loader version to roll back to"))?;
+    warn!("Rolled back from loader version {} to {}", failed_version, version);
+    match failure {
+      PayloadFailure::NotLoaded(_) => match load(config) {
+        Ok(loaded) => Ok(loaded),
+        Err(PayloadFailure::NotLoaded(e)) => Err(e),
+        Err(PayloadFailure::Initialization) => Err(AndromedaError::config(format!(
+          "Payload {version} failed to initialize"
+        )))
+      },
+      // Two payloads hooking the game is worse than none, the rolled back one starts with the game next time
+      PayloadFailure::Initialization => Err(AndromedaError::config(format!(
+        "Payload {failed_version} failed to initialize, {version} loads with the next start"
+      )))
+    }
+  }
+
+  /// Removes installed versions but the current, the previous and the newest `config.keep_loader_versions` others,
+  /// and whatever an interrupted install left behind. Returns the removed versions.
+  pub fn collect_garbage(&self, config: &AndromedaConfig) -> Result<Vec<String>, AndromedaError> {
+    let loader_dir = self.loader_dir();
+    let entries = match fs::read_dir(&loader_dir) {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+      Err(e) => return Err(AndromedaError::config(format!("Can't read {}", loader_dir.display())).with_source(e))
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+      if entry.file_name().to_string_lossy().starts_with('.') {
+        let _ = fs::remove_dir_all(entry.path());
+      }
+    }
+
+    let mut removed = Vec::new();
+    let mut kept = 0;
+    for version in self
+      .versions()
+      .context(|| AndromedaError::config(format!("Can't read {}", loader_dir.display())))?
+      .into_iter()
+      .rev()
+    {
+      if version == config.latest_version() || Some(version.as_str()) == config.previous_version() {
+        continue;
+      }
+      if kept < config.keep_loader_versions {
+        kept += 1;
+        continue;
+      }
+      let dir = self.version_dir(&version);
+      fs::remove_dir_all(&dir).context(|| AndromedaError::config(format!("Can't remove {}", dir.display())))?;
+      removed.push(version);
+    }
+    Ok(removed)
+  }
+}
+
+/// Orders versions like `1.2.10` by their numeric parts, parts that aren't numbers compare as text and sort before
+/// numbers, so `1.0.0-beta` < `1.0.0`
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+  let mut a_parts = a.split(['.', '-', '+']);
+  let mut b_parts = b.split(['.', '-', '+']);
+  loop {
+    let ordering = match (a_parts.next(), b_parts.next()) {
+      (None, None) => return Ordering::Equal,
+      // `1.0` < `1.0.1`, but `1.0.0-beta` < `1.0.0`
+      (None, Some(part)) => match part.parse::<u64>() {
+        Ok(_) => Ordering::Less,
+        Err(_) => Ordering::Greater
+      },
+      (Some(part), None) => match part.parse::<u64>() {
+        Ok(_) => Ordering::Greater,
+        Err(_) => Ordering::Less
+      },
+      (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        (Ok(_), Err(_)) => Ordering::Greater,
+        (Err(_), Ok(_)) => Ordering::Less,
+        (Err(_), Err(_)) => a.cmp(b)
+      }
+    };
+    if ordering != Ordering::Equal {
+      return ordering;
+    }
+  }
+}
+
+/// A plain directory name that isn't the dev build or hidden
+pub(crate) fn is_version_name(version: &str) -> bool {
+  !version.is_empty() && version != DEV_VERSION && !version.starts_with('.') && !version.contains(['/', '\\', ':'])
+}
+
+/// A relative path that stays inside the directory it is relative to
+pub(crate) fn is_bundle_path(file: &str) -> bool {
+  !file.is_empty() &&
+    !file.starts_with(['/', '\\']) &&
+    !file.contains(':') &&
+    file
+      .split(['/', '\\'])
+      .all(|part| !part.is_empty() && part != "." && part != "..")
+}
+
+/// Files in `bundle` and its subdirectories, relative to it and `/` separated like in a manifest
+fn bundle_files(bundle: &Path) -> Result<Vec<String>, AndromedaError> {
+  let mut files = Vec::new();
+  let mut dirs = vec![(bundle.to_path_buf(), String::new())];
+  while let Some((dir, prefix)) = dirs.pop() {
+    let entries = fs::read_dir(&dir).context(|| AndromedaError::config(format!("Can't read {}", dir.display())))?;
+    for entry in entries {
+      let entry = entry?;
+      let name = entry.file_name().to_string_lossy().into_owned();
+      let relative = format!("{prefix}{name}");
+      if entry.file_type()?.is_dir() {
+        dirs.push((entry.path(), format!("{relative}/")));
+      } else if name != SIGNATURE_FILE {
+        files.push(relative);
+      }
+    }
+  }
+  files.sort();
+  Ok(files)
+}
+
+fn copy_file(from: &Path, to: &Path) -> Result<(), AndromedaError> {
+  if let Some(parent) = to.parent() {
+    fs::create_dir_all(parent).context(|| AndromedaError::config(format!("Can't create {}", parent.display())))?;
+  }
+  fs::copy(from, to).context(|| AndromedaError::config(format!("Can't copy {}", from.display())))?;
+  Ok(())
+}
+
+fn remove_dir_if_exists(dir: &Path) -> Result<(), AndromedaError> {
+  match fs::remove_dir_all(dir) {
+    Err(e) if e.kind() != io::ErrorKind::NotFound => {
+      Err(AndromedaError::config(format!("Can't remove {}", dir.display())).with_source(e))
+    }
+    _ => Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use super::*;
+  use crate::config::{Channel, read_andromeda_config};
+
+  /// A bundle of a payload and a data file in a subdirectory
+  fn bundle(dir: &Path, payload: &str) -> PathBuf {
+    let bundle = dir.join("bundle");
+    fs::create_dir_all(bundle.join("data")).unwrap();
+    fs::write(bundle.join(PAYLOAD_FILE), payload).unwrap();
+    fs::write(bundle.join("data").join("fonts.bin"), "fonts").unwrap();
+    bundle
+  }
+
+  /// A store with `versions` installed and the config they were installed with, each one made current in turn
+  fn store_with(dir: &Path, versions: &[&str]) -> (LoaderStore, AndromedaConfig) {
+    let store = LoaderStore::new(dir.join("config"));
+    let mut config = AndromedaConfig::default();
+    for version in versions {
+      store.install(version, &bundle(dir, version)).unwrap();
+      store.make_current(&mut config, version).unwrap();
+    }
+    (store, config)
+  }
+
+  fn files(manifest: &PayloadManifest) -> Vec<&str> {
+    manifest.files.keys().map(String::as_str).collect()
+  }
+
+  #[test]
+  fn install_without_manifest_takes_every_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = LoaderStore::new(dir.path().join("config"));
+    let bundle = bundle(dir.path(), "payload");
+    // A signature without a manifest can't be over anything installed
+    fs::write(bundle.join(SIGNATURE_FILE), "00").unwrap();
+
+    let installed = store.install("1.0.0", &bundle).unwrap();
+    assert_eq!(installed, store.version_dir("1.0.0"));
+    assert_eq!(fs::read_to_string(installed.join(PAYLOAD_FILE)).unwrap(), "payload");
+    assert_eq!(fs::read_to_string(installed.join("data/fonts.bin")).unwrap(), "fonts");
+    assert!(!installed.join(SIGNATURE_FILE).exists());
+
+    let (manifest, _) = PayloadManifest::read(&installed).unwrap();
+    assert_eq!(manifest.version, "1.0.0");
+    assert_eq!(files(&manifest), ["andromeda.dll", "data/fonts.bin"]);
+    manifest.verify_file(&installed, PAYLOAD_FILE).unwrap();
+    assert!(store.is_installed("1.0.0"));
+    assert_eq!(store.versions().unwrap(), ["1.0.0"]);
+  }
+
+  #[test]
+  fn install_with_manifest_takes_its_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = LoaderStore::new(dir.path().join("config"));
+    let bundle = bundle(dir.path(), "payload");
+    PayloadManifest::from_files("1.0.0", &bundle, [PAYLOAD_FILE])
+      .unwrap()
+      .write(&bundle)
+      .unwrap();
+    fs::write(bundle.join(SIGNATURE_FILE), "signature").unwrap();
+
+    let installed = store.install("1.0.0", &bundle).unwrap();
+    assert!(installed.join(PAYLOAD_FILE).is_file());
+    assert!(!installed.join("data").exists());
+    assert_eq!(
+      fs::read(installed.join(MANIFEST_FILE)).unwrap(),
+      fs::read(bundle.join(MANIFEST_FILE)).unwrap()
+    );
+    assert_eq!(fs::read_to_string(installed.join(SIGNATURE_FILE)).unwrap(), "signature");
+
+    // The manifest has to be for this version and match the files
+    assert!(store.install("1.1.0", &bundle).is_err());
+    fs::write(bundle.join(PAYLOAD_FILE), "tampered").unwrap();
+    assert!(store.install("1.0.0", &bundle).is_err());
+    // A failed install leaves the installed version alone and no staging directory behind
+    assert_eq!(fs::read_to_string(installed.join(PAYLOAD_FILE)).unwrap(), "payload");
+    assert_eq!(fs::read_dir(store.loader_dir()).unwrap().count(), 1);
+  }
+
+  #[test]
+  fn install_replaces_an_earlier_install() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, _) = store_with(dir.path(), &["1.0.0"]);
+    let bundle = bundle(dir.path(), "rebuilt");
+    fs::remove_dir_all(bundle.join("data")).unwrap();
+
+    let installed = store.install("1.0.0", &bundle).unwrap();
+    assert_eq!(fs::read_to_string(installed.join(PAYLOAD_FILE)).unwrap(), "rebuilt");
+    assert!(!installed.join("data").exists());
+    assert_eq!(fs::read_dir(store.loader_dir()).unwrap().count(), 1);
+  }
+
+  #[test]
+  fn install_rejects_paths_outside_the_bundle() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = LoaderStore::new(dir.path().join("config"));
+    let bundle = bundle(dir.path(), "payload");
+    fs::write(dir.path().join("outside.dll"), "outside").unwrap();
+    for file in [
+      "../outside.dll",
+      "data/../../outside.dll",
+      "/outside.dll",
+      "C:outside.dll"
+    ] {
+      PayloadManifest {
+        version: "1.0.0".to_string(),
+        files: BTreeMap::from([(file.to_string(), String::new())])
+      }
+      .write(&bundle)
+      .unwrap();
+      let error = store.install("1.0.0", &bundle).unwrap_err().to_string();
+      assert!(error.contains("outside the bundle"), "{file}: {error}");
+    }
+    assert!(!store.is_installed("1.0.0"));
+
+    fs::remove_file(bundle.join(MANIFEST_FILE)).unwrap();
+    for version in ["", "dev", ".hidden", "../1.0.0", "a/b", "a\\b", "C:1.0.0"] {
+      assert!(store.install(version, &bundle).is_err(), "{version}");
+    }
+    assert!(!dir.path().join("1.0.0").exists());
+  }
+
+  #[test]
+  fn make_current_keeps_the_replaced_version() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, mut config) = store_with(dir.path(), &["1.0.0", "1.1.0"]);
+    assert_eq!(config.latest_version(), "1.1.0");
+    assert_eq!(config.previous_version(), Some("1.0.0"));
+
+    // Not installed, nothing changes
+    assert!(store.make_current(&mut config, "2.0.0").is_err());
+    assert!(store.make_current(&mut config, DEV_VERSION).is_err());
+    assert_eq!(config.latest_version(), "1.1.0");
+
+    // Making the current version current again keeps the previous one
+    store.make_current(&mut config, "1.1.0").unwrap();
+    assert_eq!(config.previous_version(), Some("1.0.0"));
+
+    store.make_current(&mut config, "1.0.0").unwrap();
+    let written = read_andromeda_config(&store.config_dir).unwrap().unwrap();
+    assert_eq!(written.latest_version(), "1.0.0");
+    assert_eq!(written.previous_version(), Some("1.1.0"));
+  }
+
+  #[test]
+  fn roll_back_goes_to_the_previous_then_the_newest_other_version() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, mut config) = store_with(dir.path(), &["1.2.0", "1.0.0", "1.1.0"]);
+
+    assert_eq!(store.roll_back(&mut config).unwrap().as_deref(), Some("1.0.0"));
+    assert_eq!(config.previous_version(), None);
+    assert_eq!(store.roll_back(&mut config).unwrap().as_deref(), Some("1.2.0"));
+    let written = read_andromeda_config(&store.config_dir).unwrap().unwrap();
+    assert_eq!((written.latest_version(), written.previous_version()), ("1.2.0", None));
+
+    // A previous version that's gone doesn't count
+    store.make_current(&mut config, "1.0.0").unwrap();
+    fs::remove_dir_all(store.version_dir("1.2.0")).unwrap();
+    assert_eq!(store.roll_back(&mut config).unwrap().as_deref(), Some("1.1.0"));
+  }
+
+  #[test]
+  fn roll_back_needs_another_version() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, mut config) = store_with(dir.path(), &["1.0.0"]);
+    assert_eq!(store.roll_back(&mut config).unwrap(), None);
+    assert_eq!(config.latest_version(), "1.0.0");
+  }
+
+  #[test]
+  fn initialization_failure_rolls_back_for_the_next_start() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, mut config) = store_with(dir.path(), &["1.0.0", "1.1.0"]);
+    let mut loaded = Vec::new();
+    let result = store.load_or_roll_back(&mut config, |config| {
+      loaded.push(config.latest_version().to_string());
+      Err::<(), _>(PayloadFailure::Initialization)
+    });
+
+    assert!(
+      result
+        .unwrap_err()
+        .to_string()
+        .contains("Payload 1.1.0 failed to initialize, 1.0.0 loads with the next start")
+    );
+    // The failed payload may have hooked the game, nothing else is loaded on top of it
+    assert_eq!(loaded, ["1.1.0"]);
+    let written = read_andromeda_config(&store.config_dir).unwrap().unwrap();
+    assert_eq!(written.latest_version(), "1.0.0");
+    assert_eq!(written.failed_versions(), ["1.1.0"]);
+  }
+
+  #[test]
+  fn payload_that_never_ran_is_replaced_right_away() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, mut config) = store_with(dir.path(), &["1.0.0", "1.1.0"]);
+    let result = store.load_or_roll_back(&mut config, |config| match config.latest_version() {
+      "1.1.0" => Err(PayloadFailure::NotLoaded(AndromedaError::integrity("Tampered"))),
+      version => Ok(version.to_string())
+    });
+    assert_eq!(result.unwrap(), "1.0.0");
+    assert_eq!(config.failed_versions(), ["1.1.0"]);
+
+    // Local builds stay as they are
+    config.set_channel(Channel::Dev);
+    let result = store.load_or_roll_back(&mut config, |_| Err::<(), _>(PayloadFailure::Initialization));
+    assert!(result.unwrap_err().to_string().contains("local payload build failed"));
+    assert_eq!(config.latest_version(), "1.0.0");
+  }
+
+  #[test]
+  fn collect_garbage_keeps_current_previous_and_the_newest_others() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, mut config) = store_with(
+      dir.path(),
+      &["1.0.0", "1.1.0", "1.2.0", "1.3.0", "1.4.0", "1.0.1", "1.2.1"]
+    );
+    store.make_current(&mut config, "1.0.0").unwrap();
+    config.keep_loader_versions = 2;
+    fs::create_dir_all(store.version_dir(DEV_VERSION)).unwrap();
+    fs::create_dir_all(store.loader_dir().join(".1.5.0.installing")).unwrap();
+
+    let removed = store.collect_garbage(&config).unwrap();
+    assert_eq!(removed, ["1.2.0", "1.1.0", "1.0.1"]);
+    assert_eq!(store.versions().unwrap(), ["1.0.0", "1.2.1", "1.3.0", "1.4.0"]);
+    assert!(store.version_dir(DEV_VERSION).is_dir());
+    assert!(!store.loader_dir().join(".1.5.0.installing").exists());
+
+    config.keep_loader_versions = 0;
+    assert_eq!(store.collect_garbage(&config).unwrap(), ["1.4.0", "1.3.0"]);
+    assert_eq!(store.versions().unwrap(), ["1.0.0", "1.2.1"]);
+  }
+
+  #[test]
+  fn versions_compare_by_their_numbers() {
+    for (older, newer) in [
+      ("1.2.9", "1.2.10"),
+      ("1.0.0-beta", "1.0.0"),
+      ("1.0.0-alpha", "1.0.0-beta"),
+      ("1.0.0-beta.2", "1.0.0-beta.10"),
+      ("1.0", "1.0.1"),
+      ("0.9.9", "1.0.0")
+    ] {
+      assert_eq!(compare_versions(older, newer), Ordering::Less, "{older} < {newer}");
+      assert_eq!(compare_versions(newer, older), Ordering::Greater, "{newer} > {older}");
+    }
+    assert_eq!(compare_versions("1.2.3", "1.2.3"), Ordering::Equal);
+  }
+}
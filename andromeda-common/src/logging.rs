@@ -0,0 +1,46 @@
+pub mod buffer;
+pub mod files;
+pub mod json;
+pub mod levels;
+pub mod sink;
+
+use std::fmt;
+
+use chrono::Local;
+use fern::FormatCallback;
+
+use crate::{
+  config::LogFormat,
+  logging::{json::andromeda_json_logging_format, sink::log_module}
+};
+
+pub type LoggingFormat = fn(FormatCallback, &fmt::Arguments, &log::Record);
+
+/// The log file format configured
+pub fn file_logging_format(format: LogFormat) -> LoggingFormat {
+  match format {
+    LogFormat::Text => andromeda_file_logging_format,
+    LogFormat::Json => andromeda_json_logging_format
+  }
+}
+
+pub fn andromeda_stdout_logging_format(out: FormatCallback, message: &fmt::Arguments, record: &log::Record) {
+  out.finish(format_args!(
+    "[Andromeda] [{}] [{}] [{}] {}",
+    record.level(),
+    log_module(),
+    record.target(),
+    message
+  ))
+}
+
+pub fn andromeda_file_logging_format(out: FormatCallback, message: &fmt::Arguments, record: &log::Record) {
+  out.finish(format_args!(
+    "[Andromeda] {} [{}] [{}] [{}] {}",
+    Local::now().format("%Y-%m-%d %H:%M:%S %:z"),
+    record.level(),
+    log_module(),
+    record.target(),
+    message
+  ))
+}
@@ -0,0 +1,209 @@
+//! The most recent log records, kept in memory for the overlay console.
+//!
+//! Every DLL has its own buffer since each has its own copy of this module. The payload subscribes to the entry's
+//! buffer through `LogApi`, so the records of every module (including the ones logged before the payload was loaded)
+//! end up in the payload's buffer as well.
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use chrono::{DateTime, Local, TimeZone};
+use log::{Level, LevelFilter};
+
+use crate::logging::sink::{LogRecord, LogRecordFn, level_from_usize, log_module, str_from_raw};
+
+pub const LOG_BUFFER_CAPACITY: usize = 5000;
+
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+  /// Increases by one with every record the buffer takes
+  pub id: u64,
+  pub time: DateTime<Local>,
+  pub level: Level,
+  /// The module that logged it, e.g. `entry`, `payload` or `plugin.foo`
+  pub module: String,
+  pub target: String,
+  pub message: String
+}
+
+pub struct LogBuffer {
+  entries: VecDeque<LogEntry>,
+  capacity: usize,
+  next_id: u64,
+  subscribers: Vec<LogRecordFn>
+}
+
+pub static LOG_BUFFER: Mutex<LogBuffer> = Mutex::new(LogBuffer::new(LOG_BUFFER_CAPACITY));
+
+impl LogBuffer {
+  pub const fn new(capacity: usize) -> Self {
+    Self {
+      entries: VecDeque::new(),
+      capacity,
+      next_id: 0,
+      subscribers: Vec::new()
+    }
+  }
+
+  pub fn push(&mut self, time: DateTime<Local>, level: Level, module: &str, target: &str, message: String) {
+    if !self.subscribers.is_empty() {
+      let record = LogRecord {
+        level: level as usize,
+        timestamp_millis: time.timestamp_millis(),
+        module: module.as_ptr(),
+        module_len: module.len(),
+        target: target.as_ptr(),
+        target_len: target.len(),
+        message: message.as_ptr(),
+        message_len: message.len(),
+        fields: std::ptr::null(),
+        fields_len: 0
+      };
+      for subscriber in &self.subscribers {
+        unsafe { subscriber(&record) };
+      }
+    }
+
+    if self.entries.len() == self.capacity {
+      self.entries.pop_front();
+    }
+    self.entries.push_back(LogEntry {
+      id: self.next_id,
+      time,
+      level,
+      module: module.to_string(),
+      target: target.to_string(),
+      message
+    });
+    self.next_id += 1;
+  }
+
+  /// Entries with an id of at least `id`, oldest first
+  pub fn since(&self, id: u64) -> impl Iterator<Item = &LogEntry> {
+    let skip = match self.entries.front() {
+      Some(first) => id.saturating_sub(first.id) as usize,
+      None => 0
+    };
+    self.entries.iter().skip(skip)
+  }
+
+  /// The last `count` entries, oldest first
+  pub fn last(&self, count: usize) -> impl Iterator<Item = &LogEntry> {
+    self.entries.iter().skip(self.entries.len().saturating_sub(count))
+  }
+
+  /// The id the next entry will get
+  pub fn next_id(&self) -> u64 {
+    self.next_id
+  }
+}
+
+/// Feeds the records of a `fern::Dispatch` into [`LOG_BUFFER`]. Chain it before any formatting so the console gets the
+/// bare message.
+pub fn log_buffer_output() -> fern::Output {
+  fern::Output::call(|record| {
+    if let Ok(mut buffer) = LOG_BUFFER.lock() {
+      buffer.push(
+        Local::now(),
+        record.level(),
+        &log_module(),
+        record.target(),
+        record.args().to_string()
+      );
+    }
+  })
+}
+
+pub(crate) unsafe extern "system" fn subscribe_to_log_buffer(sink: LogRecordFn) {
+  let Ok(mut buffer) = LOG_BUFFER.lock() else {
+    return;
+  };
+  for entry in &buffer.entries {
+    let record = LogRecord {
+      level: entry.level as usize,
+      timestamp_millis: entry.time.timestamp_millis(),
+      module: entry.module.as_ptr(),
+      module_len: entry.module.len(),
+      target: entry.target.as_ptr(),
+      target_len: entry.target.len(),
+      message: entry.message.as_ptr(),
+      message_len: entry.message.len(),
+      fields: std::ptr::null(),
+      fields_len: 0
+    };
+    unsafe { sink(&record) };
+  }
+  buffer.subscribers.push(sink);
+}
+
+/// A [`LogRecordFn`] that adds the records another DLL forwards to our [`LOG_BUFFER`]
+///
+/// # Safety
+///
+/// `record` has to be null or point to a [`LogRecord`] whose strings are valid for the call.
+pub unsafe extern "system" fn receive_log_record(record: *const LogRecord) {
+  let Some(record) = (unsafe { record.as_ref() }) else {
+    return;
+  };
+  let time = Local
+    .timestamp_millis_opt(record.timestamp_millis)
+    .single()
+    .unwrap_or_else(Local::now);
+  let module = unsafe { str_from_raw(record.module, record.module_len) };
+  let target = unsafe { str_from_raw(record.target, record.target_len) };
+  let message = unsafe { str_from_raw(record.message, record.message_len) };
+  if let Ok(mut buffer) = LOG_BUFFER.lock() {
+    buffer.push(
+      time,
+      level_from_usize(record.level),
+      &module,
+      &target,
+      message.into_owned()
+    );
+  }
+}
+
+/// What the console shows of the buffer
+#[derive(Clone, Debug)]
+pub struct LogFilter {
+  pub level: LevelFilter,
+  /// Targets starting with this, case insensitive
+  pub target: String,
+  /// Messages containing this, case insensitive
+  pub search: String
+}
+
+impl Default for LogFilter {
+  fn default() -> Self {
+    Self {
+      level: LevelFilter::Trace,
+      target: String::new(),
+      search: String::new()
+    }
+  }
+}
+
+impl LogFilter {
+  pub fn matches(&self, entry: &LogEntry) -> bool {
+    entry.level <= self.level &&
+      (self.target.is_empty() || starts_with_ignore_case(&entry.target, &self.target)) &&
+      (self.search.is_empty() || entry.message.to_lowercase().contains(&self.search.to_lowercase()))
+  }
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+  text
+    .get(..prefix.len())
+    .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+/// How an entry is copied to the clipboard
+pub fn format_log_entry(entry: &LogEntry) -> String {
+  format!(
+    "{} [{}] [{}] [{}] {}",
+    entry.time.format("%Y-%m-%d %H:%M:%S%.3f"),
+    entry.level,
+    entry.module,
+    entry.target,
+    entry.message
+  )
+}
@@ -0,0 +1,410 @@
+//! Log files that survive the next launch.
+//!
+//! Every session writes to its own `<basename>_<timestamp>.log`, which is rotated to a new timestamped file once it
+//! grows too large or the day changes. `<basename>.log` is a hard link to whichever file is being written, so the
+//! current log is always in the same place. Old session files are removed by count and by age.
+
+use std::{
+  fs::{self, File},
+  io::{self, Write},
+  path::{Path, PathBuf},
+  sync::Mutex,
+  time::{Duration, SystemTime}
+};
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+
+use crate::config::LoggingConfig;
+
+/// Basename of the entry DLL's log files
+pub const ENTRY_LOG_BASENAME: &str = "Andromeda.Entry";
+/// Basename of the payload's log files
+pub const PAYLOAD_LOG_BASENAME: &str = "Andromeda";
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+/// Length of a [`TIMESTAMP_FORMAT`] timestamp
+const TIMESTAMP_LEN: usize = 19;
+
+/// When log files are rotated and how many are kept, `None` meaning no limit
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LogFilePolicy {
+  pub max_size: Option<u64>,
+  pub daily: bool,
+  pub keep: Option<usize>,
+  pub max_age: Option<Duration>
+}
+
+impl LogFilePolicy {
+  pub fn from_config(config: &LoggingConfig) -> Self {
+    Self {
+      max_size: (config.max_file_size_mb > 0).then(|| config.max_file_size_mb * 1024 * 1024),
+      daily: config.rotate_daily,
+      keep: (config.keep_files > 0).then_some(config.keep_files),
+      max_age: (config.max_age_days > 0).then(|| Duration::from_secs(config.max_age_days * 24 * 60 * 60))
+    }
+  }
+}
+
+/// The log file currently written to and the policy for its successors
+pub struct LogFiles {
+  dir: PathBuf,
+  basename: String,
+  policy: LogFilePolicy,
+  file: File,
+  path: PathBuf,
+  size: u64,
+  day: NaiveDate
+}
+
+impl LogFiles {
+  /// Starts a new session file in `dir`, creating the directory if needed
+  pub fn open(dir: &Path, basename: &str, policy: LogFilePolicy) -> io::Result<Self> {
+    fs::create_dir_all(dir)?;
+    let now = Local::now();
+    let (file, path) = create_session_file(dir, basename, now)?;
+    Ok(Self {
+      dir: dir.to_path_buf(),
+      basename: basename.to_string(),
+      policy,
+      file,
+      path,
+      size: 0,
+      day: now.date_naive()
+    })
+  }
+
+  /// The file currently written to
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// The hard link to the current file
+  pub fn latest_path(&self) -> PathBuf {
+    latest_path(&self.dir, &self.basename)
+  }
+
+  pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+    self.write_line_at(line, Local::now())
+  }
+
+  /// Writes `line` as if it was `now`, rotating first if the line doesn't fit the current file or the day changed.
+  /// Lines are never split across files.
+  pub fn write_line_at(&mut self, line: &str, now: DateTime<Local>) -> io::Result<()> {
+    let len = line.len() as u64 + 1;
+    let too_large = self
+      .policy
+      .max_size
+      .is_some_and(|max_size| self.size > 0 && self.size + len > max_size);
+    let new_day = self.policy.daily && now.date_naive() != self.day;
+    if too_large || new_day {
+      self.rotate(now)?;
+    }
+
+    self.file.write_all(line.as_bytes())?;
+    self.file.write_all(b"\n")?;
+    self.size += len;
+    Ok(())
+  }
+
+  fn rotate(&mut self, now: DateTime<Local>) -> io::Result<()> {
+    let (file, path) = create_session_file(&self.dir, &self.basename, now)?;
+    self.file = file;
+    self.path = path;
+    self.size = 0;
+    self.day = now.date_naive();
+    // Nowhere to report this to from inside the logger, the next startup tries again
+    let _ = clean_up_log_files(&self.dir, &self.basename, &self.policy, SystemTime::now(), &self.path);
+    Ok(())
+  }
+
+  /// A `fern::Output` writing formatted records to these files
+  pub fn into_output(self) -> fern::Output {
+    let files = Mutex::new(self);
+    fern::Output::call(move |record| {
+      if let Ok(mut files) = files.lock() &&
+        let Err(e) = files.write_line(&record.args().to_string())
+      {
+        eprintln!("Failed to write log file: {e}");
+      }
+    })
+  }
+}
+
+fn latest_path(dir: &Path, basename: &str) -> PathBuf {
+  dir.join(format!("{basename}.log"))
+}
+
+/// Creates a new timestamped file, adding a counter if a file of the same second exists, and points the latest file
+/// at it
+fn create_session_file(dir: &Path, basename: &str, now: DateTime<Local>) -> io::Result<(File, PathBuf)> {
+  let timestamp = now.format(TIMESTAMP_FORMAT);
+  let mut path = dir.join(format!("{basename}_{timestamp}.log"));
+  let mut counter = 1;
+  let file = loop {
+    match File::create_new(&path) {
+      Ok(file) => break file,
+      Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+        path = dir.join(format!("{basename}_{timestamp}_{counter}.log"));
+        counter += 1;
+      }
+      Err(e) => return Err(e)
+    }
+  };
+
+  // Fails while another process has the latest file open, it keeps pointing at the previous file then
+  let latest = latest_path(dir, basename);
+  if fs::remove_file(&latest).is_ok() || !latest.exists() {
+    let _ = fs::hard_link(&path, &latest);
+  }
+  Ok((file, path))
+}
+
+/// Session files of `basename` in `dir`, oldest first
+pub fn log_files(dir: &Path, basename: &str) -> io::Result<Vec<PathBuf>> {
+  let prefix = format!("{basename}_");
+  let mut files: Vec<(String, u32, PathBuf)> = fs::read_dir(dir)?
+    .filter_map(|entry| entry.ok())
+    .filter_map(|entry| {
+      let name = entry.file_name().into_string().ok()?;
+      let rest = name.strip_prefix(&prefix)?.strip_suffix(".log")?;
+      let timestamp = rest.get(..TIMESTAMP_LEN)?;
+      NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?;
+      let counter = match &rest[TIMESTAMP_LEN..] {
+        "" => 0,
+        counter => counter.strip_prefix('_')?.parse().ok()?
+      };
+      Some((timestamp.to_string(), counter, entry.path()))
+    })
+    .collect();
+  files.sort();
+  Ok(files.into_iter().map(|(_, _, path)| path).collect())
+}
+
+/// Removes session files beyond the policy's count or age, never `current`. Returns the removed files.
+pub fn clean_up_log_files(
+  dir: &Path,
+  basename: &str,
+  policy: &LogFilePolicy,
+  now: SystemTime,
+  current: &Path
+) -> io::Result<Vec<PathBuf>> {
+  let files: Vec<PathBuf> = log_files(dir, basename)?
+    .into_iter()
+    .filter(|path| path != current)
+    .collect();
+  // The current file counts towards the kept ones
+  let excess = policy.keep.map_or(0, |keep| (files.len() + 1).saturating_sub(keep));
+
+  let mut removed = Vec::new();
+  for (index, path) in files.into_iter().enumerate() {
+    let expired = policy.max_age.is_some_and(|max_age| {
+      fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| now.duration_since(modified).is_ok_and(|age| age > max_age))
+    });
+    if (index < excess || expired) && fs::remove_file(&path).is_ok() {
+      removed.push(path);
+    }
+  }
+  Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::TimeZone;
+
+  use super::*;
+
+  const BASENAME: &str = "Test";
+
+  fn policy() -> LogFilePolicy {
+    LogFilePolicy {
+      max_size: None,
+      daily: false,
+      keep: None,
+      max_age: None
+    }
+  }
+
+  fn at(day: u32, hour: u32) -> DateTime<Local> {
+    Local.with_ymd_and_hms(2026, 3, day, hour, 0, 0).unwrap()
+  }
+
+  fn names(dir: &Path) -> Vec<String> {
+    log_files(dir, BASENAME)
+      .unwrap()
+      .iter()
+      .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+      .collect()
+  }
+
+  /// Session files named for `timestamps`, as earlier sessions would have left them
+  fn sessions(dir: &Path, timestamps: &[&str]) -> Vec<PathBuf> {
+    timestamps
+      .iter()
+      .map(|timestamp| {
+        let path = dir.join(format!("{BASENAME}_{timestamp}.log"));
+        fs::write(&path, timestamp).unwrap();
+        path
+      })
+      .collect()
+  }
+
+  #[test]
+  fn rotates_by_size_without_splitting_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut files = LogFiles::open(
+      dir.path(),
+      BASENAME,
+      LogFilePolicy {
+        max_size: Some(16),
+        ..policy()
+      }
+    )
+    .unwrap();
+    let first = files.path().to_path_buf();
+    files.write_line_at("first", at(1, 12)).unwrap();
+    files.write_line_at("second", at(1, 12)).unwrap();
+    files.write_line_at("third", at(1, 12)).unwrap();
+    // A line larger than a file still has to go somewhere
+    files.write_line_at("a line that is too long", at(1, 12)).unwrap();
+
+    assert_eq!(fs::read_to_string(&first).unwrap(), "first\nsecond\n");
+    let rotated = dir.path().join(format!("{BASENAME}_2026-03-01_12-00-00.log"));
+    assert_eq!(fs::read_to_string(&rotated).unwrap(), "third\n");
+    assert_eq!(
+      files.path(),
+      dir.path().join(format!("{BASENAME}_2026-03-01_12-00-00_1.log"))
+    );
+    assert_eq!(fs::read_to_string(files.path()).unwrap(), "a line that is too long\n");
+    assert_eq!(
+      fs::read_to_string(files.latest_path()).unwrap(),
+      "a line that is too long\n"
+    );
+  }
+
+  #[test]
+  fn rotates_when_the_day_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut files = LogFiles::open(
+      dir.path(),
+      BASENAME,
+      LogFilePolicy {
+        daily: true,
+        ..policy()
+      }
+    )
+    .unwrap();
+    files.write_line_at("morning", at(1, 8)).unwrap();
+    files.write_line_at("evening", at(1, 20)).unwrap();
+    files.write_line_at("next day", at(2, 8)).unwrap();
+
+    let day_one = dir.path().join(format!("{BASENAME}_2026-03-01_08-00-00.log"));
+    assert_eq!(fs::read_to_string(day_one).unwrap(), "morning\nevening\n");
+    assert_eq!(
+      files.path(),
+      dir.path().join(format!("{BASENAME}_2026-03-02_08-00-00.log"))
+    );
+    assert_eq!(fs::read_to_string(files.latest_path()).unwrap(), "next day\n");
+    assert_eq!(log_files(dir.path(), BASENAME).unwrap().len(), 3);
+  }
+
+  #[test]
+  fn keeps_one_file_without_daily_rotation() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut files = LogFiles::open(dir.path(), BASENAME, policy()).unwrap();
+    files.write_line_at("today", at(1, 8)).unwrap();
+    files.write_line_at("tomorrow", at(2, 8)).unwrap();
+    assert_eq!(fs::read_to_string(files.path()).unwrap(), "today\ntomorrow\n");
+    assert_eq!(log_files(dir.path(), BASENAME).unwrap().len(), 1);
+  }
+
+  #[test]
+  fn counts_up_files_of_the_same_second() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths: Vec<PathBuf> = (0..3)
+      .map(|_| create_session_file(dir.path(), BASENAME, at(1, 12)).unwrap().1)
+      .collect();
+    let expected = [
+      "Test_2026-03-01_12-00-00.log",
+      "Test_2026-03-01_12-00-00_1.log",
+      "Test_2026-03-01_12-00-00_2.log"
+    ];
+    assert_eq!(paths, expected.map(|name| dir.path().join(name)));
+    assert_eq!(names(dir.path()), expected);
+  }
+
+  #[test]
+  fn lists_session_files_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    sessions(
+      dir.path(),
+      &[
+        "2026-03-02_00-00-00",
+        "2026-03-01_00-00-00_10",
+        "2026-03-01_00-00-00_2",
+        "2026-03-01_00-00-00"
+      ]
+    );
+    // Neither session files nor this basename's
+    for name in [
+      "Test.log",
+      "Test_yesterday.log",
+      "Test_2026-03-01_00-00-00_x.log",
+      "Other_2026-03-01_00-00-00.log"
+    ] {
+      fs::write(dir.path().join(name), "").unwrap();
+    }
+    assert_eq!(
+      names(dir.path()),
+      [
+        "Test_2026-03-01_00-00-00.log",
+        "Test_2026-03-01_00-00-00_2.log",
+        "Test_2026-03-01_00-00-00_10.log",
+        "Test_2026-03-02_00-00-00.log"
+      ]
+    );
+  }
+
+  #[test]
+  fn cleans_up_by_count_keeping_the_current_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = sessions(
+      dir.path(),
+      &[
+        "2026-03-01_00-00-00",
+        "2026-03-02_00-00-00",
+        "2026-03-03_00-00-00",
+        "2026-03-04_00-00-00"
+      ]
+    );
+    let policy = LogFilePolicy {
+      keep: Some(2),
+      ..policy()
+    };
+    // The current file is the oldest, e.g. a session running since before the others
+    let removed = clean_up_log_files(dir.path(), BASENAME, &policy, SystemTime::now(), &paths[0]).unwrap();
+    assert_eq!(removed, paths[1..3]);
+    assert_eq!(
+      names(dir.path()),
+      ["Test_2026-03-01_00-00-00.log", "Test_2026-03-04_00-00-00.log"]
+    );
+  }
+
+  #[test]
+  fn cleans_up_by_age_keeping_the_current_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = sessions(dir.path(), &["2026-03-01_00-00-00", "2026-03-02_00-00-00"]);
+    let policy = LogFilePolicy {
+      max_age: Some(Duration::from_secs(24 * 60 * 60)),
+      ..policy()
+    };
+    let removed = clean_up_log_files(dir.path(), BASENAME, &policy, SystemTime::now(), &paths[0]).unwrap();
+    assert!(removed.is_empty());
+
+    let two_days_later = SystemTime::now() + Duration::from_secs(2 * 24 * 60 * 60);
+    let removed = clean_up_log_files(dir.path(), BASENAME, &policy, two_days_later, &paths[0]).unwrap();
+    assert_eq!(removed, paths[1..]);
+    assert_eq!(names(dir.path()), ["Test_2026-03-01_00-00-00.log"]);
+  }
+}
@@ -0,0 +1,180 @@
+//! JSON lines log format, one object per record for loading logs into tooling.
+
+use std::fmt;
+
+use chrono::Local;
+use fern::FormatCallback;
+use log::kv::{self, Key, Source, Value, VisitSource};
+use serde_json::{Map, Number, json};
+
+use crate::logging::sink::log_module;
+
+/// Formats a record as a single line JSON object with timestamp, level, target, module, thread id, message and the
+/// record's key-value fields
+pub fn andromeda_json_logging_format(out: FormatCallback, message: &fmt::Arguments, record: &log::Record) {
+  let line = json!({
+    "timestamp": Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+    "level": record.level().as_str(),
+    "target": record.target(),
+    "module": log_module(),
+    "thread": current_thread_id(),
+    "message": message.to_string(),
+    "fields": record_fields(record.key_values())
+  });
+  out.finish(format_args!("{line}"))
+}
+
+#[cfg(windows)]
+fn current_thread_id() -> u32 {
+  unsafe { windows::Win32::System::Threading::GetCurrentThreadId() }
+}
+
+/// Stands in for the OS thread id elsewhere, stable for the thread's lifetime
+#[cfg(not(windows))]
+fn current_thread_id() -> u32 {
+  use std::hash::{DefaultHasher, Hash, Hasher};
+
+  let mut hasher = DefaultHasher::new();
+  std::thread::current().id().hash(&mut hasher);
+  hasher.finish() as u32
+}
+
+/// The key-value fields of a record, numbers and booleans keep their type and everything else becomes a string
+pub fn record_fields(source: &dyn Source) -> Map<String, serde_json::Value> {
+  struct Collect(Map<String, serde_json::Value>);
+
+  impl<'kvs> VisitSource<'kvs> for Collect {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+      let value = if let Some(value) = value.to_bool() {
+        serde_json::Value::Bool(value)
+      } else if let Some(value) = value.to_i64() {
+        value.into()
+      } else if let Some(value) = value.to_u64() {
+        value.into()
+      } else if let Some(value) = value.to_f64().and_then(Number::from_f64) {
+        serde_json::Value::Number(value)
+      } else {
+        serde_json::Value::String(value.to_string())
+      };
+      self.0.insert(key.to_string(), value);
+      Ok(())
+    }
+  }
+
+  let mut fields = Collect(Map::new());
+  let _ = source.visit(&mut fields);
+  fields.0
+}
+
+/// Key-value fields that crossed the DLL boundary as a JSON object, see [`record_fields`]
+pub struct JsonFields(pub Map<String, serde_json::Value>);
+
+impl Source for JsonFields {
+  fn visit<'kvs>(&'kvs self, visitor: &mut dyn VisitSource<'kvs>) -> Result<(), kv::Error> {
+    for (key, value) in &self.0 {
+      let value = match value {
+        serde_json::Value::Bool(value) => Value::from(*value),
+        serde_json::Value::Number(number) => match (number.as_i64(), number.as_u64(), number.as_f64()) {
+          (Some(value), _, _) => Value::from(value),
+          (_, Some(value), _) => Value::from(value),
+          (_, _, Some(value)) => Value::from(value),
+          _ => Value::from_display(number)
+        },
+        serde_json::Value::String(value) => Value::from(value.as_str()),
+        value => Value::from_display(value)
+      };
+      visitor.visit_pair(Key::from_str(key), value)?;
+    }
+    Ok(())
+  }
+
+  fn count(&self) -> usize {
+    self.0.len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+
+  use log::Level;
+
+  use super::*;
+  use crate::logging::sink::set_log_module;
+
+  /// Formats a record through fern like the file output does and parses the line back
+  fn format_line(message: &str, fields: &dyn Source) -> serde_json::Value {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let output = lines.clone();
+    let (_, logger) = fern::Dispatch::new()
+      .format(andromeda_json_logging_format)
+      .chain(fern::Output::call(move |record| {
+        output.lock().unwrap().push(record.args().to_string())
+      }))
+      .into_log();
+    logger.log(
+      &log::Record::builder()
+        .level(Level::Warn)
+        .target("andromeda::hooks")
+        .args(format_args!("{message}"))
+        .key_values(fields)
+        .build()
+    );
+
+    let lines = lines.lock().unwrap();
+    assert_eq!(lines.len(), 1);
+    assert!(!lines[0].contains('\n'));
+    serde_json::from_str(&lines[0]).unwrap()
+  }
+
+  #[test]
+  fn lines_carry_the_record_fields() {
+    set_log_module("entry");
+    let line = format_line("hooked", &[("attempt", 2)]);
+
+    assert!(chrono::DateTime::parse_from_rfc3339(line["timestamp"].as_str().unwrap()).is_ok());
+    assert_eq!(line["level"], "WARN");
+    assert_eq!(line["target"], "andromeda::hooks");
+    assert_eq!(line["module"], "entry");
+    assert!(line["thread"].is_u64());
+    assert_eq!(line["message"], "hooked");
+    assert_eq!(line["fields"], json!({ "attempt": 2 }));
+  }
+
+  #[test]
+  fn quotes_and_newlines_are_escaped() {
+    let message = "said \"hi\"\nthen left";
+    let line = format_line(message, &[("path", "C:\\games\\\"quoted\"\n")]);
+
+    assert_eq!(line["message"], message);
+    assert_eq!(line["fields"]["path"], "C:\\games\\\"quoted\"\n");
+  }
+
+  #[test]
+  fn fields_keep_their_types_across_the_dll_boundary() {
+    let sent = [
+      ("flag", Value::from(true)),
+      ("count", Value::from(-3i64)),
+      ("big", Value::from(u64::MAX))
+    ];
+    let fields = record_fields(&sent);
+    assert_eq!(fields["flag"], json!(true));
+    assert_eq!(fields["count"], json!(-3));
+    assert_eq!(fields["big"], json!(u64::MAX));
+
+    let received = JsonFields(serde_json::from_str(&serde_json::to_string(&fields).unwrap()).unwrap());
+    assert_eq!(received.count(), 3);
+    assert_eq!(record_fields(&received), fields);
+  }
+
+  #[test]
+  fn strings_and_floats_round_trip() {
+    let text = "line \"one\"\nline two";
+    let fields = record_fields(&[("text", Value::from(text)), ("ratio", Value::from(0.5))]);
+    assert_eq!(fields["text"], json!(text));
+    assert_eq!(fields["ratio"], json!(0.5));
+
+    let line = format_line("formatted", &JsonFields(fields.clone()));
+    assert_eq!(line["fields"], serde_json::Value::Object(fields));
+  }
+}
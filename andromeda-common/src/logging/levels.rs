@@ -0,0 +1,276 @@
+//! Which records get logged, globally and per target, changeable while the game runs.
+//!
+//! Levels come from the `logging` section of `AndromedaConfig`, the [`LOG_ENV_VAR`] environment variable overrides
+//! them with `RUST_LOG` style directives like `info,andromeda::hooks=trace,plugin.foo=warn`. A directive matches a
+//! record's target or the module it was logged in, see [`crate::logging::sink`].
+
+use std::{collections::BTreeMap, str::FromStr, sync::RwLock};
+
+use log::{LevelFilter, Metadata};
+
+use crate::{
+  config::LoggingConfig,
+  logging::sink::{forward_levels, log_module}
+};
+
+pub const LOG_ENV_VAR: &str = "ANDROMEDA_LOG";
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LogLevels {
+  /// Level of targets without one of their own
+  pub default: LevelFilter,
+  /// Levels by target or module prefix, a prefix covers the ones below it in `::` or `.` separated paths
+  pub targets: BTreeMap<String, LevelFilter>
+}
+
+impl Default for LogLevels {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+static LOG_LEVELS: RwLock<LogLevels> = RwLock::new(LogLevels::new());
+
+impl LogLevels {
+  pub const fn new() -> Self {
+    Self {
+      default: LevelFilter::Debug,
+      targets: BTreeMap::new()
+    }
+  }
+
+  /// Levels as configured, with the reasons for skipping invalid ones
+  pub fn from_config(config: &LoggingConfig) -> (Self, Vec<String>) {
+    let mut levels = Self::new();
+    let mut errors = Vec::new();
+    match LevelFilter::from_str(&config.level) {
+      Ok(level) => levels.default = level,
+      Err(_) => errors.push(format!("Invalid log level '{}'", config.level))
+    }
+    for (target, level) in &config.targets {
+      match LevelFilter::from_str(level) {
+        Ok(level) => {
+          levels.targets.insert(target.clone(), level);
+        }
+        Err(_) => errors.push(format!("Invalid log level '{level}' for '{target}'"))
+      }
+    }
+    (levels, errors)
+  }
+
+  /// Writes the levels back into a config
+  pub fn store(&self, config: &mut LoggingConfig) {
+    config.level = self.default.to_string().to_lowercase();
+    config.targets = self
+      .targets
+      .iter()
+      .map(|(target, level)| (target.clone(), level.to_string().to_lowercase()))
+      .collect();
+  }
+
+  /// Applies comma separated `level`, `target=level` or `target` (everything from that target) directives on top
+  pub fn apply_directives(&mut self, directives: &str) -> Result<(), String> {
+    for directive in directives
+      .split(',')
+      .map(str::trim)
+      .filter(|directive| !directive.is_empty())
+    {
+      match directive.split_once('=') {
+        Some((target, level)) => {
+          let level = LevelFilter::from_str(level.trim()).map_err(|_| format!("Invalid log level in '{directive}'"))?;
+          if target.trim().is_empty() {
+            return Err(format!("No target in '{directive}'"));
+          }
+          self.targets.insert(target.trim().to_string(), level);
+        }
+        None => match LevelFilter::from_str(directive) {
+          Ok(level) => self.default = level,
+          Err(_) => {
+            self.targets.insert(directive.to_string(), LevelFilter::Trace);
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// The levels as directives for [`LogLevels::apply_directives`]
+  pub fn to_directives(&self) -> String {
+    let mut directives = self.default.to_string().to_lowercase();
+    for (target, level) in &self.targets {
+      directives.push_str(&format!(",{target}={}", level.to_string().to_lowercase()));
+    }
+    directives
+  }
+
+  /// The level of the most specific prefix covering `target` or `module`, the tag of the DLL a record was logged in
+  pub fn level_for(&self, module: &str, target: &str) -> LevelFilter {
+    self
+      .targets
+      .iter()
+      .filter(|(prefix, _)| covers(prefix, target) || covers(prefix, module))
+      .max_by_key(|(prefix, _)| prefix.len())
+      .map_or(self.default, |(_, level)| *level)
+  }
+
+  pub fn enabled(&self, module: &str, metadata: &Metadata) -> bool {
+    metadata.level() <= self.level_for(module, metadata.target())
+  }
+
+  /// The most verbose level any target is logged at
+  pub fn max_level(&self) -> LevelFilter {
+    self.targets.values().copied().fold(self.default, Ord::max)
+  }
+}
+
+fn covers(prefix: &str, target: &str) -> bool {
+  match target.strip_prefix(prefix) {
+    Some(rest) => rest.is_empty() || rest.starts_with("::") || rest.starts_with('.'),
+    None => false
+  }
+}
+
+pub fn log_levels() -> LogLevels {
+  LOG_LEVELS.read().map(|levels| levels.clone()).unwrap_or_default()
+}
+
+/// Replaces the levels, effective for the next record. A DLL forwarding its records passes them on to the logger's
+/// owner.
+pub fn set_log_levels(levels: LogLevels) {
+  forward_levels(&levels);
+  log::set_max_level(levels.max_level());
+  if let Ok(mut current) = LOG_LEVELS.write() {
+    *current = levels;
+  }
+}
+
+/// Filter for a `fern::Dispatch` logging at `LevelFilter::Trace`, for records of the module being logged
+pub fn log_levels_enabled(metadata: &Metadata) -> bool {
+  module_log_enabled(&log_module(), metadata)
+}
+
+pub(crate) fn module_log_enabled(module: &str, metadata: &Metadata) -> bool {
+  LOG_LEVELS
+    .read()
+    .map_or(true, |levels| levels.enabled(module, metadata))
+}
+
+/// Sets the configured levels and the environment override, call it after the logger is applied. Returns what was
+/// wrong with the configured levels.
+pub fn init_log_levels(config: &LoggingConfig) -> Vec<String> {
+  let (mut levels, mut errors) = LogLevels::from_config(config);
+  if let Ok(directives) = std::env::var(LOG_ENV_VAR) &&
+    let Err(e) = levels.apply_directives(&directives)
+  {
+    errors.push(format!("{LOG_ENV_VAR}: {e}"));
+  }
+  set_log_levels(levels);
+  errors
+}
+
+#[cfg(test)]
+mod tests {
+  use log::Level;
+
+  use super::*;
+
+  fn parse(directives: &str) -> LogLevels {
+    let mut levels = LogLevels::new();
+    levels.apply_directives(directives).unwrap();
+    levels
+  }
+
+  fn enabled(levels: &LogLevels, level: Level, module: &str, target: &str) -> bool {
+    levels.enabled(module, &Metadata::builder().level(level).target(target).build())
+  }
+
+  #[test]
+  fn directives_set_the_default_and_per_target_levels() {
+    let levels = parse(" warn , andromeda::hooks=trace,plugin.foo = error,andromeda::ui ");
+    assert_eq!(levels.default, LevelFilter::Warn);
+    assert_eq!(
+      levels.targets,
+      BTreeMap::from([
+        ("andromeda::hooks".to_string(), LevelFilter::Trace),
+        ("andromeda::ui".to_string(), LevelFilter::Trace),
+        ("plugin.foo".to_string(), LevelFilter::Error)
+      ])
+    );
+    assert_eq!(levels.max_level(), LevelFilter::Trace);
+    assert_eq!(
+      levels.to_directives(),
+      "warn,andromeda::hooks=trace,andromeda::ui=trace,plugin.foo=error"
+    );
+  }
+
+  #[test]
+  fn invalid_directives_are_rejected() {
+    let mut levels = LogLevels::new();
+    assert_eq!(
+      levels.apply_directives("andromeda=loud"),
+      Err("Invalid log level in 'andromeda=loud'".to_string())
+    );
+    assert_eq!(
+      levels.apply_directives("=info"),
+      Err("No target in '=info'".to_string())
+    );
+  }
+
+  #[test]
+  fn most_specific_prefix_of_the_target_wins() {
+    let levels = parse("info,andromeda=warn,andromeda::hooks=trace");
+    assert_eq!(
+      levels.level_for("payload", "andromeda::hooks::dx11"),
+      LevelFilter::Trace
+    );
+    assert_eq!(levels.level_for("payload", "andromeda::ui"), LevelFilter::Warn);
+    assert_eq!(levels.level_for("payload", "andromeda"), LevelFilter::Warn);
+    // Prefixes cover whole path segments only
+    assert_eq!(
+      levels.level_for("payload", "andromeda_common::logging"),
+      LevelFilter::Info
+    );
+  }
+
+  #[test]
+  fn directives_match_the_module_a_record_was_logged_in() {
+    let levels = parse("info,plugin.foo=warn,payload=debug");
+    // Plugins log with their crate's targets, only the module tells them apart
+    assert!(!enabled(&levels, Level::Info, "plugin.foo", "foo::camera"));
+    assert!(enabled(&levels, Level::Warn, "plugin.foo", "foo::camera"));
+    assert!(enabled(&levels, Level::Info, "plugin.foobar", "foobar"));
+    assert!(enabled(&levels, Level::Debug, "payload", "andromeda::hooks"));
+    assert!(!enabled(&levels, Level::Debug, "entry", "andromeda_entry"));
+
+    let levels = parse("plugin=error,plugin.foo.camera=trace");
+    assert_eq!(levels.level_for("plugin.foo", "foo"), LevelFilter::Error);
+    assert_eq!(levels.level_for("plugin.foo.camera", "foo"), LevelFilter::Trace);
+  }
+
+  #[test]
+  fn config_levels_skip_invalid_ones() {
+    let config = LoggingConfig {
+      level: "chatty".to_string(),
+      targets: BTreeMap::from([
+        ("plugin.foo".to_string(), "warn".to_string()),
+        ("andromeda".to_string(), "nope".to_string())
+      ]),
+      ..Default::default()
+    };
+    let (levels, errors) = LogLevels::from_config(&config);
+    assert_eq!(levels.default, LevelFilter::Debug);
+    assert_eq!(
+      levels.targets,
+      BTreeMap::from([("plugin.foo".to_string(), LevelFilter::Warn)])
+    );
+    assert_eq!(
+      errors,
+      ["Invalid log level 'chatty'", "Invalid log level 'nope' for 'andromeda'"]
+    );
+
+    let mut stored = LoggingConfig::default();
+    levels.store(&mut stored);
+    assert_eq!(stored.level, "debug");
+    assert_eq!(stored.targets["plugin.foo"], "warn");
+  }
+}
@@ -0,0 +1,231 @@
+//! One log for all of Andromeda, whichever DLL a record comes from.
+//!
+//! The entry DLL owns the logger: files, stdout and the log buffer. It hands [`LOG_API`] to the payload, which
+//! installs a [`ForwardingLogger`] sending its records back, and the payload passes the same API on to plugins. Every
+//! line is tagged with the module it came from, e.g. `entry`, `payload` or `plugin.foo`.
+
+use std::{borrow::Cow, cell::RefCell, sync::OnceLock};
+
+use chrono::Local;
+use log::{Level, Log, Metadata, Record, SetLoggerError};
+
+use crate::logging::{
+  buffer::subscribe_to_log_buffer,
+  json::{JsonFields, record_fields},
+  levels::{LogLevels, module_log_enabled, set_log_levels}
+};
+
+/// A log record crossing the DLL boundary, the strings are UTF-8 and only valid during the call
+#[repr(C)]
+pub struct LogRecord {
+  /// `log::Level` as a number, 1 (error) to 5 (trace)
+  pub level: usize,
+  pub timestamp_millis: i64,
+  pub module: *const u8,
+  pub module_len: usize,
+  pub target: *const u8,
+  pub target_len: usize,
+  pub message: *const u8,
+  pub message_len: usize,
+  /// The record's key-value fields as a JSON object, null if it has none
+  pub fields: *const u8,
+  pub fields_len: usize
+}
+
+pub type LogRecordFn = unsafe extern "system" fn(record: *const LogRecord);
+
+/// What the DLL owning the logger offers the others, handed over in `StartupConfig` and to plugins
+#[repr(C)]
+pub struct LogApi {
+  /// Replays the buffered records to `sink` and forwards every later one
+  pub subscribe: unsafe extern "system" fn(sink: LogRecordFn),
+  /// Logs a record of another module
+  pub log: LogRecordFn,
+  /// Whether a record of this level and target, logged in `module`, would be logged
+  pub enabled: unsafe extern "system" fn(
+    level: usize,
+    module: *const u8,
+    module_len: usize,
+    target: *const u8,
+    target_len: usize
+  ) -> bool,
+  /// Replaces the log levels with comma separated directives, see `LogLevels::apply_directives`
+  pub set_levels: unsafe extern "system" fn(directives: *const u8, directives_len: usize)
+}
+
+pub static LOG_API: LogApi = LogApi {
+  subscribe: subscribe_to_log_buffer,
+  log: log_record,
+  enabled: log_enabled,
+  set_levels: set_levels_from_directives
+};
+
+/// Tag of the records logged in this DLL
+static LOG_MODULE: OnceLock<String> = OnceLock::new();
+
+/// The API records are forwarded to, if this DLL doesn't own the logger
+static FORWARD_API: OnceLock<&'static LogApi> = OnceLock::new();
+
+thread_local! {
+  /// Tag of the forwarded record being logged on this thread
+  static FORWARDED_MODULE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Tags the records logged in this DLL with `module`, only the first call counts
+pub fn set_log_module(module: &str) {
+  let _ = LOG_MODULE.set(module.to_string());
+}
+
+/// The module the record being logged came from, for formatting and outputs
+pub fn log_module() -> String {
+  FORWARDED_MODULE
+    .with_borrow(|forwarded| forwarded.clone())
+    .or_else(|| LOG_MODULE.get().cloned())
+    .unwrap_or_default()
+}
+
+/// The API plugins get: the one records are forwarded to, or ours if this DLL owns the logger
+pub fn log_api() -> &'static LogApi {
+  FORWARD_API.get().copied().unwrap_or(&LOG_API)
+}
+
+pub(crate) fn forward_api() -> Option<&'static LogApi> {
+  FORWARD_API.get().copied()
+}
+
+pub(crate) fn level_from_usize(level: usize) -> Level {
+  match level {
+    1 => Level::Error,
+    2 => Level::Warn,
+    3 => Level::Info,
+    4 => Level::Debug,
+    _ => Level::Trace
+  }
+}
+
+/// # Safety
+///
+/// `data` has to point to `len` bytes valid for `'a`.
+pub(crate) unsafe fn str_from_raw<'a>(data: *const u8, len: usize) -> Cow<'a, str> {
+  if data.is_null() {
+    return "".into();
+  }
+  String::from_utf8_lossy(unsafe { std::slice::from_raw_parts(data, len) })
+}
+
+/// Sends the records of this DLL to another DLL's [`LogApi`]
+pub struct ForwardingLogger {
+  api: &'static LogApi,
+  module: String
+}
+
+impl Log for ForwardingLogger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    let target = metadata.target();
+    unsafe {
+      (self.api.enabled)(
+        metadata.level() as usize,
+        self.module.as_ptr(),
+        self.module.len(),
+        target.as_ptr(),
+        target.len()
+      )
+    }
+  }
+
+  fn log(&self, record: &Record) {
+    if !self.enabled(record.metadata()) {
+      return;
+    }
+    let message = record.args().to_string();
+    let target = record.target();
+    let fields = match record.key_values().count() {
+      0 => String::new(),
+      _ => serde_json::Value::Object(record_fields(record.key_values())).to_string()
+    };
+    let record = LogRecord {
+      level: record.level() as usize,
+      timestamp_millis: Local::now().timestamp_millis(),
+      module: self.module.as_ptr(),
+      module_len: self.module.len(),
+      target: target.as_ptr(),
+      target_len: target.len(),
+      message: message.as_ptr(),
+      message_len: message.len(),
+      fields: if fields.is_empty() { std::ptr::null() } else { fields.as_ptr() },
+      fields_len: fields.len()
+    };
+    unsafe { (self.api.log)(&record) };
+  }
+
+  fn flush(&self) {}
+}
+
+/// Makes this DLL's `log` macros forward to `api` as `module` instead of logging on their own
+pub fn init_forwarding_logger(api: &'static LogApi, module: &str) -> Result<(), SetLoggerError> {
+  set_log_module(module);
+  log::set_boxed_logger(Box::new(ForwardingLogger {
+    api,
+    module: module.to_string()
+  }))?;
+  let _ = FORWARD_API.set(api);
+  // `api.enabled` does the filtering
+  log::set_max_level(log::LevelFilter::Trace);
+  Ok(())
+}
+
+/// Forwards the levels to the DLL owning the logger, if there is one
+pub(crate) fn forward_levels(levels: &LogLevels) {
+  if let Some(api) = forward_api() {
+    let directives = levels.to_directives();
+    unsafe { (api.set_levels)(directives.as_ptr(), directives.len()) };
+  }
+}
+
+unsafe extern "system" fn log_record(record: *const LogRecord) {
+  let Some(record) = (unsafe { record.as_ref() }) else {
+    return;
+  };
+  let module = unsafe { str_from_raw(record.module, record.module_len) };
+  let target = unsafe { str_from_raw(record.target, record.target_len) };
+  let message = unsafe { str_from_raw(record.message, record.message_len) };
+  let fields =
+    JsonFields(serde_json::from_str(&unsafe { str_from_raw(record.fields, record.fields_len) }).unwrap_or_default());
+
+  FORWARDED_MODULE.with_borrow_mut(|forwarded| *forwarded = Some(module.into_owned()));
+  log::logger().log(
+    &Record::builder()
+      .level(level_from_usize(record.level))
+      .target(&target)
+      .args(format_args!("{message}"))
+      .key_values(&fields)
+      .build()
+  );
+  FORWARDED_MODULE.with_borrow_mut(|forwarded| *forwarded = None);
+}
+
+unsafe extern "system" fn log_enabled(
+  level: usize,
+  module: *const u8,
+  module_len: usize,
+  target: *const u8,
+  target_len: usize
+) -> bool {
+  let module = unsafe { str_from_raw(module, module_len) };
+  let target = unsafe { str_from_raw(target, target_len) };
+  module_log_enabled(
+    &module,
+    &Metadata::builder()
+      .level(level_from_usize(level))
+      .target(&target)
+      .build()
+  )
+}
+
+unsafe extern "system" fn set_levels_from_directives(directives: *const u8, directives_len: usize) {
+  let directives = unsafe { str_from_raw(directives, directives_len) };
+  let mut levels = LogLevels::new();
+  if levels.apply_directives(&directives).is_ok() {
+    set_log_levels(levels);
+  }
+}
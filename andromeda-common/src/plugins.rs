@@ -0,0 +1,118 @@
+//! Plugins installed under `<config>/plugins`, one folder per plugin with a `plugin.json` manifest next to its DLL.
+//! Hidden folders are installs in progress and not plugins yet.
+
+use std::{
+  collections::HashSet,
+  fs,
+  path::{Path, PathBuf}
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::api::{Game, GameVersion, get_game_version};
+
+pub const PLUGIN_MANIFEST_NAME: &str = "plugin.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PluginManifest {
+  pub id: String,
+  pub name: String,
+  pub version: String,
+  /// The plugin's DLL, relative to its folder
+  pub entry: String,
+  /// Game versions the plugin was built for, as reported by the game (e.g. `2025.08.07.0000.0000`). Empty if it
+  /// works with any version.
+  #[serde(rename = "gameVersions", default)]
+  pub game_versions: Vec<String>
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compatibility {
+  AnyVersion,
+  Compatible,
+  Incompatible
+}
+
+impl PluginManifest {
+  pub fn compatibility(&self, game: &Game, current: GameVersion) -> Compatibility {
+    if self.game_versions.is_empty() {
+      return Compatibility::AnyVersion;
+    }
+    let supported = current != GameVersion::Unknown &&
+      self
+        .game_versions
+        .iter()
+        .any(|version| get_game_version(game, version) == current);
+    if supported {
+      Compatibility::Compatible
+    } else {
+      Compatibility::Incompatible
+    }
+  }
+}
+
+/// A plugin folder, with the reason its manifest couldn't be used if it is broken
+#[derive(Clone, Debug)]
+pub struct DiscoveredPlugin {
+  pub dir: PathBuf,
+  pub manifest: Result<PluginManifest, String>
+}
+
+impl DiscoveredPlugin {
+  /// The manifest's id, the folder name for broken manifests
+  pub fn id(&self) -> String {
+    match &self.manifest {
+      Ok(manifest) => manifest.id.clone(),
+      Err(_) => self
+        .dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+    }
+  }
+}
+
+/// Every plugin folder in `plugins_dir`, sorted by folder name. Ids have to be unique, later folders claiming an id
+/// that is already taken are reported as broken.
+pub fn discover_plugins(plugins_dir: &Path) -> Vec<DiscoveredPlugin> {
+  let Ok(entries) = fs::read_dir(plugins_dir) else {
+    return Vec::new();
+  };
+  let mut dirs: Vec<PathBuf> = entries
+    .filter_map(|entry| entry.ok())
+    .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+    .map(|entry| entry.path())
+    .filter(|path| path.is_dir())
+    .collect();
+  dirs.sort();
+
+  let mut ids = HashSet::new();
+  dirs
+    .into_iter()
+    .map(|dir| {
+      let manifest = read_manifest(&dir).and_then(|manifest| {
+        if ids.insert(manifest.id.clone()) {
+          Ok(manifest)
+        } else {
+          Err(format!("Another plugin already uses the id '{}'", manifest.id))
+        }
+      });
+      DiscoveredPlugin { dir, manifest }
+    })
+    .collect()
+}
+
+/// The manifest in plugin folder `dir`, or why it can't be used
+pub fn read_manifest(dir: &Path) -> Result<PluginManifest, String> {
+  let contents = fs::read_to_string(dir.join(PLUGIN_MANIFEST_NAME))
+    .map_err(|e| format!("Can't read {PLUGIN_MANIFEST_NAME}: {e}"))?;
+  let manifest: PluginManifest =
+    serde_json::from_str(&contents).map_err(|e| format!("Invalid {PLUGIN_MANIFEST_NAME}: {e}"))?;
+  if manifest.id.trim().is_empty() {
+    return Err("The manifest has no id".to_string());
+  }
+  if manifest.entry.trim().is_empty() {
+    return Err("The manifest names no DLL to load".to_string());
+  }
+  Ok(manifest)
+}
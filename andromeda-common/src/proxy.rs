@@ -0,0 +1,46 @@
+//! The system DLLs the entry DLL stands in for when it is put into a game folder, and telling its copies apart from
+//! other mods' DLLs of the same names.
+
+use std::{
+  fs, io,
+  path::{Path, PathBuf}
+};
+
+use crate::logging::files::ENTRY_LOG_BASENAME;
+
+/// Names the entry DLL can be loaded by, it forwards their exports to the system's DLL. `version.dll` isn't one, the
+/// entry DLL doesn't export its functions.
+pub const PROXY_DLL_NAMES: [&str; 3] = ["dinput8.dll", "dxgi.dll", "d3d11.dll"];
+
+/// A DLL with one of [`PROXY_DLL_NAMES`] in a game folder
+#[derive(Clone, Debug)]
+pub struct ProxyDll {
+  pub path: PathBuf,
+  /// A copy of the entry DLL rather than another mod's
+  pub andromeda: bool
+}
+
+/// The DLLs in `game_dir` named like one of [`PROXY_DLL_NAMES`], in any case
+pub fn find_proxy_dlls(game_dir: &Path) -> io::Result<Vec<ProxyDll>> {
+  let mut proxies = Vec::new();
+  for entry in fs::read_dir(game_dir)? {
+    let entry = entry?;
+    let name = entry.file_name().to_string_lossy().into_owned();
+    if entry.file_type()?.is_file() && PROXY_DLL_NAMES.iter().any(|proxy| proxy.eq_ignore_ascii_case(&name)) {
+      let path = entry.path();
+      proxies.push(ProxyDll {
+        andromeda: is_entry_dll(&path)?,
+        path
+      });
+    }
+  }
+  proxies.sort_by(|a, b| a.path.cmp(&b.path));
+  Ok(proxies)
+}
+
+/// Whether `path` is a build of the entry DLL, which is the only one naming its log file
+pub fn is_entry_dll(path: &Path) -> io::Result<bool> {
+  let contents = fs::read(path)?;
+  let signature = ENTRY_LOG_BASENAME.as_bytes();
+  Ok(contents.windows(signature.len()).any(|window| window == signature))
+}
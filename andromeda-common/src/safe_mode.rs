@@ -0,0 +1,191 @@
+//! Safe mode after repeated crashes.
+//!
+//! The proxy keeps a sentinel in the config dir that is marked as running at startup and cleared when the game exits
+//! normally. A sentinel still marked as running at the next startup means the game didn't exit cleanly. After enough of
+//! those in a row Andromeda starts without plugins and optional patches, and stays that way until the user leaves safe
+//! mode from the overlay.
+
+use std::{fs, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  config::andromeda_config::get_andromeda_config_path,
+  errors::{AndromedaError, ErrorContext}
+};
+
+pub const SESSION_SENTINEL_FILE: &str = "session.json";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StartupMode {
+  Normal,
+  /// No plugins and no optional patches
+  Safe
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SessionSentinel {
+  /// A session started and hasn't exited cleanly yet
+  pub running: bool,
+  /// Sessions in a row that didn't exit cleanly
+  pub unclean_exits: u32,
+  pub safe_mode: bool
+}
+
+impl SessionSentinel {
+  /// The sentinel of a session starting now and how it starts. With `threshold` unclean exits in a row it starts in
+  /// safe mode, 0 never does.
+  pub fn start(self, threshold: u32) -> (Self, StartupMode) {
+    let unclean_exits = match self.running {
+      true => self.unclean_exits.saturating_add(1),
+      false => self.unclean_exits
+    };
+    let safe_mode = self.safe_mode || (threshold > 0 && unclean_exits >= threshold);
+    let sentinel = Self {
+      running: true,
+      unclean_exits,
+      safe_mode
+    };
+    (sentinel, sentinel.mode())
+  }
+
+  /// The session exited cleanly, safe mode stays until it is left
+  pub fn exit(self) -> Self {
+    Self {
+      running: false,
+      unclean_exits: 0,
+      safe_mode: self.safe_mode
+    }
+  }
+
+  /// The next startup is a normal one again
+  pub fn leave_safe_mode(self) -> Self {
+    Self {
+      running: self.running,
+      unclean_exits: 0,
+      safe_mode: false
+    }
+  }
+
+  pub fn mode(&self) -> StartupMode {
+    match self.safe_mode {
+      true => StartupMode::Safe,
+      false => StartupMode::Normal
+    }
+  }
+}
+
+fn session_sentinel_path() -> Option<PathBuf> {
+  get_andromeda_config_path().map(|path| path.join(SESSION_SENTINEL_FILE))
+}
+
+/// The sentinel in the config dir, a missing or broken one counts as a clean exit
+pub fn read_session_sentinel() -> SessionSentinel {
+  session_sentinel_path()
+    .and_then(|path| fs::read_to_string(path).ok())
+    .and_then(|sentinel| serde_json::from_str(&sentinel).ok())
+    .unwrap_or_default()
+}
+
+pub fn write_session_sentinel(sentinel: &SessionSentinel) -> Result<(), AndromedaError> {
+  let path = session_sentinel_path().ok_or_else(|| AndromedaError::config("No config directory"))?;
+  // Same as the config, a crash halfway through must not leave a truncated sentinel behind
+  let temp_path = path.with_extension("json.tmp");
+  fs::write(&temp_path, serde_json::to_string_pretty(sentinel)?)
+    .context(|| AndromedaError::config(format!("Can't write {}", temp_path.display())))?;
+  fs::rename(&temp_path, &path).context(|| AndromedaError::config(format!("Can't replace {}", path.display())))?;
+  Ok(())
+}
+
+/// Leaves safe mode from the next startup on
+pub fn leave_safe_mode() -> Result<(), AndromedaError> {
+  write_session_sentinel(&read_session_sentinel().leave_safe_mode())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Runs `sessions` sessions that all crash, starting from a clean slate
+  fn crash(sessions: u32, threshold: u32) -> (SessionSentinel, StartupMode) {
+    let mut started = SessionSentinel::default().start(threshold);
+    for _ in 1..sessions {
+      started = started.0.start(threshold);
+    }
+    started
+  }
+
+  #[test]
+  fn threshold_zero_never_starts_in_safe_mode() {
+    let (sentinel, mode) = crash(10, 0);
+    assert_eq!(mode, StartupMode::Normal);
+    assert_eq!(sentinel.unclean_exits, 9);
+    assert!(!sentinel.safe_mode);
+  }
+
+  #[test]
+  fn reaching_the_threshold_starts_in_safe_mode() {
+    // The first session crashing is only noticed by the second one
+    assert_eq!(crash(3, 3).1, StartupMode::Normal);
+    let (sentinel, mode) = crash(4, 3);
+    assert_eq!(mode, StartupMode::Safe);
+    assert_eq!(
+      sentinel,
+      SessionSentinel {
+        running: true,
+        unclean_exits: 3,
+        safe_mode: true
+      }
+    );
+  }
+
+  #[test]
+  fn clean_exit_resets_the_counter() {
+    let (sentinel, _) = crash(3, 3);
+    assert_eq!(sentinel.unclean_exits, 2);
+    let sentinel = sentinel.exit();
+    assert_eq!(sentinel, SessionSentinel::default());
+
+    let (sentinel, mode) = sentinel.start(3);
+    assert_eq!(mode, StartupMode::Normal);
+    assert_eq!(sentinel.unclean_exits, 0);
+  }
+
+  #[test]
+  fn safe_mode_survives_a_clean_exit() {
+    let (sentinel, _) = crash(4, 3);
+    let sentinel = sentinel.exit();
+    assert_eq!(sentinel.unclean_exits, 0);
+    assert_eq!(sentinel.mode(), StartupMode::Safe);
+    assert_eq!(sentinel.start(3).1, StartupMode::Safe);
+  }
+
+  #[test]
+  fn leave_safe_mode_starts_normally_again() {
+    let (sentinel, _) = crash(4, 3);
+    let sentinel = sentinel.leave_safe_mode();
+    // Leaving happens from the overlay while the session still runs
+    assert_eq!(
+      sentinel,
+      SessionSentinel {
+        running: true,
+        unclean_exits: 0,
+        safe_mode: false
+      }
+    );
+    assert_eq!(sentinel.exit().start(3).1, StartupMode::Normal);
+    // Even if the session leaving safe mode crashes, the count starts over
+    assert_eq!(
+      sentinel.start(3),
+      (
+        SessionSentinel {
+          running: true,
+          unclean_exits: 1,
+          safe_mode: false
+        },
+        StartupMode::Normal
+      )
+    );
+  }
+}
@@ -0,0 +1,551 @@
+//! Payload updates from a release feed.
+//!
+//! A feed is a JSON file listing releases, the channels they are on (`stable` if none), the oldest game version they
+//! support and the SHA-256 of their files. A release's files sit next to the feed in a directory named after its
+//! version, e.g. `1.2.0/andromeda.dll`, and usually include the release's `manifest.json` and its signature.
+//!
+//! ```json
+//! { "releases": [{ "version": "1.2.0", "channels": ["stable", "beta"], "minGameVersion": "2025.08.07.0000.0000",
+//!   "files": { "andromeda.dll": "9f86d0…", "manifest.json": "…", "manifest.json.sig": "…" } }] }
+//! ```
+//!
+//! [`FeedSource`] reads a feed over HTTP, [`LocalSource`] one in a directory or a `file://` URL, for air-gapped
+//! installs. Updates are installed into the [`LoaderStore`] and made current for the next start, the running payload
+//! stays as it is.
+
+use std::{
+  collections::BTreeMap,
+  fs::{self, File},
+  io,
+  path::{Path, PathBuf},
+  time::Duration
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  config::{AndromedaConfig, Channel},
+  errors::{AndromedaError, ErrorContext},
+  integrity::{parse_public_key, sha256_file, to_hex, verify_payload},
+  loader_store::{LoaderStore, PAYLOAD_FILE, compare_versions, is_bundle_path, is_version_name}
+};
+
+pub const FEED_FILE: &str = "feed.json";
+
+/// Largest file a feed may serve
+const MAX_DOWNLOAD_SIZE: u64 = 512 * 1024 * 1024;
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct ReleaseFeed {
+  pub releases: Vec<Release>
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Release {
+  pub version: String,
+  #[serde(default = "default_channels")]
+  pub channels: Vec<String>,
+  /// Oldest game version the release works with, as reported by the game. Any version if there is none.
+  #[serde(default)]
+  pub min_game_version: Option<String>,
+  /// Files of the release and their SHA-256, hex encoded
+  pub files: BTreeMap<String, String>
+}
+
+fn default_channels() -> Vec<String> {
+  Channel::Stable.feed_name().into_iter().map(str::to_string).collect()
+}
+
+impl Release {
+  /// Is on `channel` and works with `game_version`. An unknown game version only gets releases that work with any.
+  pub fn is_compatible(&self, channel: &str, game_version: Option<&str>) -> bool {
+    let game_supported = match (&self.min_game_version, game_version) {
+      (None, _) => true,
+      (Some(min), Some(game_version)) => compare_versions(game_version, min).is_ge(),
+      (Some(_), None) => false
+    };
+    game_supported && self.channels.iter().any(|release_channel| release_channel == channel)
+  }
+}
+
+/// Where releases come from
+pub trait UpdateSource {
+  fn feed(&self) -> Result<ReleaseFeed, AndromedaError>;
+
+  /// Writes `file` of `release` to `to`
+  fn fetch(&self, release: &Release, file: &str, to: &Path) -> Result<(), AndromedaError>;
+}
+
+/// A feed served over HTTP(S)
+pub struct FeedSource {
+  url: String,
+  agent: ureq::Agent
+}
+
+impl FeedSource {
+  pub fn new(url: impl Into<String>) -> Self {
+    let config = ureq::Agent::config_builder()
+      .timeout_global(Some(DOWNLOAD_TIMEOUT))
+      .build();
+    Self {
+      url: url.into(),
+      agent: ureq::Agent::new_with_config(config)
+    }
+  }
+
+  /// `file` of `release` next to the feed
+  fn file_url(&self, release: &Release, file: &str) -> String {
+    let base = self.url.rsplit_once('/').map_or(self.url.as_str(), |(base, _)| base);
+    format!("{}/{}/{}", base, release.version, file)
+  }
+
+  fn get(&self, url: &str) -> Result<impl io::Read, AndromedaError> {
+    let response = self
+      .agent
+      .get(url)
+      .call()
+      .context(|| AndromedaError::update(format!("Can't download {url}")))?;
+    Ok(
+      response
+        .into_body()
+        .into_with_config()
+        .limit(MAX_DOWNLOAD_SIZE)
+        .reader()
+    )
+  }
+}
+
+impl UpdateSource for FeedSource {
+  fn feed(&self) -> Result<ReleaseFeed, AndromedaError> {
+    serde_json::from_reader(self.get(&self.url)?)
+      .context(|| AndromedaError::update(format!("{} is not a release feed", self.url)))
+  }
+
+  fn fetch(&self, release: &Release, file: &str, to: &Path) -> Result<(), AndromedaError> {
+    let url = self.file_url(release, file);
+    let mut reader = self.get(&url)?;
+    let mut writer = create_file(to)?;
+    io::copy(&mut reader, &mut writer).context(|| AndromedaError::update(format!("Can't download {url}")))?;
+    Ok(())
+  }
+}
+
+/// A feed on disk
+pub struct LocalSource {
+  feed: PathBuf
+}
+
+impl LocalSource {
+  /// The feed at `path`, or `feed.json` in it if it is a directory
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    let path = path.into();
+    let feed = match path.is_dir() {
+      true => path.join(FEED_FILE),
+      false => path
+    };
+    Self { feed }
+  }
+
+  /// The feed at a `file://` URL
+  pub fn from_url(url: &str) -> Option<Self> {
+    let path = url.strip_prefix("file://")?;
+    // `file:///C:/Andromeda` has the drive after the root's slash
+    let path = match path.as_bytes() {
+      [b'/', _, b':', ..] => &path[1..],
+      _ => path
+    };
+    Some(Self::new(percent_decode(path)))
+  }
+
+  fn release_dir(&self, release: &Release) -> PathBuf {
+    self.feed.parent().unwrap_or(Path::new(".")).join(&release.version)
+  }
+}
+
+impl UpdateSource for LocalSource {
+  fn feed(&self) -> Result<ReleaseFeed, AndromedaError> {
+    let feed =
+      fs::read(&self.feed).context(|| AndromedaError::update(format!("Can't read {}", self.feed.display())))?;
+    serde_json::from_slice(&feed)
+      .context(|| AndromedaError::update(format!("{} is not a release feed", self.feed.display())))
+  }
+
+  fn fetch(&self, release: &Release, file: &str, to: &Path) -> Result<(), AndromedaError> {
+    let from = self.release_dir(release).join(file);
+    let mut reader = File::open(&from).context(|| AndromedaError::update(format!("Can't read {}", from.display())))?;
+    let mut writer = create_file(to)?;
+    io::copy(&mut reader, &mut writer).context(|| AndromedaError::update(format!("Can't copy {}", from.display())))?;
+    Ok(())
+  }
+}
+
+/// The source for `updateFeed`, an `http(s)://` or `file://` URL or a path
+pub fn update_source(location: &str) -> Box<dyn UpdateSource + Send> {
+  if location.starts_with("https://") || location.starts_with("http://") {
+    return Box::new(FeedSource::new(location));
+  }
+  match LocalSource::from_url(location) {
+    Some(source) => Box::new(source),
+    None => Box::new(LocalSource::new(location))
+  }
+}
+
+/// The newest release newer than `current` for `channel` and `game_version`, skipping the `failed` versions rolled back
+/// from
+pub fn select_update<'a>(
+  feed: &'a ReleaseFeed,
+  channel: &str,
+  game_version: Option<&str>,
+  current: &str,
+  failed: &[String]
+) -> Option<&'a Release> {
+  feed
+    .releases
+    .iter()
+    .filter(|release| release.is_compatible(channel, game_version))
+    .filter(|release| compare_versions(&release.version, current).is_gt())
+    .filter(|release| !failed.contains(&release.version))
+    .max_by(|a, b| compare_versions(&a.version, &b.version))
+}
+
+/// Installs the update [`select_update`] picks for the config's channel into `store`, checking every file against the
+/// feed, and makes it current for the next start. Returns the new version, `None` if there is nothing newer or the
+/// channel is a local build.
+pub fn apply_update(
+  source: &dyn UpdateSource,
+  store: &LoaderStore,
+  config: &mut AndromedaConfig,
+  game_version: Option<&str>
+) -> Result<Option<String>, AndromedaError> {
+  let Some(channel) = config.channel().feed_name() else {
+    return Ok(None);
+  };
+  let feed = source.feed()?;
+  let Some(release) = select_update(
+    &feed,
+    channel,
+    game_version,
+    config.latest_version(),
+    config.failed_versions()
+  ) else {
+    return Ok(None);
+  };
+  if !is_version_name(&release.version) {
+    return Err(AndromedaError::update(format!(
+      "'{}' is not a release version",
+      release.version
+    )));
+  }
+
+  if !store.is_installed(&release.version) {
+    // Hidden like an install in progress, the store removes what an interrupted download leaves behind
+    let staging = store.loader_dir().join(format!(".{}.download", release.version));
+    let _ = fs::remove_dir_all(&staging);
+    let result = download_release(source, release, &staging).and_then(|()| store.install(&release.version, &staging));
+    let _ = fs::remove_dir_all(&staging);
+    result?;
+  }
+
+  // Same checks as the proxy does before loading it, a release it would refuse doesn't become current
+  let trusted_keys = config
+    .trusted_keys
+    .iter()
+    .map(|key| parse_public_key(key))
+    .collect::<Result<Vec<_>, _>>()?;
+  verify_payload(&store.version_dir(&release.version), PAYLOAD_FILE, &trusted_keys)?;
+  store.make_current(config, &release.version)?;
+  Ok(Some(release.version.clone()))
+}
+
+fn download_release(source: &dyn UpdateSource, release: &Release, staging: &Path) -> Result<(), AndromedaError> {
+  if !release.files.contains_key(PAYLOAD_FILE) {
+    return Err(AndromedaError::update(format!(
+      "Release {} has no {}",
+      release.version, PAYLOAD_FILE
+    )));
+  }
+  for (file, expected) in &release.files {
+    if !is_bundle_path(file) {
+      return Err(AndromedaError::update(format!(
+        "Release {} lists {} outside its directory",
+        release.version, file
+      )));
+    }
+    let path = staging.join(file);
+    source.fetch(release, file, &path)?;
+    let actual =
+      to_hex(&sha256_file(&path).context(|| AndromedaError::update(format!("Can't hash {}", path.display())))?);
+    if !actual.eq_ignore_ascii_case(expected) {
+      return Err(AndromedaError::integrity(format!(
+        "{} of release {} has SHA-256 {}, the feed expects {}",
+        file, release.version, actual, expected
+      )));
+    }
+  }
+  Ok(())
+}
+
+fn create_file(path: &Path) -> Result<File, AndromedaError> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent).context(|| AndromedaError::update(format!("Can't create {}", parent.display())))?;
+  }
+  File::create(path).context(|| AndromedaError::update(format!("Can't create {}", path.display())))
+}
+
+/// Decodes the `%20`s of a URL path
+fn percent_decode(path: &str) -> String {
+  let bytes = path.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut index = 0;
+  while index < bytes.len() {
+    let escaped = bytes
+      .get(index + 1..index + 3)
+      .filter(|_| bytes[index] == b'%')
+      .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+    match escaped {
+      Some(byte) => {
+        decoded.push(byte);
+        index += 3;
+      }
+      None => {
+        decoded.push(bytes[index]);
+        index += 1;
+      }
+    }
+  }
+  String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn release(version: &str, channels: &[&str], min_game_version: Option<&str>) -> Release {
+    Release {
+      version: version.to_string(),
+      channels: channels.iter().map(|channel| channel.to_string()).collect(),
+      min_game_version: min_game_version.map(str::to_string),
+      files: BTreeMap::new()
+    }
+  }
+
+  fn selected(
+    feed: &ReleaseFeed,
+    channel: &str,
+    game_version: Option<&str>,
+    current: &str,
+    failed: &[&str]
+  ) -> Option<String> {
+    let failed: Vec<String> = failed.iter().map(|version| version.to_string()).collect();
+    select_update(feed, channel, game_version, current, &failed).map(|release| release.version.clone())
+  }
+
+  /// A feed in `dir/feed` with a release per version, each with just its payload
+  fn local_feed(dir: &Path, versions: &[&str]) -> LocalSource {
+    let feed_dir = dir.join("feed");
+    let mut feed = ReleaseFeed::default();
+    for version in versions {
+      let release_dir = feed_dir.join(version);
+      fs::create_dir_all(&release_dir).unwrap();
+      fs::write(release_dir.join(PAYLOAD_FILE), format!("payload {version}")).unwrap();
+      let mut release = release(version, &["stable"], None);
+      release.files.insert(
+        PAYLOAD_FILE.to_string(),
+        to_hex(&sha256_file(&release_dir.join(PAYLOAD_FILE)).unwrap())
+      );
+      feed.releases.push(release);
+    }
+    write_feed(&feed_dir, &feed);
+    LocalSource::new(feed_dir)
+  }
+
+  fn write_feed(feed_dir: &Path, feed: &ReleaseFeed) {
+    fs::write(feed_dir.join(FEED_FILE), serde_json::to_string(feed).unwrap()).unwrap();
+  }
+
+  fn read_feed(dir: &Path) -> ReleaseFeed {
+    LocalSource::new(dir.join("feed")).feed().unwrap()
+  }
+
+  fn stable_config() -> AndromedaConfig {
+    let mut config = AndromedaConfig::default();
+    config.set_channel(Channel::Stable);
+    config
+  }
+
+  #[test]
+  fn select_update_picks_the_newest_release_of_the_channel() {
+    let feed = ReleaseFeed {
+      releases: vec![
+        release("1.2.0", &["stable", "beta"], None),
+        release("1.10.0", &["stable"], None),
+        release("1.3.0", &["stable"], None),
+        release("2.0.0-beta", &["beta"], None),
+      ]
+    };
+    assert_eq!(selected(&feed, "stable", None, "1.0.0", &[]).as_deref(), Some("1.10.0"));
+    assert_eq!(
+      selected(&feed, "beta", None, "1.0.0", &[]).as_deref(),
+      Some("2.0.0-beta")
+    );
+    assert_eq!(selected(&feed, "nightly", None, "1.0.0", &[]), None);
+    // Only newer than the current version
+    assert_eq!(selected(&feed, "stable", None, "1.10.0", &[]), None);
+    assert_eq!(selected(&feed, "beta", None, "2.0.0", &[]), None);
+  }
+
+  #[test]
+  fn select_update_needs_a_supported_game_version() {
+    let feed = ReleaseFeed {
+      releases: vec![
+        release("1.1.0", &["stable"], None),
+        release("1.2.0", &["stable"], Some("2025.08.07.0000.0000")),
+      ]
+    };
+    let select = |game_version| selected(&feed, "stable", game_version, "1.0.0", &[]);
+    assert_eq!(select(Some("2025.08.07.0000.0000")).as_deref(), Some("1.2.0"));
+    assert_eq!(select(Some("2025.10.01.0000.0000")).as_deref(), Some("1.2.0"));
+    assert_eq!(select(Some("2025.06.01.0000.0000")).as_deref(), Some("1.1.0"));
+    // Unknown game versions only get releases for any game version
+    assert_eq!(select(None).as_deref(), Some("1.1.0"));
+  }
+
+  #[test]
+  fn select_update_skips_failed_versions() {
+    let feed = ReleaseFeed {
+      releases: vec![release("1.1.0", &["stable"], None), release("1.2.0", &["stable"], None)]
+    };
+    assert_eq!(
+      selected(&feed, "stable", None, "1.0.0", &["1.2.0"]).as_deref(),
+      Some("1.1.0")
+    );
+    assert_eq!(selected(&feed, "stable", None, "1.0.0", &["1.1.0", "1.2.0"]), None);
+  }
+
+  #[test]
+  fn apply_update_installs_and_makes_the_newest_release_current() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = local_feed(dir.path(), &["1.0.0", "1.1.0"]);
+    let store = LoaderStore::new(dir.path().join("config"));
+    let mut config = stable_config();
+
+    assert_eq!(
+      apply_update(&source, &store, &mut config, None).unwrap().as_deref(),
+      Some("1.1.0")
+    );
+    assert_eq!(config.latest_version(), "1.1.0");
+    assert_eq!(
+      fs::read_to_string(store.version_dir("1.1.0").join(PAYLOAD_FILE)).unwrap(),
+      "payload 1.1.0"
+    );
+    // No download left behind, and nothing newer the next time
+    assert_eq!(store.versions().unwrap(), ["1.1.0"]);
+    assert_eq!(fs::read_dir(store.loader_dir()).unwrap().count(), 1);
+    assert_eq!(apply_update(&source, &store, &mut config, None).unwrap(), None);
+
+    // Local builds aren't updated
+    config.set_channel(Channel::Dev);
+    assert_eq!(apply_update(&source, &store, &mut config, None).unwrap(), None);
+  }
+
+  #[test]
+  fn apply_update_skips_a_version_that_was_rolled_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = local_feed(dir.path(), &["1.0.0", "1.1.0"]);
+    let store = LoaderStore::new(dir.path().join("config"));
+    let mut config = stable_config();
+    store.install("1.0.0", &dir.path().join("feed").join("1.0.0")).unwrap();
+    store.make_current(&mut config, "1.0.0").unwrap();
+    apply_update(&source, &store, &mut config, None).unwrap();
+
+    // 1.1.0 failed to start, the update check after the rollback must not go back to it
+    assert_eq!(store.roll_back(&mut config).unwrap().as_deref(), Some("1.0.0"));
+    assert_eq!(apply_update(&source, &store, &mut config, None).unwrap(), None);
+    assert_eq!(config.latest_version(), "1.0.0");
+  }
+
+  #[test]
+  fn apply_update_rejects_files_not_matching_the_feed() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = local_feed(dir.path(), &["1.1.0"]);
+    let store = LoaderStore::new(dir.path().join("config"));
+    let mut config = stable_config();
+    fs::write(dir.path().join("feed/1.1.0").join(PAYLOAD_FILE), "tampered").unwrap();
+
+    let error = apply_update(&source, &store, &mut config, None)
+      .unwrap_err()
+      .to_string();
+    assert!(error.contains("the feed expects"), "{error}");
+    assert!(store.versions().unwrap().is_empty());
+    assert_eq!(fs::read_dir(store.loader_dir()).unwrap().count(), 0);
+    assert_eq!(config.latest_version(), AndromedaConfig::default().latest_version());
+  }
+
+  #[test]
+  fn apply_update_rejects_files_outside_the_release() {
+    let dir = tempfile::tempdir().unwrap();
+    let feed_dir = dir.path().join("feed");
+    let source = local_feed(dir.path(), &["1.1.0"]);
+    let store = LoaderStore::new(dir.path().join("config"));
+    let mut config = stable_config();
+    let hash = read_feed(dir.path()).releases[0].files[PAYLOAD_FILE].clone();
+
+    for file in [
+      "../1.1.0/andromeda.dll",
+      "../../config/andromeda_config.json",
+      "/andromeda.dll",
+      "C:andromeda.dll"
+    ] {
+      let mut feed = read_feed(dir.path());
+      feed.releases[0].files.insert(file.to_string(), hash.clone());
+      write_feed(&feed_dir, &feed);
+      let error = apply_update(&source, &store, &mut config, None)
+        .unwrap_err()
+        .to_string();
+      assert!(error.contains("outside its directory"), "{file}: {error}");
+    }
+
+    let mut feed = read_feed(dir.path());
+    feed.releases[0].files.clear();
+    feed.releases[0].version = "1.1.0/../../config".to_string();
+    write_feed(&feed_dir, &feed);
+    let error = apply_update(&source, &store, &mut config, None)
+      .unwrap_err()
+      .to_string();
+    assert!(error.contains("is not a release version"), "{error}");
+    assert!(store.versions().unwrap().is_empty());
+  }
+
+  #[test]
+  fn apply_update_needs_a_payload() {
+    let dir = tempfile::tempdir().unwrap();
+    let feed_dir = dir.path().join("feed");
+    let source = local_feed(dir.path(), &["1.1.0"]);
+    let mut feed = read_feed(dir.path());
+    feed.releases[0].files.clear();
+    write_feed(&feed_dir, &feed);
+
+    let store = LoaderStore::new(dir.path().join("config"));
+    let error = apply_update(&source, &store, &mut stable_config(), None)
+      .unwrap_err()
+      .to_string();
+    assert!(error.contains("has no andromeda.dll"), "{error}");
+  }
+
+  #[test]
+  fn local_sources_take_file_urls() {
+    assert_eq!(
+      LocalSource::from_url("file:///C:/Andromeda%20Feed/feed.json")
+        .unwrap()
+        .feed,
+      Path::new("C:/Andromeda Feed/feed.json")
+    );
+    assert_eq!(
+      LocalSource::from_url("file:///srv/feed.json").unwrap().feed,
+      Path::new("/srv/feed.json")
+    );
+    assert!(LocalSource::from_url("https://example.com/feed.json").is_none());
+  }
+}
@@ -1,10 +1,23 @@
 use std::ffi::CString;
+#[cfg(windows)]
+use std::ffi::c_void;
 
+#[cfg(windows)]
 use windows::{
-  Win32::{Foundation::MAX_PATH, System::SystemInformation::GetSystemDirectoryW},
-  core::{PCSTR, PCWSTR}
+  Win32::{
+    Foundation::{FILETIME, HANDLE, HLOCAL, LocalFree, MAX_PATH},
+    System::{
+      Diagnostics::Debug::{
+        FORMAT_MESSAGE_ALLOCATE_BUFFER, FORMAT_MESSAGE_FROM_SYSTEM, FORMAT_MESSAGE_IGNORE_INSERTS, FormatMessageW
+      },
+      SystemInformation::GetSystemDirectoryW,
+      Threading::{GetCurrentProcess, GetProcessTimes}
+    }
+  },
+  core::{HRESULT, PCSTR, PCWSTR, PWSTR}
 };
 
+#[cfg(windows)]
 pub fn get_system32_path() -> String {
   let mut buffer = [0u16; MAX_PATH as usize];
 
@@ -25,3 +38,59 @@ pub fn widestring<S: Into<String>>(value: S) -> Vec<u16> {
     .chain(std::iter::once(0))
     .collect::<Vec<u16>>()
 }
+
+/// The system's message for `hr`, e.g. `HRESULT 0x80070005: Access is denied.`
+#[cfg(windows)]
+pub fn hresult_to_string(hr: HRESULT) -> String {
+  unsafe {
+    let mut buf: PWSTR = PWSTR::null();
+
+    // With FORMAT_MESSAGE_ALLOCATE_BUFFER the buffer argument receives the address of the allocated buffer
+    let len = FormatMessageW(
+      FORMAT_MESSAGE_FROM_SYSTEM | FORMAT_MESSAGE_ALLOCATE_BUFFER | FORMAT_MESSAGE_IGNORE_INSERTS,
+      None,
+      hr.0 as u32, // HRESULT is i32 under the hood
+      0,
+      PWSTR(&mut buf.0 as *mut *mut u16 as *mut u16),
+      0,
+      None
+    );
+
+    if len == 0 || buf.is_null() {
+      return format!("HRESULT 0x{:08X}", hr.0);
+    }
+
+    // Take ownership of the buffer and turn it into a Rust String
+    let slice = std::slice::from_raw_parts(buf.0, len as usize);
+    let mut message = String::from_utf16_lossy(slice);
+
+    // Clean up the allocated buffer
+    LocalFree(Some(HLOCAL(buf.0 as *mut c_void)));
+
+    // Strip trailing \r\n if present
+    message = message.trim_end().to_string();
+
+    format!("HRESULT 0x{:08X}: {}", hr.0, message)
+  }
+}
+
+/// When `process` was created, as a `FILETIME` in 100ns ticks. Together with its id this names a process, ids are
+/// reused.
+#[cfg(windows)]
+pub fn process_creation_time(process: HANDLE) -> Option<u64> {
+  let mut created = FILETIME::default();
+  let (mut exited, mut kernel, mut user) = (created, created, created);
+  unsafe { GetProcessTimes(process, &mut created, &mut exited, &mut kernel, &mut user).ok()? };
+  Some((created.dwHighDateTime as u64) << 32 | created.dwLowDateTime as u64)
+}
+
+#[cfg(windows)]
+pub fn current_process_creation_time() -> Option<u64> {
+  process_creation_time(unsafe { GetCurrentProcess() })
+}
+
+/// Games only run on Windows, elsewhere no process was started by the runner
+#[cfg(not(windows))]
+pub fn current_process_creation_time() -> Option<u64> {
+  None
+}
@@ -6,6 +6,8 @@ use andromeda_common::config::StartupConfig;
 
 use crate::utils::win32::dll::RealDll;
 
+pub(crate) use dinput8::INPUT_API;
+
 pub(crate) static DINPUT8: RealDll = RealDll::new("dinput8.dll");
 pub(crate) static D3D11: RealDll = RealDll::new("d3d11.dll");
 
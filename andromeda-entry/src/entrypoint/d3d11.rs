@@ -1,4 +1,4 @@
-use andromeda_common::exports::D3D11CreateDeviceFn;
+use andromeda_common::{exports::D3D11CreateDeviceFn, guard::catch_panic};
 use log::info;
 
 use crate::{
@@ -37,7 +37,9 @@ pub unsafe extern "system" fn D3D11CreateDevice(
   };
 
   if result.is_ok() && !pp_device.is_null() {
-    info!("Got D3D11 device at {:?}", *pp_device);
+    catch_panic("D3D11CreateDevice", || {
+      info!("Got D3D11 device at {:?}", unsafe { *pp_device })
+    });
   }
 
   result
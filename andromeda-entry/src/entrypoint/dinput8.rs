@@ -1,8 +1,69 @@
-use std::ffi::c_void;
+use std::{
+  collections::HashMap,
+  ffi::c_void,
+  sync::{
+    Mutex,
+    atomic::{AtomicUsize, Ordering}
+  }
+};
+
+use andromeda_common::{
+  crash::{CrashNote, note_crash_context},
+  guard::{HookGuard, catch_panic},
+  input::{DeviceInput, InjectedInput, InputApi, InputDevice, InputEvent, InputFilterFn}
+};
+use log::{error, info};
+use once_cell::sync::Lazy;
+
+use crate::{DirectInput8CreateFn, E_FAIL, GUID, HINSTANCE, HRESULT, IUnknown, entrypoint::DINPUT8};
+
+/// `IUnknown::Release`
+const RELEASE_INDEX: usize = 2;
+/// `IDirectInput8::CreateDevice`
+const CREATE_DEVICE_INDEX: usize = 3;
+/// `IDirectInputDevice8::GetDeviceState`
+const GET_DEVICE_STATE_INDEX: usize = 9;
+/// `IDirectInputDevice8::GetDeviceData`
+const GET_DEVICE_DATA_INDEX: usize = 10;
+
+const GUID_SYS_MOUSE: GUID = GUID::from_u128(0x6f1d2b60_d5a0_11cf_bfc7_444553540000);
+const GUID_SYS_KEYBOARD: GUID = GUID::from_u128(0x6f1d2b61_d5a0_11cf_bfc7_444553540000);
+
+const DIGDD_PEEK: u32 = 0x1;
 
-use log::info;
+type CreateDeviceFn = unsafe extern "system" fn(
+  this: *mut c_void,
+  rguid: *const GUID,
+  device: *mut *mut c_void,
+  outer: *mut IUnknown
+) -> HRESULT;
+type ReleaseFn = unsafe extern "system" fn(this: *mut c_void) -> u32;
+type GetDeviceStateFn = unsafe extern "system" fn(this: *mut c_void, size: u32, data: *mut c_void) -> HRESULT;
+type GetDeviceDataFn = unsafe extern "system" fn(
+  this: *mut c_void,
+  object_data_size: u32,
+  object_data: *mut c_void,
+  count: *mut u32,
+  flags: u32
+) -> HRESULT;
 
-use crate::{DirectInput8CreateFn, GUID, HINSTANCE, HRESULT, IUnknown, entrypoint::DINPUT8};
+/// Trampolines keyed by the method they replace. The ANSI and wide interfaces have methods of their own, both end up
+/// in the same detours.
+static TRAMPOLINES: Lazy<Mutex<HashMap<usize, usize>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+/// Keyboards and mice the game created and hasn't released, keyed by device pointer
+static DEVICES: Lazy<Mutex<HashMap<usize, DeviceInput>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static INJECTED: Lazy<Mutex<InjectedInput>> = Lazy::new(|| Mutex::new(InjectedInput::default()));
+static FILTER: AtomicUsize = AtomicUsize::new(0);
+
+static CREATE_DEVICE_GUARD: HookGuard = HookGuard::new("IDirectInput8::CreateDevice");
+static RELEASE_GUARD: HookGuard = HookGuard::new("IDirectInputDevice8::Release");
+static GET_DEVICE_STATE_GUARD: HookGuard = HookGuard::new("IDirectInputDevice8::GetDeviceState");
+static GET_DEVICE_DATA_GUARD: HookGuard = HookGuard::new("IDirectInputDevice8::GetDeviceData");
+
+pub(crate) static INPUT_API: InputApi = InputApi {
+  set_filter: set_input_filter,
+  inject: inject_input
+};
 
 #[unsafe(no_mangle)]
 pub unsafe extern "system" fn DirectInput8Create(
@@ -18,7 +79,236 @@ pub unsafe extern "system" fn DirectInput8Create(
 
   if result.is_ok() && !ppv_out.is_null() {
     info!("Got IDirectInput8 interface at {ppv_out:?}");
+    CREATE_DEVICE_GUARD
+      .run(|| unsafe { hook_method(*ppv_out, CREATE_DEVICE_INDEX, create_device_hook as *mut c_void) });
   }
 
   result
 }
+
+unsafe extern "system" fn set_input_filter(filter: Option<InputFilterFn>) {
+  FILTER.store(filter.map_or(0, |filter| filter as usize), Ordering::Release);
+}
+
+unsafe extern "system" fn inject_input(event: *const InputEvent) {
+  catch_panic("inject_input", || {
+    if let Some(event) = unsafe { event.as_ref() } &&
+      let Ok(mut injected) = INJECTED.lock()
+    {
+      injected.push(*event);
+    }
+  });
+}
+
+/// Asks the payload whether `event` is blocked
+fn is_blocked(event: &InputEvent) -> bool {
+  match FILTER.load(Ordering::Acquire) {
+    0 => false,
+    filter => unsafe {
+      let filter: InputFilterFn = std::mem::transmute(filter);
+      filter(event)
+    }
+  }
+}
+
+/// Hooks the method at `index` of the vtable `this` points to, unless an earlier object of that type got it hooked
+unsafe fn hook_method(this: *mut c_void, index: usize, detour: *mut c_void) {
+  unsafe {
+    if this.is_null() {
+      return;
+    }
+    let target = *(*(this as *const *const usize)).add(index);
+    let Ok(mut trampolines) = TRAMPOLINES.lock() else {
+      return;
+    };
+    if trampolines.contains_key(&target) {
+      return;
+    }
+
+    // The game can get here before our own thread initialized MinHook
+    let _ = min_hook_rs::initialize();
+    match min_hook_rs::create_hook(target as *mut c_void, detour) {
+      Ok(trampoline) => {
+        if min_hook_rs::enable_hook(target as *mut c_void).is_err() {
+          error!("Failed to enable DirectInput hook at vtable index {}", index);
+          return;
+        }
+        trampolines.insert(target, trampoline as usize);
+        note_crash_context(CrashNote::Hook, &format!("DirectInput vtable index {index}"));
+      }
+      Err(_) => error!("Failed to hook DirectInput vtable index {}", index)
+    }
+  }
+}
+
+/// The original of the method at `index` of the vtable `this` points to
+unsafe fn original<T>(this: *mut c_void, index: usize) -> Option<T> {
+  unsafe {
+    let target = *(*(this as *const *const usize)).add(index);
+    let trampoline = *TRAMPOLINES.lock().ok()?.get(&target)?;
+    Some(std::mem::transmute_copy(&trampoline))
+  }
+}
+
+unsafe extern "system" fn create_device_hook(
+  this: *mut c_void,
+  rguid: *const GUID,
+  device: *mut *mut c_void,
+  outer: *mut IUnknown
+) -> HRESULT {
+  unsafe {
+    let Some(create_device) = original::<CreateDeviceFn>(this, CREATE_DEVICE_INDEX) else {
+      return E_FAIL;
+    };
+    let result = create_device(this, rguid, device, outer);
+    if result.is_err() || rguid.is_null() || device.is_null() || (*device).is_null() {
+      return result;
+    }
+
+    CREATE_DEVICE_GUARD.run(|| on_device_created(*rguid, *device));
+    result
+  }
+}
+
+/// Starts filtering the game's keyboards and mice
+unsafe fn on_device_created(rguid: GUID, device: *mut c_void) {
+  let kind = match rguid {
+    GUID_SYS_KEYBOARD => InputDevice::Keyboard,
+    GUID_SYS_MOUSE => InputDevice::Mouse,
+    _ => return
+  };
+  info!("Game created a DirectInput {:?} device at {:?}", kind, device);
+  if let Ok(mut devices) = DEVICES.lock() {
+    devices.insert(device as usize, DeviceInput::new(kind));
+  }
+  unsafe {
+    hook_method(device, RELEASE_INDEX, release_hook as *mut c_void);
+    hook_method(device, GET_DEVICE_STATE_INDEX, get_device_state_hook as *mut c_void);
+    hook_method(device, GET_DEVICE_DATA_INDEX, get_device_data_hook as *mut c_void);
+  }
+}
+
+/// Forgets a device once its last reference is released, its address may be reused by the next one
+unsafe extern "system" fn release_hook(this: *mut c_void) -> u32 {
+  unsafe {
+    let Some(release) = original::<ReleaseFn>(this, RELEASE_INDEX) else {
+      return 0;
+    };
+    // Locked across the call, so a device created at the same address right after can't lose its entry to this one
+    let mut devices = DEVICES.lock().ok();
+    let references = release(this);
+    if references == 0 &&
+      let Some(devices) = &mut devices
+    {
+      RELEASE_GUARD.run(|| {
+        if let Some(device) = devices.remove(&(this as usize)) {
+          info!(
+            "Game released its DirectInput {:?} device at {:?}",
+            device.device(),
+            this
+          );
+        }
+      });
+    }
+    references
+  }
+}
+
+unsafe extern "system" fn get_device_state_hook(this: *mut c_void, size: u32, data: *mut c_void) -> HRESULT {
+  unsafe {
+    let Some(get_device_state) = original::<GetDeviceStateFn>(this, GET_DEVICE_STATE_INDEX) else {
+      return E_FAIL;
+    };
+    let result = get_device_state(this, size, data);
+    if result.is_err() || data.is_null() {
+      return result;
+    }
+
+    GET_DEVICE_STATE_GUARD.run(|| {
+      let state = std::slice::from_raw_parts_mut(data as *mut u8, size as usize);
+      // The filter runs without the injected input locked, it may inject events itself
+      let device = match DEVICES.lock() {
+        Ok(mut devices) => match devices.get_mut(&(this as usize)) {
+          Some(device) => {
+            device.filter_state(state, &mut is_blocked);
+            device.device()
+          }
+          None => return
+        },
+        Err(_) => return
+      };
+      if let Ok(mut injected) = INJECTED.lock() {
+        injected.apply_state(device, state);
+      }
+    });
+
+    result
+  }
+}
+
+unsafe extern "system" fn get_device_data_hook(
+  this: *mut c_void,
+  object_data_size: u32,
+  object_data: *mut c_void,
+  count: *mut u32,
+  flags: u32
+) -> HRESULT {
+  unsafe {
+    let Some(get_device_data) = original::<GetDeviceDataFn>(this, GET_DEVICE_DATA_INDEX) else {
+      return E_FAIL;
+    };
+    let capacity = if count.is_null() { 0 } else { *count };
+    let result = get_device_data(this, object_data_size, object_data, count, flags);
+    // Without a buffer the game only counts or flushes, a peek must not consume blocked or injected events
+    let stride = object_data_size as usize;
+    if result.is_err() || object_data.is_null() || count.is_null() || flags & DIGDD_PEEK != 0 || stride < 8 {
+      return result;
+    }
+
+    GET_DEVICE_DATA_GUARD.run(|| {
+      let records = object_data as *mut u8;
+      // `dwOfs` and `dwData` lead every `DIDEVICEOBJECTDATA` layout
+      let read = |index: usize| {
+        let record = records.add(index * stride) as *const u32;
+        (record.read_unaligned(), record.add(1).read_unaligned())
+      };
+
+      let device = match DEVICES.lock() {
+        Ok(mut devices) => match devices.get_mut(&(this as usize)) {
+          Some(device) => {
+            let mut kept = 0;
+            for index in 0..*count as usize {
+              let (offset, data) = read(index);
+              if device.filter_buffered(offset, data, &mut is_blocked) {
+                if kept != index {
+                  std::ptr::copy_nonoverlapping(records.add(index * stride), records.add(kept * stride), stride);
+                }
+                kept += 1;
+              }
+            }
+            *count = kept as u32;
+            device.device()
+          }
+          None => return
+        },
+        Err(_) => return
+      };
+
+      let free = capacity.saturating_sub(*count) as usize;
+      if free > 0 &&
+        let Ok(mut injected) = INJECTED.lock()
+      {
+        // Injected records carry no timestamp or sequence number, games only order by position
+        for (offset, data) in injected.take_buffered(device, free) {
+          let record = records.add(*count as usize * stride);
+          std::ptr::write_bytes(record, 0, stride);
+          (record as *mut u32).write_unaligned(offset);
+          (record as *mut u32).add(1).write_unaligned(data);
+          *count += 1;
+        }
+      }
+    });
+
+    result
+  }
+}
@@ -3,21 +3,39 @@ mod patches;
 mod util;
 mod utils;
 
-use andromeda_common::api::{Game, get_game};
+use andromeda_common::api::{get_game, read_game_version};
 use andromeda_common::config::{
-  AndromedaConfig, create_andromeda_config, get_andromeda_config, get_andromeda_loader_path, get_andromeda_log_path
+  AndromedaConfig, andromeda_config::get_andromeda_config_path, create_andromeda_config, get_andromeda_config,
+  get_andromeda_loader_path, get_andromeda_log_path
 };
+use andromeda_common::crash::{CRASH_API, exception::install_exception_handler, install_panic_reporter, noted_hooks};
 use andromeda_common::errors::AndromedaError;
-use andromeda_common::logging::{andromeda_file_logging_format, andromeda_stdout_logging_format};
+use andromeda_common::guard::catch_panic;
+use andromeda_common::integrity::{allows_unverified, parse_public_key, verify_payload};
+use andromeda_common::launch::{
+  StartupStatus, current_launch_session, end_current_launch_session, write_startup_status
+};
+use andromeda_common::loader_store::{LoaderStore, PAYLOAD_FILE, PayloadFailure};
+use andromeda_common::logging::{
+  andromeda_stdout_logging_format,
+  buffer::log_buffer_output,
+  file_logging_format,
+  files::{ENTRY_LOG_BASENAME, LogFilePolicy, LogFiles, clean_up_log_files},
+  levels::{init_log_levels, log_levels_enabled},
+  sink::{LOG_API, set_log_module}
+};
+use andromeda_common::safe_mode::{SessionSentinel, StartupMode, read_session_sentinel, write_session_sentinel};
+use andromeda_common::updates::{apply_update, update_source};
 use andromeda_common::utils::win32;
-use log::{error, info};
+use log::{error, info, warn};
 use once_cell::sync::OnceCell;
 use std::error::Error;
 use std::ffi::c_void;
-use std::fs::{File, OpenOptions};
+use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
 use std::{ffi::CString, ffi::c_int, iter, mem};
 use std::{fmt, io};
 use windows::Win32::Graphics::Direct3D::{D3D_DRIVER_TYPE, D3D_FEATURE_LEVEL};
@@ -32,7 +50,6 @@ use windows::{Win32::Foundation::*, Win32::System::LibraryLoader::*};
 
 use crate::entrypoint::InjectAndromedaEntrypointFn;
 use crate::patches::apply_all_patches;
-use crate::util::xiv;
 use crate::utils::win32::module::LoadedModule;
 use crate::utils::win32::process::Process;
 
@@ -45,58 +62,138 @@ type DirectInput8CreateFn = unsafe extern "system" fn(
 ) -> HRESULT;
 
 static PAYLOAD_LOADED: AtomicBool = AtomicBool::new(false);
+/// The session sentinel is marked as running, see `safe_mode`
+static SESSION_STARTED: AtomicBool = AtomicBool::new(false);
 static H_MODULE: OnceLock<Mutex<LoadedModule>> = OnceLock::new();
 
-fn ensure_payload_loaded(config: AndromedaConfig) -> Result<(), AndromedaError> {
-  if PAYLOAD_LOADED.load(Ordering::Acquire) {
-    return Ok(());
+/// Loads the payload and returns its version, or the local build it came from. `None` if it was loaded already.
+fn ensure_payload_loaded(
+  mut config: AndromedaConfig,
+  sentinel: SessionSentinel
+) -> Result<Option<String>, AndromedaError> {
+  // Mark payload as loaded even on failure to prevent retry storming, a rollback retries once at most
+  if PAYLOAD_LOADED.swap(true, Ordering::AcqRel) {
+    return Ok(None);
+  }
+  let store = LoaderStore::open().ok_or_else(|| AndromedaError::config("No config directory"))?;
+  let payload = store.load_or_roll_back(&mut config, |config| load_payload(config, sentinel))?;
+  collect_loader_garbage(&config);
+  Ok(Some(payload))
+}
+
+/// The process name and the game's version, if it is a game we know
+fn current_game() -> (String, Option<String>) {
+  let process = Process::current();
+  let process_name = process
+    .base_name()
+    .map(|p| p.to_string_lossy().into_owned())
+    .unwrap_or_default();
+
+  let game_version = process
+    .path_of()
+    .and_then(|path| read_game_version(&get_game(&process_name), &path));
+  (process_name, game_version)
+}
+
+/// Looks for a newer payload in the background, it is loaded from the next start on. Without network access or a
+/// reachable feed the game just keeps the version it has.
+fn spawn_update_check(feed: String) {
+  let check = move || -> Result<Option<String>, AndromedaError> {
+    let store = LoaderStore::open().ok_or_else(|| AndromedaError::config("No config directory"))?;
+    // Read again, a rollback may have changed the current version
+    let mut config = get_andromeda_config().ok_or_else(|| AndromedaError::config("No config file"))?;
+    let (_, game_version) = current_game();
+    let source = update_source(&feed);
+    apply_update(source.as_ref(), &store, &mut config, game_version.as_deref())
+  };
+  let spawned = std::thread::Builder::new()
+    .name("andromeda-updates".into())
+    .spawn(move || match catch_panic("spawn_update_check", check) {
+      Some(Ok(Some(version))) => info!("Installed update {}, it loads with the next start", version),
+      Some(Ok(None)) => info!("Andromeda is up to date"),
+      Some(Err(e)) => warn!("Failed to check for updates: {:#}", e),
+      None => {}
+    });
+  if let Err(e) = spawned {
+    warn!("Failed to start the update check: {}", e);
+  }
+}
+
+/// Removes loader versions that are neither current nor among the ones kept
+fn collect_loader_garbage(config: &AndromedaConfig) {
+  if config.channel().is_local_build() {
+    return;
+  }
+  match LoaderStore::open().map(|store| store.collect_garbage(config)) {
+    Some(Ok(removed)) if !removed.is_empty() => info!("Removed old loader versions: {}", removed.join(", ")),
+    Some(Err(e)) => warn!("Failed to remove old loader versions: {:#}", e),
+    _ => {}
   }
-  const PAYLOAD_NAME: &str = "andromeda.dll";
-  let loader_path = get_andromeda_loader_path(config).map_or(PAYLOAD_NAME.into(), |path| path.join(PAYLOAD_NAME));
+}
+
+/// Loads and starts the payload, returning its version or the local build it came from
+fn load_payload(config: &AndromedaConfig, sentinel: SessionSentinel) -> Result<String, PayloadFailure> {
+  let trusted_keys = config
+    .trusted_keys
+    .iter()
+    .map(|key| parse_public_key(key))
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(PayloadFailure::NotLoaded)?;
+  // Never a bare name, that would load whatever `andromeda.dll` the DLL search path turns up
+  let loader_dir = get_andromeda_loader_path(config)
+    .ok_or_else(|| PayloadFailure::NotLoaded(AndromedaError::config("No loader directory")))?;
+  let payload_name = if allows_unverified(&loader_dir, config.channel().is_local_build(), &trusted_keys) {
+    warn!("Loading a local payload build without a manifest, it isn't verified");
+    loader_dir.display().to_string()
+  } else {
+    let manifest = verify_payload(&loader_dir, PAYLOAD_FILE, &trusted_keys).map_err(PayloadFailure::NotLoaded)?;
+    info!("Verified payload {}", manifest.version);
+    manifest.version
+  };
+
+  let loader_path = loader_dir.join(PAYLOAD_FILE);
   let loader_path = loader_path
     .to_str()
-    .ok_or_else(|| AndromedaError::Path("Invalid payload path was specified".to_string()))?;
+    .ok_or_else(|| PayloadFailure::NotLoaded(AndromedaError::config("Invalid payload path was specified")))?;
   info!("Loader path: {}", loader_path);
   let wide = win32::widestring(loader_path);
 
   unsafe {
-    let payload = LoadLibraryW(PCWSTR::from_raw(wide.as_ptr()));
-    if let Ok(payload) = payload {
-      let proc = GetProcAddress(
-        payload,
-        PCSTR(
-          CString::new("inject_andromeda_entrypoint")
-            .expect("CString had internal null byte present")
-            .as_ptr() as *const u8
-        )
-      );
-      if let Some(proc) = proc {
-        let inject_andromeda_entrypoint: InjectAndromedaEntrypointFn = std::mem::transmute(proc);
-        let process = Process::current();
-        let process_name = process
-          .base_name()
-          .map(|p| p.to_string_lossy().into_owned())
-          .unwrap_or_default();
-
-        let game_version = match get_game(&process_name) {
-          Game::Ffxiv => xiv::read_game_version(process.path_of().unwrap_or_default()),
-          _ => None
-        };
-
-        inject_andromeda_entrypoint(andromeda_common::config::StartupConfig {
-          process_name: CString::new(process_name)
-            .expect("CString had internal null byte present")
-            .into_raw(),
-          version: CString::new(game_version.unwrap_or_default()).unwrap().into_raw()
-        });
-      }
-    } else if let Err(err) = payload {
-      error!("Error loading payload!: {}", err);
+    let payload = LoadLibraryW(PCWSTR::from_raw(wide.as_ptr()))
+      .map_err(|e| PayloadFailure::NotLoaded(AndromedaError::config("Error loading payload").with_source(e)))?;
+    let proc = GetProcAddress(
+      payload,
+      PCSTR(
+        CString::new("inject_andromeda_entrypoint")
+          .expect("CString had internal null byte present")
+          .as_ptr() as *const u8
+      )
+    );
+    let Some(proc) = proc else {
+      let _ = FreeLibrary(payload);
+      return Err(PayloadFailure::NotLoaded(AndromedaError::config(
+        "The payload has no inject_andromeda_entrypoint export"
+      )));
+    };
+    let inject_andromeda_entrypoint: InjectAndromedaEntrypointFn = std::mem::transmute(proc);
+    let (process_name, game_version) = current_game();
+
+    let started = inject_andromeda_entrypoint(andromeda_common::config::StartupConfig {
+      process_name: CString::new(process_name)
+        .expect("CString had internal null byte present")
+        .into_raw(),
+      version: CString::new(game_version.unwrap_or_default()).unwrap().into_raw(),
+      input: &entrypoint::INPUT_API,
+      logs: &LOG_API,
+      crash: &CRASH_API,
+      safe_mode: sentinel.mode() == StartupMode::Safe,
+      unclean_exits: sentinel.unclean_exits
+    });
+    match started {
+      true => Ok(payload_name),
+      false => Err(PayloadFailure::Initialization)
     }
-    // Mark payload as loaded even on failure to prevent retry storming
-    PAYLOAD_LOADED.store(true, Ordering::Release);
   }
-  Ok(())
 }
 
 fn init_console() -> io::Result<()> {
@@ -125,29 +222,52 @@ fn init_console() -> io::Result<()> {
 
 pub fn init_logger() -> Result<(), AndromedaError> {
   let log_dir = get_andromeda_log_path().unwrap_or_else(|| "logs".into());
+  let logging = get_andromeda_config().map(|config| config.logging).unwrap_or_default();
 
-  let log_file = OpenOptions::new()
-    .write(true)
-    .create(true)
-    .truncate(true)
-    .open(log_dir.join("Andromeda.Entry.log"))?;
+  // The payload and plugins log through this logger too, see `LOG_API`
+  set_log_module("entry");
+  let policy = LogFilePolicy::from_config(&logging);
+  let log_files = LogFiles::open(&log_dir, ENTRY_LOG_BASENAME, policy.clone())?;
+  let log_file = log_files.path().to_path_buf();
 
-  let logger = fern::Dispatch::new().level(log::LevelFilter::Debug);
+  // Levels are checked per record so they can change at runtime, see `set_log_levels`
+  let logger = fern::Dispatch::new()
+    .level(log::LevelFilter::Trace)
+    .filter(log_levels_enabled);
 
   // Output to stdout and files
   let file_config = fern::Dispatch::new()
-    .format(andromeda_file_logging_format)
-    .chain(log_file);
+    .format(file_logging_format(logging.format))
+    .chain(log_files.into_output());
   let stdout_config = fern::Dispatch::new()
     .format(andromeda_stdout_logging_format)
     .chain(std::io::stdout());
 
-  logger.chain(file_config).chain(stdout_config).apply()?;
+  logger
+    .chain(file_config)
+    .chain(stdout_config)
+    .chain(log_buffer_output())
+    .apply()?;
+
+  for error in init_log_levels(&logging) {
+    warn!("{}", error);
+  }
+
+  match clean_up_log_files(&log_dir, ENTRY_LOG_BASENAME, &policy, SystemTime::now(), &log_file) {
+    Ok(removed) if !removed.is_empty() => info!("Removed {} old log files", removed.len()),
+    Ok(_) => {}
+    Err(e) => warn!("Failed to clean up old log files: {}", e)
+  }
 
   Ok(())
 }
 
 unsafe extern "system" fn thread_main(_: *mut c_void) -> u32 {
+  // The game keeps running without Andromeda
+  catch_panic("thread_main", || unsafe { start() }).unwrap_or(1)
+}
+
+unsafe fn start() -> u32 {
   unsafe {
     init_console();
   }
@@ -214,6 +334,9 @@ unsafe extern "system" fn thread_main(_: *mut c_void) -> u32 {
     Ok(_) => info!("Logger initialized!"),
     Err(ref e) => error!("Failed to initialize logger! {e}")
   }
+  // The payload reports its crashes here too, see `CRASH_API`
+  install_panic_reporter();
+  install_exception_handler();
 
   let config = match get_andromeda_config() {
     Some(config) => config,
@@ -230,9 +353,33 @@ unsafe extern "system" fn thread_main(_: *mut c_void) -> u32 {
   //     eerror!("Manual map failed: {}", e);
   //   }
   // }
-  apply_all_patches();
+  let (sentinel, mode) = read_session_sentinel().start(config.safe_mode_after_crashes);
+  match write_session_sentinel(&sentinel) {
+    Ok(()) => SESSION_STARTED.store(true, Ordering::Release),
+    Err(e) => error!("Failed to write the session sentinel: {:#}", e)
+  }
+  if mode == StartupMode::Safe {
+    warn!(
+      "Starting in safe mode after {} unclean exits in a row, plugins and optional patches are off",
+      sentinel.unclean_exits
+    );
+  }
+  apply_all_patches(mode);
 
-  let _ = ensure_payload_loaded(config);
+  let update_feed = match config.check_for_updates() && !config.channel().is_local_build() {
+    true => config.update_feed.clone(),
+    false => None
+  };
+  let payload = ensure_payload_loaded(config, sentinel).map_err(|e| {
+    error!("Failed to load the payload: {:#}", e);
+    format!("{:#}", e)
+  });
+  // The payload notes its hooks through the crash reporter it was handed, which is ours
+  let status = StartupStatus::new(payload, noted_hooks(), mode == StartupMode::Safe);
+  report_startup_status(&status);
+  if let Some(feed) = update_feed {
+    spawn_update_check(feed);
+  }
 
   0
 }
@@ -243,38 +390,64 @@ pub extern "system" fn DllMain(
   reason: u32,
   _reserved: *mut std::ffi::c_void
 ) -> i32 {
-  if reason == DLL_PROCESS_ATTACH {
-    unsafe {
-      DisableThreadLibraryCalls(_hinst.into());
-      let handle = CreateThread(None, 0, Some(thread_main), None, THREAD_CREATION_FLAGS(0), None);
-
-      if handle.is_err() {
-        // fallback: try std::thread if CreateThread failed (unlikely)
-        error!("CreateThread failed; falling back to std::thread");
-        std::thread::spawn(|| match crate::thread_main(std::ptr::null_mut()) {
-          0 => 0,
-          err => {
-            error!("Error occurred when injecting: {}", err);
-            1
-          }
-        });
-      } else {
-        info!("Background thread spawned");
+  catch_panic("DllMain", || {
+    if reason == DLL_PROCESS_ATTACH {
+      unsafe {
+        DisableThreadLibraryCalls(_hinst.into());
+        let handle = CreateThread(None, 0, Some(thread_main), None, THREAD_CREATION_FLAGS(0), None);
+
+        if handle.is_err() {
+          // fallback: try std::thread if CreateThread failed (unlikely)
+          error!("CreateThread failed; falling back to std::thread");
+          std::thread::spawn(|| match crate::thread_main(std::ptr::null_mut()) {
+            0 => 0,
+            err => {
+              error!("Error occurred when injecting: {}", err);
+              1
+            }
+          });
+        } else {
+          info!("Background thread spawned");
+        }
       }
-    }
 
-    H_MODULE.get_or_init(|| Mutex::new(LoadedModule::default()));
-    let module = H_MODULE.get();
-    if let Some(m) = module {
-      if let Ok(mut h) = m.lock() {
-        h.handle.attach(_hinst.into(), false);
+      H_MODULE.get_or_init(|| Mutex::new(LoadedModule::default()));
+      let module = H_MODULE.get();
+      if let Some(m) = module {
+        if let Ok(mut h) = m.lock() {
+          h.handle.attach(_hinst.into(), false);
+        }
       }
+      // let result = unsafe { patch_entry_point_for_injection(GetCurrentProcess()) };
+
+      // unsafe { (*result).LoadInstalledXivAlexDllOnly = true };
+    } else if reason == DLL_PROCESS_DETACH {
+      end_session();
     }
-    // let result = unsafe { patch_entry_point_for_injection(GetCurrentProcess()) };
+  });
+  1
+}
 
-    // unsafe { (*result).LoadInstalledXivAlexDllOnly = true };
+/// Marks the session as exited cleanly. Crashes and `TerminateProcess` never get here, which is what the sentinel
+/// counts on.
+fn end_session() {
+  if SESSION_STARTED.load(Ordering::Acquire) {
+    let _ = write_session_sentinel(&read_session_sentinel().exit());
+  }
+  end_current_launch_session();
+}
+
+/// Tells the runner how the start went, if it launched the game
+fn report_startup_status(status: &StartupStatus) {
+  if current_launch_session().is_none() {
+    return;
+  }
+  let Some(config_dir) = get_andromeda_config_path() else {
+    return;
+  };
+  if let Err(e) = write_startup_status(&config_dir, std::process::id(), status) {
+    warn!("Failed to report the startup status to the runner: {:#}", e);
   }
-  1
 }
 
 // DirectInput8Create=FORWARDER_DirectInput8Create		@1
@@ -5,6 +5,11 @@ use std::{
   ptr
 };
 
+use andromeda_common::{
+  crash::{CrashNote, note_crash_context},
+  guard::HookGuard,
+  safe_mode::StartupMode
+};
 use log::{error, info, warn};
 use once_cell::sync::OnceCell;
 use windows::{
@@ -28,6 +33,9 @@ use crate::util::{LoadedModule, MemoryTenderizer};
 static ORIG_SYM_FROM_ADDR: OnceCell<SymFromAddrFn> = OnceCell::new();
 static ORIG_OPENPROCESS: OnceCell<OpenProcessFn> = OnceCell::new();
 
+static OPENPROCESS_GUARD: HookGuard = HookGuard::new("OpenProcess");
+static SYM_FROM_ADDR_GUARD: HookGuard = HookGuard::new("SymFromAddr");
+
 type SymFromAddrFn =
   unsafe extern "system" fn(hProcess: HANDLE, Address: u64, Displacement: *mut u64, Symbol: *mut SYMBOL_INFO) -> BOOL;
 
@@ -42,19 +50,22 @@ pub(super) mod xiv {
 
   use crate::{
     DuplicateHandle, GetModuleHandleA, GetProcAddress, HANDLE, PCSTR, error, info,
-    patches::{ORIG_OPENPROCESS, OpenProcessFn},
+    patches::{OPENPROCESS_GUARD, ORIG_OPENPROCESS, OpenProcessFn},
     utils::win32::iat::IatHook
   };
 
   static IAT_OPENPROCESS: OnceCell<IatHook> = OnceCell::new();
 
   unsafe extern "system" fn open_process_hook(dwDesiredAccess: u32, bInheritHandle: BOOL, dwProcessId: u32) -> HANDLE {
-    info!("[HOOK] IAT OpenProcess called (process: {:?})", dwProcessId);
     let iat = IAT_OPENPROCESS.get().expect("IAT for OpenProcess not found");
     let orig: OpenProcessFn = std::mem::transmute(iat.original());
-    let self_pid = windows::Win32::System::Threading::GetCurrentProcessId();
-    if dwProcessId == self_pid && dwDesiredAccess & 0x20 != 0 {
+    let denied = OPENPROCESS_GUARD.run(|| {
+      info!("[HOOK] IAT OpenProcess called (process: {:?})", dwProcessId);
+      let self_pid = unsafe { windows::Win32::System::Threading::GetCurrentProcessId() };
       // PROCESS_VM_WRITE
+      dwProcessId == self_pid && dwDesiredAccess & 0x20 != 0
+    });
+    if denied == Some(true) {
       windows::Win32::Foundation::SetLastError(ERROR_ACCESS_DENIED);
       return HANDLE::default();
     }
@@ -66,10 +77,13 @@ pub(super) mod xiv {
     bInheritHandle: BOOL,
     dwProcessId: u32
   ) -> HANDLE {
-    info!("[HOOK] Global OpenProcess called (process: {:?})", dwProcessId);
     let orig = ORIG_OPENPROCESS.get().expect("orig OpenProcess not found");
-    let self_pid = windows::Win32::System::Threading::GetCurrentProcessId();
-    if dwProcessId == self_pid {
+    let redirected = OPENPROCESS_GUARD.run(|| unsafe {
+      info!("[HOOK] Global OpenProcess called (process: {:?})", dwProcessId);
+      let self_pid = windows::Win32::System::Threading::GetCurrentProcessId();
+      if dwProcessId != self_pid {
+        return None;
+      }
       let current = windows::Win32::System::Threading::GetCurrentProcess();
       let mut dup = HANDLE(std::ptr::null_mut());
       if DuplicateHandle(
@@ -83,9 +97,12 @@ pub(super) mod xiv {
       )
       .is_ok()
       {
-        return dup;
+        return Some(dup);
       }
-      return HANDLE::default();
+      Some(HANDLE::default())
+    });
+    if let Some(Some(handle)) = redirected {
+      return handle;
     }
     orig(dwDesiredAccess, bInheritHandle, dwProcessId)
   }
@@ -130,12 +147,13 @@ unsafe extern "system" fn sym_from_addr_hook(
   displacement: *mut u64,
   symbol: *mut SYMBOL_INFO
 ) -> BOOL {
-  info!(
-    "[HOOK] SymFromAddr called for address: 0x{:X} (process: {:?})",
-    address, hProcess
-  );
-
-  info!("Suppressed SymInitialize");
+  SYM_FROM_ADDR_GUARD.run(|| {
+    info!(
+      "[HOOK] SymFromAddr called for address: 0x{:X} (process: {:?})",
+      address, hProcess
+    );
+    info!("Suppressed SymInitialize");
+  });
   unsafe { SetLastError(ERROR_NOT_SUPPORTED) };
   false.into()
 }
@@ -158,16 +176,27 @@ fn symbol_load_patches() {
   }
 }
 
-pub fn apply_all_patches() {
-  let patches = vec![
-    symbol_load_patches,
-    xiv::redirect_openprocess,
-    xiv::disable_openprocess_access_check,
+/// Applies the patches, in safe mode only the ones Andromeda can't run without
+pub fn apply_all_patches(mode: StartupMode) {
+  // Name, whether it's optional and the patch
+  let patches: Vec<(&str, bool, fn())> = vec![
+    ("symbol_load_patches", true, symbol_load_patches),
+    ("redirect_openprocess", false, xiv::redirect_openprocess),
+    (
+      "disable_openprocess_access_check",
+      false,
+      xiv::disable_openprocess_access_check
+    ),
   ];
 
   warn!("Applying all patches to running process..");
 
-  for patch in patches {
+  for (name, optional, patch) in patches {
+    if optional && mode == StartupMode::Safe {
+      info!("Skipped {} in safe mode", name);
+      continue;
+    }
     patch();
+    note_crash_context(CrashNote::Patch, name);
   }
 }
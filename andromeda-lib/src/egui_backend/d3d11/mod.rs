@@ -1,2 +1,3 @@
 mod dx11_device;
 pub(crate) mod dx11_renderer;
+pub(crate) mod pipeline_state;
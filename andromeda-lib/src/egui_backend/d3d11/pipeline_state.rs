@@ -0,0 +1,148 @@
+use windows::Win32::{
+  Foundation::RECT,
+  Graphics::{
+    Direct3D::D3D_PRIMITIVE_TOPOLOGY,
+    Direct3D11::{
+      D3D11_SIMULTANEOUS_RENDER_TARGET_COUNT, D3D11_VIEWPORT, ID3D11BlendState, ID3D11Buffer, ID3D11DepthStencilView,
+      ID3D11DeviceContext, ID3D11DomainShader, ID3D11GeometryShader, ID3D11HullShader, ID3D11InputLayout,
+      ID3D11PixelShader, ID3D11RasterizerState, ID3D11RenderTargetView, ID3D11SamplerState, ID3D11ShaderResourceView,
+      ID3D11VertexShader
+    },
+    Dxgi::Common::DXGI_FORMAT
+  }
+};
+
+const RENDER_TARGETS: usize = D3D11_SIMULTANEOUS_RENDER_TARGET_COUNT as usize;
+
+/// The game's pipeline state on a device context, everything `egui_directx11::Renderer::render` overrides or
+/// expects to be unbound. Captured before the overlay draws and restored afterwards, so the game's next draw finds
+/// its own state and none of our views stay bound to the context.
+pub(crate) struct PipelineState {
+  render_targets: [Option<ID3D11RenderTargetView>; RENDER_TARGETS],
+  depth_stencil: Option<ID3D11DepthStencilView>,
+  blend_state: Option<ID3D11BlendState>,
+  blend_factor: [f32; 4],
+  sample_mask: u32,
+  viewports: Vec<D3D11_VIEWPORT>,
+  scissor_rects: Vec<RECT>,
+  rasterizer_state: Option<ID3D11RasterizerState>,
+  topology: D3D_PRIMITIVE_TOPOLOGY,
+  input_layout: Option<ID3D11InputLayout>,
+  vertex_buffer: Option<ID3D11Buffer>,
+  vertex_stride: u32,
+  vertex_offset: u32,
+  index_buffer: Option<ID3D11Buffer>,
+  index_format: DXGI_FORMAT,
+  index_offset: u32,
+  vertex_shader: Option<ID3D11VertexShader>,
+  hull_shader: Option<ID3D11HullShader>,
+  domain_shader: Option<ID3D11DomainShader>,
+  geometry_shader: Option<ID3D11GeometryShader>,
+  pixel_shader: Option<ID3D11PixelShader>,
+  pixel_resource: [Option<ID3D11ShaderResourceView>; 1],
+  pixel_sampler: [Option<ID3D11SamplerState>; 1]
+}
+
+impl PipelineState {
+  /// Saves the state and unbinds the shader stages the renderer assumes are inactive
+  pub unsafe fn capture(context: &ID3D11DeviceContext) -> Self {
+    unsafe {
+      let mut state = Self {
+        render_targets: Default::default(),
+        depth_stencil: None,
+        blend_state: None,
+        blend_factor: [0.0; 4],
+        sample_mask: 0,
+        viewports: Vec::new(),
+        scissor_rects: Vec::new(),
+        rasterizer_state: context.RSGetState().ok(),
+        topology: context.IAGetPrimitiveTopology(),
+        input_layout: context.IAGetInputLayout().ok(),
+        vertex_buffer: None,
+        vertex_stride: 0,
+        vertex_offset: 0,
+        index_buffer: None,
+        index_format: DXGI_FORMAT::default(),
+        index_offset: 0,
+        vertex_shader: None,
+        hull_shader: None,
+        domain_shader: None,
+        geometry_shader: None,
+        pixel_shader: None,
+        pixel_resource: Default::default(),
+        pixel_sampler: Default::default()
+      };
+
+      context.OMGetRenderTargets(Some(&mut state.render_targets), Some(&mut state.depth_stencil));
+      context.OMGetBlendState(
+        Some(&mut state.blend_state),
+        Some(&mut state.blend_factor),
+        Some(&mut state.sample_mask)
+      );
+
+      // Asking with no array returns how many are bound
+      let mut count = 0;
+      context.RSGetViewports(&mut count, None);
+      state.viewports = vec![D3D11_VIEWPORT::default(); count as usize];
+      context.RSGetViewports(&mut count, Some(state.viewports.as_mut_ptr()));
+      let mut count = 0;
+      context.RSGetScissorRects(&mut count, None);
+      state.scissor_rects = vec![RECT::default(); count as usize];
+      context.RSGetScissorRects(&mut count, Some(state.scissor_rects.as_mut_ptr()));
+
+      context.IAGetVertexBuffers(
+        0,
+        1,
+        Some(&mut state.vertex_buffer),
+        Some(&mut state.vertex_stride),
+        Some(&mut state.vertex_offset)
+      );
+      context.IAGetIndexBuffer(
+        Some(&mut state.index_buffer),
+        Some(&mut state.index_format),
+        Some(&mut state.index_offset)
+      );
+
+      context.VSGetShader(&mut state.vertex_shader, None, None);
+      context.HSGetShader(&mut state.hull_shader, None, None);
+      context.DSGetShader(&mut state.domain_shader, None, None);
+      context.GSGetShader(&mut state.geometry_shader, None, None);
+      context.PSGetShader(&mut state.pixel_shader, None, None);
+      context.PSGetShaderResources(0, Some(&mut state.pixel_resource));
+      context.PSGetSamplers(0, Some(&mut state.pixel_sampler));
+
+      context.HSSetShader(None, None);
+      context.DSSetShader(None, None);
+      context.GSSetShader(None, None);
+
+      state
+    }
+  }
+
+  pub unsafe fn restore(self, context: &ID3D11DeviceContext) {
+    unsafe {
+      context.OMSetRenderTargets(Some(&self.render_targets), self.depth_stencil.as_ref());
+      context.OMSetBlendState(self.blend_state.as_ref(), Some(&self.blend_factor), self.sample_mask);
+      context.RSSetViewports(Some(&self.viewports));
+      context.RSSetScissorRects(Some(&self.scissor_rects));
+      context.RSSetState(self.rasterizer_state.as_ref());
+      context.IASetPrimitiveTopology(self.topology);
+      context.IASetInputLayout(self.input_layout.as_ref());
+      context.IASetVertexBuffers(
+        0,
+        1,
+        Some(&self.vertex_buffer),
+        Some(&self.vertex_stride),
+        Some(&self.vertex_offset)
+      );
+      context.IASetIndexBuffer(self.index_buffer.as_ref(), self.index_format, self.index_offset);
+      context.VSSetShader(self.vertex_shader.as_ref(), None);
+      context.HSSetShader(self.hull_shader.as_ref(), None);
+      context.DSSetShader(self.domain_shader.as_ref(), None);
+      context.GSSetShader(self.geometry_shader.as_ref(), None);
+      context.PSSetShader(self.pixel_shader.as_ref(), None);
+      context.PSSetShaderResources(0, Some(&self.pixel_resource));
+      context.PSSetSamplers(0, Some(&self.pixel_sampler));
+    }
+  }
+}
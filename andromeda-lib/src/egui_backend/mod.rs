@@ -1,19 +1,41 @@
-mod d3d11;
+//! Everything that paints the overlay or feeds it input. Only [`software`], [`vulkan`] and [`win32_input`] build
+//! outside of Windows, they are what the overlay's tests run on.
+
+#[cfg(windows)]
+pub(crate) mod d3d11;
+pub mod software;
+pub mod vulkan;
+#[cfg(windows)]
 pub(crate) mod win32_backend;
+pub mod win32_input;
 
+#[cfg(windows)]
 use std::error::Error;
 
-use windows::Win32::Graphics::{
-  Direct3D11::{ID3D11RenderTargetView, ID3D11Texture2D},
-  Dxgi::IDXGISwapChain
+#[cfg(windows)]
+use windows::Win32::{
+  Foundation::HWND,
+  Graphics::{
+    Direct3D11::{ID3D11RenderTargetView, ID3D11Texture2D},
+    Dxgi::IDXGISwapChain
+  }
 };
 
+#[cfg(windows)]
 use crate::egui_backend::d3d11::dx11_renderer::Dx11Renderer;
 
+/// Platform side of the overlay: where its input comes from and where clipboard output goes
+#[cfg(windows)]
 pub trait EGuiBackend {
-  fn render(&self);
+  /// Routes the input of `hwnd` to the overlay, replacing the window attached before
+  fn attach(&self, hwnd: HWND);
+  /// Input collected since the last frame, for a frame `target_size` physical pixels large
+  fn take_input(&self, target_size: [u32; 2]) -> egui::RawInput;
+  /// Carries out what a frame asked of the platform and remembers which input the overlay wants to keep
+  fn handle_output(&self, ctx: &egui::Context, output: &egui::PlatformOutput);
 }
 
+#[cfg(windows)]
 #[derive(Default)]
 pub struct Viewport {
   swapchain: *mut IDXGISwapChain,
@@ -21,10 +43,12 @@ pub struct Viewport {
   render_target_view: *mut ID3D11RenderTargetView
 }
 
+#[cfg(windows)]
 impl Viewport {
   fn new(&self, renderer: &Dx11Renderer, swapchain: *mut IDXGISwapChain, width: u32, height: u32) {}
 }
 
+#[cfg(windows)]
 trait Renderer {
   fn create(&self) -> Result<Viewport, Box<dyn Error>>;
 }
@@ -0,0 +1,456 @@
+use std::{
+  collections::HashMap,
+  fs::File,
+  io::{self, BufReader, BufWriter, Read, Write},
+  path::Path
+};
+
+use egui::{
+  ClippedPrimitive, Color32, ImageData, Pos2, TextureFilter, TextureId, TextureOptions, TextureWrapMode, TexturesDelta,
+  epaint::{Primitive, Vertex}
+};
+
+use crate::ui::{OverlayUi, screen_input};
+
+/// An RGBA image in egui's blending space: premultiplied alpha, gamma encoded
+#[derive(Clone, PartialEq)]
+pub struct Canvas {
+  width: u32,
+  height: u32,
+  pixels: Vec<Color32>
+}
+
+impl Canvas {
+  pub fn new(width: u32, height: u32, background: Color32) -> Self {
+    Self {
+      width,
+      height,
+      pixels: vec![background; width as usize * height as usize]
+    }
+  }
+
+  /// Premultiplied `pixels` row by row, `None` if there aren't `width` x `height` of them
+  pub fn from_pixels(width: u32, height: u32, pixels: Vec<Color32>) -> Option<Self> {
+    (pixels.len() == width as usize * height as usize).then_some(Self { width, height, pixels })
+  }
+
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  pub fn pixel(&self, x: u32, y: u32) -> Color32 {
+    self.pixels[(y * self.width + x) as usize]
+  }
+
+  /// Unmultiplied RGBA8, the layout PNG expects
+  pub fn to_rgba8(&self) -> Vec<u8> {
+    self
+      .pixels
+      .iter()
+      .flat_map(|pixel| pixel.to_srgba_unmultiplied())
+      .collect()
+  }
+
+  pub fn write_png(&self, writer: impl Write) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(writer, self.width, self.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&self.to_rgba8())
+  }
+
+  pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+    self
+      .write_png(BufWriter::new(File::create(path)?))
+      .map_err(io::Error::other)
+  }
+
+  /// Reads an 8-bit RGBA PNG, such as one written by [`Canvas::write_png`]
+  pub fn read_png(reader: impl Read) -> io::Result<Self> {
+    let mut decoder = png::Decoder::new(reader).read_info().map_err(io::Error::other)?;
+    let mut buffer = vec![0; decoder.output_buffer_size()];
+    let info = decoder.next_frame(&mut buffer).map_err(io::Error::other)?;
+    if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "only 8-bit RGBA images can be loaded as a canvas"
+      ));
+    }
+
+    Ok(Self {
+      width: info.width,
+      height: info.height,
+      pixels: buffer[..info.buffer_size()]
+        .chunks_exact(4)
+        .map(|p| Color32::from_rgba_unmultiplied(p[0], p[1], p[2], p[3]))
+        .collect()
+    })
+  }
+
+  pub fn load_png(path: impl AsRef<Path>) -> io::Result<Self> {
+    Self::read_png(BufReader::new(File::open(path)?))
+  }
+
+  /// Number of pixels where any channel differs by more than `tolerance`, `None` if the sizes don't match
+  pub fn count_differences(&self, other: &Canvas, tolerance: u8) -> Option<usize> {
+    if (self.width, self.height) != (other.width, other.height) {
+      return None;
+    }
+
+    let differs = |a: &Color32, b: &Color32| {
+      a.to_array()
+        .iter()
+        .zip(b.to_array())
+        .any(|(a, b)| a.abs_diff(b) > tolerance)
+    };
+    Some(
+      self
+        .pixels
+        .iter()
+        .zip(&other.pixels)
+        .filter(|(a, b)| differs(a, b))
+        .count()
+    )
+  }
+
+  /// Premultiplied "over" in gamma space, the blend every egui painter uses
+  fn blend(&mut self, x: u32, y: u32, src: [f32; 4]) {
+    let dst = &mut self.pixels[(y * self.width + x) as usize];
+    let inv_alpha = 1.0 - src[3];
+    let channel = |src: f32, dst: u8| {
+      ((src + dst as f32 / 255.0 * inv_alpha) * 255.0)
+        .round()
+        .clamp(0.0, 255.0) as u8
+    };
+    *dst = Color32::from_rgba_premultiplied(
+      channel(src[0], dst.r()),
+      channel(src[1], dst.g()),
+      channel(src[2], dst.b()),
+      channel(src[3], dst.a())
+    );
+  }
+}
+
+struct SoftwareTexture {
+  size: [usize; 2],
+  pixels: Vec<Color32>,
+  options: TextureOptions
+}
+
+impl SoftwareTexture {
+  fn texel(&self, x: i64, y: i64) -> [f32; 4] {
+    let x = wrap(x, self.size[0], self.options.wrap_mode);
+    let y = wrap(y, self.size[1], self.options.wrap_mode);
+    color_to_f32(self.pixels[y * self.size[0] + x])
+  }
+
+  /// Samples at normalized `uv`, texel centers sit at half-integer coordinates like on the GPU
+  fn sample(&self, uv: Pos2, magnified: bool) -> [f32; 4] {
+    if self.size[0] == 0 || self.size[1] == 0 {
+      return [0.0; 4];
+    }
+
+    let filter = match magnified {
+      true => self.options.magnification,
+      false => self.options.minification
+    };
+    let x = uv.x * self.size[0] as f32 - 0.5;
+    let y = uv.y * self.size[1] as f32 - 0.5;
+
+    match filter {
+      TextureFilter::Nearest => self.texel(x.round() as i64, y.round() as i64),
+      TextureFilter::Linear => {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = lerp4(self.texel(x0, y0), self.texel(x0 + 1, y0), fx);
+        let bottom = lerp4(self.texel(x0, y0 + 1), self.texel(x0 + 1, y0 + 1), fx);
+        lerp4(top, bottom, fy)
+      }
+    }
+  }
+}
+
+/// Clip rectangle in pixels, `max` is exclusive
+#[derive(Clone, Copy)]
+struct PixelRect {
+  min_x: u32,
+  min_y: u32,
+  max_x: u32,
+  max_y: u32
+}
+
+/// Rasterizes egui output on the CPU.
+///
+/// Follows the same rules as the GPU painters (premultiplied alpha, gamma space blending, top-left fill rule), so its
+/// images are what the overlay looks like in game up to rounding. Meant for snapshots of the UI, not for frame rates.
+#[derive(Default)]
+pub struct SoftwarePainter {
+  textures: HashMap<TextureId, SoftwareTexture>
+}
+
+impl SoftwarePainter {
+  pub fn update_textures(&mut self, delta: &TexturesDelta) {
+    for (id, image_delta) in &delta.set {
+      let ImageData::Color(image) = &image_delta.image;
+      match image_delta.pos {
+        Some([x0, y0]) => {
+          let Some(texture) = self.textures.get_mut(id) else {
+            continue;
+          };
+          let [width, height] = image.size;
+          for y in 0..height.min(texture.size[1].saturating_sub(y0)) {
+            for x in 0..width.min(texture.size[0].saturating_sub(x0)) {
+              texture.pixels[(y0 + y) * texture.size[0] + x0 + x] = image.pixels[y * width + x];
+            }
+          }
+        }
+        None => {
+          self.textures.insert(
+            *id,
+            SoftwareTexture {
+              size: image.size,
+              pixels: image.pixels.clone(),
+              options: image_delta.options
+            }
+          );
+        }
+      }
+    }
+  }
+
+  pub fn free_textures(&mut self, delta: &TexturesDelta) {
+    for id in &delta.free {
+      self.textures.remove(id);
+    }
+  }
+
+  pub fn paint(&self, canvas: &mut Canvas, pixels_per_point: f32, primitives: &[ClippedPrimitive]) {
+    for primitive in primitives {
+      let Primitive::Mesh(mesh) = &primitive.primitive else {
+        continue;
+      };
+      let Some(texture) = self.textures.get(&mesh.texture_id) else {
+        continue;
+      };
+      let Some(clip) = clip_to_pixels(&primitive.clip_rect, pixels_per_point, canvas) else {
+        continue;
+      };
+
+      for triangle in mesh.indices.chunks_exact(3) {
+        let vertex = |i: usize| mesh.vertices.get(triangle[i] as usize);
+        if let (Some(a), Some(b), Some(c)) = (vertex(0), vertex(1), vertex(2)) {
+          rasterize_triangle(canvas, clip, texture, pixels_per_point, [a, b, c]);
+        }
+      }
+    }
+  }
+}
+
+/// Runs `ui` for `frames` frames of input covering `width` x `height` pixels and rasterizes the last one.
+///
+/// egui sizes windows during their first frame without showing them, so a snapshot needs at least two frames. Frame
+/// times are fixed, the same UI always produces the same image.
+pub fn snapshot(ui: &mut OverlayUi, width: u32, height: u32, pixels_per_point: f32, frames: usize) -> Canvas {
+  let frames = frames.max(1);
+  let mut painter = SoftwarePainter::default();
+  let mut canvas = Canvas::new(width, height, Color32::TRANSPARENT);
+
+  for frame in 0..frames {
+    let mut raw_input = screen_input(width, height, pixels_per_point);
+    raw_input.time = Some(frame as f64 / 60.0);
+    let output = ui.run(raw_input);
+
+    painter.update_textures(&output.textures_delta);
+    if frame + 1 == frames {
+      let primitives = ui.context().tessellate(output.shapes, output.pixels_per_point);
+      painter.paint(&mut canvas, output.pixels_per_point, &primitives);
+    }
+    painter.free_textures(&output.textures_delta);
+  }
+
+  canvas
+}
+
+fn clip_to_pixels(clip_rect: &egui::Rect, pixels_per_point: f32, canvas: &Canvas) -> Option<PixelRect> {
+  let to_pixels = |value: f32, max: u32| (value * pixels_per_point).round().clamp(0.0, max as f32) as u32;
+  let rect = PixelRect {
+    min_x: to_pixels(clip_rect.min.x, canvas.width),
+    min_y: to_pixels(clip_rect.min.y, canvas.height),
+    max_x: to_pixels(clip_rect.max.x, canvas.width),
+    max_y: to_pixels(clip_rect.max.y, canvas.height)
+  };
+  (rect.max_x > rect.min_x && rect.max_y > rect.min_y).then_some(rect)
+}
+
+fn rasterize_triangle(
+  canvas: &mut Canvas,
+  clip: PixelRect,
+  texture: &SoftwareTexture,
+  pixels_per_point: f32,
+  vertices: [&Vertex; 3]
+) {
+  let [mut a, mut b, c] = vertices;
+  let mut area = edge(a.pos, b.pos, c.pos);
+  if area == 0.0 {
+    return;
+  }
+  // Wind every triangle the same way so the fill rule treats shared edges consistently
+  if area < 0.0 {
+    (a, b) = (b, a);
+    area = -area;
+  }
+
+  let [pa, pb, pc] = [a.pos, b.pos, c.pos].map(|pos| (pos.to_vec2() * pixels_per_point).to_pos2());
+  let area_px = area * pixels_per_point * pixels_per_point;
+
+  let min_x = (pa.x.min(pb.x).min(pc.x).floor().max(clip.min_x as f32)) as u32;
+  let min_y = (pa.y.min(pb.y).min(pc.y).floor().max(clip.min_y as f32)) as u32;
+  let max_x = (pa.x.max(pb.x).max(pc.x).ceil().min(clip.max_x as f32)) as u32;
+  let max_y = (pa.y.max(pb.y).max(pc.y).ceil().min(clip.max_y as f32)) as u32;
+
+  // A texel covering a pixel or more means magnification, decided per triangle rather than per pixel
+  let uv_area = edge(a.uv, b.uv, c.uv).abs() * (texture.size[0] * texture.size[1]) as f32;
+  let magnified = uv_area <= area_px;
+
+  for y in min_y..max_y {
+    for x in min_x..max_x {
+      let center = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+      let w_a = edge(pb, pc, center);
+      let w_b = edge(pc, pa, center);
+      let w_c = edge(pa, pb, center);
+      if !(covers(w_a, pb, pc) && covers(w_b, pc, pa) && covers(w_c, pa, pb)) {
+        continue;
+      }
+
+      let weights = [w_a / area_px, w_b / area_px, w_c / area_px];
+      let uv = Pos2::new(
+        a.uv.x * weights[0] + b.uv.x * weights[1] + c.uv.x * weights[2],
+        a.uv.y * weights[0] + b.uv.y * weights[1] + c.uv.y * weights[2]
+      );
+      let [ca, cb, cc] = [a.color, b.color, c.color].map(color_to_f32);
+      let texel = texture.sample(uv, magnified);
+      let src = std::array::from_fn(|i| (ca[i] * weights[0] + cb[i] * weights[1] + cc[i] * weights[2]) * texel[i]);
+
+      canvas.blend(x, y, src);
+    }
+  }
+}
+
+/// Twice the signed area of `a`, `b`, `p`, positive when `p` is on the inner side of a positively wound edge
+fn edge(a: Pos2, b: Pos2, p: Pos2) -> f32 {
+  (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+/// Top-left fill rule: pixels exactly on an edge belong to only one of the two triangles sharing it, so translucent
+/// feathering isn't blended twice
+fn covers(weight: f32, a: Pos2, b: Pos2) -> bool {
+  let (dx, dy) = (b.x - a.x, b.y - a.y);
+  weight > 0.0 || (weight == 0.0 && (dy > 0.0 || (dy == 0.0 && dx < 0.0)))
+}
+
+fn wrap(coordinate: i64, size: usize, mode: TextureWrapMode) -> usize {
+  let size = size as i64;
+  let wrapped = match mode {
+    TextureWrapMode::ClampToEdge => coordinate.clamp(0, size - 1),
+    TextureWrapMode::Repeat => coordinate.rem_euclid(size),
+    TextureWrapMode::MirroredRepeat => {
+      let period = coordinate.rem_euclid(2 * size);
+      if period < size { period } else { 2 * size - 1 - period }
+    }
+  };
+  wrapped as usize
+}
+
+fn color_to_f32(color: Color32) -> [f32; 4] {
+  color.to_array().map(|channel| channel as f32 / 255.0)
+}
+
+fn lerp4(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+  std::array::from_fn(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use egui::{ColorImage, Mesh, Rect, epaint::ImageDelta};
+
+  use super::*;
+
+  /// A painter with a single white texel, what egui draws untextured shapes with
+  fn white_painter() -> SoftwarePainter {
+    let mut painter = SoftwarePainter::default();
+    painter.update_textures(&TexturesDelta {
+      set: vec![(
+        TextureId::default(),
+        ImageDelta::full(
+          ImageData::Color(Arc::new(ColorImage::new([1, 1], vec![Color32::WHITE]))),
+          TextureOptions::NEAREST
+        )
+      )],
+      free: Vec::new()
+    });
+    painter
+  }
+
+  fn rect(min: (f32, f32), max: (f32, f32), color: Color32) -> ClippedPrimitive {
+    let mut mesh = Mesh::with_texture(TextureId::default());
+    mesh.add_rect_with_uv(
+      Rect::from_min_max(min.into(), max.into()),
+      Rect::from_min_max(Pos2::ZERO, Pos2::ZERO),
+      color
+    );
+    ClippedPrimitive {
+      clip_rect: Rect::EVERYTHING,
+      primitive: Primitive::Mesh(mesh)
+    }
+  }
+
+  #[test]
+  fn translucent_quads_are_blended_once_per_pixel() {
+    let mut canvas = Canvas::new(8, 8, Color32::BLACK);
+    let half_white = Color32::from_white_alpha(128);
+    white_painter().paint(&mut canvas, 1.0, &[rect((2.0, 2.0), (6.0, 6.0), half_white)]);
+
+    let blended = canvas.pixel(2, 2);
+    assert_ne!(blended, Color32::BLACK);
+    for y in 0..8 {
+      for x in 0..8 {
+        let inside = (2..6).contains(&x) && (2..6).contains(&y);
+        let expected = if inside { blended } else { Color32::BLACK };
+        assert_eq!(canvas.pixel(x, y), expected, "pixel {x}, {y}");
+      }
+    }
+  }
+
+  #[test]
+  fn meshes_are_clipped_and_scaled() {
+    let mut canvas = Canvas::new(8, 8, Color32::TRANSPARENT);
+    let mut primitive = rect((0.0, 0.0), (4.0, 4.0), Color32::RED);
+    primitive.clip_rect = Rect::from_min_max(Pos2::ZERO, egui::pos2(2.0, 4.0));
+    white_painter().paint(&mut canvas, 2.0, &[primitive]);
+
+    assert_eq!(canvas.pixel(3, 7), Color32::RED);
+    assert_eq!(canvas.pixel(4, 0), Color32::TRANSPARENT);
+  }
+
+  #[test]
+  fn png_round_trip() {
+    let mut canvas = Canvas::new(3, 2, Color32::TRANSPARENT);
+    white_painter().paint(&mut canvas, 1.0, &[rect((0.0, 0.0), (2.0, 1.0), Color32::BLUE)]);
+
+    let mut png = Vec::new();
+    canvas.write_png(&mut png).unwrap();
+    let read = Canvas::read_png(png.as_slice()).unwrap();
+    assert!(read == canvas);
+    assert_eq!(read.count_differences(&canvas, 0), Some(0));
+    assert_eq!(
+      read.count_differences(&Canvas::new(3, 3, Color32::TRANSPARENT), 0),
+      None
+    );
+  }
+}
@@ -0,0 +1,469 @@
+use std::ffi::CStr;
+
+use ash::{prelude::VkResult, vk};
+use egui::Color32;
+
+use crate::egui_backend::{
+  software::Canvas,
+  vulkan::painter::{PainterTarget, VulkanPainter, one_time_submit}
+};
+
+/// Renders egui into an offscreen image on whatever Vulkan device is available and reads the pixels back.
+///
+/// Needs no window or surface, so it runs on CI machines with a software implementation such as lavapipe
+/// (`VK_ICD_FILENAMES=/usr/share/vulkan/icd.d/lvp_icd.x86_64.json`).
+pub struct HeadlessRenderer {
+  _entry: ash::Entry,
+  instance: ash::Instance,
+  physical_device: vk::PhysicalDevice,
+  device: ash::Device,
+  queue: vk::Queue,
+  command_pool: vk::CommandPool,
+  image: vk::Image,
+  image_memory: vk::DeviceMemory,
+  image_view: vk::ImageView,
+  framebuffer: vk::Framebuffer,
+  readback: vk::Buffer,
+  readback_memory: vk::DeviceMemory,
+  extent: vk::Extent2D,
+  painter: Option<VulkanPainter>
+}
+
+impl HeadlessRenderer {
+  const FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+  pub fn new(width: u32, height: u32) -> Result<Self, String> {
+    let entry = unsafe { ash::Entry::load() }.map_err(|e| format!("Failed to load Vulkan: {e}"))?;
+    unsafe { Self::with_entry(entry, width, height) }.map_err(|e| format!("Failed to create headless renderer: {e}"))
+  }
+
+  unsafe fn with_entry(entry: ash::Entry, width: u32, height: u32) -> VkResult<Self> {
+    unsafe {
+      let app_info = vk::ApplicationInfo::default()
+        .application_name(c"andromeda-headless")
+        .api_version(vk::API_VERSION_1_1);
+      let instance = entry.create_instance(&vk::InstanceCreateInfo::default().application_info(&app_info), None)?;
+
+      let (physical_device, queue_family_index) = instance
+        .enumerate_physical_devices()?
+        .into_iter()
+        .find_map(|physical_device| {
+          instance
+            .get_physical_device_queue_family_properties(physical_device)
+            .iter()
+            .position(|family| family.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            .map(|index| (physical_device, index as u32))
+        })
+        .ok_or(vk::Result::ERROR_INITIALIZATION_FAILED)?;
+
+      let priorities = [1.0];
+      let queue_infos = [vk::DeviceQueueCreateInfo::default()
+        .queue_family_index(queue_family_index)
+        .queue_priorities(&priorities)];
+      let device = instance.create_device(
+        physical_device,
+        &vk::DeviceCreateInfo::default().queue_create_infos(&queue_infos),
+        None
+      )?;
+      let queue = device.get_device_queue(queue_family_index, 0);
+      let command_pool = device.create_command_pool(
+        &vk::CommandPoolCreateInfo::default()
+          .queue_family_index(queue_family_index)
+          .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER),
+        None
+      )?;
+      let memory_properties = instance.get_physical_device_memory_properties(physical_device);
+      let find_memory = |type_bits: u32, flags: vk::MemoryPropertyFlags| {
+        (0..memory_properties.memory_type_count)
+          .find(|&i| {
+            type_bits & (1 << i) != 0 &&
+              memory_properties.memory_types[i as usize]
+                .property_flags
+                .contains(flags)
+          })
+          .ok_or(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)
+      };
+
+      let extent = vk::Extent2D { width, height };
+      let image = device.create_image(
+        &vk::ImageCreateInfo::default()
+          .image_type(vk::ImageType::TYPE_2D)
+          .format(Self::FORMAT)
+          .extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1
+          })
+          .mip_levels(1)
+          .array_layers(1)
+          .samples(vk::SampleCountFlags::TYPE_1)
+          .tiling(vk::ImageTiling::OPTIMAL)
+          .usage(
+            vk::ImageUsageFlags::COLOR_ATTACHMENT |
+              vk::ImageUsageFlags::TRANSFER_SRC |
+              vk::ImageUsageFlags::TRANSFER_DST
+          )
+          .initial_layout(vk::ImageLayout::UNDEFINED),
+        None
+      )?;
+      let requirements = device.get_image_memory_requirements(image);
+      let image_memory = device.allocate_memory(
+        &vk::MemoryAllocateInfo::default()
+          .allocation_size(requirements.size)
+          .memory_type_index(find_memory(
+            requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL
+          )?),
+        None
+      )?;
+      device.bind_image_memory(image, image_memory, 0)?;
+      let image_view = device.create_image_view(
+        &vk::ImageViewCreateInfo::default()
+          .image(image)
+          .view_type(vk::ImageViewType::TYPE_2D)
+          .format(Self::FORMAT)
+          .subresource_range(Self::subresource_range()),
+        None
+      )?;
+
+      let readback_size = width as vk::DeviceSize * height as vk::DeviceSize * 4;
+      let readback = device.create_buffer(
+        &vk::BufferCreateInfo::default()
+          .size(readback_size)
+          .usage(vk::BufferUsageFlags::TRANSFER_DST),
+        None
+      )?;
+      let requirements = device.get_buffer_memory_requirements(readback);
+      let readback_memory = device.allocate_memory(
+        &vk::MemoryAllocateInfo::default()
+          .allocation_size(requirements.size)
+          .memory_type_index(find_memory(
+            requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+          )?),
+        None
+      )?;
+      device.bind_buffer_memory(readback, readback_memory, 0)?;
+
+      // The image is cleared with a transfer before every frame, and copied out with one afterwards
+      let painter = VulkanPainter::new(
+        device.clone(),
+        memory_properties,
+        PainterTarget {
+          format: Self::FORMAT,
+          initial_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+          final_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+        },
+        1
+      )?;
+      let framebuffer = painter.create_framebuffer(image_view, extent)?;
+
+      Ok(Self {
+        _entry: entry,
+        instance,
+        physical_device,
+        device,
+        queue,
+        command_pool,
+        image,
+        image_memory,
+        image_view,
+        framebuffer,
+        readback,
+        readback_memory,
+        extent,
+        painter: Some(painter)
+      })
+    }
+  }
+
+  fn subresource_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+      aspect_mask: vk::ImageAspectFlags::COLOR,
+      base_mip_level: 0,
+      level_count: 1,
+      base_array_layer: 0,
+      layer_count: 1
+    }
+  }
+
+  /// Paints one egui frame over a transparent background and reads it back
+  pub fn render(&mut self, egui_ctx: &egui::Context, output: egui::FullOutput) -> VkResult<Canvas> {
+    let Some(painter) = self.painter.as_mut() else {
+      return Err(vk::Result::ERROR_DEVICE_LOST);
+    };
+    let primitives = egui_ctx.tessellate(output.shapes, output.pixels_per_point);
+    painter.update_textures(self.queue, self.command_pool, &output.textures_delta)?;
+
+    let (device, image, extent) = (&self.device, self.image, self.extent);
+    let mut painted = Ok(());
+    unsafe {
+      one_time_submit(device, self.queue, self.command_pool, |cmd| {
+        let to_transfer = vk::ImageMemoryBarrier::default()
+          .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+          .old_layout(vk::ImageLayout::UNDEFINED)
+          .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+          .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+          .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+          .image(image)
+          .subresource_range(Self::subresource_range());
+        device.cmd_pipeline_barrier(
+          cmd,
+          vk::PipelineStageFlags::TOP_OF_PIPE,
+          vk::PipelineStageFlags::TRANSFER,
+          vk::DependencyFlags::empty(),
+          &[],
+          &[],
+          &[to_transfer]
+        );
+        device.cmd_clear_color_image(
+          cmd,
+          image,
+          vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+          &vk::ClearColorValue { float32: [0.0; 4] },
+          &[Self::subresource_range()]
+        );
+        // The render pass only waits for earlier color attachment writes, not for the clear
+        let cleared = vk::MemoryBarrier::default()
+          .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+          .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+        device.cmd_pipeline_barrier(
+          cmd,
+          vk::PipelineStageFlags::TRANSFER,
+          vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+          vk::DependencyFlags::empty(),
+          &[cleared],
+          &[],
+          &[]
+        );
+
+        painted = painter.paint(cmd, self.framebuffer, extent, output.pixels_per_point, &primitives, 0);
+
+        let painted = vk::MemoryBarrier::default()
+          .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+          .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+        device.cmd_pipeline_barrier(
+          cmd,
+          vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+          vk::PipelineStageFlags::TRANSFER,
+          vk::DependencyFlags::empty(),
+          &[painted],
+          &[],
+          &[]
+        );
+        let region = vk::BufferImageCopy::default()
+          .image_subresource(vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1
+          })
+          .image_extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1
+          });
+        device.cmd_copy_image_to_buffer(
+          cmd,
+          image,
+          vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+          self.readback,
+          &[region]
+        );
+        let copied = vk::MemoryBarrier::default()
+          .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+          .dst_access_mask(vk::AccessFlags::HOST_READ);
+        device.cmd_pipeline_barrier(
+          cmd,
+          vk::PipelineStageFlags::TRANSFER,
+          vk::PipelineStageFlags::HOST,
+          vk::DependencyFlags::empty(),
+          &[copied],
+          &[],
+          &[]
+        );
+      })?;
+      painted?;
+
+      painter.free_textures(&output.textures_delta);
+
+      let size = extent.width as usize * extent.height as usize * 4;
+      let mapped = device.map_memory(
+        self.readback_memory,
+        0,
+        size as vk::DeviceSize,
+        vk::MemoryMapFlags::empty()
+      )?;
+      // egui paints premultiplied colors in gamma space, the same layout a canvas keeps
+      let pixels = std::slice::from_raw_parts(mapped as *const u8, size)
+        .chunks_exact(4)
+        .map(|p| Color32::from_rgba_premultiplied(p[0], p[1], p[2], p[3]))
+        .collect();
+      device.unmap_memory(self.readback_memory);
+      Canvas::from_pixels(extent.width, extent.height, pixels).ok_or(vk::Result::ERROR_UNKNOWN)
+    }
+  }
+
+  pub fn device_name(&self) -> String {
+    unsafe {
+      let properties = self.instance.get_physical_device_properties(self.physical_device);
+      CStr::from_ptr(properties.device_name.as_ptr())
+        .to_string_lossy()
+        .into_owned()
+    }
+  }
+}
+
+impl Drop for HeadlessRenderer {
+  fn drop(&mut self) {
+    unsafe {
+      let _ = self.device.device_wait_idle();
+      if let Some(mut painter) = self.painter.take() {
+        painter.destroy();
+      }
+      self.device.destroy_framebuffer(self.framebuffer, None);
+      self.device.destroy_image_view(self.image_view, None);
+      self.device.destroy_image(self.image, None);
+      self.device.free_memory(self.image_memory, None);
+      self.device.destroy_buffer(self.readback, None);
+      self.device.free_memory(self.readback_memory, None);
+      self.device.destroy_command_pool(self.command_pool, None);
+      self.device.destroy_device(None);
+      self.instance.destroy_instance(None);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+
+  use egui::{ColorImage, LayerId, Pos2, Rect, TextureOptions, pos2};
+
+  use super::*;
+  use crate::{
+    egui_backend::software::SoftwarePainter,
+    plugins::show_test_plugins,
+    ui::{OverlayUi, screen_input}
+  };
+
+  /// A renderer on the first Vulkan device, `None` without one unless `ANDROMEDA_REQUIRE_VULKAN` is set, as it is on
+  /// CI where lavapipe is installed
+  fn renderer(width: u32, height: u32) -> Option<HeadlessRenderer> {
+    match HeadlessRenderer::new(width, height) {
+      Ok(renderer) => {
+        println!("Rendering on {}", renderer.device_name());
+        Some(renderer)
+      }
+      Err(e) if env::var_os("ANDROMEDA_REQUIRE_VULKAN").is_none() => {
+        println!("Skipping, no Vulkan device: {e}");
+        None
+      }
+      Err(e) => panic!("{e}")
+    }
+  }
+
+  fn frame(ctx: &egui::Context, size: [u32; 2], paint: impl FnMut(&egui::Context)) -> egui::FullOutput {
+    ctx.run(screen_input(size[0], size[1], 1.0), paint)
+  }
+
+  #[test]
+  fn paints_shapes_where_egui_put_them() {
+    let Some(mut renderer) = renderer(64, 32) else {
+      return;
+    };
+    let ctx = egui::Context::default();
+    let output = frame(&ctx, [64, 32], |ctx| {
+      let painter = ctx.layer_painter(LayerId::background());
+      painter.rect_filled(Rect::from_min_max(pos2(8.0, 8.0), pos2(24.0, 24.0)), 0.0, Color32::RED);
+      painter.rect_filled(
+        Rect::from_min_max(pos2(40.0, 8.0), pos2(56.0, 24.0)),
+        0.0,
+        Color32::from_white_alpha(128)
+      );
+    });
+    let canvas = renderer.render(&ctx, output).unwrap();
+
+    assert_eq!((canvas.width(), canvas.height()), (64, 32));
+    assert_eq!(canvas.pixel(16, 16), Color32::RED);
+    assert_eq!(canvas.pixel(48, 16), Color32::from_white_alpha(128));
+    assert_eq!(canvas.pixel(2, 2), Color32::TRANSPARENT);
+    assert_eq!(canvas.pixel(32, 16), Color32::TRANSPARENT);
+  }
+
+  #[test]
+  fn partial_texture_updates_reach_the_image() {
+    let Some(mut renderer) = renderer(32, 32) else {
+      return;
+    };
+    let ctx = egui::Context::default();
+    let mut texture = ctx.load_texture(
+      "checker",
+      ColorImage::new([4, 4], vec![Color32::BLUE; 16]),
+      TextureOptions::NEAREST
+    );
+    let draw = |texture: &egui::TextureHandle| {
+      let id = texture.id();
+      move |ctx: &egui::Context| {
+        ctx.layer_painter(LayerId::background()).image(
+          id,
+          Rect::from_min_max(Pos2::ZERO, pos2(32.0, 32.0)),
+          Rect::from_min_max(Pos2::ZERO, pos2(1.0, 1.0)),
+          Color32::WHITE
+        );
+      }
+    };
+    let output = frame(&ctx, [32, 32], draw(&texture));
+    let canvas = renderer.render(&ctx, output).unwrap();
+    assert_eq!(canvas.pixel(4, 4), Color32::BLUE);
+    assert_eq!(canvas.pixel(28, 28), Color32::BLUE);
+
+    texture.set_partial(
+      [2, 2],
+      ColorImage::new([2, 2], vec![Color32::GREEN; 4]),
+      TextureOptions::NEAREST
+    );
+    let output = frame(&ctx, [32, 32], draw(&texture));
+    let canvas = renderer.render(&ctx, output).unwrap();
+    assert_eq!(canvas.pixel(4, 4), Color32::BLUE);
+    assert_eq!(canvas.pixel(20, 20), Color32::GREEN);
+    assert_eq!(canvas.pixel(28, 28), Color32::GREEN);
+  }
+
+  #[test]
+  fn matches_the_software_painter() {
+    const SIZE: [u32; 2] = [1400, 850];
+    let Some(mut renderer) = renderer(SIZE[0], SIZE[1]) else {
+      return;
+    };
+    show_test_plugins();
+    let mut ui = OverlayUi::new();
+    let mut software = SoftwarePainter::default();
+    let mut expected = Canvas::new(SIZE[0], SIZE[1], Color32::TRANSPARENT);
+    let mut rendered = None;
+
+    const FRAMES: usize = 10;
+    for frame in 0..FRAMES {
+      let mut raw_input = screen_input(SIZE[0], SIZE[1], 1.0);
+      raw_input.time = Some(frame as f64 / 60.0);
+      let output = ui.run(raw_input);
+
+      software.update_textures(&output.textures_delta);
+      if frame + 1 == FRAMES {
+        let primitives = ui.context().tessellate(output.shapes.clone(), output.pixels_per_point);
+        software.paint(&mut expected, output.pixels_per_point, &primitives);
+      }
+      software.free_textures(&output.textures_delta);
+
+      rendered = Some(renderer.render(ui.context(), output).unwrap());
+    }
+
+    // Rasterizers may round coverage of edge pixels differently, the bulk of the image has to agree
+    let differences = rendered.unwrap().count_differences(&expected, 8).unwrap();
+    let pixels = (SIZE[0] * SIZE[1]) as usize;
+    assert!(
+      differences * 200 < pixels,
+      "{differences} of {pixels} pixels differ from the software painter"
+    );
+  }
+}
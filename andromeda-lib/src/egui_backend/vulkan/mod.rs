@@ -0,0 +1,4 @@
+pub mod headless;
+#[cfg(windows)]
+pub(crate) mod overlay;
+pub mod painter;
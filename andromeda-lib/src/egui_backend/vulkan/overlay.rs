@@ -0,0 +1,222 @@
+use ash::{prelude::VkResult, vk};
+
+use crate::egui_backend::vulkan::painter::{PainterTarget, VulkanPainter};
+
+/// Resources tied to one swapchain image
+struct ImageFrame {
+  view: vk::ImageView,
+  framebuffer: vk::Framebuffer,
+  command_buffer: vk::CommandBuffer,
+  /// Signaled when the overlay submission for this image has finished
+  fence: vk::Fence,
+  /// Signaled by the overlay submission, presentation waits on it instead of the game's semaphores
+  render_finished: vk::Semaphore
+}
+
+/// Draws egui on top of the images of a game's swapchain.
+///
+/// Everything is created for one swapchain and destroyed with it, a recreated swapchain gets a new overlay.
+pub struct VulkanSwapchainOverlay {
+  device: ash::Device,
+  painter: VulkanPainter,
+  command_pool: vk::CommandPool,
+  extent: vk::Extent2D,
+  frames: Vec<ImageFrame>
+}
+
+impl VulkanSwapchainOverlay {
+  pub fn new(
+    device: ash::Device,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    queue_family_index: u32,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    images: &[vk::Image]
+  ) -> VkResult<Self> {
+    let painter = VulkanPainter::new(
+      device.clone(),
+      memory_properties,
+      PainterTarget::swapchain(format),
+      images.len()
+    )?;
+    let command_pool = unsafe {
+      device.create_command_pool(
+        &vk::CommandPoolCreateInfo::default()
+          .queue_family_index(queue_family_index)
+          .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER),
+        None
+      )
+    };
+    let mut overlay = Self {
+      device,
+      painter,
+      command_pool: vk::CommandPool::null(),
+      extent,
+      frames: Vec::with_capacity(images.len())
+    };
+    // From here on `Drop` cleans up whatever was created before a failure
+    overlay.command_pool = command_pool?;
+
+    for &image in images {
+      let frame = unsafe { overlay.create_frame(image, format)? };
+      overlay.frames.push(frame);
+    }
+    Ok(overlay)
+  }
+
+  unsafe fn create_frame(&self, image: vk::Image, format: vk::Format) -> VkResult<ImageFrame> {
+    unsafe {
+      let view = self.device.create_image_view(
+        &vk::ImageViewCreateInfo::default()
+          .image(image)
+          .view_type(vk::ImageViewType::TYPE_2D)
+          .format(format)
+          .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1
+          }),
+        None
+      )?;
+      let mut frame = ImageFrame {
+        view,
+        framebuffer: vk::Framebuffer::null(),
+        command_buffer: vk::CommandBuffer::null(),
+        fence: vk::Fence::null(),
+        render_finished: vk::Semaphore::null()
+      };
+
+      let created = (|| {
+        frame.framebuffer = self.painter.create_framebuffer(view, self.extent)?;
+        frame.command_buffer = self.device.allocate_command_buffers(
+          &vk::CommandBufferAllocateInfo::default()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1)
+        )?[0];
+        frame.fence = self.device.create_fence(
+          &vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED),
+          None
+        )?;
+        frame.render_finished = self
+          .device
+          .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?;
+        Ok(())
+      })();
+
+      match created {
+        Ok(()) => Ok(frame),
+        Err(e) => {
+          self.destroy_frame(&frame);
+          Err(e)
+        }
+      }
+    }
+  }
+
+  unsafe fn destroy_frame(&self, frame: &ImageFrame) {
+    unsafe {
+      self.device.destroy_semaphore(frame.render_finished, None);
+      self.device.destroy_fence(frame.fence, None);
+      self.device.destroy_framebuffer(frame.framebuffer, None);
+      self.device.destroy_image_view(frame.view, None);
+    }
+  }
+
+  pub fn extent(&self) -> vk::Extent2D {
+    self.extent
+  }
+
+  /// Paints `output` onto swapchain image `image_index` after `wait_semaphores` and returns the semaphore the
+  /// present has to wait on instead.
+  pub fn render(
+    &mut self,
+    queue: vk::Queue,
+    image_index: u32,
+    wait_semaphores: &[vk::Semaphore],
+    egui_ctx: &egui::Context,
+    output: egui::FullOutput
+  ) -> VkResult<vk::Semaphore> {
+    let Some(frame) = self.frames.get(image_index as usize) else {
+      return Err(vk::Result::ERROR_OUT_OF_DATE_KHR);
+    };
+    let (command_buffer, fence, framebuffer, render_finished) = (
+      frame.command_buffer,
+      frame.fence,
+      frame.framebuffer,
+      frame.render_finished
+    );
+
+    let primitives = egui_ctx.tessellate(output.shapes, output.pixels_per_point);
+    self
+      .painter
+      .update_textures(queue, self.command_pool, &output.textures_delta)?;
+
+    unsafe {
+      // The previous overlay pass on this image has to be done before its command buffer and vertices are reused
+      self.device.wait_for_fences(&[fence], true, u64::MAX)?;
+
+      self
+        .device
+        .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+      self.device.begin_command_buffer(
+        command_buffer,
+        &vk::CommandBufferBeginInfo::default().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+      )?;
+      self.painter.paint(
+        command_buffer,
+        framebuffer,
+        self.extent,
+        output.pixels_per_point,
+        &primitives,
+        image_index as usize
+      )?;
+      self.device.end_command_buffer(command_buffer)?;
+
+      let wait_stages = vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT; wait_semaphores.len()];
+      let command_buffers = [command_buffer];
+      let signal_semaphores = [render_finished];
+      let submit = vk::SubmitInfo::default()
+        .wait_semaphores(wait_semaphores)
+        .wait_dst_stage_mask(&wait_stages)
+        .command_buffers(&command_buffers)
+        .signal_semaphores(&signal_semaphores);
+
+      self.device.reset_fences(&[fence])?;
+      self.device.queue_submit(queue, &[submit], fence)?;
+
+      // Freed textures may still be referenced by passes in flight, egui rarely frees so waiting is fine
+      if !output.textures_delta.free.is_empty() {
+        self.wait_idle();
+        self.painter.free_textures(&output.textures_delta);
+      }
+    }
+
+    Ok(render_finished)
+  }
+
+  /// Waits until no overlay pass is running on the GPU anymore
+  fn wait_idle(&self) {
+    let fences: Vec<vk::Fence> = self.frames.iter().map(|frame| frame.fence).collect();
+    if !fences.is_empty() {
+      unsafe {
+        let _ = self.device.wait_for_fences(&fences, true, u64::MAX);
+      }
+    }
+  }
+}
+
+impl Drop for VulkanSwapchainOverlay {
+  fn drop(&mut self) {
+    self.wait_idle();
+    unsafe {
+      for frame in &self.frames {
+        self.destroy_frame(frame);
+      }
+      self.painter.destroy();
+      self.device.destroy_command_pool(self.command_pool, None);
+    }
+  }
+}
@@ -0,0 +1,852 @@
+use std::{collections::HashMap, ffi::CStr, mem};
+
+use ash::{prelude::VkResult, vk};
+use egui::{ClippedPrimitive, ImageData, TextureId, TexturesDelta, epaint::Primitive};
+
+const VERTEX_SHADER: &[u8] = include_bytes!("shaders/egui.vert.spv");
+const FRAGMENT_SHADER: &[u8] = include_bytes!("shaders/egui.frag.spv");
+const ENTRY_POINT: &CStr = c"main";
+
+/// Push constants shared by both shader stages, see `shaders/egui.vert`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PushConstants {
+  screen_size: [f32; 2],
+  output_srgb: u32
+}
+
+struct VulkanTexture {
+  image: vk::Image,
+  memory: vk::DeviceMemory,
+  view: vk::ImageView,
+  descriptor_set: vk::DescriptorSet,
+  size: [usize; 2]
+}
+
+/// A host visible buffer that grows to fit the largest frame seen so far
+#[derive(Default)]
+struct HostBuffer {
+  buffer: vk::Buffer,
+  memory: vk::DeviceMemory,
+  size: vk::DeviceSize
+}
+
+/// Vertex and index storage for one frame in flight
+#[derive(Default)]
+struct FrameBuffers {
+  vertices: HostBuffer,
+  indices: HostBuffer
+}
+
+/// Where the painter draws to and what layout the image is in before and after the overlay pass
+#[derive(Clone, Copy, Debug)]
+pub struct PainterTarget {
+  pub format: vk::Format,
+  pub initial_layout: vk::ImageLayout,
+  pub final_layout: vk::ImageLayout
+}
+
+impl PainterTarget {
+  /// Swapchain images arrive in `PRESENT_SRC_KHR` and have to leave in it
+  pub fn swapchain(format: vk::Format) -> Self {
+    Self {
+      format,
+      initial_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+      final_layout: vk::ImageLayout::PRESENT_SRC_KHR
+    }
+  }
+}
+
+/// Renders egui output into existing Vulkan images.
+///
+/// Knows nothing about swapchains or hooks, so it works the same in a game, on a headless software device, or on
+/// lavapipe. Command buffers are recorded by the caller, the painter only appends the overlay pass.
+pub struct VulkanPainter {
+  device: ash::Device,
+  memory_properties: vk::PhysicalDeviceMemoryProperties,
+  target: PainterTarget,
+  render_pass: vk::RenderPass,
+  descriptor_set_layout: vk::DescriptorSetLayout,
+  descriptor_pool: vk::DescriptorPool,
+  pipeline_layout: vk::PipelineLayout,
+  pipeline: vk::Pipeline,
+  linear_sampler: vk::Sampler,
+  nearest_sampler: vk::Sampler,
+  textures: HashMap<TextureId, VulkanTexture>,
+  frames: Vec<FrameBuffers>
+}
+
+impl VulkanPainter {
+  const MAX_TEXTURES: u32 = 1024;
+
+  pub fn new(
+    device: ash::Device,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    target: PainterTarget,
+    frames_in_flight: usize
+  ) -> VkResult<Self> {
+    unsafe {
+      let render_pass = Self::create_render_pass(&device, target)?;
+
+      let bindings = [
+        vk::DescriptorSetLayoutBinding::default()
+          .binding(0)
+          .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+          .descriptor_count(1)
+          .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+        vk::DescriptorSetLayoutBinding::default()
+          .binding(1)
+          .descriptor_type(vk::DescriptorType::SAMPLER)
+          .descriptor_count(1)
+          .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+      ];
+      let descriptor_set_layout =
+        device.create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings), None)?;
+
+      let pool_sizes = [
+        vk::DescriptorPoolSize {
+          ty: vk::DescriptorType::SAMPLED_IMAGE,
+          descriptor_count: Self::MAX_TEXTURES
+        },
+        vk::DescriptorPoolSize {
+          ty: vk::DescriptorType::SAMPLER,
+          descriptor_count: Self::MAX_TEXTURES
+        }
+      ];
+      let descriptor_pool = device.create_descriptor_pool(
+        &vk::DescriptorPoolCreateInfo::default()
+          .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+          .max_sets(Self::MAX_TEXTURES)
+          .pool_sizes(&pool_sizes),
+        None
+      )?;
+
+      let push_constant_ranges = [vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: mem::size_of::<PushConstants>() as u32
+      }];
+      let set_layouts = [descriptor_set_layout];
+      let pipeline_layout = device.create_pipeline_layout(
+        &vk::PipelineLayoutCreateInfo::default()
+          .set_layouts(&set_layouts)
+          .push_constant_ranges(&push_constant_ranges),
+        None
+      )?;
+
+      let pipeline = Self::create_pipeline(&device, render_pass, pipeline_layout)?;
+
+      let sampler_info = |filter: vk::Filter| {
+        vk::SamplerCreateInfo::default()
+          .mag_filter(filter)
+          .min_filter(filter)
+          .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+          .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+          .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+          .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+      };
+      let linear_sampler = device.create_sampler(&sampler_info(vk::Filter::LINEAR), None)?;
+      let nearest_sampler = device.create_sampler(&sampler_info(vk::Filter::NEAREST), None)?;
+
+      Ok(Self {
+        device,
+        memory_properties,
+        target,
+        render_pass,
+        descriptor_set_layout,
+        descriptor_pool,
+        pipeline_layout,
+        pipeline,
+        linear_sampler,
+        nearest_sampler,
+        textures: HashMap::new(),
+        frames: (0..frames_in_flight.max(1)).map(|_| FrameBuffers::default()).collect()
+      })
+    }
+  }
+
+  /// The render pass framebuffers handed to [`Self::paint`] have to be compatible with
+  pub fn render_pass(&self) -> vk::RenderPass {
+    self.render_pass
+  }
+
+  pub fn target(&self) -> PainterTarget {
+    self.target
+  }
+
+  pub fn create_framebuffer(&self, view: vk::ImageView, extent: vk::Extent2D) -> VkResult<vk::Framebuffer> {
+    let attachments = [view];
+    unsafe {
+      self.device.create_framebuffer(
+        &vk::FramebufferCreateInfo::default()
+          .render_pass(self.render_pass)
+          .attachments(&attachments)
+          .width(extent.width)
+          .height(extent.height)
+          .layers(1),
+        None
+      )
+    }
+  }
+
+  unsafe fn create_render_pass(device: &ash::Device, target: PainterTarget) -> VkResult<vk::RenderPass> {
+    // Draw on top of whatever the game rendered
+    let attachments = [vk::AttachmentDescription::default()
+      .format(target.format)
+      .samples(vk::SampleCountFlags::TYPE_1)
+      .load_op(vk::AttachmentLoadOp::LOAD)
+      .store_op(vk::AttachmentStoreOp::STORE)
+      .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+      .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+      .initial_layout(target.initial_layout)
+      .final_layout(target.final_layout)];
+    let color_refs = [vk::AttachmentReference {
+      attachment: 0,
+      layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+    }];
+    let subpasses = [vk::SubpassDescription::default()
+      .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+      .color_attachments(&color_refs)];
+    let dependencies = [vk::SubpassDependency {
+      src_subpass: vk::SUBPASS_EXTERNAL,
+      dst_subpass: 0,
+      src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+      dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+      src_access_mask: vk::AccessFlags::empty(),
+      dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+      dependency_flags: vk::DependencyFlags::empty()
+    }];
+
+    unsafe {
+      device.create_render_pass(
+        &vk::RenderPassCreateInfo::default()
+          .attachments(&attachments)
+          .subpasses(&subpasses)
+          .dependencies(&dependencies),
+        None
+      )
+    }
+  }
+
+  unsafe fn create_shader_module(device: &ash::Device, spirv: &[u8]) -> VkResult<vk::ShaderModule> {
+    let code =
+      ash::util::read_spv(&mut std::io::Cursor::new(spirv)).map_err(|_| vk::Result::ERROR_INVALID_SHADER_NV)?;
+    unsafe { device.create_shader_module(&vk::ShaderModuleCreateInfo::default().code(&code), None) }
+  }
+
+  unsafe fn create_pipeline(
+    device: &ash::Device,
+    render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout
+  ) -> VkResult<vk::Pipeline> {
+    unsafe {
+      let vertex_module = Self::create_shader_module(device, VERTEX_SHADER)?;
+      let fragment_module = Self::create_shader_module(device, FRAGMENT_SHADER)?;
+
+      let stages = [
+        vk::PipelineShaderStageCreateInfo::default()
+          .stage(vk::ShaderStageFlags::VERTEX)
+          .module(vertex_module)
+          .name(ENTRY_POINT),
+        vk::PipelineShaderStageCreateInfo::default()
+          .stage(vk::ShaderStageFlags::FRAGMENT)
+          .module(fragment_module)
+          .name(ENTRY_POINT)
+      ];
+
+      // Matches `egui::epaint::Vertex`: pos, uv, then sRGBA8 color
+      let vertex_bindings = [vk::VertexInputBindingDescription {
+        binding: 0,
+        stride: mem::size_of::<egui::epaint::Vertex>() as u32,
+        input_rate: vk::VertexInputRate::VERTEX
+      }];
+      let vertex_attributes = [
+        vk::VertexInputAttributeDescription {
+          location: 0,
+          binding: 0,
+          format: vk::Format::R32G32_SFLOAT,
+          offset: 0
+        },
+        vk::VertexInputAttributeDescription {
+          location: 1,
+          binding: 0,
+          format: vk::Format::R32G32_SFLOAT,
+          offset: 8
+        },
+        vk::VertexInputAttributeDescription {
+          location: 2,
+          binding: 0,
+          format: vk::Format::R8G8B8A8_UNORM,
+          offset: 16
+        }
+      ];
+      let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
+        .vertex_binding_descriptions(&vertex_bindings)
+        .vertex_attribute_descriptions(&vertex_attributes);
+      let input_assembly =
+        vk::PipelineInputAssemblyStateCreateInfo::default().topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+      let viewport = vk::PipelineViewportStateCreateInfo::default()
+        .viewport_count(1)
+        .scissor_count(1);
+      let rasterization = vk::PipelineRasterizationStateCreateInfo::default()
+        .polygon_mode(vk::PolygonMode::FILL)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .line_width(1.0);
+      let multisample =
+        vk::PipelineMultisampleStateCreateInfo::default().rasterization_samples(vk::SampleCountFlags::TYPE_1);
+      // egui outputs premultiplied alpha
+      let blend_attachments = [vk::PipelineColorBlendAttachmentState::default()
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::ONE)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_DST_ALPHA)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+        .alpha_blend_op(vk::BlendOp::ADD)
+        .color_write_mask(vk::ColorComponentFlags::RGBA)];
+      let color_blend = vk::PipelineColorBlendStateCreateInfo::default().attachments(&blend_attachments);
+      let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default();
+      let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+      let dynamic_state = vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+      let create_info = vk::GraphicsPipelineCreateInfo::default()
+        .stages(&stages)
+        .vertex_input_state(&vertex_input)
+        .input_assembly_state(&input_assembly)
+        .viewport_state(&viewport)
+        .rasterization_state(&rasterization)
+        .multisample_state(&multisample)
+        .depth_stencil_state(&depth_stencil)
+        .color_blend_state(&color_blend)
+        .dynamic_state(&dynamic_state)
+        .layout(pipeline_layout)
+        .render_pass(render_pass)
+        .subpass(0);
+
+      let pipelines = device.create_graphics_pipelines(vk::PipelineCache::null(), &[create_info], None);
+
+      device.destroy_shader_module(vertex_module, None);
+      device.destroy_shader_module(fragment_module, None);
+
+      pipelines.map(|p| p[0]).map_err(|(_, e)| e)
+    }
+  }
+
+  fn find_memory_type(&self, type_bits: u32, flags: vk::MemoryPropertyFlags) -> VkResult<u32> {
+    (0..self.memory_properties.memory_type_count)
+      .find(|&i| {
+        type_bits & (1 << i) != 0 &&
+          self.memory_properties.memory_types[i as usize]
+            .property_flags
+            .contains(flags)
+      })
+      .ok_or(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)
+  }
+
+  unsafe fn create_host_buffer(&self, size: vk::DeviceSize, usage: vk::BufferUsageFlags) -> VkResult<HostBuffer> {
+    unsafe {
+      let buffer = self.device.create_buffer(
+        &vk::BufferCreateInfo::default()
+          .size(size)
+          .usage(usage)
+          .sharing_mode(vk::SharingMode::EXCLUSIVE),
+        None
+      )?;
+      let requirements = self.device.get_buffer_memory_requirements(buffer);
+      let memory_type = self.find_memory_type(
+        requirements.memory_type_bits,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+      )?;
+      let memory = self.device.allocate_memory(
+        &vk::MemoryAllocateInfo::default()
+          .allocation_size(requirements.size)
+          .memory_type_index(memory_type),
+        None
+      )?;
+      self.device.bind_buffer_memory(buffer, memory, 0)?;
+      Ok(HostBuffer { buffer, memory, size })
+    }
+  }
+
+  unsafe fn destroy_host_buffer(&self, buffer: &mut HostBuffer) {
+    unsafe {
+      if buffer.buffer != vk::Buffer::null() {
+        self.device.destroy_buffer(buffer.buffer, None);
+        self.device.free_memory(buffer.memory, None);
+      }
+    }
+    *buffer = HostBuffer::default();
+  }
+
+  /// Grows the buffer if needed and copies `data` to its start
+  unsafe fn upload<T: Copy>(&self, buffer: &mut HostBuffer, data: &[T], usage: vk::BufferUsageFlags) -> VkResult<()> {
+    let size = mem::size_of_val(data) as vk::DeviceSize;
+    if size == 0 {
+      return Ok(());
+    }
+
+    unsafe {
+      if buffer.size < size {
+        self.destroy_host_buffer(buffer);
+        *buffer = self.create_host_buffer(size.next_power_of_two(), usage)?;
+      }
+
+      let mapped = self
+        .device
+        .map_memory(buffer.memory, 0, size, vk::MemoryMapFlags::empty())?;
+      std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, mapped as *mut u8, size as usize);
+      self.device.unmap_memory(buffer.memory);
+    }
+    Ok(())
+  }
+
+  /// Applies `delta.set`. Uploads are submitted to `queue` right away and waited on, egui only sends textures when
+  /// fonts or images change so this stays off the hot path.
+  pub fn update_textures(
+    &mut self,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    delta: &TexturesDelta
+  ) -> VkResult<()> {
+    for (id, image_delta) in &delta.set {
+      let ImageData::Color(image) = &image_delta.image;
+      let filter = match image_delta.options.magnification {
+        egui::TextureFilter::Nearest => self.nearest_sampler,
+        egui::TextureFilter::Linear => self.linear_sampler
+      };
+
+      unsafe {
+        match image_delta.pos {
+          Some(pos) => {
+            // Copies outside of the image are undefined behaviour, not just ignored
+            if let Some(texture) = self.textures.get(id) &&
+              pos[0] + image.size[0] <= texture.size[0] &&
+              pos[1] + image.size[1] <= texture.size[1]
+            {
+              self.upload_region(queue, command_pool, texture.image, pos, image, false)?;
+            }
+          }
+          None => {
+            if let Some(old) = self.textures.remove(id) {
+              self.destroy_texture(old);
+            }
+            let texture = self.create_texture(image.size, filter)?;
+            self.upload_region(queue, command_pool, texture.image, [0, 0], image, true)?;
+            self.textures.insert(*id, texture);
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Applies `delta.free`, call after the frame that used them has finished on the GPU
+  pub fn free_textures(&mut self, delta: &TexturesDelta) {
+    for id in &delta.free {
+      if let Some(texture) = self.textures.remove(id) {
+        unsafe { self.destroy_texture(texture) };
+      }
+    }
+  }
+
+  unsafe fn create_texture(&self, size: [usize; 2], sampler: vk::Sampler) -> VkResult<VulkanTexture> {
+    unsafe {
+      let image = self.device.create_image(
+        &vk::ImageCreateInfo::default()
+          .image_type(vk::ImageType::TYPE_2D)
+          .format(vk::Format::R8G8B8A8_UNORM)
+          .extent(vk::Extent3D {
+            width: size[0] as u32,
+            height: size[1] as u32,
+            depth: 1
+          })
+          .mip_levels(1)
+          .array_layers(1)
+          .samples(vk::SampleCountFlags::TYPE_1)
+          .tiling(vk::ImageTiling::OPTIMAL)
+          .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+          .sharing_mode(vk::SharingMode::EXCLUSIVE)
+          .initial_layout(vk::ImageLayout::UNDEFINED),
+        None
+      )?;
+      let requirements = self.device.get_image_memory_requirements(image);
+      let memory_type = self.find_memory_type(requirements.memory_type_bits, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+      let memory = self.device.allocate_memory(
+        &vk::MemoryAllocateInfo::default()
+          .allocation_size(requirements.size)
+          .memory_type_index(memory_type),
+        None
+      )?;
+      self.device.bind_image_memory(image, memory, 0)?;
+
+      let view = self.device.create_image_view(
+        &vk::ImageViewCreateInfo::default()
+          .image(image)
+          .view_type(vk::ImageViewType::TYPE_2D)
+          .format(vk::Format::R8G8B8A8_UNORM)
+          .subresource_range(Self::color_subresource_range()),
+        None
+      )?;
+
+      let set_layouts = [self.descriptor_set_layout];
+      let descriptor_set = self.device.allocate_descriptor_sets(
+        &vk::DescriptorSetAllocateInfo::default()
+          .descriptor_pool(self.descriptor_pool)
+          .set_layouts(&set_layouts)
+      )?[0];
+      let image_info = [vk::DescriptorImageInfo {
+        sampler: vk::Sampler::null(),
+        image_view: view,
+        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+      }];
+      let sampler_info = [vk::DescriptorImageInfo {
+        sampler,
+        image_view: vk::ImageView::null(),
+        image_layout: vk::ImageLayout::UNDEFINED
+      }];
+      let writes = [
+        vk::WriteDescriptorSet::default()
+          .dst_set(descriptor_set)
+          .dst_binding(0)
+          .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+          .image_info(&image_info),
+        vk::WriteDescriptorSet::default()
+          .dst_set(descriptor_set)
+          .dst_binding(1)
+          .descriptor_type(vk::DescriptorType::SAMPLER)
+          .image_info(&sampler_info)
+      ];
+      self.device.update_descriptor_sets(&writes, &[]);
+
+      Ok(VulkanTexture {
+        image,
+        memory,
+        view,
+        descriptor_set,
+        size
+      })
+    }
+  }
+
+  unsafe fn destroy_texture(&self, texture: VulkanTexture) {
+    unsafe {
+      let _ = self
+        .device
+        .free_descriptor_sets(self.descriptor_pool, &[texture.descriptor_set]);
+      self.device.destroy_image_view(texture.view, None);
+      self.device.destroy_image(texture.image, None);
+      self.device.free_memory(texture.memory, None);
+    }
+  }
+
+  fn color_subresource_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+      aspect_mask: vk::ImageAspectFlags::COLOR,
+      base_mip_level: 0,
+      level_count: 1,
+      base_array_layer: 0,
+      layer_count: 1
+    }
+  }
+
+  unsafe fn upload_region(
+    &self,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    image: vk::Image,
+    pos: [usize; 2],
+    image_data: &egui::ColorImage,
+    fresh: bool
+  ) -> VkResult<()> {
+    let size = image_data.size;
+    let pixels: Vec<u8> = image_data.pixels.iter().flat_map(|p| p.to_array()).collect();
+    unsafe {
+      let mut staging = HostBuffer::default();
+      self.upload(&mut staging, &pixels, vk::BufferUsageFlags::TRANSFER_SRC)?;
+
+      let result = one_time_submit(&self.device, queue, command_pool, |cmd| {
+        let to_transfer = vk::ImageMemoryBarrier::default()
+          .src_access_mask(vk::AccessFlags::SHADER_READ)
+          .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+          .old_layout(if fresh {
+            vk::ImageLayout::UNDEFINED
+          } else {
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+          })
+          .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+          .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+          .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+          .image(image)
+          .subresource_range(Self::color_subresource_range());
+        self.device.cmd_pipeline_barrier(
+          cmd,
+          vk::PipelineStageFlags::FRAGMENT_SHADER,
+          vk::PipelineStageFlags::TRANSFER,
+          vk::DependencyFlags::empty(),
+          &[],
+          &[],
+          &[to_transfer]
+        );
+
+        let region = vk::BufferImageCopy::default()
+          .image_subresource(vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1
+          })
+          .image_offset(vk::Offset3D {
+            x: pos[0] as i32,
+            y: pos[1] as i32,
+            z: 0
+          })
+          .image_extent(vk::Extent3D {
+            width: size[0] as u32,
+            height: size[1] as u32,
+            depth: 1
+          });
+        self.device.cmd_copy_buffer_to_image(
+          cmd,
+          staging.buffer,
+          image,
+          vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+          &[region]
+        );
+
+        let to_shader = vk::ImageMemoryBarrier::default()
+          .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+          .dst_access_mask(vk::AccessFlags::SHADER_READ)
+          .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+          .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+          .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+          .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+          .image(image)
+          .subresource_range(Self::color_subresource_range());
+        self.device.cmd_pipeline_barrier(
+          cmd,
+          vk::PipelineStageFlags::TRANSFER,
+          vk::PipelineStageFlags::FRAGMENT_SHADER,
+          vk::DependencyFlags::empty(),
+          &[],
+          &[],
+          &[to_shader]
+        );
+      });
+
+      self.destroy_host_buffer(&mut staging);
+      result
+    }
+  }
+
+  /// Records the overlay pass into `cmd`. `frame_index` selects the vertex/index buffers, it must not be reused
+  /// before the GPU is done with the previous submission that used it.
+  pub fn paint(
+    &mut self,
+    cmd: vk::CommandBuffer,
+    framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D,
+    pixels_per_point: f32,
+    primitives: &[ClippedPrimitive],
+    frame_index: usize
+  ) -> VkResult<()> {
+    let meshes: Vec<(&egui::Rect, &egui::Mesh)> = primitives
+      .iter()
+      .filter_map(|p| match &p.primitive {
+        Primitive::Mesh(mesh) if !mesh.indices.is_empty() => Some((&p.clip_rect, mesh)),
+        _ => None
+      })
+      .collect();
+
+    let vertices: Vec<egui::epaint::Vertex> = meshes.iter().flat_map(|(_, m)| m.vertices.iter().copied()).collect();
+    let indices: Vec<u32> = meshes.iter().flat_map(|(_, m)| m.indices.iter().copied()).collect();
+
+    let frame_slot = frame_index % self.frames.len();
+    let mut frame = mem::take(&mut self.frames[frame_slot]);
+    let uploaded = unsafe {
+      self
+        .upload(&mut frame.vertices, &vertices, vk::BufferUsageFlags::VERTEX_BUFFER)
+        .and_then(|_| self.upload(&mut frame.indices, &indices, vk::BufferUsageFlags::INDEX_BUFFER))
+    };
+    let (vertex_buffer, index_buffer) = (frame.vertices.buffer, frame.indices.buffer);
+    self.frames[frame_slot] = frame;
+    uploaded?;
+
+    let push_constants = PushConstants {
+      screen_size: [
+        extent.width as f32 / pixels_per_point,
+        extent.height as f32 / pixels_per_point
+      ],
+      output_srgb: is_srgb(self.target.format) as u32
+    };
+
+    unsafe {
+      let render_area = vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent
+      };
+      self.device.cmd_begin_render_pass(
+        cmd,
+        &vk::RenderPassBeginInfo::default()
+          .render_pass(self.render_pass)
+          .framebuffer(framebuffer)
+          .render_area(render_area),
+        vk::SubpassContents::INLINE
+      );
+
+      if !meshes.is_empty() {
+        self
+          .device
+          .cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        self.device.cmd_bind_vertex_buffers(cmd, 0, &[vertex_buffer], &[0]);
+        self
+          .device
+          .cmd_bind_index_buffer(cmd, index_buffer, 0, vk::IndexType::UINT32);
+        self.device.cmd_set_viewport(
+          cmd,
+          0,
+          &[vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0
+          }]
+        );
+        let bytes = std::slice::from_raw_parts(
+          &push_constants as *const PushConstants as *const u8,
+          mem::size_of::<PushConstants>()
+        );
+        self.device.cmd_push_constants(
+          cmd,
+          self.pipeline_layout,
+          vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+          0,
+          bytes
+        );
+
+        let mut vertex_offset = 0i32;
+        let mut index_offset = 0u32;
+        for (clip_rect, mesh) in meshes {
+          let scissor = clip_to_scissor(clip_rect, pixels_per_point, extent);
+          if let Some(texture) = self.textures.get(&mesh.texture_id) &&
+            let Some(scissor) = scissor
+          {
+            self.device.cmd_set_scissor(cmd, 0, &[scissor]);
+            self.device.cmd_bind_descriptor_sets(
+              cmd,
+              vk::PipelineBindPoint::GRAPHICS,
+              self.pipeline_layout,
+              0,
+              &[texture.descriptor_set],
+              &[]
+            );
+            self
+              .device
+              .cmd_draw_indexed(cmd, mesh.indices.len() as u32, 1, index_offset, vertex_offset, 0);
+          }
+          vertex_offset += mesh.vertices.len() as i32;
+          index_offset += mesh.indices.len() as u32;
+        }
+      }
+
+      self.device.cmd_end_render_pass(cmd);
+    }
+    Ok(())
+  }
+
+  /// Destroys every resource, the device must be idle
+  pub fn destroy(&mut self) {
+    unsafe {
+      for (_, texture) in self.textures.drain().collect::<Vec<_>>() {
+        self.destroy_texture(texture);
+      }
+      for mut frame in mem::take(&mut self.frames) {
+        self.destroy_host_buffer(&mut frame.vertices);
+        self.destroy_host_buffer(&mut frame.indices);
+      }
+      self.device.destroy_sampler(self.linear_sampler, None);
+      self.device.destroy_sampler(self.nearest_sampler, None);
+      self.device.destroy_pipeline(self.pipeline, None);
+      self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+      self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+      self
+        .device
+        .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+      self.device.destroy_render_pass(self.render_pass, None);
+    }
+  }
+}
+
+/// Converts an egui clip rect in points to a scissor rect in pixels, `None` if nothing would be visible
+fn clip_to_scissor(clip_rect: &egui::Rect, pixels_per_point: f32, extent: vk::Extent2D) -> Option<vk::Rect2D> {
+  let min_x = (clip_rect.min.x * pixels_per_point)
+    .round()
+    .clamp(0.0, extent.width as f32) as u32;
+  let min_y = (clip_rect.min.y * pixels_per_point)
+    .round()
+    .clamp(0.0, extent.height as f32) as u32;
+  let max_x = (clip_rect.max.x * pixels_per_point)
+    .round()
+    .clamp(0.0, extent.width as f32) as u32;
+  let max_y = (clip_rect.max.y * pixels_per_point)
+    .round()
+    .clamp(0.0, extent.height as f32) as u32;
+
+  (max_x > min_x && max_y > min_y).then(|| vk::Rect2D {
+    offset: vk::Offset2D {
+      x: min_x as i32,
+      y: min_y as i32
+    },
+    extent: vk::Extent2D {
+      width: max_x - min_x,
+      height: max_y - min_y
+    }
+  })
+}
+
+fn is_srgb(format: vk::Format) -> bool {
+  matches!(
+    format,
+    vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32
+  )
+}
+
+/// Records `record` into a fresh command buffer, submits it and blocks until it finished
+pub(crate) unsafe fn one_time_submit(
+  device: &ash::Device,
+  queue: vk::Queue,
+  command_pool: vk::CommandPool,
+  record: impl FnOnce(vk::CommandBuffer)
+) -> VkResult<()> {
+  unsafe {
+    let cmd = device.allocate_command_buffers(
+      &vk::CommandBufferAllocateInfo::default()
+        .command_pool(command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1)
+    )?[0];
+    let fence = device.create_fence(&vk::FenceCreateInfo::default(), None)?;
+
+    let result = (|| {
+      device.begin_command_buffer(
+        cmd,
+        &vk::CommandBufferBeginInfo::default().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+      )?;
+      record(cmd);
+      device.end_command_buffer(cmd)?;
+      let command_buffers = [cmd];
+      device.queue_submit(
+        queue,
+        &[vk::SubmitInfo::default().command_buffers(&command_buffers)],
+        fence
+      )?;
+      device.wait_for_fences(&[fence], true, u64::MAX)
+    })();
+
+    device.destroy_fence(fence, None);
+    device.free_command_buffers(command_pool, &[cmd]);
+    result
+  }
+}
@@ -0,0 +1,32 @@
+#version 450
+
+// Compiled to egui.frag.spv, recompile with `glslc egui.frag -o egui.frag.spv` after editing
+
+layout(push_constant) uniform PushConstants {
+  vec2 screen_size;
+  uint output_srgb;
+} pc;
+
+layout(set = 0, binding = 0) uniform texture2D u_texture;
+layout(set = 0, binding = 1) uniform sampler u_sampler;
+
+layout(location = 0) in vec4 v_color;
+layout(location = 1) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+// egui blends in gamma space, sRGB targets expect linear values and encode them on write
+vec3 linear_from_gamma(vec3 srgb) {
+  bvec3 cutoff = lessThan(srgb, vec3(0.04045));
+  vec3 lower = srgb / vec3(12.92);
+  vec3 higher = pow((srgb + vec3(0.055)) / vec3(1.055), vec3(2.4));
+  return mix(higher, lower, vec3(cutoff));
+}
+
+void main() {
+  vec4 color = v_color * texture(sampler2D(u_texture, u_sampler), v_tex_coord);
+  if (pc.output_srgb != 0u) {
+    color = vec4(linear_from_gamma(color.rgb), color.a);
+  }
+  f_color = color;
+}
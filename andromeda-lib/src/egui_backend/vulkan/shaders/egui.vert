@@ -0,0 +1,21 @@
+#version 450
+
+// Compiled to egui.vert.spv, recompile with `glslc egui.vert -o egui.vert.spv` after editing
+
+layout(push_constant) uniform PushConstants {
+  vec2 screen_size;
+  uint output_srgb;
+} pc;
+
+layout(location = 0) in vec2 a_pos;
+layout(location = 1) in vec2 a_tex_coord;
+layout(location = 2) in vec4 a_color;
+
+layout(location = 0) out vec4 v_color;
+layout(location = 1) out vec2 v_tex_coord;
+
+void main() {
+  gl_Position = vec4(2.0 * a_pos.x / pc.screen_size.x - 1.0, 2.0 * a_pos.y / pc.screen_size.y - 1.0, 0.0, 1.0);
+  v_color = a_color;
+  v_tex_coord = a_tex_coord;
+}
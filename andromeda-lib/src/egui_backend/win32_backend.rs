@@ -1,8 +1,248 @@
-use crate::egui_backend::EGuiBackend;
+use std::{
+  collections::HashMap,
+  ffi::c_void,
+  sync::{
+    Mutex,
+    atomic::{AtomicBool, AtomicIsize, Ordering}
+  }
+};
 
+use andromeda_common::guard::HookGuard;
+use egui::{OutputCommand, PlatformOutput, RawInput};
+use log::{error, info};
+use once_cell::sync::Lazy;
+use windows::Win32::{
+  Foundation::{HANDLE, HWND, LPARAM, LRESULT, RECT, WPARAM},
+  System::{
+    DataExchange::{CloseClipboard, EmptyClipboard, GetClipboardData, OpenClipboard, SetClipboardData},
+    Memory::{GMEM_MOVEABLE, GlobalAlloc, GlobalLock, GlobalUnlock},
+    Ole::CF_UNICODETEXT
+  },
+  UI::{
+    HiDpi::GetDpiForWindow,
+    Input::{
+      Ime::{
+        GCS_COMPSTR, GCS_RESULTSTR, IME_COMPOSITION_STRING, ImmGetCompositionStringW, ImmGetContext, ImmReleaseContext
+      },
+      KeyboardAndMouse::{TME_LEAVE, TRACKMOUSEEVENT, TrackMouseEvent}
+    },
+    WindowsAndMessaging::{CallWindowProcW, GWLP_WNDPROC, GetClientRect, SetWindowLongPtrW, WNDPROC}
+  }
+};
+
+use crate::egui_backend::{
+  EGuiBackend,
+  win32_input::{WM_MOUSELEAVE, WM_MOUSEMOVE, Win32Input, should_swallow}
+};
+
+/// Original window procedures of every window we subclassed, keyed by window handle
+static ORIGINAL_WNDPROCS: Lazy<Mutex<HashMap<isize, isize>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+/// The window whose input goes to the overlay, other subclassed windows only forward
+static ACTIVE_HWND: AtomicIsize = AtomicIsize::new(0);
+static INPUT: Lazy<Mutex<Win32Input>> = Lazy::new(|| Mutex::new(Win32Input::default()));
+
+/// What the overlay wanted after its last frame, read by the window procedure
+static WANTS_POINTER: AtomicBool = AtomicBool::new(false);
+static WANTS_KEYBOARD: AtomicBool = AtomicBool::new(false);
+/// `WM_MOUSELEAVE` is only sent once per `TrackMouseEvent`
+static TRACKING_MOUSE: AtomicBool = AtomicBool::new(false);
+
+static WNDPROC_GUARD: HookGuard = HookGuard::new("overlay window procedure");
+
+pub(crate) fn wants_pointer() -> bool {
+  WANTS_POINTER.load(Ordering::Relaxed)
+}
+
+pub(crate) fn wants_keyboard() -> bool {
+  WANTS_KEYBOARD.load(Ordering::Relaxed)
+}
+
+/// Feeds the overlay from the game window's messages by replacing its window procedure
 #[derive(Default)]
 pub(crate) struct Win32Backend;
 
 impl EGuiBackend for Win32Backend {
-  fn render(&self) {}
+  fn attach(&self, hwnd: HWND) {
+    if hwnd.is_invalid() || ACTIVE_HWND.swap(hwnd.0 as isize, Ordering::AcqRel) == hwnd.0 as isize {
+      return;
+    }
+
+    unsafe {
+      let dpi = GetDpiForWindow(hwnd);
+      let mut input = Win32Input::new(if dpi > 0 { dpi as f32 / 96.0 } else { 1.0 });
+      let mut rect = RECT::default();
+      if GetClientRect(hwnd, &mut rect).is_ok() {
+        input.set_client_size((rect.right - rect.left) as u32, (rect.bottom - rect.top) as u32);
+      }
+      if let Ok(mut current) = INPUT.lock() {
+        *current = input;
+      }
+
+      let Ok(mut originals) = ORIGINAL_WNDPROCS.lock() else {
+        return;
+      };
+      // Windows stay subclassed once we saw them, restoring would break anyone who subclassed after us
+      if originals.contains_key(&(hwnd.0 as isize)) {
+        return;
+      }
+      let original = SetWindowLongPtrW(hwnd, GWLP_WNDPROC, overlay_wndproc as *const () as isize);
+      if original == 0 {
+        error!("Failed to subclass window {:?}", hwnd.0);
+        return;
+      }
+      originals.insert(hwnd.0 as isize, original);
+      info!("Routing input of window {:?} to the overlay", hwnd.0);
+    }
+  }
+
+  fn take_input(&self, target_size: [u32; 2]) -> RawInput {
+    match INPUT.lock() {
+      Ok(mut input) => input.take_raw_input(target_size),
+      Err(_) => crate::ui::screen_input(target_size[0], target_size[1], 1.0)
+    }
+  }
+
+  fn handle_output(&self, ctx: &egui::Context, output: &PlatformOutput) {
+    WANTS_POINTER.store(
+      ctx.wants_pointer_input() || ctx.is_pointer_over_area(),
+      Ordering::Relaxed
+    );
+    WANTS_KEYBOARD.store(ctx.wants_keyboard_input(), Ordering::Relaxed);
+
+    for command in &output.commands {
+      if let OutputCommand::CopyText(text) = command &&
+        let Err(e) = unsafe { write_clipboard(text) }
+      {
+        error!("Failed to copy to the clipboard: {}", e);
+      }
+    }
+  }
+}
+
+unsafe extern "system" fn overlay_wndproc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+  unsafe {
+    let original = ORIGINAL_WNDPROCS
+      .lock()
+      .ok()
+      .and_then(|originals| originals.get(&(hwnd.0 as isize)).copied())
+      .unwrap_or_default();
+
+    if WNDPROC_GUARD.run(|| handle_message(hwnd, msg, wparam, lparam)) == Some(true) {
+      return LRESULT(0);
+    }
+    let original: WNDPROC = std::mem::transmute(original);
+    CallWindowProcW(original, hwnd, msg, wparam, lparam)
+  }
+}
+
+/// Feeds a message of the game's window to egui, returns whether the overlay keeps it from the game
+unsafe fn handle_message(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> bool {
+  unsafe {
+    if ACTIVE_HWND.load(Ordering::Acquire) != hwnd.0 as isize {
+      return false;
+    }
+
+    let Ok(translated) = INPUT.lock().map(|mut input| input.translate(msg, wparam.0, lparam.0)) else {
+      return false;
+    };
+
+    match msg {
+      WM_MOUSEMOVE if !TRACKING_MOUSE.swap(true, Ordering::Relaxed) => {
+        let mut track = TRACKMOUSEEVENT {
+          cbSize: size_of::<TRACKMOUSEEVENT>() as u32,
+          dwFlags: TME_LEAVE,
+          hwndTrack: hwnd,
+          dwHoverTime: 0
+        };
+        let _ = TrackMouseEvent(&mut track);
+      }
+      WM_MOUSELEAVE => TRACKING_MOUSE.store(false, Ordering::Relaxed),
+      _ => {}
+    }
+
+    // The clipboard and IME are read without holding the input lock, both can send messages to this window
+    if translated.paste_requested &&
+      let Some(text) = read_clipboard(hwnd) &&
+      let Ok(mut input) = INPUT.lock()
+    {
+      input.paste(text);
+    }
+    if translated.ime_composition {
+      let (preedit, committed) = read_ime_composition(hwnd, lparam);
+      if let Ok(mut input) = INPUT.lock() {
+        input.ime_composition(preedit, committed);
+      }
+    }
+
+    let wants_pointer = WANTS_POINTER.load(Ordering::Relaxed);
+    let wants_keyboard = WANTS_KEYBOARD.load(Ordering::Relaxed);
+    should_swallow(translated.kind, wants_pointer, wants_keyboard)
+  }
+}
+
+unsafe fn read_clipboard(hwnd: HWND) -> Option<String> {
+  unsafe {
+    OpenClipboard(Some(hwnd)).ok()?;
+    let text = GetClipboardData(CF_UNICODETEXT.0 as u32).ok().and_then(|data| {
+      let memory = windows::Win32::Foundation::HGLOBAL(data.0);
+      let locked = GlobalLock(memory) as *const u16;
+      if locked.is_null() {
+        return None;
+      }
+      let length = (0..).take_while(|&i| *locked.add(i) != 0).count();
+      let text = String::from_utf16_lossy(std::slice::from_raw_parts(locked, length));
+      let _ = GlobalUnlock(memory);
+      Some(text)
+    });
+    let _ = CloseClipboard();
+    text
+  }
+}
+
+unsafe fn write_clipboard(text: &str) -> windows::core::Result<()> {
+  unsafe {
+    let wide: Vec<u16> = text.replace('\n', "\r\n").encode_utf16().chain(Some(0)).collect();
+    OpenClipboard(None)?;
+    let written = (|| {
+      EmptyClipboard()?;
+      let memory = GlobalAlloc(GMEM_MOVEABLE, wide.len() * size_of::<u16>())?;
+      let locked = GlobalLock(memory) as *mut u16;
+      if !locked.is_null() {
+        std::ptr::copy_nonoverlapping(wide.as_ptr(), locked, wide.len());
+        let _ = GlobalUnlock(memory);
+      }
+      // The clipboard owns the memory from here on
+      SetClipboardData(CF_UNICODETEXT.0 as u32, Some(HANDLE(memory.0)))?;
+      Ok(())
+    })();
+    let _ = CloseClipboard();
+    written
+  }
+}
+
+/// Reads the composition strings a `WM_IME_COMPOSITION` announced in its `lparam`
+unsafe fn read_ime_composition(hwnd: HWND, lparam: LPARAM) -> (Option<String>, Option<String>) {
+  unsafe {
+    let context = ImmGetContext(hwnd);
+    if context.is_invalid() {
+      return (None, None);
+    }
+
+    let read = |kind: IME_COMPOSITION_STRING| -> Option<String> {
+      if lparam.0 as u32 & kind.0 == 0 {
+        return None;
+      }
+      let bytes = ImmGetCompositionStringW(context, kind, None, 0);
+      if bytes < 0 {
+        return None;
+      }
+      let mut buffer = vec![0u16; bytes as usize / size_of::<u16>()];
+      ImmGetCompositionStringW(context, kind, Some(buffer.as_mut_ptr() as *mut c_void), bytes as u32);
+      Some(String::from_utf16_lossy(&buffer))
+    };
+    let strings = (read(GCS_COMPSTR), read(GCS_RESULTSTR));
+
+    let _ = ImmReleaseContext(hwnd, context);
+    strings
+  }
 }
@@ -0,0 +1,786 @@
+//! Translation of Win32 window messages into egui input.
+//!
+//! Pure on purpose: messages come in as the plain `(msg, wparam, lparam)` triple a WndProc receives, nothing here
+//! calls into Windows, so recorded message sequences can be replayed anywhere. The few things that need the OS
+//! (clipboard text, IME composition strings) are read by the caller and handed in.
+
+use egui::{Event, ImeEvent, Key, Modifiers, MouseWheelUnit, PointerButton, Pos2, RawInput, Vec2};
+
+use crate::ui::screen_input;
+
+pub const WM_SIZE: u32 = 0x0005;
+pub const WM_SETFOCUS: u32 = 0x0007;
+pub const WM_KILLFOCUS: u32 = 0x0008;
+pub const WM_KEYDOWN: u32 = 0x0100;
+pub const WM_KEYUP: u32 = 0x0101;
+pub const WM_CHAR: u32 = 0x0102;
+pub const WM_SYSKEYDOWN: u32 = 0x0104;
+pub const WM_SYSKEYUP: u32 = 0x0105;
+pub const WM_SYSCHAR: u32 = 0x0106;
+pub const WM_IME_STARTCOMPOSITION: u32 = 0x010D;
+pub const WM_IME_ENDCOMPOSITION: u32 = 0x010E;
+pub const WM_IME_COMPOSITION: u32 = 0x010F;
+pub const WM_MOUSEMOVE: u32 = 0x0200;
+pub const WM_LBUTTONDOWN: u32 = 0x0201;
+pub const WM_LBUTTONUP: u32 = 0x0202;
+pub const WM_LBUTTONDBLCLK: u32 = 0x0203;
+pub const WM_RBUTTONDOWN: u32 = 0x0204;
+pub const WM_RBUTTONUP: u32 = 0x0205;
+pub const WM_RBUTTONDBLCLK: u32 = 0x0206;
+pub const WM_MBUTTONDOWN: u32 = 0x0207;
+pub const WM_MBUTTONUP: u32 = 0x0208;
+pub const WM_MBUTTONDBLCLK: u32 = 0x0209;
+pub const WM_MOUSEWHEEL: u32 = 0x020A;
+pub const WM_XBUTTONDOWN: u32 = 0x020B;
+pub const WM_XBUTTONUP: u32 = 0x020C;
+pub const WM_XBUTTONDBLCLK: u32 = 0x020D;
+pub const WM_MOUSEHWHEEL: u32 = 0x020E;
+pub const WM_MOUSELEAVE: u32 = 0x02A3;
+pub const WM_DPICHANGED: u32 = 0x02E0;
+
+const WHEEL_DELTA: f32 = 120.0;
+const USER_DEFAULT_SCREEN_DPI: f32 = 96.0;
+
+const VK_SHIFT: usize = 0x10;
+const VK_CONTROL: usize = 0x11;
+const VK_MENU: usize = 0x12;
+
+/// Which kind of input a message carried, decides whether the game gets to see it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputKind {
+  Pointer,
+  Keyboard,
+  /// Window state like focus, size and DPI, always passed on
+  Window,
+  /// Not an input message
+  None
+}
+
+/// What happened to a single message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Translated {
+  pub kind: InputKind,
+  /// Ctrl+V was pressed, the caller should read the clipboard and pass it to [`Win32Input::paste`]
+  pub paste_requested: bool,
+  /// The IME composition changed, the caller should read the strings and pass them to
+  /// [`Win32Input::ime_composition`]
+  pub ime_composition: bool
+}
+
+impl Translated {
+  fn of(kind: InputKind) -> Self {
+    Self {
+      kind,
+      paste_requested: false,
+      ime_composition: false
+    }
+  }
+}
+
+/// Whether a message should be kept from the game, given what the overlay wanted after its last frame
+pub fn should_swallow(kind: InputKind, wants_pointer: bool, wants_keyboard: bool) -> bool {
+  match kind {
+    InputKind::Pointer => wants_pointer,
+    InputKind::Keyboard => wants_keyboard,
+    InputKind::Window | InputKind::None => false
+  }
+}
+
+/// Input collected from a window between two overlay frames
+pub struct Win32Input {
+  events: Vec<Event>,
+  modifiers: Modifiers,
+  pointer: Pos2,
+  pixels_per_point: f32,
+  /// Client area in physical pixels, `None` until the first `WM_SIZE` or [`Win32Input::set_client_size`]
+  client_size: Option<[u32; 2]>,
+  focused: bool,
+  /// First half of a UTF-16 surrogate pair delivered through `WM_CHAR`
+  high_surrogate: Option<u16>
+}
+
+impl Default for Win32Input {
+  fn default() -> Self {
+    Self {
+      events: Vec::new(),
+      modifiers: Modifiers::default(),
+      pointer: Pos2::ZERO,
+      pixels_per_point: 1.0,
+      client_size: None,
+      focused: true,
+      high_surrogate: None
+    }
+  }
+}
+
+impl Win32Input {
+  pub fn new(pixels_per_point: f32) -> Self {
+    Self {
+      pixels_per_point,
+      ..Default::default()
+    }
+  }
+
+  pub fn set_client_size(&mut self, width: u32, height: u32) {
+    self.client_size = Some([width, height]);
+  }
+
+  pub fn pixels_per_point(&self) -> f32 {
+    self.pixels_per_point
+  }
+
+  pub fn events(&self) -> &[Event] {
+    &self.events
+  }
+
+  /// Feeds one message, events are buffered until [`Win32Input::take_raw_input`]
+  pub fn translate(&mut self, msg: u32, wparam: usize, lparam: isize) -> Translated {
+    match msg {
+      WM_MOUSEMOVE => {
+        self.pointer = self.lparam_to_pos(lparam);
+        self.events.push(Event::PointerMoved(self.pointer));
+        Translated::of(InputKind::Pointer)
+      }
+      WM_MOUSELEAVE => {
+        self.events.push(Event::PointerGone);
+        Translated::of(InputKind::Pointer)
+      }
+      WM_LBUTTONDOWN | WM_LBUTTONDBLCLK => self.pointer_button(PointerButton::Primary, true, lparam),
+      WM_LBUTTONUP => self.pointer_button(PointerButton::Primary, false, lparam),
+      WM_RBUTTONDOWN | WM_RBUTTONDBLCLK => self.pointer_button(PointerButton::Secondary, true, lparam),
+      WM_RBUTTONUP => self.pointer_button(PointerButton::Secondary, false, lparam),
+      WM_MBUTTONDOWN | WM_MBUTTONDBLCLK => self.pointer_button(PointerButton::Middle, true, lparam),
+      WM_MBUTTONUP => self.pointer_button(PointerButton::Middle, false, lparam),
+      WM_XBUTTONDOWN | WM_XBUTTONDBLCLK | WM_XBUTTONUP => {
+        let button = match hiword(wparam) {
+          1 => PointerButton::Extra1,
+          _ => PointerButton::Extra2
+        };
+        self.pointer_button(button, msg != WM_XBUTTONUP, lparam)
+      }
+      WM_MOUSEWHEEL | WM_MOUSEHWHEEL => {
+        let lines = hiword(wparam) as i16 as f32 / WHEEL_DELTA;
+        // Positive deltas move the content right and down: the wheel turned away from the user scrolls up, tilting
+        // it right scrolls right
+        let delta = match msg {
+          WM_MOUSEWHEEL => Vec2::new(0.0, lines),
+          _ => Vec2::new(-lines, 0.0)
+        };
+        self.events.push(Event::MouseWheel {
+          unit: MouseWheelUnit::Line,
+          delta,
+          modifiers: self.modifiers
+        });
+        Translated::of(InputKind::Pointer)
+      }
+      WM_KEYDOWN | WM_SYSKEYDOWN | WM_KEYUP | WM_SYSKEYUP => {
+        let pressed = matches!(msg, WM_KEYDOWN | WM_SYSKEYDOWN);
+        self.key(wparam, lparam, pressed)
+      }
+      WM_CHAR | WM_SYSCHAR => {
+        self.character(wparam as u16);
+        Translated::of(InputKind::Keyboard)
+      }
+      WM_IME_STARTCOMPOSITION => {
+        self.events.push(Event::Ime(ImeEvent::Enabled));
+        Translated::of(InputKind::Keyboard)
+      }
+      WM_IME_COMPOSITION => Translated {
+        ime_composition: true,
+        ..Translated::of(InputKind::Keyboard)
+      },
+      WM_IME_ENDCOMPOSITION => {
+        self.events.push(Event::Ime(ImeEvent::Disabled));
+        Translated::of(InputKind::Keyboard)
+      }
+      WM_SETFOCUS | WM_KILLFOCUS => {
+        self.focused = msg == WM_SETFOCUS;
+        // Key releases that happen while another window has focus never reach us
+        self.modifiers = Modifiers::default();
+        self.events.push(Event::WindowFocused(self.focused));
+        Translated::of(InputKind::Window)
+      }
+      WM_SIZE => {
+        self.client_size = Some([loword(lparam as usize) as u32, hiword(lparam as usize) as u32]);
+        Translated::of(InputKind::Window)
+      }
+      WM_DPICHANGED => {
+        let dpi = loword(wparam) as f32;
+        if dpi > 0.0 {
+          self.pixels_per_point = dpi / USER_DEFAULT_SCREEN_DPI;
+        }
+        Translated::of(InputKind::Window)
+      }
+      _ => Translated::of(InputKind::None)
+    }
+  }
+
+  /// Clipboard text for a paste requested by [`Translated::paste_requested`]
+  pub fn paste(&mut self, text: String) {
+    if !text.is_empty() {
+      self.events.push(Event::Paste(text.replace("\r\n", "\n")));
+    }
+  }
+
+  /// Strings read from the IME after [`Translated::ime_composition`]. `committed` is the finished text (GCS_RESULTSTR),
+  /// `preedit` the text still being composed (GCS_COMPSTR).
+  pub fn ime_composition(&mut self, preedit: Option<String>, committed: Option<String>) {
+    if let Some(committed) = committed {
+      self.events.push(Event::Ime(ImeEvent::Commit(committed)));
+    }
+    if let Some(preedit) = preedit {
+      self.events.push(Event::Ime(ImeEvent::Preedit(preedit)));
+    }
+  }
+
+  /// Drains the buffered events into input for the next frame. `target_size` is the size of the image the overlay is
+  /// drawn into in physical pixels. Games may render at a different resolution than their window, pointer positions
+  /// are scaled from the client area to match.
+  pub fn take_raw_input(&mut self, target_size: [u32; 2]) -> RawInput {
+    let [width, height] = target_size;
+    let mut events = std::mem::take(&mut self.events);
+
+    if let Some([client_width, client_height]) = self.client_size &&
+      client_width > 0 &&
+      client_height > 0 &&
+      [client_width, client_height] != target_size
+    {
+      let scale = Vec2::new(width as f32 / client_width as f32, height as f32 / client_height as f32);
+      for event in &mut events {
+        match event {
+          Event::PointerMoved(pos) | Event::PointerButton { pos, .. } => *pos = (pos.to_vec2() * scale).to_pos2(),
+          _ => {}
+        }
+      }
+    }
+
+    RawInput {
+      modifiers: self.modifiers,
+      events,
+      focused: self.focused,
+      ..screen_input(width, height, self.pixels_per_point)
+    }
+  }
+
+  fn lparam_to_pos(&self, lparam: isize) -> Pos2 {
+    // Coordinates are signed, they go negative while the mouse is captured outside the client area
+    let x = loword(lparam as usize) as i16 as f32;
+    let y = hiword(lparam as usize) as i16 as f32;
+    Pos2::new(x, y) / self.pixels_per_point
+  }
+
+  fn pointer_button(&mut self, button: PointerButton, pressed: bool, lparam: isize) -> Translated {
+    self.pointer = self.lparam_to_pos(lparam);
+    self.events.push(Event::PointerButton {
+      pos: self.pointer,
+      button,
+      pressed,
+      modifiers: self.modifiers
+    });
+    Translated::of(InputKind::Pointer)
+  }
+
+  fn key(&mut self, virtual_key: usize, lparam: isize, pressed: bool) -> Translated {
+    match virtual_key {
+      VK_SHIFT => self.modifiers.shift = pressed,
+      VK_CONTROL => {
+        self.modifiers.ctrl = pressed;
+        self.modifiers.command = pressed;
+      }
+      VK_MENU => self.modifiers.alt = pressed,
+      _ => {}
+    }
+
+    let Some(key) = virtual_key_to_key(virtual_key) else {
+      return Translated::of(InputKind::Keyboard);
+    };
+
+    // Bit 30 holds the previous key state, set for auto-repeated presses
+    let repeat = pressed && (lparam >> 30) & 1 == 1;
+    let mut translated = Translated::of(InputKind::Keyboard);
+    if pressed && self.modifiers.ctrl && !self.modifiers.alt {
+      match key {
+        Key::C => self.events.push(Event::Copy),
+        Key::X => self.events.push(Event::Cut),
+        Key::V => translated.paste_requested = !repeat,
+        _ => {}
+      }
+    }
+
+    self.events.push(Event::Key {
+      key,
+      physical_key: None,
+      pressed,
+      repeat,
+      modifiers: self.modifiers
+    });
+    translated
+  }
+
+  fn character(&mut self, unit: u16) {
+    let text = match unit {
+      0xD800..=0xDBFF => {
+        self.high_surrogate = Some(unit);
+        return;
+      }
+      0xDC00..=0xDFFF => {
+        let Some(high) = self.high_surrogate.take() else {
+          return;
+        };
+        String::from_utf16_lossy(&[high, unit])
+      }
+      _ => {
+        self.high_surrogate = None;
+        String::from_utf16_lossy(&[unit])
+      }
+    };
+
+    // Control characters arrive as WM_CHAR too (Ctrl+A is 0x01), egui gets those as key events. Ctrl+Alt is AltGr
+    // and does produce text.
+    let is_control = text.chars().any(char::is_control);
+    let is_shortcut = self.modifiers.ctrl && !self.modifiers.alt;
+    if !is_control && !is_shortcut {
+      self.events.push(Event::Text(text));
+    }
+  }
+}
+
+fn loword(value: usize) -> u16 {
+  (value & 0xFFFF) as u16
+}
+
+fn hiword(value: usize) -> u16 {
+  ((value >> 16) & 0xFFFF) as u16
+}
+
+/// Maps a Windows virtual key code to the egui key, `None` for keys egui doesn't know
+pub fn virtual_key_to_key(virtual_key: usize) -> Option<Key> {
+  const LETTERS: [Key; 26] = [
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z
+  ];
+  const DIGITS: [Key; 10] = [
+    Key::Num0,
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9
+  ];
+  const FUNCTION_KEYS: [Key; 24] = [
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+    Key::F13,
+    Key::F14,
+    Key::F15,
+    Key::F16,
+    Key::F17,
+    Key::F18,
+    Key::F19,
+    Key::F20,
+    Key::F21,
+    Key::F22,
+    Key::F23,
+    Key::F24
+  ];
+
+  let key = match virtual_key {
+    0x08 => Key::Backspace,
+    0x09 => Key::Tab,
+    0x0D => Key::Enter,
+    0x1B => Key::Escape,
+    0x20 => Key::Space,
+    0x21 => Key::PageUp,
+    0x22 => Key::PageDown,
+    0x23 => Key::End,
+    0x24 => Key::Home,
+    0x25 => Key::ArrowLeft,
+    0x26 => Key::ArrowUp,
+    0x27 => Key::ArrowRight,
+    0x28 => Key::ArrowDown,
+    0x2D => Key::Insert,
+    0x2E => Key::Delete,
+    0x30..=0x39 => DIGITS[virtual_key - 0x30],
+    0x41..=0x5A => LETTERS[virtual_key - 0x41],
+    0x60..=0x69 => DIGITS[virtual_key - 0x60],
+    0x6B => Key::Plus,
+    0x6D => Key::Minus,
+    0x6E => Key::Period,
+    0x6F => Key::Slash,
+    0x70..=0x87 => FUNCTION_KEYS[virtual_key - 0x70],
+    0xA6 => Key::BrowserBack,
+    0xBA => Key::Semicolon,
+    0xBB => Key::Equals,
+    0xBC => Key::Comma,
+    0xBD => Key::Minus,
+    0xBE => Key::Period,
+    0xBF => Key::Slash,
+    0xC0 => Key::Backtick,
+    0xDB => Key::OpenBracket,
+    0xDC => Key::Backslash,
+    0xDD => Key::CloseBracket,
+    0xDE => Key::Quote,
+    _ => return None
+  };
+  Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const WM_PAINT: u32 = 0x000F;
+  const VK_A: usize = 0x41;
+  const VK_C: usize = 0x43;
+  const VK_V: usize = 0x56;
+  /// `lparam` bit 30, the key was already down
+  const REPEAT: isize = 1 << 30;
+
+  fn point(x: i16, y: i16) -> isize {
+    (x as u16 as isize) | ((y as u16 as isize) << 16)
+  }
+
+  fn words(low: u16, high: u16) -> usize {
+    low as usize | (high as usize) << 16
+  }
+
+  /// Feeds a recorded message sequence, returning what each message was
+  fn replay(input: &mut Win32Input, messages: &[(u32, usize, isize)]) -> Vec<Translated> {
+    messages
+      .iter()
+      .map(|&(msg, wparam, lparam)| input.translate(msg, wparam, lparam))
+      .collect()
+  }
+
+  fn kinds(translated: &[Translated]) -> Vec<InputKind> {
+    translated.iter().map(|translated| translated.kind).collect()
+  }
+
+  fn key(key: Key, pressed: bool, repeat: bool, modifiers: Modifiers) -> Event {
+    Event::Key {
+      key,
+      physical_key: None,
+      pressed,
+      repeat,
+      modifiers
+    }
+  }
+
+  #[test]
+  fn clicks_and_mouse_leave() {
+    let mut input = Win32Input::new(1.0);
+    let translated = replay(
+      &mut input,
+      &[
+        (WM_MOUSEMOVE, 0, point(10, 20)),
+        (WM_LBUTTONDOWN, 0, point(10, 20)),
+        (WM_LBUTTONUP, 0, point(12, 20)),
+        (WM_RBUTTONDBLCLK, 0, point(12, 20)),
+        (WM_XBUTTONDOWN, words(0, 2), point(12, 20)),
+        (WM_MOUSELEAVE, 0, 0)
+      ]
+    );
+    assert_eq!(kinds(&translated), [InputKind::Pointer; 6]);
+
+    let button = |button, pressed, pos: Pos2| Event::PointerButton {
+      pos,
+      button,
+      pressed,
+      modifiers: Modifiers::default()
+    };
+    assert_eq!(
+      input.take_raw_input([800, 600]).events,
+      [
+        Event::PointerMoved(Pos2::new(10.0, 20.0)),
+        button(PointerButton::Primary, true, Pos2::new(10.0, 20.0)),
+        button(PointerButton::Primary, false, Pos2::new(12.0, 20.0)),
+        button(PointerButton::Secondary, true, Pos2::new(12.0, 20.0)),
+        button(PointerButton::Extra2, true, Pos2::new(12.0, 20.0)),
+        Event::PointerGone
+      ]
+    );
+    assert!(input.events().is_empty());
+  }
+
+  #[test]
+  fn pointer_positions_are_signed() {
+    let mut input = Win32Input::new(1.0);
+    input.translate(WM_MOUSEMOVE, 0, point(-5, -300));
+    assert_eq!(input.events(), [Event::PointerMoved(Pos2::new(-5.0, -300.0))]);
+  }
+
+  #[test]
+  fn wheels_scroll_by_lines() {
+    let mut input = Win32Input::new(1.0);
+    replay(
+      &mut input,
+      &[
+        (WM_MOUSEWHEEL, words(0, 120), 0),
+        (WM_MOUSEWHEEL, words(0, -240i16 as u16), 0),
+        (WM_MOUSEHWHEEL, words(0, 120), 0)
+      ]
+    );
+    let deltas: Vec<Vec2> = input
+      .events()
+      .iter()
+      .map(|event| match event {
+        Event::MouseWheel {
+          unit: MouseWheelUnit::Line,
+          delta,
+          ..
+        } => *delta,
+        other => panic!("unexpected event {other:?}")
+      })
+      .collect();
+    assert_eq!(
+      deltas,
+      [Vec2::new(0.0, 1.0), Vec2::new(0.0, -2.0), Vec2::new(-1.0, 0.0)]
+    );
+  }
+
+  #[test]
+  fn typing_with_shift() {
+    let mut input = Win32Input::new(1.0);
+    let translated = replay(
+      &mut input,
+      &[
+        (WM_KEYDOWN, VK_SHIFT, 0),
+        (WM_KEYDOWN, VK_A, 0),
+        (WM_CHAR, 'A' as usize, 0),
+        (WM_KEYDOWN, VK_A, REPEAT),
+        (WM_CHAR, 'A' as usize, REPEAT),
+        (WM_KEYUP, VK_A, 0),
+        (WM_KEYUP, VK_SHIFT, 0)
+      ]
+    );
+    assert_eq!(kinds(&translated), [InputKind::Keyboard; 7]);
+    assert!(translated.iter().all(|translated| !translated.paste_requested));
+
+    let shift = Modifiers::SHIFT;
+    let raw_input = input.take_raw_input([800, 600]);
+    assert_eq!(
+      raw_input.events,
+      [
+        key(Key::A, true, false, shift),
+        Event::Text("A".to_string()),
+        key(Key::A, true, true, shift),
+        Event::Text("A".to_string()),
+        key(Key::A, false, false, shift)
+      ]
+    );
+    assert_eq!(raw_input.modifiers, Modifiers::default());
+  }
+
+  #[test]
+  fn ctrl_shortcuts_and_paste() {
+    let mut input = Win32Input::new(1.0);
+    let translated = replay(
+      &mut input,
+      &[
+        (WM_KEYDOWN, VK_CONTROL, 0),
+        (WM_KEYDOWN, VK_C, 0),
+        // Ctrl+C arrives as the control character 0x03
+        (WM_CHAR, 0x03, 0),
+        (WM_KEYDOWN, VK_V, 0),
+        (WM_CHAR, 0x16, 0),
+        (WM_KEYDOWN, VK_V, REPEAT)
+      ]
+    );
+    let paste_requested: Vec<bool> = translated.iter().map(|translated| translated.paste_requested).collect();
+    assert_eq!(paste_requested, [false, false, false, true, false, false]);
+    input.paste("one\r\ntwo".to_string());
+    input.paste(String::new());
+
+    let ctrl = Modifiers::CTRL | Modifiers::COMMAND;
+    assert_eq!(
+      input.events(),
+      [
+        Event::Copy,
+        key(Key::C, true, false, ctrl),
+        key(Key::V, true, false, ctrl),
+        key(Key::V, true, true, ctrl),
+        Event::Paste("one\ntwo".to_string())
+      ]
+    );
+  }
+
+  #[test]
+  fn altgr_and_surrogate_pairs_produce_text() {
+    let mut input = Win32Input::new(1.0);
+    replay(
+      &mut input,
+      &[
+        (WM_KEYDOWN, VK_CONTROL, 0),
+        (WM_KEYDOWN, VK_MENU, 0),
+        (WM_CHAR, '@' as usize, 0),
+        (WM_KEYUP, VK_MENU, 0),
+        (WM_KEYUP, VK_CONTROL, 0),
+        (WM_CHAR, 0xD83D, 0),
+        (WM_CHAR, 0xDE00, 0),
+        // A low surrogate without its high half is dropped
+        (WM_CHAR, 0xDE00, 0)
+      ]
+    );
+    assert_eq!(
+      input.events(),
+      [Event::Text("@".to_string()), Event::Text("\u{1F600}".to_string())]
+    );
+  }
+
+  #[test]
+  fn ime_composition() {
+    let mut input = Win32Input::new(1.0);
+    let translated = replay(
+      &mut input,
+      &[(WM_IME_STARTCOMPOSITION, 0, 0), (WM_IME_COMPOSITION, 0, 0)]
+    );
+    assert_eq!(kinds(&translated), [InputKind::Keyboard; 2]);
+    assert_eq!(
+      translated
+        .iter()
+        .map(|translated| translated.ime_composition)
+        .collect::<Vec<_>>(),
+      [false, true]
+    );
+    input.ime_composition(Some("か".to_string()), None);
+
+    assert!(input.translate(WM_IME_COMPOSITION, 0, 0).ime_composition);
+    input.ime_composition(Some(String::new()), Some("漢字".to_string()));
+    assert_eq!(input.translate(WM_IME_ENDCOMPOSITION, 0, 0).kind, InputKind::Keyboard);
+
+    assert_eq!(
+      input.events(),
+      [
+        Event::Ime(ImeEvent::Enabled),
+        Event::Ime(ImeEvent::Preedit("か".to_string())),
+        Event::Ime(ImeEvent::Commit("漢字".to_string())),
+        Event::Ime(ImeEvent::Preedit(String::new())),
+        Event::Ime(ImeEvent::Disabled)
+      ]
+    );
+  }
+
+  #[test]
+  fn dpi_changes_scale_points() {
+    let mut input = Win32Input::new(1.0);
+    assert_eq!(
+      input.translate(WM_DPICHANGED, words(144, 144), 0).kind,
+      InputKind::Window
+    );
+    assert_eq!(input.pixels_per_point(), 1.5);
+    // A zero DPI is ignored
+    input.translate(WM_DPICHANGED, 0, 0);
+    assert_eq!(input.pixels_per_point(), 1.5);
+
+    input.translate(WM_MOUSEMOVE, 0, point(300, 150));
+    let raw_input = input.take_raw_input([1500, 900]);
+    assert_eq!(raw_input.events, [Event::PointerMoved(Pos2::new(200.0, 100.0))]);
+    assert_eq!(raw_input.screen_rect.unwrap().size(), Vec2::new(1000.0, 600.0));
+  }
+
+  #[test]
+  fn pointer_is_scaled_from_the_client_area_to_the_target() {
+    let mut input = Win32Input::new(1.0);
+    assert_eq!(input.translate(WM_SIZE, 0, point(800, 600)).kind, InputKind::Window);
+    input.translate(WM_MOUSEMOVE, 0, point(400, 150));
+    input.translate(WM_LBUTTONDOWN, 0, point(400, 150));
+
+    let raw_input = input.take_raw_input([1600, 1200]);
+    assert_eq!(raw_input.events[0], Event::PointerMoved(Pos2::new(800.0, 300.0)));
+    assert!(matches!(
+      raw_input.events[1],
+      Event::PointerButton { pos, .. } if pos == Pos2::new(800.0, 300.0)
+    ));
+  }
+
+  #[test]
+  fn losing_focus_releases_modifiers() {
+    let mut input = Win32Input::new(1.0);
+    input.translate(WM_KEYDOWN, VK_CONTROL, 0);
+    assert_eq!(input.translate(WM_KILLFOCUS, 0, 0).kind, InputKind::Window);
+
+    let raw_input = input.take_raw_input([800, 600]);
+    assert_eq!(raw_input.modifiers, Modifiers::default());
+    assert!(!raw_input.focused);
+    assert_eq!(raw_input.events, [Event::WindowFocused(false)]);
+
+    input.translate(WM_SETFOCUS, 0, 0);
+    assert!(input.take_raw_input([800, 600]).focused);
+  }
+
+  #[test]
+  fn swallows_only_what_the_overlay_wants() {
+    let mut input = Win32Input::new(1.0);
+    let kinds = kinds(&replay(
+      &mut input,
+      &[
+        (WM_MOUSEMOVE, 0, point(1, 1)),
+        (WM_MOUSELEAVE, 0, 0),
+        (WM_KEYDOWN, VK_A, 0),
+        (WM_SYSKEYDOWN, VK_MENU, 0),
+        (WM_IME_COMPOSITION, 0, 0),
+        (WM_SETFOCUS, 0, 0),
+        (WM_DPICHANGED, words(96, 96), 0),
+        (WM_PAINT, 0, 0)
+      ]
+    ));
+    let swallowed = |wants_pointer, wants_keyboard| {
+      kinds
+        .iter()
+        .map(|&kind| should_swallow(kind, wants_pointer, wants_keyboard))
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(swallowed(false, false), [false; 8]);
+    assert_eq!(
+      swallowed(true, false),
+      [true, true, false, false, false, false, false, false]
+    );
+    assert_eq!(
+      swallowed(false, true),
+      [false, false, true, true, true, false, false, false]
+    );
+    assert_eq!(
+      swallowed(true, true),
+      [true, true, true, true, true, false, false, false]
+    );
+  }
+}
@@ -7,20 +7,39 @@ use windows::Win32::Graphics::Direct3D::{D3D_DRIVER_TYPE, D3D_FEATURE_LEVEL};
 use windows::Win32::Graphics::Direct3D11::{
   D3D11_CREATE_DEVICE_FLAG, ID3D11Device, ID3D11DeviceContext, ID3D11RenderTargetView
 };
+use windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT;
 use windows::Win32::Graphics::Dxgi::{
   DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_CHAIN_DESC1, DXGI_SWAP_CHAIN_FULLSCREEN_DESC, IDXGIAdapter, IDXGIFactory,
-  IDXGIFactory2, IDXGIOutput, IDXGISwapChain, IDXGISwapChain1
+  IDXGIFactory2, IDXGIOutput, IDXGISwapChain, IDXGISwapChain1, IDXGISwapChain3
 };
 use windows::core::{GUID, HRESULT};
 use windows_core::IUnknown;
 
-pub(crate) static EGUI_CTX: Mutex<Option<egui::Context>> = Mutex::new(None);
-pub(crate) static EGUI_DX11: Mutex<Option<egui_directx11::Renderer>> = Mutex::new(None);
-pub(crate) static RT_VIEW: Mutex<Option<ID3D11RenderTargetView>> = Mutex::new(None);
-
 pub(crate) type PresentFn =
   unsafe extern "system" fn(this: *mut IDXGISwapChain, sync_interval: u32, flags: u32) -> HRESULT;
 
+pub(crate) type ResizeBuffersFn = unsafe extern "system" fn(
+  this: *mut IDXGISwapChain,
+  buffer_count: u32,
+  width: u32,
+  height: u32,
+  new_format: DXGI_FORMAT,
+  swapchain_flags: u32
+) -> HRESULT;
+
+pub(crate) type ResizeBuffers1Fn = unsafe extern "system" fn(
+  this: *mut IDXGISwapChain3,
+  buffer_count: u32,
+  width: u32,
+  height: u32,
+  format: DXGI_FORMAT,
+  swapchain_flags: u32,
+  creation_node_mask: *const u32,
+  present_queue: *const *mut IUnknown
+) -> HRESULT;
+
+pub(crate) type SwapChainReleaseFn = unsafe extern "system" fn(this: *mut IDXGISwapChain) -> u32;
+
 pub(crate) type CreateSwapChainFn = unsafe extern "system" fn(
   this: *mut IDXGIFactory,
   device: *mut IUnknown,
@@ -38,6 +57,23 @@ pub(crate) type CreateSwapChainForHwndFn = unsafe extern "system" fn(
   swapchain: *mut *mut IDXGISwapChain1
 ) -> HRESULT;
 
+pub(crate) type CreateSwapChainForCoreWindowFn = unsafe extern "system" fn(
+  this: *mut IDXGIFactory2,
+  device: *mut IUnknown,
+  window: *mut IUnknown,
+  desc: *const DXGI_SWAP_CHAIN_DESC1,
+  restrict_to_output: *mut IDXGIOutput,
+  swapchain: *mut *mut IDXGISwapChain1
+) -> HRESULT;
+
+pub(crate) type CreateSwapChainForCompositionFn = unsafe extern "system" fn(
+  this: *mut IDXGIFactory2,
+  device: *mut IUnknown,
+  desc: *const DXGI_SWAP_CHAIN_DESC1,
+  restrict_to_output: *mut IDXGIOutput,
+  swapchain: *mut *mut IDXGISwapChain1
+) -> HRESULT;
+
 pub(crate) type DXGICreateFactoryFn =
   unsafe extern "system" fn(riid: *const GUID, pp_factory: *mut *mut c_void) -> HRESULT;
 
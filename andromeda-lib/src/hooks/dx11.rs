@@ -1,4 +1,8 @@
-use andromeda_common::exports::{D3D11CreateDeviceAndSwapChainFn, D3D11CreateDeviceFn};
+use andromeda_common::{
+  exports::{D3D11CreateDeviceAndSwapChainFn, D3D11CreateDeviceFn},
+  guard::HookGuard,
+  utils::win32::hresult_to_string
+};
 use log::{error, info};
 use once_cell::sync::{Lazy, OnceCell};
 use std::{
@@ -13,16 +17,16 @@ use std::{
 };
 use windows::{
   Win32::{
-    Foundation::{HINSTANCE, HMODULE, HWND},
+    Foundation::{E_INVALIDARG, HINSTANCE, HMODULE, HWND},
     Graphics::{
       Direct3D::{
         D3D_DRIVER_TYPE, D3D_DRIVER_TYPE_REFERENCE, D3D_DRIVER_TYPE_WARP, D3D_FEATURE_LEVEL, D3D_FEATURE_LEVEL_11_0
       },
       Direct3D11::{D3D11_CREATE_DEVICE_FLAG, ID3D11Device, ID3D11DeviceContext},
       Dxgi::{
-        DXGI_ADAPTER_DESC, DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_CHAIN_DESC1, DXGI_SWAP_CHAIN_FULLSCREEN_DESC, IDXGIAdapter,
-        IDXGIDevice, IDXGIDevice1, IDXGIFactory, IDXGIFactory1, IDXGIFactory2, IDXGIOutput, IDXGISwapChain,
-        IDXGISwapChain1
+        Common::DXGI_FORMAT, DXGI_ADAPTER_DESC, DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_CHAIN_DESC1,
+        DXGI_SWAP_CHAIN_FULLSCREEN_DESC, IDXGIAdapter, IDXGIDevice, IDXGIDevice1, IDXGIFactory, IDXGIFactory1,
+        IDXGIFactory2, IDXGIOutput, IDXGISwapChain, IDXGISwapChain1, IDXGISwapChain3
       }
     }
   },
@@ -31,21 +35,28 @@ use windows::{
 use windows_core::{IUnknown, Interface};
 
 use crate::{
-  exports::{CreateSwapChainFn, CreateSwapChainForHwndFn, DXGICreateFactory2Fn, DXGICreateFactoryFn, PresentFn},
+  exports::{
+    CreateSwapChainFn, CreateSwapChainForCompositionFn, CreateSwapChainForCoreWindowFn, CreateSwapChainForHwndFn,
+    DXGICreateFactory2Fn, DXGICreateFactoryFn, PresentFn, ResizeBuffers1Fn, ResizeBuffersFn, SwapChainReleaseFn
+  },
   hooks::hook_vtable_method,
   internal::{
     INTERFACES,
-    swapchain_util::{DX11Swapchain, RENDER_TARGETS, register_swapchain}
+    swapchain_util::{
+      DX11Swapchain, SwapchainBase, find_dx11_swapchain, register_swapchain, unregister_dx11_swapchain
+    }
   },
-  util::{get_module_symbol_address, hresult_to_string, log}
+  util::get_module_symbol_address
 };
 
+pub(crate) static ORIG_PRESENT: OnceLock<PresentFn> = OnceLock::new();
+pub(crate) static ORIG_RESIZE_BUFFERS: OnceLock<ResizeBuffersFn> = OnceLock::new();
+pub(crate) static ORIG_RESIZE_BUFFERS1: OnceLock<ResizeBuffers1Fn> = OnceLock::new();
+pub(crate) static ORIG_SWAPCHAIN_RELEASE: OnceLock<SwapChainReleaseFn> = OnceLock::new();
 pub(crate) static ORIG_CREATE_SWAPCHAIN: OnceLock<CreateSwapChainFn> = OnceLock::new();
 pub(crate) static ORIG_CREATE_SWAPCHAIN_FOR_HWND: OnceLock<CreateSwapChainForHwndFn> = OnceLock::new();
-// pub(crate) static ORIG_CREATE_SWAPCHAIN_FOR_COREWINDOW: OnceCell<CreateSwapChainForCoreWindowFn> =
-//   OnceCell::new();
-// pub(crate) static ORIG_CREATE_SWAPCHAIN_FOR_COMPOSITION: OnceCell<CreateSwapChainForCompositionFn> =
-//   OnceCell::new();
+pub(crate) static ORIG_CREATE_SWAPCHAIN_FOR_COREWINDOW: OnceLock<CreateSwapChainForCoreWindowFn> = OnceLock::new();
+pub(crate) static ORIG_CREATE_SWAPCHAIN_FOR_COMPOSITION: OnceLock<CreateSwapChainForCompositionFn> = OnceLock::new();
 pub struct DX11Hooks {
   pub d3d11_create_device: OnceLock<D3D11CreateDeviceFn>,
   pub d3d11_create_device_and_sc: OnceLock<D3D11CreateDeviceAndSwapChainFn>,
@@ -75,6 +86,13 @@ thread_local! {
   static G_IN_DXGI_RUNTIME: Cell<bool> = const { Cell::new(false) }
 }
 
+static CREATE_DEVICE_AND_SWAPCHAIN_GUARD: HookGuard = HookGuard::new("D3D11CreateDeviceAndSwapChain");
+static PRESENT_GUARD: HookGuard = HookGuard::new("IDXGISwapChain::Present");
+static RESIZE_BUFFERS_GUARD: HookGuard = HookGuard::new("IDXGISwapChain::ResizeBuffers");
+static SWAPCHAIN_RELEASE_GUARD: HookGuard = HookGuard::new("IDXGISwapChain::Release");
+static CREATE_SWAPCHAIN_GUARD: HookGuard = HookGuard::new("IDXGIFactory::CreateSwapChain");
+static CREATE_FACTORY_GUARD: HookGuard = HookGuard::new("CreateDXGIFactory");
+
 pub(crate) unsafe extern "system" fn d3d11_create_device_hook(
   p_adapter: *mut IDXGIAdapter,
   driver_type: D3D_DRIVER_TYPE,
@@ -147,7 +165,7 @@ pub(crate) unsafe extern "system" fn d3d11_create_device_and_swapchain_hook(
 
   // windows::Win32::Graphics::Direct3D11::D3D11CreateDeviceAndSwapChain()
 
-  if G_IN_DXGI_RUNTIME.get() {
+  if G_IN_DXGI_RUNTIME.get() || CREATE_DEVICE_AND_SWAPCHAIN_GUARD.is_disabled() {
     // Forward to original
     return orig(
       p_adapter,
@@ -165,6 +183,63 @@ pub(crate) unsafe extern "system" fn d3d11_create_device_and_swapchain_hook(
     );
   }
 
+  let created = CREATE_DEVICE_AND_SWAPCHAIN_GUARD.run(|| unsafe {
+    create_device_and_swapchain(
+      *orig,
+      p_adapter,
+      driver_type,
+      software,
+      flags,
+      p_feature_levels,
+      feature_levels,
+      sdk_version,
+      p_swap_chain_desc,
+      pp_swap_chain,
+      pp_device,
+      p_feature_level,
+      pp_immediate_context
+    )
+  });
+  match created {
+    Some(hr) => hr,
+    // create_device_and_swapchain returns errors instead of panicking once the original created the device, so
+    // calling it again doesn't create a second one
+    None => unsafe {
+      G_IN_DXGI_RUNTIME.set(false);
+      orig(
+        p_adapter,
+        driver_type,
+        software,
+        flags,
+        p_feature_levels,
+        feature_levels,
+        sdk_version,
+        p_swap_chain_desc,
+        pp_swap_chain,
+        pp_device,
+        p_feature_level,
+        pp_immediate_context
+      )
+    }
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn create_device_and_swapchain(
+  orig: D3D11CreateDeviceAndSwapChainFn,
+  p_adapter: *mut IDXGIAdapter,
+  driver_type: D3D_DRIVER_TYPE,
+  software: HMODULE,
+  flags: D3D11_CREATE_DEVICE_FLAG,
+  p_feature_levels: *const D3D_FEATURE_LEVEL,
+  feature_levels: u32,
+  sdk_version: u32,
+  p_swap_chain_desc: *const DXGI_SWAP_CHAIN_DESC,
+  pp_swap_chain: *mut *mut IDXGISwapChain,
+  pp_device: *mut *mut ID3D11Device,
+  p_feature_level: *mut D3D_FEATURE_LEVEL,
+  pp_immediate_context: *mut *mut ID3D11DeviceContext
+) -> HRESULT {
   let feature_level = D3D_FEATURE_LEVEL_11_0;
 
   G_IN_DXGI_RUNTIME.set(true);
@@ -199,15 +274,19 @@ pub(crate) unsafe extern "system" fn d3d11_create_device_and_swapchain_hook(
   info!("Using feature level {}", feature_level.0);
 
   if pp_device.is_null() {
-    assert!(pp_swap_chain.is_null() && pp_immediate_context.is_null());
     return hr;
   }
 
   let device = unsafe { ID3D11Device::from_raw(*pp_device as *mut c_void) };
-  let dxgi_device: IDXGIDevice1 = device
-    .cast()
-    .map_err(|e| hr = e.code())
-    .expect("Failed to obtain IDXGIDevice!");
+  let dxgi_device: IDXGIDevice1 = match device.cast() {
+    Ok(dxgi_device) => dxgi_device,
+    Err(e) => {
+      error!("[HOOK] Failed to obtain IDXGIDevice1: {}", e);
+      // Dropping the device releases it
+      unsafe { *pp_device = std::ptr::null_mut() };
+      return e.code();
+    }
+  };
 
   let mut adapter: Option<IDXGIAdapter> = match p_adapter.is_null() {
     true => None,
@@ -230,9 +309,11 @@ pub(crate) unsafe extern "system" fn d3d11_create_device_and_swapchain_hook(
   }
 
   if !p_swap_chain_desc.is_null() {
-    assert!(!pp_swap_chain.is_null());
+    if pp_swap_chain.is_null() {
+      hr = E_INVALIDARG;
+    }
 
-    if adapter.is_none() {
+    if hr.is_ok() && adapter.is_none() {
       adapter = match unsafe { dxgi_device.GetAdapter() } {
         Ok(a) => Some(a),
         Err(e) => {
@@ -240,17 +321,16 @@ pub(crate) unsafe extern "system" fn d3d11_create_device_and_swapchain_hook(
           None
         }
       };
-      assert!(hr.is_ok());
     }
 
     let mut factory: Option<IDXGIFactory> = None;
-    if let Some(ref adapter) = adapter {
+    if hr.is_ok() &&
+      let Some(ref adapter) = adapter
+    {
       match unsafe { adapter.GetParent() } {
         Ok(f) => factory = Some(f),
         Err(e) => hr = e.code()
       }
-      info!("nice");
-      assert!(hr.is_ok());
     }
 
     info!("[HOOK] Calling IDXGIFactory::CreateSwapChain:");
@@ -265,13 +345,17 @@ pub(crate) unsafe extern "system" fn d3d11_create_device_and_swapchain_hook(
     // hr = create_swapchain_hook(factory as *mut IDXGIFactory, device as *mut c_void, p_swap_chain_desc as *mut c_void, pp_swap_chain);
   }
 
-  if hr.is_ok() {
-    info!("nice");
-    if !pp_immediate_context.is_null() {
-      unsafe { *pp_immediate_context = device.GetImmediateContext().unwrap().as_raw() as *mut ID3D11DeviceContext };
+  if hr.is_ok() && !pp_immediate_context.is_null() {
+    match unsafe { device.GetImmediateContext() } {
+      Ok(context) => unsafe { *pp_immediate_context = context.into_raw() as *mut ID3D11DeviceContext },
+      Err(e) => hr = e.code()
     }
-  } else {
-    unsafe { *pp_device = std::ptr::null_mut() };
+  }
+  match hr.is_ok() {
+    // The caller owns the device now
+    true => std::mem::forget(device),
+    // Dropping the device releases it
+    false => unsafe { *pp_device = std::ptr::null_mut() }
   }
 
   // if hr.is_ok() {
@@ -289,30 +373,142 @@ pub(crate) unsafe extern "system" fn dxgi_present_hook(
   sync_interval: u32,
   flags: u32
 ) -> HRESULT {
-  if let Ok(mut i) = INTERFACES.get().unwrap().lock() {
-    (*i).render_andromeda();
+  PRESENT_GUARD.run(|| {
+    if let Some(swapchain) = find_dx11_swapchain(this as *mut c_void) &&
+      let Some(dx11) = swapchain.as_any().downcast_ref::<DX11Swapchain>()
+    {
+      dx11.present_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    if let Some(interfaces) = INTERFACES.get() &&
+      let Ok(mut i) = interfaces.lock()
+    {
+      unsafe { (*i).render_andromeda(this as *mut c_void) };
+    }
+  });
+
+  // Every swapchain sharing this vtable goes through the same trampoline, registered or not
+  match ORIG_PRESENT.get() {
+    Some(orig) => orig(this, sync_interval, flags),
+    None => HRESULT(0)
   }
+}
 
-  let swapchain = this as *mut c_void;
+// ----------------- Hook ResizeBuffers -----------------
+pub(crate) unsafe extern "system" fn dxgi_resize_buffers_hook(
+  this: *mut IDXGISwapChain,
+  buffer_count: u32,
+  width: u32,
+  height: u32,
+  new_format: DXGI_FORMAT,
+  swapchain_flags: u32
+) -> HRESULT {
+  info!("[HOOK] IDXGISwapChain::ResizeBuffers called ({width}x{height}, buffers={buffer_count})");
+  RESIZE_BUFFERS_GUARD.run(|| unsafe { release_overlay_views(this as *mut c_void) });
 
-  if let Some(hooked) = RENDER_TARGETS
-    .get()
-    .expect("Failed to retrieve render targets")
-    .lock()
-    .unwrap()
-    .iter()
-    .find_map(|h| {
-      h.as_any()
-        .downcast_ref::<DX11Swapchain>()
-        .filter(|dx11| dx11.swapchain.as_raw() == swapchain)
-    })
+  let orig = ORIG_RESIZE_BUFFERS.get().expect("orig ResizeBuffers missing");
+  let hr = orig(this, buffer_count, width, height, new_format, swapchain_flags);
+  if hr.is_err() {
+    error!("[HOOK] ResizeBuffers failed with error code {}", hresult_to_string(hr));
+  }
+  hr
+}
+
+pub(crate) unsafe extern "system" fn dxgi_resize_buffers1_hook(
+  this: *mut IDXGISwapChain3,
+  buffer_count: u32,
+  width: u32,
+  height: u32,
+  format: DXGI_FORMAT,
+  swapchain_flags: u32,
+  creation_node_mask: *const u32,
+  present_queue: *const *mut IUnknown
+) -> HRESULT {
+  info!("[HOOK] IDXGISwapChain3::ResizeBuffers1 called ({width}x{height}, buffers={buffer_count})");
+  RESIZE_BUFFERS_GUARD.run(|| unsafe { release_overlay_views(this as *mut c_void) });
+
+  let orig = ORIG_RESIZE_BUFFERS1.get().expect("orig ResizeBuffers1 missing");
+  let hr = orig(
+    this,
+    buffer_count,
+    width,
+    height,
+    format,
+    swapchain_flags,
+    creation_node_mask,
+    present_queue
+  );
+  if hr.is_err() {
+    error!("[HOOK] ResizeBuffers1 failed with error code {}", hresult_to_string(hr));
+  }
+  hr
+}
+
+/// Drops every view the overlay holds on the swapchain's back buffers, they are recreated on the next present
+unsafe fn release_overlay_views(swapchain: *mut c_void) {
+  if let Some(registered) = find_dx11_swapchain(swapchain) &&
+    let Some(dx11) = registered.as_any().downcast_ref::<DX11Swapchain>() &&
+    dx11.release_render_target()
   {
-    if let Some(orig) = hooked.original_present {
-      return orig(this, sync_interval, flags);
+    info!("Released overlay render target view for swapchain {:?}", swapchain);
+  }
+}
+
+// ----------------- Hook Release -----------------
+pub(crate) unsafe extern "system" fn dxgi_swapchain_release_hook(this: *mut IDXGISwapChain) -> u32 {
+  let orig = ORIG_SWAPCHAIN_RELEASE
+    .get()
+    .expect("orig IDXGISwapChain::Release missing");
+  let remaining = orig(this);
+
+  // Once only the overlay's own references are left the game is done with the swapchain
+  let swapchain = this as *mut c_void;
+  SWAPCHAIN_RELEASE_GUARD.run(|| {
+    if let Some(registered) = find_dx11_swapchain(swapchain) &&
+      let Some(dx11) = registered.as_any().downcast_ref::<DX11Swapchain>() &&
+      dx11.only_held_by_overlay(remaining)
+    {
+      info!("[HOOK] Swapchain {:?} released by the game, unregistering", swapchain);
+      dx11.release_render_target();
+      // Dropped outside the registry lock, dropping them calls back into this hook
+      let removed = unregister_dx11_swapchain(swapchain);
+      drop(removed);
+      drop(registered);
     }
+  });
+
+  remaining
+}
+
+unsafe fn install_dxgi_swapchain_hooks(swapchain: &IDXGISwapChain) {
+  let vtable = *(swapchain.as_raw() as *mut *mut *mut c_void);
+
+  // IUnknown::Release (index 2)
+  if ORIG_SWAPCHAIN_RELEASE.get().is_none() &&
+    let Ok(trampoline) = hook_vtable_method(vtable, 2, dxgi_swapchain_release_hook as *mut c_void)
+  {
+    ORIG_SWAPCHAIN_RELEASE.set(std::mem::transmute(trampoline));
+    info!("Hooked IDXGISwapChain::Release");
+  }
+
+  // IDXGISwapChain::ResizeBuffers (index 13)
+  if ORIG_RESIZE_BUFFERS.get().is_none() &&
+    let Ok(trampoline) = hook_vtable_method(vtable, 13, dxgi_resize_buffers_hook as *mut c_void)
+  {
+    ORIG_RESIZE_BUFFERS.set(std::mem::transmute(trampoline));
+    info!("Hooked IDXGISwapChain::ResizeBuffers");
   }
 
-  HRESULT(0)
+  // IDXGISwapChain3::ResizeBuffers1 (index 39)
+  if let Ok(swapchain3) = swapchain.cast::<IDXGISwapChain3>() {
+    let vtable = *(swapchain3.as_raw() as *mut *mut *mut c_void);
+    if ORIG_RESIZE_BUFFERS1.get().is_none() &&
+      let Ok(trampoline) = hook_vtable_method(vtable, 39, dxgi_resize_buffers1_hook as *mut c_void)
+    {
+      ORIG_RESIZE_BUFFERS1.set(std::mem::transmute(trampoline));
+      info!("Hooked IDXGISwapChain3::ResizeBuffers1");
+    }
+  }
 }
 
 unsafe fn install_dxgi_present_hook(
@@ -328,53 +524,59 @@ unsafe fn install_dxgi_present_hook(
   let vtable = *(swapchain.as_raw() as *mut *mut *mut c_void);
   let present_addr = *vtable.add(8);
 
-  if HOOKED_PRESENT_SLOTS.lock().unwrap().contains(&(present_addr as usize)) {
-    return Err("Present vtable already hooked");
+  let newly_hooked = !HOOKED_PRESENT_SLOTS.lock().unwrap().contains(&(present_addr as usize));
+  if newly_hooked {
+    let trampoline = min_hook_rs::create_hook(present_addr as *mut _, dxgi_present_hook as *mut _)
+      .map_err(|_| "Failed to create Present hook")?;
+    ORIG_PRESENT.set(std::mem::transmute(trampoline));
   }
-
-  let original_present: PresentFn = std::mem::transmute(
-    min_hook_rs::create_hook(present_addr as *mut _, dxgi_present_hook as *mut _)
-      .map_err(|_| "Failed to create Present hook")?
-  );
+  let original_present = *ORIG_PRESENT.get().ok_or("Present trampoline missing")?;
 
   info!("My swapchain pointer is {:?}", swapchain.as_raw());
+  install_dxgi_swapchain_hooks(&swapchain);
 
   // Push to renderer-independent registry
-  register_swapchain(Arc::new(DX11Swapchain {
-    swapchain: swapchain.clone(),
-    device: device.clone(),
-    context: context.clone(),
-    original_present: Some(original_present)
-  }));
-
-  HOOKED_PRESENT_SLOTS.lock().unwrap().insert(present_addr as usize);
-
-  min_hook_rs::enable_hook(present_addr as *mut _).map_err(|_| "Failed to enable Present hook")?;
-
-  // HOOKED_SWAPCHAINS.lock().unwrap().push(DX11Swapchain {
-  //   swapchain,
-  //   device,
-  //   context,
-  //   original_present: Some(original_present)
-  // });
+  register_swapchain(Arc::new(DX11Swapchain::new(
+    swapchain.clone(),
+    device.clone(),
+    context.clone(),
+    Some(original_present)
+  )));
+
+  if newly_hooked {
+    HOOKED_PRESENT_SLOTS.lock().unwrap().insert(present_addr as usize);
+    min_hook_rs::enable_hook(present_addr as *mut _).map_err(|_| "Failed to enable Present hook")?;
+  }
 
   Ok(original_present)
 }
 
-unsafe fn on_swapchain_created(swapchain: *mut IDXGISwapChain, device: *mut ID3D11Device) -> Result<(), String> {
-  if !swapchain.is_null() && !device.is_null() {
-    let swapchain = swapchain as *mut _;
-    let sc = IDXGISwapChain::from_raw(swapchain);
-    let device = device as *mut _;
-    let device = ID3D11Device::from_raw(device);
-    // if let Some(sc) = sc
-    //   && let Some(device) = device
-    // {
-    info!("[HOOK] Swapchain created: {:?}", swapchain);
-    install_dxgi_present_hook(sc, device)?;
-    // }
-  }
-  Err("There was an error when creating a swapchain".into())
+/// Shared registration for every swapchain creation path. `device` is whatever the game handed to the factory, so
+/// it is queried for a D3D11 device rather than assumed to be one (D3D12 passes a command queue here).
+unsafe fn on_swapchain_created(swapchain: *mut IDXGISwapChain, device: *mut IUnknown) -> Result<(), String> {
+  if swapchain.is_null() || device.is_null() {
+    return Err("Swapchain or device pointer was null".into());
+  }
+
+  let swapchain = swapchain as *mut c_void;
+  let device = device as *mut c_void;
+  // The game owns these pointers, take our own references instead of adopting theirs
+  let sc = IDXGISwapChain::from_raw_borrowed(&swapchain)
+    .cloned()
+    .ok_or("Failed to borrow swapchain")?;
+  let device = IUnknown::from_raw_borrowed(&device)
+    .ok_or("Failed to borrow device")?
+    .cast::<ID3D11Device>()
+    .map_err(|e| {
+      format!(
+        "Swapchain device is not a D3D11 device: {}",
+        hresult_to_string(e.code())
+      )
+    })?;
+
+  info!("[HOOK] Swapchain created: {:?}", swapchain);
+  install_dxgi_present_hook(sc, device)?;
+  Ok(())
 }
 
 // IDXGIFactory::CreateSwapChain
@@ -392,12 +594,14 @@ pub(crate) unsafe extern "system" fn create_swapchain_hook(
 
   if hr.is_ok() && !swapchain.is_null() {
     info!("swapchain is created");
-    let sc = unsafe { *swapchain };
-    on_swapchain_created(sc, device as *mut ID3D11Device);
+    if let Some(Err(e)) = CREATE_SWAPCHAIN_GUARD.run(|| unsafe { on_swapchain_created(*swapchain, device) }) {
+      error!("Failed to register swapchain from CreateSwapChain: {}", e);
+    }
   }
   hr
 }
 
+// IDXGIFactory2::CreateSwapChainForHwnd
 pub(crate) unsafe extern "system" fn create_swapchain_for_hwnd_hook(
   this: *mut IDXGIFactory2,
   device: *mut IUnknown,
@@ -407,13 +611,72 @@ pub(crate) unsafe extern "system" fn create_swapchain_for_hwnd_hook(
   restrict_to_output: *mut IDXGIOutput,
   swapchain: *mut *mut IDXGISwapChain1
 ) -> HRESULT {
+  info!("[HOOK] hooked CreateSwapChainForHwnd called (hwnd: {:?})", hwnd);
+
   unsafe {
     let orig = ORIG_CREATE_SWAPCHAIN_FOR_HWND
       .get()
       .expect("orig CreateSwapChainForHwnd missing");
     let hr = orig(this, device, hwnd, desc, fullscreen_desc, restrict_to_output, swapchain);
-    if hr.is_ok() {
-      info!("eeee");
+    if hr.is_ok() &&
+      !swapchain.is_null() &&
+      let Some(Err(e)) =
+        CREATE_SWAPCHAIN_GUARD.run(|| on_swapchain_created(*swapchain as *mut IDXGISwapChain, device))
+    {
+      error!("Failed to register swapchain from CreateSwapChainForHwnd: {}", e);
+    }
+    hr
+  }
+}
+
+// IDXGIFactory2::CreateSwapChainForCoreWindow
+pub(crate) unsafe extern "system" fn create_swapchain_for_corewindow_hook(
+  this: *mut IDXGIFactory2,
+  device: *mut IUnknown,
+  window: *mut IUnknown,
+  desc: *const DXGI_SWAP_CHAIN_DESC1,
+  restrict_to_output: *mut IDXGIOutput,
+  swapchain: *mut *mut IDXGISwapChain1
+) -> HRESULT {
+  info!("[HOOK] hooked CreateSwapChainForCoreWindow called");
+
+  unsafe {
+    let orig = ORIG_CREATE_SWAPCHAIN_FOR_COREWINDOW
+      .get()
+      .expect("orig CreateSwapChainForCoreWindow missing");
+    let hr = orig(this, device, window, desc, restrict_to_output, swapchain);
+    if hr.is_ok() &&
+      !swapchain.is_null() &&
+      let Some(Err(e)) =
+        CREATE_SWAPCHAIN_GUARD.run(|| on_swapchain_created(*swapchain as *mut IDXGISwapChain, device))
+    {
+      error!("Failed to register swapchain from CreateSwapChainForCoreWindow: {}", e);
+    }
+    hr
+  }
+}
+
+// IDXGIFactory2::CreateSwapChainForComposition
+pub(crate) unsafe extern "system" fn create_swapchain_for_composition_hook(
+  this: *mut IDXGIFactory2,
+  device: *mut IUnknown,
+  desc: *const DXGI_SWAP_CHAIN_DESC1,
+  restrict_to_output: *mut IDXGIOutput,
+  swapchain: *mut *mut IDXGISwapChain1
+) -> HRESULT {
+  info!("[HOOK] hooked CreateSwapChainForComposition called");
+
+  unsafe {
+    let orig = ORIG_CREATE_SWAPCHAIN_FOR_COMPOSITION
+      .get()
+      .expect("orig CreateSwapChainForComposition missing");
+    let hr = orig(this, device, desc, restrict_to_output, swapchain);
+    if hr.is_ok() &&
+      !swapchain.is_null() &&
+      let Some(Err(e)) =
+        CREATE_SWAPCHAIN_GUARD.run(|| on_swapchain_created(*swapchain as *mut IDXGISwapChain, device))
+    {
+      error!("Failed to register swapchain from CreateSwapChainForComposition: {}", e);
     }
     hr
   }
@@ -456,25 +719,25 @@ unsafe fn install_dxgi_factory_hooks(factory_ptr: *mut IDXGIFactory) -> Result<(
     info!("Factory supports IDXGIFactory2");
 
     // IDXGIFactory2::CreateSwapChainForHwnd (index 15)
-    if let Ok(trampoline) = hook_vtable_method(vtable, 15, create_swapchain_for_hwnd_hook as *mut c_void) &&
-      ORIG_CREATE_SWAPCHAIN_FOR_HWND.get().is_none()
+    if ORIG_CREATE_SWAPCHAIN_FOR_HWND.get().is_none() &&
+      let Ok(trampoline) = hook_vtable_method(vtable, 15, create_swapchain_for_hwnd_hook as *mut c_void)
     {
       ORIG_CREATE_SWAPCHAIN_FOR_HWND.set(std::mem::transmute(trampoline));
     }
 
-    // // IDXGIFactory2::CreateSwapChainForCoreWindow (index 16)
-    // hook_vtable_method(
-    //   vtable,
-    //   16,
-    //   create_swapchain_for_corewindow_detour as *mut c_void
-    // )?;
+    // IDXGIFactory2::CreateSwapChainForCoreWindow (index 16)
+    if ORIG_CREATE_SWAPCHAIN_FOR_COREWINDOW.get().is_none() &&
+      let Ok(trampoline) = hook_vtable_method(vtable, 16, create_swapchain_for_corewindow_hook as *mut c_void)
+    {
+      ORIG_CREATE_SWAPCHAIN_FOR_COREWINDOW.set(std::mem::transmute(trampoline));
+    }
 
-    // // IDXGIFactory2::CreateSwapChainForComposition (index 24)
-    // hook_method(
-    //   vtable,
-    //   24,
-    //   create_swapchain_for_composition_detour as *mut c_void
-    // )?;
+    // IDXGIFactory2::CreateSwapChainForComposition (index 24)
+    if ORIG_CREATE_SWAPCHAIN_FOR_COMPOSITION.get().is_none() &&
+      let Ok(trampoline) = hook_vtable_method(vtable, 24, create_swapchain_for_composition_hook as *mut c_void)
+    {
+      ORIG_CREATE_SWAPCHAIN_FOR_COMPOSITION.set(std::mem::transmute(trampoline));
+    }
   }
 
   Ok(())
@@ -496,9 +759,10 @@ pub(crate) unsafe extern "system" fn dxgi_create_factory_hook(
   if hr.is_ok() && !pp_factory.is_null() {
     let factory = *pp_factory as *mut IDXGIFactory;
     info!("[HOOK] Factory created - waiting for swapchain");
-    match unsafe { install_dxgi_factory_hooks(factory) } {
-      Ok(_) => info!("[HOOK] Installed DXGIFactory hooks successfully!"),
-      Err(e) => error!("Error installing DXGIFactory hooks: {}", e)
+    match CREATE_FACTORY_GUARD.run(|| unsafe { install_dxgi_factory_hooks(factory) }) {
+      Some(Ok(_)) => info!("[HOOK] Installed DXGIFactory hooks successfully!"),
+      Some(Err(e)) => error!("Error installing DXGIFactory hooks: {}", e),
+      None => {}
     }
   }
 
@@ -521,9 +785,10 @@ pub(crate) unsafe extern "system" fn dxgi_create_factory1_hook(
   if hr.is_ok() && !pp_factory.is_null() {
     let factory = *pp_factory as *mut IDXGIFactory;
     info!("[HOOK] Factory created - waiting for swapchain");
-    match unsafe { install_dxgi_factory_hooks(factory) } {
-      Ok(_) => info!("[HOOK] Installed DXGIFactory hooks successfully!"),
-      Err(e) => error!("Error installing DXGIFactory hooks: {}", e)
+    match CREATE_FACTORY_GUARD.run(|| unsafe { install_dxgi_factory_hooks(factory) }) {
+      Some(Ok(_)) => info!("[HOOK] Installed DXGIFactory hooks successfully!"),
+      Some(Err(e)) => error!("Error installing DXGIFactory hooks: {}", e),
+      None => {}
     }
   }
 
@@ -547,9 +812,10 @@ pub(crate) unsafe extern "system" fn dxgi_create_factory2_hook(
   if hr.is_ok() && !pp_factory.is_null() {
     let factory = *pp_factory as *mut IDXGIFactory;
     info!("[HOOK] Factory created - waiting for swapchain");
-    match unsafe { install_dxgi_factory_hooks(factory) } {
-      Ok(_) => info!("[HOOK] Installed DXGIFactory hooks successfully!"),
-      Err(e) => error!("Error installing DXGIFactory hooks: {}", e)
+    match CREATE_FACTORY_GUARD.run(|| unsafe { install_dxgi_factory_hooks(factory) }) {
+      Some(Ok(_)) => info!("[HOOK] Installed DXGIFactory hooks successfully!"),
+      Some(Err(e)) => error!("Error installing DXGIFactory hooks: {}", e),
+      None => {}
     }
   }
 
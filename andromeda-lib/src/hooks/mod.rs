@@ -1,7 +1,9 @@
 pub(crate) mod dx11;
+pub(crate) mod vulkan;
 
 use andromeda_common::{
   exports::{D3D11CreateDeviceAndSwapChainFn, D3D11CreateDeviceFn},
+  guard::HookGuard,
   utils::win32
 };
 use log::info;
@@ -28,13 +30,18 @@ pub(crate) type LoadLibraryWFn = unsafe extern "system" fn(lpLibFileName: *const
 
 pub(crate) static ORIG_LOADLIBRARYW: OnceCell<LoadLibraryWFn> = OnceCell::new();
 
+static LOADLIBRARYW_GUARD: HookGuard = HookGuard::new("LoadLibraryW");
+static DLL_NOTIFY_GUARD: HookGuard = HookGuard::new("DLL notification");
+
 pub(crate) unsafe extern "system" fn hook_loadlibraryw(lpLibFileName: *const u16) -> HMODULE {
-  let name = widestring::U16CStr::from_ptr_str(lpLibFileName).to_string_lossy();
-  if name.to_lowercase().contains("d3d11.dll") || name.to_lowercase().contains("dxgi.dll") {
-    println!("[+] {} loaded, installing DX hooks", name);
-    // install_dxgi_hooks();
-  }
-  ORIG_LOADLIBRARYW.get().unwrap()(lpLibFileName)
+  LOADLIBRARYW_GUARD.run(|| {
+    let name = unsafe { widestring::U16CStr::from_ptr_str(lpLibFileName) }.to_string_lossy();
+    if name.to_lowercase().contains("d3d11.dll") || name.to_lowercase().contains("dxgi.dll") {
+      info!("{} loaded, installing DX hooks", name);
+      // install_dxgi_hooks();
+    }
+  });
+  ORIG_LOADLIBRARYW.get().expect("orig LoadLibraryW missing")(lpLibFileName)
 }
 
 /// Generic vtable hook installer
@@ -115,6 +122,10 @@ pub(crate) unsafe extern "system" fn dll_notify(
   data: *const LDR_DLL_NOTIFICATION_DATA,
   _context: *mut c_void
 ) {
+  DLL_NOTIFY_GUARD.run(|| unsafe { on_dll_notification(reason, data) });
+}
+
+unsafe fn on_dll_notification(reason: u64, data: *const LDR_DLL_NOTIFICATION_DATA) {
   if reason == 1 {
     // DLL loaded
     let base_name = unicode_to_string(&(*data).Loaded.BaseDllName);
@@ -0,0 +1,542 @@
+use std::{
+  cell::Cell,
+  collections::HashMap,
+  error::Error,
+  ffi::{CStr, c_char, c_void},
+  ptr,
+  sync::{
+    Arc, Mutex, OnceLock,
+    atomic::{AtomicU64, Ordering}
+  }
+};
+
+use andromeda_common::guard::HookGuard;
+use ash::vk;
+use log::{error, info};
+use once_cell::sync::Lazy;
+use windows::Win32::Foundation::HWND;
+
+use crate::{
+  internal::{
+    INTERFACES,
+    swapchain_util::{VulkanSwapchain, find_vulkan_swapchain, register_swapchain, unregister_vulkan_swapchain}
+  },
+  util::get_module_symbol_address
+};
+
+const VULKAN_MODULE: &str = "vulkan-1.dll";
+
+pub struct VulkanHooks {
+  pub create_device: OnceLock<vk::PFN_vkCreateDevice>,
+  pub destroy_device: OnceLock<vk::PFN_vkDestroyDevice>,
+  pub create_win32_surface: OnceLock<vk::PFN_vkCreateWin32SurfaceKHR>,
+  pub get_device_proc_addr: OnceLock<vk::PFN_vkGetDeviceProcAddr>,
+  pub create_swapchain: OnceLock<vk::PFN_vkCreateSwapchainKHR>,
+  pub destroy_swapchain: OnceLock<vk::PFN_vkDestroySwapchainKHR>,
+  pub queue_present: OnceLock<vk::PFN_vkQueuePresentKHR>
+}
+
+impl VulkanHooks {
+  pub const fn new() -> Self {
+    Self {
+      create_device: OnceLock::new(),
+      destroy_device: OnceLock::new(),
+      create_win32_surface: OnceLock::new(),
+      get_device_proc_addr: OnceLock::new(),
+      create_swapchain: OnceLock::new(),
+      destroy_swapchain: OnceLock::new(),
+      queue_present: OnceLock::new()
+    }
+  }
+}
+
+pub(crate) static VULKAN_HOOKS: VulkanHooks = VulkanHooks::new();
+
+static GET_DEVICE_PROC_ADDR_GUARD: HookGuard = HookGuard::new("vkGetDeviceProcAddr");
+static DEVICE_GUARD: HookGuard = HookGuard::new("vkCreateDevice");
+static SURFACE_GUARD: HookGuard = HookGuard::new("vkCreateWin32SurfaceKHR");
+static SWAPCHAIN_GUARD: HookGuard = HookGuard::new("vkCreateSwapchainKHR");
+static QUEUE_PRESENT_GUARD: HookGuard = HookGuard::new("vkQueuePresentKHR");
+
+#[derive(Clone, Copy)]
+struct QueueInfo {
+  family_index: u32,
+  flags: vk::QueueFlags
+}
+
+/// What the overlay needs to know about a device the game created
+struct VulkanDevice {
+  device: ash::Device,
+  memory_properties: vk::PhysicalDeviceMemoryProperties,
+  swapchain_fn: ash::khr::swapchain::DeviceFn,
+  queues: HashMap<vk::Queue, QueueInfo>
+}
+
+static VULKAN_DEVICES: Lazy<Mutex<HashMap<vk::Device, VulkanDevice>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Window behind every surface the game created, swapchains only know their surface
+static SURFACE_WINDOWS: Lazy<Mutex<HashMap<vk::SurfaceKHR, vk::HWND>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Core instance functions resolved from the loader's exports, they dispatch on their first argument
+static LOADER_INSTANCE_FN: Lazy<ash::InstanceFnV1_0> = Lazy::new(|| {
+  ash::InstanceFnV1_0::load(|name| {
+    name
+      .to_str()
+      .ok()
+      .and_then(|name| get_module_symbol_address(VULKAN_MODULE, name))
+      .map_or(ptr::null(), |address| address as *const c_void)
+  })
+});
+
+thread_local! {
+  /// Set while we resolve device functions for ourselves, so they aren't swapped for our detours
+  static G_IN_OVERLAY_LOADER: Cell<bool> = const { Cell::new(false) }
+}
+
+pub(crate) unsafe fn try_install_vulkan_hooks() -> Result<(), Box<dyn Error>> {
+  unsafe {
+    if get_module_symbol_address(VULKAN_MODULE, "vkQueuePresentKHR").is_none() {
+      return Err(format!("{VULKAN_MODULE} is not loaded").into());
+    }
+
+    hook_vulkan_export(
+      &VULKAN_HOOKS.create_device,
+      "vkCreateDevice",
+      vk_create_device_hook as *mut c_void
+    )?;
+    hook_vulkan_export(
+      &VULKAN_HOOKS.destroy_device,
+      "vkDestroyDevice",
+      vk_destroy_device_hook as *mut c_void
+    )?;
+    // vkGetInstanceProcAddr hands out the loader's own WSI trampolines, so hooking the export catches every caller
+    hook_vulkan_export(
+      &VULKAN_HOOKS.create_win32_surface,
+      "vkCreateWin32SurfaceKHR",
+      vk_create_win32_surface_hook as *mut c_void
+    )?;
+    hook_vulkan_export(
+      &VULKAN_HOOKS.get_device_proc_addr,
+      "vkGetDeviceProcAddr",
+      vk_get_device_proc_addr_hook as *mut c_void
+    )?;
+    hook_vulkan_export(
+      &VULKAN_HOOKS.create_swapchain,
+      "vkCreateSwapchainKHR",
+      vk_create_swapchain_hook as *mut c_void
+    )?;
+    hook_vulkan_export(
+      &VULKAN_HOOKS.destroy_swapchain,
+      "vkDestroySwapchainKHR",
+      vk_destroy_swapchain_hook as *mut c_void
+    )?;
+    hook_vulkan_export(
+      &VULKAN_HOOKS.queue_present,
+      "vkQueuePresentKHR",
+      vk_queue_present_hook as *mut c_void
+    )?;
+
+    Ok(())
+  }
+}
+
+/// Hooks a loader export once. The trampoline stays valid for every device, the loader dispatches on the handle.
+unsafe fn hook_vulkan_export<T: Copy>(
+  original: &OnceLock<T>,
+  symbol: &str,
+  detour: *mut c_void
+) -> Result<(), Box<dyn Error>> {
+  unsafe {
+    if original.get().is_some() {
+      return Ok(());
+    }
+
+    info!("Hooking {symbol}");
+    let (trampoline, target) = min_hook_rs::create_hook_api_ex(VULKAN_MODULE, symbol, detour)
+      .map_err(|_| format!("Failed to hook {symbol}!"))?;
+
+    original.get_or_init(|| std::mem::transmute_copy(&trampoline));
+    min_hook_rs::enable_hook(target)?;
+    Ok(())
+  }
+}
+
+// ----------------- Hook vkGetDeviceProcAddr -----------------
+/// Most games fetch device functions through vkGetDeviceProcAddr and never call the loader exports, so hand out our
+/// detours there as well
+pub(crate) unsafe extern "system" fn vk_get_device_proc_addr_hook(
+  device: vk::Device,
+  p_name: *const c_char
+) -> vk::PFN_vkVoidFunction {
+  unsafe {
+    let orig = VULKAN_HOOKS
+      .get_device_proc_addr
+      .get()
+      .expect("orig vkGetDeviceProcAddr missing");
+
+    if !G_IN_OVERLAY_LOADER.get() &&
+      !p_name.is_null() &&
+      let Some(Some(detour)) = GET_DEVICE_PROC_ADDR_GUARD.run(|| device_detour(CStr::from_ptr(p_name)))
+    {
+      return Some(detour);
+    }
+
+    orig(device, p_name)
+  }
+}
+
+fn device_detour(name: &CStr) -> Option<unsafe extern "system" fn()> {
+  let detour = match name.to_bytes() {
+    b"vkDestroyDevice" if VULKAN_HOOKS.destroy_device.get().is_some() => vk_destroy_device_hook as *const (),
+    b"vkCreateSwapchainKHR" if VULKAN_HOOKS.create_swapchain.get().is_some() => vk_create_swapchain_hook as *const (),
+    b"vkDestroySwapchainKHR" if VULKAN_HOOKS.destroy_swapchain.get().is_some() => {
+      vk_destroy_swapchain_hook as *const ()
+    }
+    b"vkQueuePresentKHR" if VULKAN_HOOKS.queue_present.get().is_some() => vk_queue_present_hook as *const (),
+    _ => return None
+  };
+  Some(unsafe { std::mem::transmute::<*const (), unsafe extern "system" fn()>(detour) })
+}
+
+// ----------------- Hook vkCreateDevice / vkDestroyDevice -----------------
+pub(crate) unsafe extern "system" fn vk_create_device_hook(
+  physical_device: vk::PhysicalDevice,
+  p_create_info: *const vk::DeviceCreateInfo<'_>,
+  p_allocator: *const vk::AllocationCallbacks<'_>,
+  p_device: *mut vk::Device
+) -> vk::Result {
+  unsafe {
+    info!("[HOOK] vkCreateDevice called");
+    let orig = VULKAN_HOOKS.create_device.get().expect("orig vkCreateDevice missing");
+
+    let result = orig(physical_device, p_create_info, p_allocator, p_device);
+    if result == vk::Result::SUCCESS &&
+      !p_device.is_null() &&
+      let Some(create_info) = p_create_info.as_ref() &&
+      let Some(Err(e)) = DEVICE_GUARD.run(|| on_device_created(physical_device, create_info, *p_device))
+    {
+      error!("[HOOK] Failed to track Vulkan device: {}", e);
+    }
+    result
+  }
+}
+
+unsafe fn on_device_created(
+  physical_device: vk::PhysicalDevice,
+  create_info: &vk::DeviceCreateInfo<'_>,
+  device: vk::Device
+) -> Result<(), String> {
+  unsafe {
+    let instance_fn = &*LOADER_INSTANCE_FN;
+
+    G_IN_OVERLAY_LOADER.set(true);
+    let ash_device = ash::Device::load(instance_fn, device);
+    let swapchain_fn = ash::khr::swapchain::DeviceFn::load(|name| {
+      (instance_fn.get_device_proc_addr)(device, name.as_ptr()).map_or(ptr::null(), |f| f as *const c_void)
+    });
+    G_IN_OVERLAY_LOADER.set(false);
+
+    let mut memory_properties = vk::PhysicalDeviceMemoryProperties::default();
+    (instance_fn.get_physical_device_memory_properties)(physical_device, &mut memory_properties);
+
+    let mut family_count = 0;
+    (instance_fn.get_physical_device_queue_family_properties)(physical_device, &mut family_count, ptr::null_mut());
+    let mut families = vec![vk::QueueFamilyProperties::default(); family_count as usize];
+    (instance_fn.get_physical_device_queue_family_properties)(
+      physical_device,
+      &mut family_count,
+      families.as_mut_ptr()
+    );
+
+    let queue_create_infos = raw_slice(create_info.p_queue_create_infos, create_info.queue_create_info_count);
+
+    let mut queues = HashMap::new();
+    for queue_create_info in queue_create_infos {
+      // Protected queues can only be fetched with vkGetDeviceQueue2, the overlay never draws on them
+      if !queue_create_info.flags.is_empty() {
+        continue;
+      }
+      let Some(family) = families.get(queue_create_info.queue_family_index as usize) else {
+        return Err(format!(
+          "Queue family {} does not exist",
+          queue_create_info.queue_family_index
+        ));
+      };
+      for index in 0..queue_create_info.queue_count {
+        let queue = ash_device.get_device_queue(queue_create_info.queue_family_index, index);
+        queues.insert(
+          queue,
+          QueueInfo {
+            family_index: queue_create_info.queue_family_index,
+            flags: family.queue_flags
+          }
+        );
+      }
+    }
+
+    info!("Tracking Vulkan device {:?} with {} queues", device, queues.len());
+    VULKAN_DEVICES
+      .lock()
+      .map_err(|_| "Vulkan device registry is poisoned".to_string())?
+      .insert(
+        device,
+        VulkanDevice {
+          device: ash_device,
+          memory_properties,
+          swapchain_fn,
+          queues
+        }
+      );
+    Ok(())
+  }
+}
+
+pub(crate) unsafe extern "system" fn vk_destroy_device_hook(
+  device: vk::Device,
+  p_allocator: *const vk::AllocationCallbacks<'_>
+) {
+  unsafe {
+    info!("[HOOK] vkDestroyDevice called");
+    DEVICE_GUARD.run(|| {
+      if let Ok(mut devices) = VULKAN_DEVICES.lock() {
+        devices.remove(&device);
+      }
+    });
+
+    let orig = VULKAN_HOOKS.destroy_device.get().expect("orig vkDestroyDevice missing");
+    orig(device, p_allocator)
+  }
+}
+
+// ----------------- Hook vkCreateWin32SurfaceKHR -----------------
+pub(crate) unsafe extern "system" fn vk_create_win32_surface_hook(
+  instance: vk::Instance,
+  p_create_info: *const vk::Win32SurfaceCreateInfoKHR<'_>,
+  p_allocator: *const vk::AllocationCallbacks<'_>,
+  p_surface: *mut vk::SurfaceKHR
+) -> vk::Result {
+  unsafe {
+    info!("[HOOK] vkCreateWin32SurfaceKHR called");
+    let orig = VULKAN_HOOKS
+      .create_win32_surface
+      .get()
+      .expect("orig vkCreateWin32SurfaceKHR missing");
+
+    let result = orig(instance, p_create_info, p_allocator, p_surface);
+    if result == vk::Result::SUCCESS &&
+      !p_surface.is_null() &&
+      let Some(create_info) = p_create_info.as_ref()
+    {
+      SURFACE_GUARD.run(|| {
+        // Surface handles can be reused after vkDestroySurfaceKHR, the latest window wins
+        if let Ok(mut windows) = SURFACE_WINDOWS.lock() {
+          windows.insert(*p_surface, create_info.hwnd);
+        }
+      });
+    }
+    result
+  }
+}
+
+// ----------------- Hook vkCreateSwapchainKHR / vkDestroySwapchainKHR -----------------
+pub(crate) unsafe extern "system" fn vk_create_swapchain_hook(
+  device: vk::Device,
+  p_create_info: *const vk::SwapchainCreateInfoKHR<'_>,
+  p_allocator: *const vk::AllocationCallbacks<'_>,
+  p_swapchain: *mut vk::SwapchainKHR
+) -> vk::Result {
+  unsafe {
+    info!("[HOOK] vkCreateSwapchainKHR called");
+    let orig = VULKAN_HOOKS
+      .create_swapchain
+      .get()
+      .expect("orig vkCreateSwapchainKHR missing");
+
+    let Some(create_info) = p_create_info.as_ref().filter(|_| !SWAPCHAIN_GUARD.is_disabled()) else {
+      return orig(device, p_create_info, p_allocator, p_swapchain);
+    };
+
+    // The overlay renders straight into the swapchain images, surfaces always support color attachment usage
+    let mut create_info = *create_info;
+    create_info.image_usage |= vk::ImageUsageFlags::COLOR_ATTACHMENT;
+
+    let result = orig(device, &create_info, p_allocator, p_swapchain);
+    if result != vk::Result::SUCCESS || p_swapchain.is_null() {
+      return result;
+    }
+
+    let registered = SWAPCHAIN_GUARD.run(|| {
+      // A successful recreate retires the old swapchain, our views and framebuffers of its images go with it
+      if create_info.old_swapchain != vk::SwapchainKHR::null() {
+        drop(unregister_vulkan_swapchain(create_info.old_swapchain));
+      }
+      on_swapchain_created(device, &create_info, *p_swapchain)
+    });
+    if let Some(Err(e)) = registered {
+      error!("[HOOK] Failed to register Vulkan swapchain: {}", e);
+    }
+    result
+  }
+}
+
+unsafe fn on_swapchain_created(
+  device: vk::Device,
+  create_info: &vk::SwapchainCreateInfoKHR<'_>,
+  swapchain: vk::SwapchainKHR
+) -> Result<(), String> {
+  unsafe {
+    let devices = VULKAN_DEVICES
+      .lock()
+      .map_err(|_| "Vulkan device registry is poisoned".to_string())?;
+    let Some(tracked) = devices.get(&device) else {
+      return Err(format!("Swapchain was created on untracked device {:?}", device));
+    };
+
+    let get_swapchain_images = tracked.swapchain_fn.get_swapchain_images_khr;
+    let mut image_count = 0;
+    let result = get_swapchain_images(device, swapchain, &mut image_count, ptr::null_mut());
+    if result != vk::Result::SUCCESS {
+      return Err(format!("vkGetSwapchainImagesKHR failed: {}", result));
+    }
+    let mut images = vec![vk::Image::null(); image_count as usize];
+    let result = get_swapchain_images(device, swapchain, &mut image_count, images.as_mut_ptr());
+    if result != vk::Result::SUCCESS {
+      return Err(format!("vkGetSwapchainImagesKHR failed: {}", result));
+    }
+    images.truncate(image_count as usize);
+
+    // Surfaces created before we were injected have no known window, their overlay gets no input
+    let hwnd = SURFACE_WINDOWS
+      .lock()
+      .ok()
+      .and_then(|windows| windows.get(&create_info.surface).copied())
+      .unwrap_or_default();
+
+    info!(
+      "Registered Vulkan swapchain {:?} ({}x{}, {:?}, {} images)",
+      swapchain,
+      create_info.image_extent.width,
+      create_info.image_extent.height,
+      create_info.image_format,
+      images.len()
+    );
+    register_swapchain(Arc::new(VulkanSwapchain {
+      swapchain,
+      device: tracked.device.clone(),
+      memory_properties: tracked.memory_properties,
+      format: create_info.image_format,
+      extent: create_info.image_extent,
+      images,
+      hwnd: HWND(hwnd as *mut c_void),
+      present_count: AtomicU64::new(0),
+      overlay: Mutex::new(None)
+    }));
+    Ok(())
+  }
+}
+
+pub(crate) unsafe extern "system" fn vk_destroy_swapchain_hook(
+  device: vk::Device,
+  swapchain: vk::SwapchainKHR,
+  p_allocator: *const vk::AllocationCallbacks<'_>
+) {
+  unsafe {
+    info!("[HOOK] vkDestroySwapchainKHR called");
+    // Overlay resources reference the swapchain images and must be gone first
+    SWAPCHAIN_GUARD.run(|| drop(unregister_vulkan_swapchain(swapchain)));
+
+    let orig = VULKAN_HOOKS
+      .destroy_swapchain
+      .get()
+      .expect("orig vkDestroySwapchainKHR missing");
+    orig(device, swapchain, p_allocator)
+  }
+}
+
+// ----------------- Hook vkQueuePresentKHR -----------------
+pub(crate) unsafe extern "system" fn vk_queue_present_hook(
+  queue: vk::Queue,
+  p_present_info: *const vk::PresentInfoKHR<'_>
+) -> vk::Result {
+  unsafe {
+    let orig = VULKAN_HOOKS
+      .queue_present
+      .get()
+      .expect("orig vkQueuePresentKHR missing");
+
+    let Some(present_info) = p_present_info.as_ref() else {
+      return orig(queue, p_present_info);
+    };
+
+    match QUEUE_PRESENT_GUARD
+      .run(|| render_overlays(queue, present_info))
+      .flatten()
+    {
+      Some(overlay_finished) => {
+        // The overlay pass already waited on the game's semaphores, presentation only has to wait on the overlay
+        let wait_semaphores = [overlay_finished];
+        let mut present_info = *present_info;
+        present_info.wait_semaphore_count = 1;
+        present_info.p_wait_semaphores = wait_semaphores.as_ptr();
+        orig(queue, &present_info)
+      }
+      None => orig(queue, p_present_info)
+    }
+  }
+}
+
+/// Draws the overlay onto every presented swapchain that wants it, chaining the submissions with semaphores.
+/// Returns the semaphore signaled by the last overlay pass, `None` if nothing was drawn.
+unsafe fn render_overlays(queue: vk::Queue, present_info: &vk::PresentInfoKHR<'_>) -> Option<vk::Semaphore> {
+  unsafe {
+    let swapchains: &[vk::SwapchainKHR] = raw_slice(present_info.p_swapchains, present_info.swapchain_count);
+    let image_indices: &[u32] = raw_slice(present_info.p_image_indices, present_info.swapchain_count);
+
+    let targets: Vec<_> = swapchains
+      .iter()
+      .zip(image_indices)
+      .filter_map(|(&swapchain, &image_index)| find_vulkan_swapchain(swapchain).map(|target| (target, image_index)))
+      .collect();
+    for (target, _) in &targets {
+      if let Some(vulkan) = target.as_any().downcast_ref::<VulkanSwapchain>() {
+        vulkan.present_count.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+
+    let queue_info = VULKAN_DEVICES
+      .lock()
+      .ok()?
+      .values()
+      .find_map(|device| device.queues.get(&queue).copied())?;
+    if !queue_info.flags.contains(vk::QueueFlags::GRAPHICS) {
+      return None;
+    }
+
+    let mut interfaces = INTERFACES.get()?.lock().ok()?;
+    let mut wait_semaphores = raw_slice(present_info.p_wait_semaphores, present_info.wait_semaphore_count).to_vec();
+    let mut overlay_finished = None;
+
+    for (target, image_index) in &targets {
+      match interfaces.render_vulkan(target, queue, queue_info.family_index, *image_index, &wait_semaphores) {
+        Ok(Some(semaphore)) => {
+          wait_semaphores = vec![semaphore];
+          overlay_finished = Some(semaphore);
+        }
+        Ok(None) => {}
+        Err(e) => error!("Failed to render Vulkan overlay: {}", e)
+      }
+    }
+
+    overlay_finished
+  }
+}
+
+/// Borrows a pointer and count pair from a Vulkan create or present info
+unsafe fn raw_slice<'a, T>(data: *const T, len: u32) -> &'a [T] {
+  unsafe {
+    match data.is_null() || len == 0 {
+      true => &[],
+      false => std::slice::from_raw_parts(data, len as usize)
+    }
+  }
+}
@@ -0,0 +1,79 @@
+//! Keyboard and mouse input the game reads through DirectInput.
+//!
+//! The proxy hands us its [`InputApi`] at startup. Every event the game reads passes the observers registered here and
+//! is kept from the game while the overlay has focus. Without the proxy (the payload was injected some other way)
+//! observers never run and injecting does nothing.
+
+use std::sync::{Arc, Mutex, OnceLock};
+
+pub use andromeda_common::input::{InputDevice, InputEvent};
+use andromeda_common::{guard::HookGuard, input::InputApi};
+use log::info;
+use once_cell::sync::Lazy;
+
+use crate::{egui_backend::win32_backend, ui::overlay_visible};
+
+pub type InputObserver = Arc<dyn Fn(&InputEvent) + Send + Sync>;
+
+/// A `static` of the proxy, which outlives us
+static PROXY_INPUT: OnceLock<&'static InputApi> = OnceLock::new();
+static OBSERVERS: Lazy<Mutex<Vec<InputObserver>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static FILTER_GUARD: HookGuard = HookGuard::new("input filter");
+
+pub(crate) unsafe fn init(api: *const InputApi) {
+  let Some(api) = (unsafe { api.as_ref() }) else {
+    info!("Loaded without the DirectInput proxy, game input can't be observed");
+    return;
+  };
+  if PROXY_INPUT.set(api).is_ok() {
+    unsafe { (api.set_filter)(Some(filter)) };
+    info!("Filtering DirectInput input of the game");
+  }
+}
+
+/// Calls `observer` with every event the game reads, including the ones the overlay keeps from it
+pub fn observe(observer: impl Fn(&InputEvent) + Send + Sync + 'static) {
+  if let Ok(mut observers) = OBSERVERS.lock() {
+    observers.push(Arc::new(observer));
+  }
+}
+
+/// Makes the game read `event` as if it came from its keyboard or mouse. Returns `false` without the proxy.
+pub fn inject(event: InputEvent) -> bool {
+  match PROXY_INPUT.get() {
+    Some(api) => {
+      unsafe { (api.inject)(&event) };
+      true
+    }
+    None => false
+  }
+}
+
+/// Whether the overlay keeps input of `device` from the game right now
+pub fn overlay_captures(device: InputDevice) -> bool {
+  overlay_visible() &&
+    match device {
+      InputDevice::Keyboard => win32_backend::wants_keyboard(),
+      InputDevice::Mouse => win32_backend::wants_pointer()
+    }
+}
+
+unsafe extern "system" fn filter(event: *const InputEvent) -> bool {
+  let Some(event) = (unsafe { event.as_ref() }) else {
+    return false;
+  };
+  // The game gets its input whenever the filter can't decide
+  FILTER_GUARD.run(|| filter_event(event)).unwrap_or(false)
+}
+
+fn filter_event(event: &InputEvent) -> bool {
+  // Observers run without the list locked, so they may register more observers or inject input
+  let observers = match OBSERVERS.lock() {
+    Ok(observers) => observers.clone(),
+    Err(_) => Vec::new()
+  };
+  for observer in observers {
+    observer(event);
+  }
+  overlay_captures(event.device())
+}
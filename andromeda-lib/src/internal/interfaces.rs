@@ -1,17 +1,25 @@
-use std::{ptr::swap, sync::Arc};
+use std::{ffi::c_void, ptr::swap, sync::Arc};
 
+use andromeda_common::config::OverlayConfig;
+use ash::{prelude::VkResult, vk};
+use log::{error, info};
 use windows::{
-  Win32::Graphics::{
-    Direct3D11::{ID3D11Device, ID3D11RenderTargetView, ID3D11Texture2D},
-    Dxgi::IDXGISwapChain
+  Win32::{
+    Foundation::HWND,
+    Graphics::{
+      Direct3D11::{ID3D11Device, ID3D11RenderTargetView},
+      Dxgi::IDXGISwapChain
+    }
   },
   core::HRESULT
 };
 
 use crate::{
-  egui_backend::{EGuiBackend, win32_backend::Win32Backend},
-  exports::{EGUI_CTX, EGUI_DX11, RT_VIEW},
-  internal::swapchain_util::{Backend, DX11Swapchain, RENDER_TARGETS, game_device_swapchain}
+  egui_backend::{EGuiBackend, d3d11::pipeline_state::PipelineState, win32_backend::Win32Backend},
+  internal::swapchain_util::{
+    DX11OverlayState, DX11Swapchain, SwapchainBase, VulkanSwapchain, find_dx11_swapchain, game_device_swapchain
+  },
+  ui::{PainterTextures, SharedOverlay, screen_input}
 };
 
 // if let (Some(ctx), Some(painter)) = (&EGUI_CTX, &mut EGUI_DX11) {
@@ -47,7 +55,11 @@ use crate::{
 // }
 
 pub(crate) struct Interfaces {
-  backend: Option<Arc<dyn EGuiBackend>>
+  backend: Option<Arc<dyn EGuiBackend>>,
+  process_name: String,
+  overlay_config: OverlayConfig,
+  /// One overlay for every swapchain, only the painters are per swapchain
+  overlay: SharedOverlay
 }
 
 unsafe impl Send for Interfaces {}
@@ -55,75 +67,158 @@ unsafe impl Sync for Interfaces {}
 
 impl Interfaces {
   pub fn new() -> Self {
-    Self { backend: None }
+    Self {
+      backend: None,
+      process_name: String::new(),
+      overlay_config: OverlayConfig::default(),
+      overlay: SharedOverlay::new()
+    }
+  }
+
+  pub fn configure(&mut self, process_name: String, overlay_config: OverlayConfig) {
+    info!(
+      "Overlay main swapchain rule for '{}': {:?} (mirror: {})",
+      process_name,
+      overlay_config.main_swapchain_rule(&process_name),
+      overlay_config.mirror_overlay
+    );
+    self.process_name = process_name;
+    self.overlay_config = overlay_config;
   }
 
   unsafe fn setup_hooks() {}
 
-  pub unsafe fn render_andromeda(&mut self) -> Option<Arc<dyn EGuiBackend>> {
+  /// Draws the overlay onto the DX11 swapchain `this` is about to present, if it is the main swapchain or a mirror
+  pub unsafe fn render_andromeda(&mut self, this: *mut c_void) -> Option<Arc<dyn EGuiBackend>> {
     let backend = unsafe { self.backend.clone().or_else(|| self.init_backend()) };
 
-    if let Ok(mut c) = EGUI_CTX.lock() {
-      if c.is_none() {
-        *c = Some(egui::Context::default());
-      }
+    // A clone of the registry's entry, so swapchain `Release` calls made while rendering can't deadlock on it
+    let Some(target) = find_dx11_swapchain(this) else {
+      return backend;
+    };
+    let Some(swapchain) = target.as_any().downcast_ref::<DX11Swapchain>() else {
+      return backend;
+    };
+    let rule = self.overlay_config.main_swapchain_rule(&self.process_name);
+    let is_main = game_device_swapchain(rule).is_some_and(|main| Arc::ptr_eq(&main, &target));
+    if !is_main && !self.overlay_config.mirror_overlay {
+      return backend;
     }
 
-    if let Ok(mut r) = EGUI_DX11.lock() {
-      let game_device = game_device_swapchain().unwrap();
-      let game_device = game_device.as_any().downcast_ref::<DX11Swapchain>().unwrap();
-      if r.is_none() {
-        let swap_chain_texture = game_device.swapchain.GetBuffer::<ID3D11Texture2D>(0).unwrap();
-        if let Ok(mut rt) = RT_VIEW.lock() {
-          if rt.is_none() {
-            let mut render_target = None;
-            game_device
-              .device
-              .CreateRenderTargetView(&swap_chain_texture, None, Some(&mut render_target));
-            *rt = render_target;
-          }
-        }
-        *r = Some(egui_directx11::Renderer::new(&game_device.device).unwrap());
-      }
+    // Mirrors show the main swapchain's last frame, only its window drives the overlay
+    if is_main {
+      let info = swapchain.info();
+      self.run_overlay(backend.as_deref(), swapchain.hwnd, [info.width, info.height]);
+    }
+    if let Err(e) = unsafe { Self::render_dx11(&self.overlay, swapchain) } {
+      error!("Failed to render overlay: {}", e);
     }
 
-    let targets = RENDER_TARGETS.get().unwrap().lock().unwrap();
-    for t in targets.iter() {
-      match t.backend() {
-        Backend::DX11 => {
-          let context = t.as_any().downcast_ref::<DX11Swapchain>().unwrap();
-          if let (Ok(ctx), Ok(mut renderer), Ok(mut rt)) = (EGUI_CTX.lock(), EGUI_DX11.lock(), RT_VIEW.lock()) {
-            if let Some(egui_ctx) = ctx.as_ref() &&
-              let Some(egui_dx11) = renderer.as_mut() &&
-              let Some(rt) = rt.as_mut()
-            {
-              // Begin egui frame
-              let raw_input = egui::RawInput::default();
-              egui_ctx.begin_pass(raw_input);
-
-              egui::Window::new("Andromeda")
-                .default_pos(egui::pos2(500.0, 300.0))
-                .default_size(egui::Vec2::new(800.0, 500.0))
-                .show(&egui_ctx, |ui| {
-                  ui.label("Hello from egui!");
-                  ui.add(egui::Slider::new(&mut 25, 0..=100).text("Sliiide"));
-
-                  if ui.button("Testing testing").clicked() {}
-                });
-
-              let full_output = egui_ctx.end_pass();
-              let (renderer_output, platform_output, _) = egui_directx11::split_output(full_output);
-              egui_dx11.render(&context.context, &rt, &egui_ctx, renderer_output, 1.0);
-            }
-          }
-        }
-        Backend::Vulkan => {
-          // Call Vulkan overlay renderer
-        }
+    backend
+  }
+
+  /// Runs a frame of the overlay with the input of the main swapchain's window
+  fn run_overlay(&mut self, input: Option<&dyn EGuiBackend>, hwnd: HWND, size: [u32; 2]) {
+    let raw_input = match input {
+      Some(input) => {
+        input.attach(hwnd);
+        input.take_input(size)
       }
+      None => screen_input(size[0], size[1], 1.0)
+    };
+    let platform_output = self.overlay.run(raw_input, size);
+    if let Some(input) = input {
+      input.handle_output(self.overlay.context(), &platform_output);
     }
+  }
 
-    backend
+  unsafe fn render_dx11(shared: &SharedOverlay, swapchain: &DX11Swapchain) -> windows::core::Result<()> {
+    let Ok(mut overlay) = swapchain.overlay.lock() else {
+      return Ok(());
+    };
+
+    if overlay.is_none() {
+      *overlay = Some(DX11OverlayState {
+        textures: PainterTextures::default(),
+        renderer: egui_directx11::Renderer::new(&swapchain.device)?,
+        render_target: None,
+        render_target_references: 0
+      });
+    }
+    let Some(state) = overlay.as_mut() else {
+      return Ok(());
+    };
+
+    // The view is dropped whenever the swapchain resizes, so recreate it from the new back buffer
+    if state.render_target.is_none() {
+      unsafe { swapchain.create_render_target(state) }?;
+    }
+    let Some(render_target) = state.render_target.as_ref() else {
+      return Ok(());
+    };
+
+    let info = swapchain.info();
+    let Some(full_output) = shared.frame_for(&mut state.textures, [info.width, info.height]) else {
+      return Ok(());
+    };
+    let pixels_per_point = full_output.pixels_per_point;
+    let (renderer_output, _, _) = egui_directx11::split_output(full_output);
+    // The game's state goes back even if rendering fails halfway, our view must not stay bound past this frame
+    let game_state = unsafe { PipelineState::capture(&swapchain.context) };
+    let rendered = state.renderer.render(
+      &swapchain.context,
+      render_target,
+      shared.context(),
+      renderer_output,
+      pixels_per_point
+    );
+    unsafe { game_state.restore(&swapchain.context) };
+    rendered
+  }
+
+  /// Draws the overlay onto `image_index` of a Vulkan swapchain if it is an overlay target. Returns the semaphore the
+  /// present has to wait on, or `None` if nothing was drawn and the game's semaphores still apply.
+  pub unsafe fn render_vulkan(
+    &mut self,
+    target: &Arc<dyn SwapchainBase + Send + Sync>,
+    queue: vk::Queue,
+    queue_family_index: u32,
+    image_index: u32,
+    wait_semaphores: &[vk::Semaphore]
+  ) -> VkResult<Option<vk::Semaphore>> {
+    let Some(swapchain) = target.as_any().downcast_ref::<VulkanSwapchain>() else {
+      return Ok(None);
+    };
+    let rule = self.overlay_config.main_swapchain_rule(&self.process_name);
+    let is_main = game_device_swapchain(rule).is_some_and(|main| Arc::ptr_eq(&main, target));
+    if !self.overlay_config.mirror_overlay && !is_main {
+      return Ok(None);
+    }
+    if is_main {
+      let input = unsafe { self.backend.clone().or_else(|| self.init_backend()) };
+      self.run_overlay(
+        input.as_deref(),
+        swapchain.hwnd,
+        [swapchain.extent.width, swapchain.extent.height]
+      );
+    }
+
+    let mut overlay = swapchain.overlay_state(queue_family_index)?;
+    let Some(state) = overlay.as_mut() else {
+      return Ok(None);
+    };
+
+    let extent = state.overlay.extent();
+    let Some(full_output) = self
+      .overlay
+      .frame_for(&mut state.textures, [extent.width, extent.height])
+    else {
+      return Ok(None);
+    };
+    state
+      .overlay
+      .render(queue, image_index, wait_semaphores, self.overlay.context(), full_output)
+      .map(Some)
   }
 
   unsafe fn init_backend(&mut self) -> Option<Arc<dyn EGuiBackend>> {
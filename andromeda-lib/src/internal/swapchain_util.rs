@@ -1,41 +1,78 @@
 use std::{
   any::Any,
   ffi::c_void,
+  mem,
   rc::Rc,
-  sync::{Arc, Mutex, Weak}
+  sync::{
+    Arc, Mutex, MutexGuard, Weak,
+    atomic::{AtomicU32, AtomicU64, Ordering}
+  }
 };
 
+use andromeda_common::config::MainSwapchainRule;
+use ash::{prelude::VkResult, vk};
 use once_cell::sync::OnceCell;
-use windows::Win32::Graphics::{
-  Direct3D11::{ID3D11Device, ID3D11DeviceContext},
-  Dxgi::IDXGISwapChain
+use windows::Win32::{
+  Foundation::HWND,
+  Graphics::{
+    Direct3D11::{ID3D11Device, ID3D11DeviceContext, ID3D11RenderTargetView, ID3D11Texture2D},
+    Dxgi::IDXGISwapChain
+  }
 };
 use windows_core::{HRESULT, Interface};
 
-use crate::exports::PresentFn;
-
-// static mut GAME_DEVICE_SWAPCHAIN: Option<Box<dyn SwapchainBase + Send>> = None;
+use crate::{
+  egui_backend::vulkan::overlay::VulkanSwapchainOverlay, exports::PresentFn, hooks::dx11::ORIG_SWAPCHAIN_RELEASE,
+  ui::PainterTextures
+};
 
-// I guess we assume the game device swapchain is the first one we find, which is often the case, but this should be handled better
-pub fn game_device_swapchain() -> Option<Arc<dyn SwapchainBase + Send + Sync>> {
+/// Picks the game's main swapchain out of every registered one according to `rule`
+pub fn game_device_swapchain(rule: MainSwapchainRule) -> Option<Arc<dyn SwapchainBase + Send + Sync>> {
   if let Some(render_target) = RENDER_TARGETS.get() &&
     let Ok(mutex) = render_target.lock()
   {
-    return (*mutex).first().cloned();
+    let infos: Vec<SwapchainInfo> = mutex.iter().map(|t| t.info()).collect();
+    return select_main_swapchain(&infos, rule).and_then(|index| mutex.get(index).cloned());
   }
   None
 }
 
+/// Index of the main swapchain in `infos`, which are in registration order
+pub fn select_main_swapchain(infos: &[SwapchainInfo], rule: MainSwapchainRule) -> Option<usize> {
+  let indexed = infos.iter().enumerate();
+  // `max_by_key` keeps the last maximum, ties should go to the swapchain that was created first
+  let first_max_by = |key: fn(&SwapchainInfo) -> u64| {
+    indexed
+      .clone()
+      .rev()
+      .max_by_key(|(_, info)| key(info))
+      .map(|(index, _)| index)
+  };
+
+  match rule {
+    MainSwapchainRule::First => (!infos.is_empty()).then_some(0),
+    MainSwapchainRule::Largest => first_max_by(|info| info.width as u64 * info.height as u64),
+    MainSwapchainRule::MostPresents => first_max_by(|info| info.present_count),
+    MainSwapchainRule::HasHwnd => indexed
+      .clone()
+      .find(|(_, info)| info.has_hwnd)
+      .map(|(index, _)| index)
+      .or((!infos.is_empty()).then_some(0))
+  }
+}
+
 // ---------------- Backend Abstraction ----------------
-#[derive(Clone, Copy, Debug)]
-pub enum Backend {
-  DX11,
-  Vulkan
+/// Backend independent facts used to pick the main swapchain
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SwapchainInfo {
+  pub width: u32,
+  pub height: u32,
+  pub has_hwnd: bool,
+  pub present_count: u64
 }
 
 pub trait SwapchainBase {
-  fn backend(&self) -> Backend;
-  fn present(&self, sync_interval: u32, flags: u32);
+  fn info(&self) -> SwapchainInfo;
 
   fn as_any(&self) -> &dyn Any;
 }
@@ -47,25 +84,78 @@ pub fn register_swapchain(s: Arc<dyn SwapchainBase + Send + Sync>) {
   RENDER_TARGETS.get().unwrap().lock().unwrap().push(s);
 }
 
+pub fn find_dx11_swapchain(swapchain: *mut c_void) -> Option<Arc<dyn SwapchainBase + Send + Sync>> {
+  let targets = RENDER_TARGETS.get()?.lock().ok()?;
+  targets
+    .iter()
+    .find(|t| {
+      t.as_any()
+        .downcast_ref::<DX11Swapchain>()
+        .is_some_and(|dx11| dx11.swapchain.as_raw() == swapchain)
+    })
+    .cloned()
+}
+
+/// Removes a DX11 swapchain from the registry, handing the entry back so the caller can drop it outside the lock.
+/// Dropping it releases our reference, which re-enters the `Release` hook.
+pub fn unregister_dx11_swapchain(swapchain: *mut c_void) -> Option<Arc<dyn SwapchainBase + Send + Sync>> {
+  let mut targets = RENDER_TARGETS.get()?.lock().ok()?;
+  let index = targets.iter().position(|t| {
+    t.as_any()
+      .downcast_ref::<DX11Swapchain>()
+      .is_some_and(|dx11| dx11.swapchain.as_raw() == swapchain)
+  })?;
+  Some(targets.remove(index))
+}
+
+pub fn is_dx11_swapchain_registered(swapchain: *mut c_void) -> bool {
+  RENDER_TARGETS
+    .get()
+    .and_then(|targets| targets.lock().ok())
+    .is_some_and(|targets| {
+      targets.iter().any(|t| {
+        t.as_any()
+          .downcast_ref::<DX11Swapchain>()
+          .is_some_and(|dx11| dx11.swapchain.as_raw() == swapchain)
+      })
+    })
+}
+
 // ---------------- DX11 Swapchain Wrapper ----------------
-#[derive(Clone)]
+/// Overlay resources owned by a single swapchain
+pub struct DX11OverlayState {
+  pub textures: PainterTextures,
+  pub renderer: egui_directx11::Renderer,
+  /// View of back buffer 0, dropped before the swapchain resizes or goes away
+  pub render_target: Option<ID3D11RenderTargetView>,
+  /// References on the swapchain that the view keeps alive through the back buffer
+  pub render_target_references: u32
+}
+
 pub struct DX11Swapchain {
   pub swapchain: IDXGISwapChain,
   pub device: ID3D11Device,
   pub context: ID3D11DeviceContext,
-  pub original_present: Option<PresentFn>
+  pub hwnd: HWND,
+  pub original_present: Option<PresentFn>,
+  pub present_count: AtomicU64,
+  pub overlay: Mutex<Option<DX11OverlayState>>,
+  /// References on the swapchain held by the overlay: `swapchain` itself plus whatever the render target keeps
+  pub held_references: AtomicU32
 }
 
-impl SwapchainBase for DX11Swapchain {
-  fn backend(&self) -> Backend {
-    Backend::DX11
-  }
+// Window handles are plain identifiers, every other field is thread safe on its own
+unsafe impl Send for DX11Swapchain {}
+unsafe impl Sync for DX11Swapchain {}
 
-  fn present(&self, sync_interval: u32, flags: u32) {
-    unsafe {
-      if let Some(orig) = self.original_present {
-        orig(&self.swapchain as *const _ as *mut _, sync_interval, flags);
-      }
+impl SwapchainBase for DX11Swapchain {
+  fn info(&self) -> SwapchainInfo {
+    let desc = unsafe { self.swapchain.GetDesc() }.unwrap_or_default();
+    SwapchainInfo {
+      width: desc.BufferDesc.Width,
+      height: desc.BufferDesc.Height,
+      has_hwnd: !self.hwnd.is_invalid(),
+      present_count: self.present_count.load(Ordering::Relaxed)
     }
   }
 
@@ -75,6 +165,76 @@ impl SwapchainBase for DX11Swapchain {
 }
 
 impl DX11Swapchain {
+  pub fn new(
+    swapchain: IDXGISwapChain,
+    device: ID3D11Device,
+    context: ID3D11DeviceContext,
+    original_present: Option<PresentFn>
+  ) -> Self {
+    let hwnd = unsafe { swapchain.GetDesc() }
+      .map(|desc| desc.OutputWindow)
+      .unwrap_or_default();
+    Self {
+      swapchain,
+      device,
+      context,
+      hwnd,
+      original_present,
+      present_count: AtomicU64::new(0),
+      overlay: Mutex::new(None),
+      held_references: AtomicU32::new(1)
+    }
+  }
+
+  /// Creates the view of back buffer 0. Whatever references it keeps on the swapchain count as held by the overlay.
+  pub unsafe fn create_render_target(&self, state: &mut DX11OverlayState) -> windows::core::Result<()> {
+    let before = self.reference_count();
+    let back_buffer = unsafe { self.swapchain.GetBuffer::<ID3D11Texture2D>(0) }?;
+    let mut view = None;
+    unsafe { self.device.CreateRenderTargetView(&back_buffer, None, Some(&mut view)) }?;
+    drop(back_buffer);
+
+    let references = self.reference_count().saturating_sub(before);
+    state.render_target = view;
+    state.render_target_references = references;
+    self.held_references.fetch_add(references, Ordering::SeqCst);
+    Ok(())
+  }
+
+  /// The swapchain's current reference count, released through the original `Release` so our hook doesn't see it
+  fn reference_count(&self) -> u32 {
+    let raw = self.swapchain.as_raw();
+    unsafe {
+      let vtable = Interface::vtable(&self.swapchain);
+      (vtable.base__.base__.base__.AddRef)(raw);
+      match ORIG_SWAPCHAIN_RELEASE.get() {
+        Some(release) => release(raw as *mut IDXGISwapChain),
+        None => (vtable.base__.base__.base__.Release)(raw)
+      }
+    }
+  }
+
+  /// Drops the overlay's view of the back buffer. DXGI refuses to resize buffers while views on them are alive, the
+  /// renderer restores the game's render targets after every frame so the view isn't bound anywhere by now.
+  pub fn release_render_target(&self) -> bool {
+    let released = self.overlay.lock().ok().and_then(|mut overlay| {
+      let state = overlay.as_mut()?;
+      let view = state.render_target.take()?;
+      Some((view, mem::take(&mut state.render_target_references)))
+    });
+    let Some((view, references)) = released else {
+      return false;
+    };
+    drop(view);
+    self.held_references.fetch_sub(references, Ordering::SeqCst);
+    true
+  }
+
+  /// Whether every reference left on the swapchain is one of ours
+  pub fn only_held_by_overlay(&self, remaining: u32) -> bool {
+    remaining <= self.held_references.load(Ordering::SeqCst)
+  }
+
   // pub unsafe fn hook_present(&mut self) -> Result<(), &'static str> {
   //   let vtable = *(self.swapchain.as_raw() as *mut *mut *mut c_void);
   //   let present_addr = *vtable.add(8);
@@ -94,20 +254,36 @@ impl DX11Swapchain {
 }
 
 // ---------------- Vulkan Swapchain Wrapper ----------------
-#[derive(Clone)]
+/// Overlay resources owned by a single Vulkan swapchain
+pub struct VulkanOverlayState {
+  pub textures: PainterTextures,
+  pub overlay: VulkanSwapchainOverlay
+}
+
 pub struct VulkanSwapchain {
-  pub swapchain: ash::vk::SwapchainKHR,
+  pub swapchain: vk::SwapchainKHR,
   pub device: ash::Device,
-  pub queue: ash::vk::Queue
+  pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+  pub format: vk::Format,
+  pub extent: vk::Extent2D,
+  pub images: Vec<vk::Image>,
+  /// Window of the surface, invalid if the surface was created before the hooks were installed
+  pub hwnd: HWND,
+  pub present_count: AtomicU64,
+  pub overlay: Mutex<Option<VulkanOverlayState>>
 }
 
-impl SwapchainBase for VulkanSwapchain {
-  fn backend(&self) -> Backend {
-    Backend::Vulkan
-  }
+unsafe impl Send for VulkanSwapchain {}
+unsafe impl Sync for VulkanSwapchain {}
 
-  fn present(&self, _sync_interval: u32, _flags: u32) {
-    // Submit overlay command buffers and call vkQueuePresentKHR
+impl SwapchainBase for VulkanSwapchain {
+  fn info(&self) -> SwapchainInfo {
+    SwapchainInfo {
+      width: self.extent.width,
+      height: self.extent.height,
+      has_hwnd: !self.hwnd.is_invalid(),
+      present_count: self.present_count.load(Ordering::Relaxed)
+    }
   }
 
   fn as_any(&self) -> &dyn Any {
@@ -115,6 +291,51 @@ impl SwapchainBase for VulkanSwapchain {
   }
 }
 
+impl VulkanSwapchain {
+  /// Creates the overlay on first use. Needs the queue family of the presenting queue, which is only known at present.
+  pub fn overlay_state(&self, queue_family_index: u32) -> VkResult<MutexGuard<'_, Option<VulkanOverlayState>>> {
+    let mut overlay = self.overlay.lock().map_err(|_| vk::Result::ERROR_UNKNOWN)?;
+    if overlay.is_none() {
+      *overlay = Some(VulkanOverlayState {
+        textures: PainterTextures::default(),
+        overlay: VulkanSwapchainOverlay::new(
+          self.device.clone(),
+          self.memory_properties,
+          queue_family_index,
+          self.format,
+          self.extent,
+          &self.images
+        )?
+      });
+    }
+    Ok(overlay)
+  }
+}
+
+pub fn find_vulkan_swapchain(swapchain: vk::SwapchainKHR) -> Option<Arc<dyn SwapchainBase + Send + Sync>> {
+  let targets = RENDER_TARGETS.get()?.lock().ok()?;
+  targets
+    .iter()
+    .find(|t| {
+      t.as_any()
+        .downcast_ref::<VulkanSwapchain>()
+        .is_some_and(|vulkan| vulkan.swapchain == swapchain)
+    })
+    .cloned()
+}
+
+/// Removes a Vulkan swapchain from the registry. Dropping the returned entry destroys its overlay resources, which
+/// has to happen before the swapchain itself is destroyed.
+pub fn unregister_vulkan_swapchain(swapchain: vk::SwapchainKHR) -> Option<Arc<dyn SwapchainBase + Send + Sync>> {
+  let mut targets = RENDER_TARGETS.get()?.lock().ok()?;
+  let index = targets.iter().position(|t| {
+    t.as_any()
+      .downcast_ref::<VulkanSwapchain>()
+      .is_some_and(|vulkan| vulkan.swapchain == swapchain)
+  })?;
+  Some(targets.remove(index))
+}
+
 // ---------------- Utilities ----------------
 // pub trait AsAny {
 //   fn as_any(&self) -> &dyn std::any::Any;
@@ -125,15 +346,13 @@ impl SwapchainBase for VulkanSwapchain {
 // }
 
 impl SwapchainBase for Arc<dyn SwapchainBase + Send + Sync> {
-  fn backend(&self) -> Backend {
-    (**self).backend()
-  }
-  fn present(&self, sync_interval: u32, flags: u32) {
-    (**self).present(sync_interval, flags)
+  fn info(&self) -> SwapchainInfo {
+    (**self).info()
   }
 
+  // Forward to the inner swapchain, otherwise downcasting through an `Arc` would only ever see the `Arc` itself
   fn as_any(&self) -> &dyn Any {
-    self
+    (**self).as_any()
   }
 }
 
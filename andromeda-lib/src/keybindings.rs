@@ -0,0 +1,201 @@
+//! Named actions that users can bind to key chords.
+//!
+//! Core and plugins register actions with an optional default chord, user bindings come from `AndromedaConfig` and
+//! win over the defaults. Bindings for actions that aren't registered (yet) are kept, so a plugin that loads late or
+//! is disabled for a while doesn't lose its bindings.
+
+use std::{
+  collections::BTreeMap,
+  sync::{Arc, Mutex}
+};
+
+use andromeda_common::{
+  config::update_andromeda_config,
+  errors::AndromedaError,
+  keybindings::{Chord, KeybindingConflict, find_conflicts}
+};
+use egui::{Event, Key, Modifiers};
+use log::{error, info};
+use once_cell::sync::Lazy;
+
+pub const TOGGLE_OVERLAY_ACTION: &str = "andromeda.toggleOverlay";
+
+pub type ActionHandler = Arc<dyn Fn() + Send + Sync>;
+
+pub struct Action {
+  pub id: String,
+  pub label: String,
+  pub default_chord: Option<Chord>,
+  handler: ActionHandler
+}
+
+#[derive(Default)]
+pub struct KeybindingRegistry {
+  actions: Vec<Action>,
+  /// User bindings keyed by action id, an empty chord unbinds the action
+  saved: BTreeMap<String, String>
+}
+
+pub static KEYBINDINGS: Lazy<Mutex<KeybindingRegistry>> = Lazy::new(|| Mutex::new(KeybindingRegistry::default()));
+
+impl KeybindingRegistry {
+  /// Takes the bindings stored in the config, invalid chords are logged and fall back to the action's default
+  pub fn load(&mut self, saved: BTreeMap<String, String>) {
+    for (action, chord) in &saved {
+      if !chord.is_empty() &&
+        let Err(e) = chord.parse::<Chord>()
+      {
+        error!("Ignoring keybinding for '{}': {}", action, e);
+      }
+    }
+    self.saved = saved;
+  }
+
+  pub fn register(
+    &mut self,
+    id: &str,
+    label: &str,
+    default_chord: Option<Chord>,
+    handler: impl Fn() + Send + Sync + 'static
+  ) -> Result<(), String> {
+    if self.actions.iter().any(|action| action.id == id) {
+      return Err(format!("Action '{id}' is already registered"));
+    }
+
+    self.actions.push(Action {
+      id: id.to_string(),
+      label: label.to_string(),
+      default_chord,
+      handler: Arc::new(handler)
+    });
+    if let Some(chord) = self.binding(id) {
+      info!("Registered action '{}' bound to {}", id, chord);
+    }
+    Ok(())
+  }
+
+  pub fn actions(&self) -> &[Action] {
+    &self.actions
+  }
+
+  /// The chord an action currently answers to
+  pub fn binding(&self, id: &str) -> Option<Chord> {
+    if let Some(chord) = self.saved.get(id) {
+      if chord.is_empty() {
+        return None;
+      }
+      if let Ok(chord) = chord.parse() {
+        return Some(chord);
+      }
+    }
+    self
+      .actions
+      .iter()
+      .find(|action| action.id == id)
+      .and_then(|action| action.default_chord.clone())
+  }
+
+  pub fn set_binding(&mut self, id: &str, chord: Option<&Chord>) {
+    self
+      .saved
+      .insert(id.to_string(), chord.map(Chord::to_string).unwrap_or_default());
+  }
+
+  /// Drops the user binding so the action uses its default again
+  pub fn reset_binding(&mut self, id: &str) {
+    self.saved.remove(id);
+  }
+
+  pub fn saved(&self) -> &BTreeMap<String, String> {
+    &self.saved
+  }
+
+  pub fn conflicts(&self) -> Vec<KeybindingConflict> {
+    let bindings: Vec<(&str, Chord)> = self
+      .actions
+      .iter()
+      .filter_map(|action| Some((action.id.as_str(), self.binding(&action.id)?)))
+      .collect();
+    find_conflicts(bindings.iter().map(|(id, chord)| (*id, chord)))
+  }
+
+  /// Handlers of the actions whose chord was pressed in `events`
+  pub fn triggered(&self, events: &[Event]) -> Vec<ActionHandler> {
+    events
+      .iter()
+      .filter_map(|event| match event {
+        Event::Key {
+          key,
+          pressed: true,
+          repeat: false,
+          modifiers,
+          ..
+        } => Some((*key, *modifiers)),
+        _ => None
+      })
+      .flat_map(|(key, modifiers)| {
+        self.actions.iter().filter(move |action| {
+          self
+            .binding(&action.id)
+            .is_some_and(|chord| chord_matches(&chord, key, modifiers))
+        })
+      })
+      .map(|action| action.handler.clone())
+      .collect()
+  }
+}
+
+pub fn chord_matches(chord: &Chord, key: Key, modifiers: Modifiers) -> bool {
+  Key::from_name(&chord.key) == Some(key) &&
+    chord.ctrl == modifiers.ctrl &&
+    chord.shift == modifiers.shift &&
+    chord.alt == modifiers.alt
+}
+
+/// Registers an action on the global registry
+pub fn register_action(
+  id: &str,
+  label: &str,
+  default_chord: Option<&str>,
+  handler: impl Fn() + Send + Sync + 'static
+) -> Result<(), String> {
+  let default_chord = default_chord.map(str::parse).transpose()?;
+  KEYBINDINGS
+    .lock()
+    .map_err(|_| "Keybinding registry is poisoned".to_string())?
+    .register(id, label, default_chord, handler)
+}
+
+/// Runs the actions bound to chords pressed in `events`. Handlers run without the registry locked, so they may
+/// register or rebind actions themselves.
+pub fn dispatch(events: &[Event]) {
+  let handlers = match KEYBINDINGS.lock() {
+    Ok(registry) => registry.triggered(events),
+    Err(_) => return
+  };
+  for handler in handlers {
+    handler();
+  }
+}
+
+/// Writes the current bindings back to the config file, leaving the rest of the config as it is on disk
+pub fn save_keybindings() -> Result<(), AndromedaError> {
+  let saved = KEYBINDINGS
+    .lock()
+    .map_err(|_| AndromedaError::config("Keybinding registry is poisoned"))?
+    .saved()
+    .clone();
+  update_andromeda_config(|config| config.keybindings = saved)
+}
+
+#[cfg(any(windows, test))]
+pub(crate) fn register_core_actions() {
+  if let Err(e) = register_action(
+    TOGGLE_OVERLAY_ACTION,
+    "Show or hide the overlay",
+    Some("Shift+F12"),
+    crate::ui::toggle_overlay
+  ) {
+    error!("Failed to register core action: {}", e);
+  }
+}
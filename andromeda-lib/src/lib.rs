@@ -1,130 +1,232 @@
-mod egui_backend;
+//! The payload DLL. Everything that hooks into the game only builds on Windows, the overlay UI and its software
+//! painter build anywhere so they can be tested headless.
+
+pub mod egui_backend;
+#[cfg(windows)]
 mod exports;
+#[cfg(windows)]
 mod hooks;
+#[cfg(windows)]
+pub mod input;
+#[cfg(windows)]
 mod internal;
+pub mod keybindings;
+pub mod plugins;
+pub mod ui;
+#[cfg(windows)]
 mod util;
 
+#[cfg(windows)]
 use andromeda_common::{
   api::{get_game, get_game_version},
-  config::{StartupConfig, get_andromeda_log_path},
+  config::StartupConfig,
+  crash::{
+    CrashApi, CrashNote, exception::install_exception_handler, init_forwarding_crash_reporter, install_panic_reporter,
+    note_crash_context
+  },
+  guard::catch_panic
+};
+use andromeda_common::{
+  config::{get_andromeda_config, get_andromeda_log_path},
   errors::AndromedaError,
-  exports::{D3D11CreateDeviceAndSwapChainFn, D3D11CreateDeviceFn},
-  logging::{andromeda_file_logging_format, andromeda_stdout_logging_format}
+  logging::{
+    andromeda_stdout_logging_format,
+    buffer::{log_buffer_output, receive_log_record},
+    file_logging_format,
+    files::{LogFilePolicy, LogFiles, PAYLOAD_LOG_BASENAME, clean_up_log_files},
+    levels::{init_log_levels, log_levels_enabled},
+    sink::{LogApi, init_forwarding_logger, set_log_module}
+  }
 };
-use chrono::Local;
-use log::info;
+#[cfg(windows)]
+use log::{debug, error};
+use log::{info, warn};
+use std::time::SystemTime;
+#[cfg(windows)]
 use std::{
-  error::Error,
   ffi::{CString, c_void},
-  fmt,
-  fs::OpenOptions,
-  io, ptr,
   sync::Mutex,
   thread,
   time::Duration
 };
+#[cfg(windows)]
 use windows::{
-  Win32::{
-    Foundation::HINSTANCE,
-    System::{
-      LibraryLoader::{GetModuleHandleA, GetProcAddress},
-      SystemServices::DLL_PROCESS_ATTACH,
-      Threading::{CreateThread, THREAD_CREATION_FLAGS}
-    }
-  },
+  Win32::{Foundation::HINSTANCE, System::SystemServices::DLL_PROCESS_ATTACH},
   core::BOOL
 };
 
+#[cfg(windows)]
 use crate::{
-  hooks::try_install_dx11_hooks,
+  hooks::{try_install_dx11_hooks, vulkan::try_install_vulkan_hooks},
   internal::{INTERFACES, interfaces::Interfaces},
-  util::log
+  keybindings::{KEYBINDINGS, register_core_actions},
+  plugins::init_plugins
 };
 
+#[cfg(windows)]
 unsafe fn try_install_hooks() -> Result<(), AndromedaError> {
   info!("Attempt to hook functions with MinHook");
   min_hook_rs::initialize()?;
 
+  // A game can present through both, DX11 may still turn up after Vulkan is hooked
+  let mut vulkan_hooked = false;
   for _ in 0..40 {
+    if !vulkan_hooked && unsafe { try_install_vulkan_hooks().is_ok() } {
+      info!("Hooked Vulkan functions successfully!");
+      note_crash_context(CrashNote::Hook, "Vulkan");
+      vulkan_hooked = true;
+    }
+
     if unsafe { try_install_dx11_hooks().is_ok() } {
       info!("Hooked DX11 functions successfully!");
+      note_crash_context(CrashNote::Hook, "DX11");
       return Ok(());
     }
 
     thread::sleep(Duration::from_millis(50));
   }
 
-  Err(AndromedaError::Hooking("Failed to install any hooks!".to_string()))
+  match vulkan_hooked {
+    true => Ok(()),
+    false => Err(AndromedaError::hook("Failed to install any hooks!"))
+  }
 }
 
-pub fn init_logger() -> Result<(), AndromedaError> {
+#[cfg(windows)]
+/// Reports crashes through the entry DLL if it handed its reporter over, writes reports on its own otherwise
+fn init_crash_reporter(crash: Option<&'static CrashApi>) {
+  match crash {
+    Some(crash) => init_forwarding_crash_reporter(crash),
+    None => install_exception_handler()
+  }
+  // Panics of this DLL go through its own panic hook either way
+  install_panic_reporter();
+}
+
+/// Forwards to the entry DLL's logger if it handed one over, logs on its own otherwise
+pub fn init_logger(logs: Option<&'static LogApi>) -> Result<(), AndromedaError> {
+  let logging = get_andromeda_config().map(|config| config.logging).unwrap_or_default();
+
+  if let Some(logs) = logs {
+    init_forwarding_logger(logs, "payload")?;
+    // Keeps a copy of the levels for the overlay, the entry already reported what's wrong with them
+    let _ = init_log_levels(&logging);
+    // The records of every module come back for the console
+    unsafe { (logs.subscribe)(receive_log_record) };
+    return Ok(());
+  }
+
+  set_log_module("payload");
   let log_dir = get_andromeda_log_path().unwrap_or_else(|| "logs".into());
 
-  let log_file = OpenOptions::new()
-    .write(true)
-    .create(true)
-    .truncate(true)
-    .open(log_dir.join("Andromeda.log"))?;
+  let policy = LogFilePolicy::from_config(&logging);
+  let log_files = LogFiles::open(&log_dir, PAYLOAD_LOG_BASENAME, policy.clone())?;
+  let log_file = log_files.path().to_path_buf();
 
-  let logger = fern::Dispatch::new().level(log::LevelFilter::Debug);
+  // Levels are checked per record so they can change at runtime, see `set_log_levels`
+  let logger = fern::Dispatch::new()
+    .level(log::LevelFilter::Trace)
+    .filter(log_levels_enabled);
 
   // Output to stdout and files
   let file_config = fern::Dispatch::new()
-    .format(andromeda_file_logging_format)
-    .chain(log_file);
+    .format(file_logging_format(logging.format))
+    .chain(log_files.into_output());
   let stdout_config = fern::Dispatch::new()
     .format(andromeda_stdout_logging_format)
     .chain(std::io::stdout());
 
-  logger.chain(file_config).chain(stdout_config).apply()?;
+  logger
+    .chain(file_config)
+    .chain(stdout_config)
+    .chain(log_buffer_output())
+    .apply()?;
+
+  for error in init_log_levels(&logging) {
+    warn!("{}", error);
+  }
+
+  match clean_up_log_files(&log_dir, PAYLOAD_LOG_BASENAME, &policy, SystemTime::now(), &log_file) {
+    Ok(removed) if !removed.is_empty() => info!("Removed {} old log files", removed.len()),
+    Ok(_) => {}
+    Err(e) => warn!("Failed to clean up old log files: {}", e)
+  }
 
   Ok(())
 }
 
+#[cfg(windows)]
 #[unsafe(no_mangle)]
 unsafe extern "system" fn inject_andromeda_entrypoint(startup_config: StartupConfig) -> bool {
+  // The proxy carries on without us, like it does when the payload fails to load
+  catch_panic("inject_andromeda_entrypoint", || unsafe { start(startup_config) }).unwrap_or(false)
+}
+
+#[cfg(windows)]
+unsafe fn start(startup_config: StartupConfig) -> bool {
   // Initialize singletons
   INTERFACES.get_or_init(|| Mutex::new(Interfaces::new()));
 
-  match init_logger() {
+  match init_logger(unsafe { startup_config.logs.as_ref() }) {
     Ok(_) => info!("Logger initialized!"),
     Err(e) => println!("Failed to initialize logger! {e}")
   }
+  init_crash_reporter(unsafe { startup_config.crash.as_ref() });
 
-  let process_name = CString::from_raw(startup_config.process_name);
-  let version = CString::from_raw(startup_config.version);
+  let process_name = unsafe { CString::from_raw(startup_config.process_name) };
+  let version = unsafe { CString::from_raw(startup_config.version) };
   let game = get_game(&process_name.to_string_lossy());
 
-  info!(
-    "Game: {:?}, Game version: {:?}",
+  let config = get_andromeda_config().unwrap_or_default();
+  if let Some(interfaces) = INTERFACES.get() &&
+    let Ok(mut interfaces) = interfaces.lock()
+  {
+    interfaces.configure(process_name.to_string_lossy().into_owned(), config.overlay);
+  }
+  if let Ok(mut keybindings) = KEYBINDINGS.lock() {
+    keybindings.load(config.keybindings);
+  }
+  register_core_actions();
+  unsafe {
+    input::init(startup_config.input);
+  }
+
+  let game_version = get_game_version(&game, &version.to_string_lossy());
+  info!("Game: {:?}, Game version: {:?}", game, game_version);
+  note_crash_context(CrashNote::Game, &format!("{:?}", game));
+  note_crash_context(CrashNote::GameVersion, &format!("{:?}", game_version));
+  init_plugins(
     game,
-    get_game_version(&game, &version.to_string_lossy())
+    game_version,
+    startup_config.safe_mode.then_some(startup_config.unclean_exits)
   );
 
-  // Setup Andromeda and install hooks
-  unsafe {
-    try_install_hooks();
+  // Without hooks the overlay never shows, the proxy rolls back to another version
+  if let Err(e) = unsafe { try_install_hooks() } {
+    error!("{:#}", e);
+    return false;
   }
-
   true
 }
 
+#[cfg(windows)]
 unsafe extern "system" fn thread_main(_: *mut c_void) -> u32 {
-  log("[Andromeda] thread_main running");
+  debug!("thread_main running");
   // A console may be useful for printing to 'stdout'
   unsafe {
     inject_andromeda_entrypoint(StartupConfig::default());
   }
 
-  log("[Andromeda] thread_main finished");
+  debug!("thread_main finished");
   0
 }
 
+#[cfg(windows)]
 #[unsafe(no_mangle)]
 #[allow(non_snake_case, unused_variables)]
 pub unsafe extern "system" fn DllMain(_module: HINSTANCE, call_reason: u32, _: *mut ()) -> BOOL {
   if call_reason == DLL_PROCESS_ATTACH {
-    log("[Andromeda] DllMain: PROCESS_ATTACH");
+    debug!("DllMain: PROCESS_ATTACH");
 
     // unsafe {
     //   let mut cookie: *mut c_void = std::ptr::null_mut();
@@ -150,7 +252,7 @@ pub unsafe extern "system" fn DllMain(_module: HINSTANCE, call_reason: u32, _: *
 
     //   if handle.is_err() {
     //     // fallback: try std::thread if CreateThread failed (unlikely)
-    //     log("[Andromeda] CreateThread failed; falling back to std::thread");
+    //     debug!("CreateThread failed; falling back to std::thread");
     //     std::thread::spawn(|| {
     //       match crate::thread_main(ptr::null_mut()) {
     //         0 => 0,
@@ -161,7 +263,7 @@ pub unsafe extern "system" fn DllMain(_module: HINSTANCE, call_reason: u32, _: *
     //       }
     //     });
     //   } else {
-    //     log("[Andromeda] background thread spawned");
+    //     debug!("background thread spawned");
     //   }
     // }
   }
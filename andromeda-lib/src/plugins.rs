@@ -0,0 +1,372 @@
+//! Loads the enabled plugins and keeps track of how that went for the plugin manager.
+//!
+//! A plugin is a DLL that gets loaded into the game. If it exports [`PLUGIN_LOGGER_EXPORT`] that is called first with
+//! the `LogApi` to log through, see `init_forwarding_logger`. If it exports [`PLUGIN_LOAD_EXPORT`] that is called
+//! right after, returning `false` from it marks the plugin as failed. Plugins can't be unloaded, disabling a loaded
+//! plugin takes effect after a restart.
+//!
+//! Both exports are called as `extern "system-unwind"`, so a plugin declaring them that way gets marked as failed
+//! when it panics instead of taking the game down. A plain `extern "system"` export aborts on its own side.
+//!
+//! [`PLUGINS`] isn't locked while a plugin loads, so its exports can use anything that reads the plugin list.
+
+#[cfg(windows)]
+use std::ffi::CString;
+use std::{
+  path::PathBuf,
+  sync::{Mutex, MutexGuard}
+};
+
+use andromeda_common::{
+  api::{Game, GameVersion},
+  config::update_andromeda_config,
+  errors::AndromedaError,
+  plugins::{Compatibility, PluginManifest},
+  safe_mode
+};
+#[cfg(windows)]
+use andromeda_common::{
+  config::{
+    andromeda_config::get_andromeda_config_path, get_andromeda_plugins_path, read_andromeda_config,
+    write_andromeda_config
+  },
+  crash::note_plugin,
+  guard::catch_panic,
+  logging::sink::{LogApi, log_api},
+  plugins::discover_plugins,
+  utils::win32
+};
+use log::{error, info};
+use once_cell::sync::Lazy;
+#[cfg(windows)]
+use windows::{
+  Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW},
+  core::{PCSTR, PCWSTR}
+};
+
+pub const PLUGIN_LOAD_EXPORT: &str = "andromeda_plugin_load";
+pub const PLUGIN_LOGGER_EXPORT: &str = "andromeda_plugin_logger";
+
+#[cfg(windows)]
+type PluginLoadFn = unsafe extern "system-unwind" fn() -> bool;
+/// Gets the API to log through and the module to tag the plugin's records with, `plugin.<id>`
+#[cfg(windows)]
+type PluginLoggerFn = unsafe extern "system-unwind" fn(logs: *const LogApi, module: *const u8, module_len: usize);
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PluginStatus {
+  Loaded,
+  Disabled,
+  /// Enabled, but Andromeda started in safe mode
+  SafeMode,
+  Failed(String)
+}
+
+pub struct Plugin {
+  /// The manifest's id, or the folder name if the manifest is broken
+  pub id: String,
+  pub dir: PathBuf,
+  pub manifest: Option<PluginManifest>,
+  pub enabled: bool,
+  /// Discovered for the first time this session
+  pub new: bool,
+  pub status: PluginStatus
+}
+
+pub struct PluginManager {
+  plugins: Vec<Plugin>,
+  game: Game,
+  game_version: GameVersion,
+  /// Unclean exits in a row that got Andromeda into safe mode, `None` outside of it
+  safe_mode: Option<u32>
+}
+
+pub static PLUGINS: Lazy<Mutex<PluginManager>> = Lazy::new(|| {
+  Mutex::new(PluginManager {
+    plugins: Vec::new(),
+    game: Game::Unknown,
+    game_version: GameVersion::Unknown,
+    safe_mode: None
+  })
+});
+
+/// Held while plugins load instead of [`PLUGINS`], so a plugin enabled twice in a row isn't loaded twice
+static LOADING: Mutex<()> = Mutex::new(());
+
+/// A plugin that passed the checks and is loaded next, see [`load_plugins`]
+struct PendingLoad {
+  id: String,
+  dir: PathBuf,
+  manifest: PluginManifest
+}
+
+impl PluginManager {
+  pub fn plugins(&self) -> &[Plugin] {
+    &self.plugins
+  }
+
+  /// Unclean exits in a row that got Andromeda into safe mode, `None` outside of it
+  pub fn safe_mode(&self) -> Option<u32> {
+    self.safe_mode
+  }
+
+  pub fn compatibility(&self, plugin: &Plugin) -> Option<Compatibility> {
+    plugin
+      .manifest
+      .as_ref()
+      .map(|manifest| manifest.compatibility(&self.game, self.game_version))
+  }
+
+  /// The name of the plugin with a usable manifest and the id `id`
+  fn usable_name(&self, id: &str) -> Option<String> {
+    let plugin = self.plugins.iter().find(|plugin| plugin.id == id)?;
+    Some(plugin.manifest.as_ref()?.name.clone())
+  }
+
+  /// Settles the status of the plugins that aren't loaded, `only` the one with that id if given, and returns the ones
+  /// to load
+  fn pending_loads(&mut self, only: Option<&str>) -> Vec<PendingLoad> {
+    let mut pending = Vec::new();
+    for index in 0..self.plugins.len() {
+      let plugin = &self.plugins[index];
+      if plugin.status == PluginStatus::Loaded || only.is_some_and(|id| id != plugin.id) {
+        continue;
+      }
+      match self.check(plugin) {
+        Ok(manifest) => pending.push(PendingLoad {
+          id: plugin.id.clone(),
+          dir: plugin.dir.clone(),
+          manifest: manifest.clone()
+        }),
+        Err(status) => self.set_status(index, status)
+      }
+    }
+    pending
+  }
+
+  /// The manifest of a plugin that can be loaded, or else the status it gets instead
+  fn check<'a>(&self, plugin: &'a Plugin) -> Result<&'a PluginManifest, PluginStatus> {
+    let Some(manifest) = &plugin.manifest else {
+      return Err(plugin.status.clone());
+    };
+    if !plugin.enabled {
+      return Err(PluginStatus::Disabled);
+    }
+    if self.safe_mode.is_some() {
+      return Err(PluginStatus::SafeMode);
+    }
+    if self.compatibility(plugin) == Some(Compatibility::Incompatible) {
+      return Err(PluginStatus::Failed(format!(
+        "Supports game versions {}, not {:?}",
+        manifest.game_versions.join(", "),
+        self.game_version
+      )));
+    }
+    Ok(manifest)
+  }
+
+  fn set_status(&mut self, index: usize, status: PluginStatus) {
+    if let PluginStatus::Failed(reason) = &status {
+      error!("Plugin '{}' failed: {}", self.plugins[index].id, reason);
+    }
+    self.plugins[index].status = status;
+  }
+}
+
+impl PendingLoad {
+  #[cfg(windows)]
+  fn load(&self) -> PluginStatus {
+    let manifest = &self.manifest;
+    let path = self.dir.join(&manifest.entry);
+    let wide = win32::widestring(path.to_string_lossy());
+    unsafe {
+      let module = match LoadLibraryW(PCWSTR::from_raw(wide.as_ptr())) {
+        Ok(module) => module,
+        Err(e) => return PluginStatus::Failed(format!("Can't load {}: {}", manifest.entry, e))
+      };
+      let export = CString::new(PLUGIN_LOGGER_EXPORT).expect("CString had internal null byte present");
+      if let Some(logger) = GetProcAddress(module, PCSTR(export.as_ptr() as *const u8)) {
+        let logger: PluginLoggerFn = std::mem::transmute(logger);
+        let log_module = format!("plugin.{}", manifest.id);
+        let called = catch_panic(&format!("{PLUGIN_LOGGER_EXPORT} of plugin '{}'", manifest.id), || {
+          logger(log_api(), log_module.as_ptr(), log_module.len())
+        });
+        if called.is_none() {
+          return PluginStatus::Failed(format!("{PLUGIN_LOGGER_EXPORT} panicked"));
+        }
+      }
+
+      let export = CString::new(PLUGIN_LOAD_EXPORT).expect("CString had internal null byte present");
+      if let Some(load) = GetProcAddress(module, PCSTR(export.as_ptr() as *const u8)) {
+        let load: PluginLoadFn = std::mem::transmute(load);
+        match catch_panic(&format!("{PLUGIN_LOAD_EXPORT} of plugin '{}'", manifest.id), || load()) {
+          Some(true) => {}
+          Some(false) => return PluginStatus::Failed(format!("{PLUGIN_LOAD_EXPORT} reported a failure")),
+          None => return PluginStatus::Failed(format!("{PLUGIN_LOAD_EXPORT} panicked"))
+        }
+      }
+    }
+    info!("Loaded plugin '{}' {}", manifest.id, manifest.version);
+    note_plugin(&manifest.id, &manifest.version);
+    PluginStatus::Loaded
+  }
+
+  /// Plugins are Windows DLLs
+  #[cfg(not(windows))]
+  fn load(&self) -> PluginStatus {
+    PluginStatus::Failed(format!(
+      "Can't load {} outside of Windows",
+      self.dir.join(&self.manifest.entry).display()
+    ))
+  }
+}
+
+fn lock_plugins() -> Result<MutexGuard<'static, PluginManager>, AndromedaError> {
+  PLUGINS
+    .lock()
+    .map_err(|_| AndromedaError::plugin("Plugin manager is poisoned"))
+}
+
+/// Loads the enabled plugins that aren't loaded yet, `only` the one with that id if given
+fn load_plugins(only: Option<&str>) -> Result<(), AndromedaError> {
+  let _loading = LOADING
+    .lock()
+    .map_err(|_| AndromedaError::plugin("Plugin loading is poisoned"))?;
+  let pending = lock_plugins()?.pending_loads(only);
+  let loaded: Vec<(String, PluginStatus)> = pending
+    .into_iter()
+    .map(|plugin| {
+      let status = plugin.load();
+      (plugin.id, status)
+    })
+    .collect();
+
+  let mut manager = lock_plugins()?;
+  for (id, status) in loaded {
+    // The list only changes in init_plugins, before anything is loaded
+    if let Some(index) = manager.plugins.iter().position(|plugin| plugin.id == id) {
+      manager.set_status(index, status);
+    }
+  }
+  Ok(())
+}
+
+/// Remembers the plugins with `ids` as seen, returning the ones that are new and the ones that are enabled
+#[cfg(windows)]
+fn see_plugins(ids: &[String]) -> Result<(Vec<String>, Vec<String>), AndromedaError> {
+  let config_dir = get_andromeda_config_path().ok_or_else(|| AndromedaError::config("No config directory"))?;
+  let mut config = read_andromeda_config(&config_dir)?.unwrap_or_default();
+  let enabled = ids.iter().filter(|id| config.is_plugin_enabled(id)).cloned().collect();
+  let new = config.mark_plugins_seen(ids.iter().map(String::as_str));
+  if !new.is_empty() &&
+    let Err(e) = write_andromeda_config(&config_dir, &config)
+  {
+    error!("Failed to remember the new plugins: {:#}", e);
+  }
+  Ok((new, enabled))
+}
+
+/// Discovers the installed plugins, remembers them as seen and loads the enabled ones unless `safe_mode` has the
+/// unclean exits that got Andromeda into safe mode
+#[cfg(windows)]
+pub(crate) fn init_plugins(game: Game, game_version: GameVersion, safe_mode: Option<u32>) {
+  let Some(plugins_dir) = get_andromeda_plugins_path() else {
+    error!("No plugin directory, plugins won't be loaded");
+    return;
+  };
+  let discovered = discover_plugins(&plugins_dir);
+  let ids: Vec<String> = discovered.iter().map(|plugin| plugin.id()).collect();
+  let (new, enabled) = see_plugins(&ids).unwrap_or_else(|e| {
+    error!("Can't read the config, plugins stay disabled: {:#}", e);
+    (Vec::new(), Vec::new())
+  });
+
+  let Ok(mut manager) = PLUGINS.lock() else {
+    return;
+  };
+  manager.game = game;
+  manager.game_version = game_version;
+  manager.safe_mode = safe_mode;
+  manager.plugins = discovered
+    .into_iter()
+    .map(|plugin| {
+      let id = plugin.id();
+      let status = match &plugin.manifest {
+        Ok(_) => PluginStatus::Disabled,
+        Err(reason) => PluginStatus::Failed(reason.clone())
+      };
+      Plugin {
+        enabled: enabled.contains(&id),
+        new: new.contains(&id),
+        dir: plugin.dir,
+        manifest: plugin.manifest.ok(),
+        id,
+        status
+      }
+    })
+    .collect();
+  info!("Discovered {} plugins in {:?}", manager.plugins.len(), plugins_dir);
+  drop(manager);
+
+  if let Err(e) = load_plugins(None) {
+    error!("Failed to load the plugins: {:#}", e);
+  }
+}
+
+/// Leaves safe mode and loads the enabled plugins. Optional patches are applied from the next start on.
+pub fn leave_safe_mode() -> Result<(), AndromedaError> {
+  safe_mode::leave_safe_mode()?;
+  lock_plugins()?.safe_mode = None;
+  info!("Left safe mode");
+  load_plugins(None)
+}
+
+/// Saves the choice to enable or disable a plugin to the config, enabling loads it right away
+pub fn set_plugin_enabled(id: &str, enabled: bool) -> Result<(), AndromedaError> {
+  let name = lock_plugins()?
+    .usable_name(id)
+    .ok_or_else(|| AndromedaError::plugin(format!("No usable plugin with the id '{id}'")))?;
+  update_andromeda_config(|config| config.set_plugin_enabled(id, &name, enabled))?;
+  let mut manager = lock_plugins()?;
+  if let Some(plugin) = manager.plugins.iter_mut().find(|plugin| plugin.id == id) {
+    plugin.enabled = enabled;
+  }
+  drop(manager);
+  load_plugins(Some(id))
+}
+
+/// Replaces the plugin list with a fixed one, so the overlay looks the same in tests wherever they run
+#[cfg(test)]
+pub(crate) fn show_test_plugins() {
+  let plugin = |id: &str, name: &str, enabled, new, status| Plugin {
+    id: id.to_string(),
+    dir: PathBuf::from(id),
+    manifest: Some(PluginManifest {
+      id: id.to_string(),
+      name: name.to_string(),
+      version: "1.2.0".to_string(),
+      entry: format!("{id}.dll"),
+      game_versions: Vec::new()
+    }),
+    enabled,
+    new,
+    status
+  };
+  let plugins = vec![
+    plugin("photo-mode", "Photo Mode", true, false, PluginStatus::Loaded),
+    plugin("free-camera", "Free Camera", false, true, PluginStatus::Disabled),
+    Plugin {
+      manifest: None,
+      ..plugin(
+        "broken",
+        "Broken",
+        false,
+        false,
+        PluginStatus::Failed("Invalid manifest".to_string())
+      )
+    },
+  ];
+  if let Ok(mut manager) = PLUGINS.lock() {
+    manager.plugins = plugins;
+  }
+}
@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+
+use andromeda_common::{
+  config::update_andromeda_config,
+  errors::AndromedaError,
+  logging::{
+    buffer::{LOG_BUFFER, LOG_BUFFER_CAPACITY, LogEntry, LogFilter, format_log_entry},
+    levels::{LogLevels, log_levels, set_log_levels}
+  }
+};
+use egui::{Color32, RichText, TextStyle};
+use log::{Level, LevelFilter, error};
+
+/// The console's own copy of the log buffer, so pausing and filtering don't hold the buffer locked
+pub(super) struct ConsoleState {
+  pub(super) open: bool,
+  entries: VecDeque<LogEntry>,
+  /// Id of the next entry to take from the log buffer
+  next_id: u64,
+  filter: LogFilter,
+  /// Ids of the entries passing the filter
+  visible: Vec<u64>,
+  paused: bool,
+  /// Keep scrolled to the newest entry
+  follow: bool,
+  /// Target typed into the log levels section, not added yet
+  new_target: String
+}
+
+impl Default for ConsoleState {
+  fn default() -> Self {
+    Self {
+      open: false,
+      entries: VecDeque::new(),
+      next_id: 0,
+      filter: LogFilter::default(),
+      visible: Vec::new(),
+      paused: false,
+      follow: true,
+      new_target: String::new()
+    }
+  }
+}
+
+impl ConsoleState {
+  fn entry(&self, id: u64) -> Option<&LogEntry> {
+    let first = self.entries.front()?.id;
+    self.entries.get(id.checked_sub(first)? as usize)
+  }
+
+  /// Takes the entries logged since the last frame
+  fn refresh(&mut self) {
+    let Ok(buffer) = LOG_BUFFER.lock() else {
+      return;
+    };
+    for entry in buffer.since(self.next_id) {
+      if self.filter.matches(entry) {
+        self.visible.push(entry.id);
+      }
+      self.entries.push_back(entry.clone());
+    }
+    self.next_id = buffer.next_id();
+    drop(buffer);
+
+    if self.entries.len() > LOG_BUFFER_CAPACITY {
+      self.entries.drain(..self.entries.len() - LOG_BUFFER_CAPACITY);
+    }
+    if let Some(first) = self.entries.front().map(|entry| entry.id) {
+      let dropped = self.visible.partition_point(|id| *id < first);
+      self.visible.drain(..dropped);
+    }
+  }
+
+  fn apply_filter(&mut self) {
+    self.visible = self
+      .entries
+      .iter()
+      .filter(|entry| self.filter.matches(entry))
+      .map(|entry| entry.id)
+      .collect();
+  }
+}
+
+pub(super) fn show(ctx: &egui::Context, console: &mut ConsoleState) {
+  if !console.open {
+    return;
+  }
+  if !console.paused {
+    console.refresh();
+  }
+
+  let mut open = true;
+  egui::Window::new("Console")
+    .open(&mut open)
+    .default_size([700.0, 350.0])
+    .show(ctx, |ui| {
+      let mut filter_changed = false;
+      ui.horizontal(|ui| {
+        egui::ComboBox::from_id_salt("console_level")
+          .selected_text(console.filter.level.to_string())
+          .show_ui(ui, |ui| {
+            for level in LevelFilter::iter().skip(1) {
+              filter_changed |= ui
+                .selectable_value(&mut console.filter.level, level, level.to_string())
+                .changed();
+            }
+          });
+        filter_changed |= ui
+          .add(
+            egui::TextEdit::singleline(&mut console.filter.target)
+              .hint_text("Target")
+              .desired_width(140.0)
+          )
+          .changed();
+        filter_changed |= ui
+          .add(
+            egui::TextEdit::singleline(&mut console.filter.search)
+              .hint_text("Search")
+              .desired_width(180.0)
+          )
+          .changed();
+
+        ui.toggle_value(&mut console.paused, "Pause");
+        ui.checkbox(&mut console.follow, "Follow");
+        if ui.button("Copy").on_hover_text("Copy the shown lines").clicked() {
+          let text: Vec<String> = console
+            .visible
+            .iter()
+            .filter_map(|id| console.entry(*id))
+            .map(format_log_entry)
+            .collect();
+          ui.ctx().copy_text(text.join("\n"));
+        }
+        if ui.button("Clear").clicked() {
+          console.entries.clear();
+          console.visible.clear();
+        }
+      });
+      if filter_changed {
+        console.apply_filter();
+      }
+      egui::CollapsingHeader::new("Log levels").show(ui, |ui| levels_editor(ui, &mut console.new_target));
+      ui.separator();
+
+      let row_height = ui.text_style_height(&TextStyle::Monospace);
+      egui::ScrollArea::both()
+        .auto_shrink(false)
+        .stick_to_bottom(console.follow)
+        .show_rows(ui, row_height, console.visible.len(), |ui, rows| {
+          for id in &console.visible[rows] {
+            let Some(entry) = console.entry(*id) else {
+              continue;
+            };
+            ui.horizontal(|ui| {
+              ui.spacing_mut().item_spacing.x = 6.0;
+              ui.label(
+                RichText::new(entry.time.format("%H:%M:%S%.3f").to_string())
+                  .monospace()
+                  .weak()
+              );
+              ui.label(
+                RichText::new(format!("{:<5}", entry.level))
+                  .monospace()
+                  .color(level_color(entry.level))
+              );
+              ui.label(RichText::new(&entry.module).monospace());
+              ui.label(RichText::new(&entry.target).monospace().weak());
+              ui.label(RichText::new(&entry.message).monospace());
+            });
+          }
+        });
+    });
+  console.open = open;
+}
+
+/// What gets logged at all, as opposed to the filter above which only picks what is shown
+fn levels_editor(ui: &mut egui::Ui, new_target: &mut String) {
+  let mut levels = log_levels();
+  let mut changed = false;
+
+  egui::Grid::new("log_levels").num_columns(3).show(ui, |ui| {
+    ui.label("Everything else");
+    changed |= level_combo(ui, "log_level_default", &mut levels.default);
+    ui.end_row();
+
+    let mut removed = None;
+    for (target, level) in levels.targets.iter_mut() {
+      ui.monospace(target);
+      changed |= level_combo(ui, target, level);
+      if ui.small_button("Remove").clicked() {
+        removed = Some(target.clone());
+      }
+      ui.end_row();
+    }
+    if let Some(target) = removed {
+      levels.targets.remove(&target);
+      changed = true;
+    }
+
+    ui.add(
+      egui::TextEdit::singleline(new_target)
+        .hint_text("andromeda::hooks")
+        .desired_width(180.0)
+    );
+    let target = new_target.trim();
+    if ui
+      .add_enabled(
+        !target.is_empty() && !levels.targets.contains_key(target),
+        egui::Button::new("Add")
+      )
+      .clicked()
+    {
+      levels.targets.insert(target.to_string(), levels.default);
+      new_target.clear();
+      changed = true;
+    }
+    ui.end_row();
+  });
+
+  if changed {
+    set_log_levels(levels.clone());
+    if let Err(e) = save_log_levels(&levels) {
+      error!("Failed to save log levels: {:#}", e);
+    }
+  }
+}
+
+fn level_combo(ui: &mut egui::Ui, id: &str, level: &mut LevelFilter) -> bool {
+  let mut changed = false;
+  egui::ComboBox::from_id_salt(id)
+    .selected_text(level.to_string())
+    .show_ui(ui, |ui| {
+      for option in LevelFilter::iter() {
+        changed |= ui.selectable_value(level, option, option.to_string()).changed();
+      }
+    });
+  changed
+}
+
+fn save_log_levels(levels: &LogLevels) -> Result<(), AndromedaError> {
+  update_andromeda_config(|config| levels.store(&mut config.logging))
+}
+
+fn level_color(level: Level) -> Color32 {
+  match level {
+    Level::Error => Color32::from_rgb(230, 80, 80),
+    Level::Warn => Color32::from_rgb(230, 180, 60),
+    Level::Info => Color32::from_rgb(110, 200, 110),
+    Level::Debug => Color32::from_rgb(110, 160, 230),
+    Level::Trace => Color32::GRAY
+  }
+}
@@ -0,0 +1,190 @@
+mod console;
+mod plugins;
+mod safe_mode;
+mod settings;
+mod shared;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use egui::{FullOutput, RawInput};
+
+pub use shared::{PainterTextures, SharedOverlay};
+
+use crate::keybindings;
+
+/// Shared by every swapchain's overlay, so mirrors hide together with the main one
+static OVERLAY_VISIBLE: AtomicBool = AtomicBool::new(true);
+
+pub fn overlay_visible() -> bool {
+  OVERLAY_VISIBLE.load(Ordering::Relaxed)
+}
+
+pub fn toggle_overlay() {
+  OVERLAY_VISIBLE.fetch_xor(true, Ordering::Relaxed);
+}
+
+/// The Andromeda overlay, independent of the graphics API that ends up drawing it.
+///
+/// Platform backends feed it input, painters (D3D11, Vulkan, the software rasterizer) consume the shapes and texture
+/// updates in the returned output.
+pub struct OverlayUi {
+  ctx: egui::Context,
+  state: OverlayState
+}
+
+/// Everything the overlay windows keep between frames
+#[derive(Default)]
+struct OverlayState {
+  settings_open: bool,
+  console: console::ConsoleState,
+  /// Action whose new chord the settings page is waiting for
+  recording: Option<String>
+}
+
+impl Default for OverlayUi {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl OverlayUi {
+  pub fn new() -> Self {
+    Self {
+      ctx: egui::Context::default(),
+      state: OverlayState::default()
+    }
+  }
+
+  /// Painters need the context to tessellate the output
+  pub fn context(&self) -> &egui::Context {
+    &self.ctx
+  }
+
+  /// Runs one frame of the overlay, including the actions bound to chords pressed since the last one
+  pub fn run(&mut self, raw_input: RawInput) -> FullOutput {
+    // The chord being recorded must not also fire whatever it is currently bound to
+    if self.state.recording.is_none() {
+      keybindings::dispatch(&raw_input.events);
+    }
+
+    let state = &mut self.state;
+    self.ctx.run(raw_input, |ctx| Self::draw(ctx, state))
+  }
+
+  fn draw(ctx: &egui::Context, state: &mut OverlayState) {
+    if !overlay_visible() {
+      state.recording = None;
+      return;
+    }
+
+    egui::Window::new("Andromeda")
+      .default_pos(egui::pos2(500.0, 300.0))
+      .default_size(egui::Vec2::new(800.0, 500.0))
+      .show(ctx, |ui| {
+        ui.heading("Plugins");
+        plugins::show(ui);
+
+        ui.separator();
+        ui.horizontal(|ui| {
+          if ui.button("Keybindings").clicked() {
+            state.settings_open = true;
+          }
+          if ui.button("Console").clicked() {
+            state.console.open = true;
+          }
+        });
+      });
+
+    safe_mode::show(ctx);
+    settings::show(ctx, state);
+    console::show(ctx, &mut state.console);
+  }
+}
+
+/// Input for a frame covering `width` x `height` physical pixels
+pub fn screen_input(width: u32, height: u32, pixels_per_point: f32) -> RawInput {
+  let mut raw_input = RawInput {
+    screen_rect: Some(egui::Rect::from_min_size(
+      egui::Pos2::ZERO,
+      egui::vec2(width as f32, height as f32) / pixels_per_point
+    )),
+    ..Default::default()
+  };
+  raw_input
+    .viewports
+    .entry(egui::ViewportId::ROOT)
+    .or_default()
+    .native_pixels_per_point = Some(pixels_per_point);
+  raw_input
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, fs, path::PathBuf};
+
+  use super::*;
+  use crate::{
+    egui_backend::software::{Canvas, snapshot},
+    keybindings::register_core_actions,
+    plugins::show_test_plugins
+  };
+
+  /// Compares `canvas` with `snapshots/<name>.png`, or rewrites that with `UPDATE_SNAPSHOTS` set. A mismatch leaves
+  /// the new image next to it as `<name>.new.png`.
+  fn assert_snapshot(name: &str, canvas: &Canvas) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("snapshots");
+    let path = dir.join(format!("{name}.png"));
+    let new_path = dir.join(format!("{name}.new.png"));
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+      fs::create_dir_all(&dir).unwrap();
+      canvas.save_png(&path).unwrap();
+      return;
+    }
+
+    let expected = Canvas::load_png(&path).unwrap_or_else(|e| {
+      panic!(
+        "Can't load {}: {e}, run the tests with UPDATE_SNAPSHOTS=1 to create it",
+        path.display()
+      )
+    });
+    match canvas.count_differences(&expected, 1) {
+      Some(0) => {
+        let _ = fs::remove_file(&new_path);
+      }
+      differences => {
+        canvas.save_png(&new_path).unwrap();
+        panic!(
+          "{name} doesn't match its snapshot ({differences:?} pixels differ), see {}",
+          new_path.display()
+        );
+      }
+    }
+  }
+
+  /// Enough frames for windows to finish fading in
+  const FRAMES: usize = 10;
+
+  fn overlay() -> OverlayUi {
+    show_test_plugins();
+    register_core_actions();
+    OverlayUi::new()
+  }
+
+  #[test]
+  fn main_window_snapshot() {
+    assert_snapshot("main_window", &snapshot(&mut overlay(), 1400, 850, 1.0, FRAMES));
+  }
+
+  #[test]
+  fn settings_and_console_snapshot() {
+    let mut overlay = overlay();
+    overlay.state.settings_open = true;
+    overlay.state.console.open = true;
+    assert_snapshot("settings_and_console", &snapshot(&mut overlay, 1400, 850, 1.0, FRAMES));
+  }
+
+  #[test]
+  fn scaled_main_window_snapshot() {
+    assert_snapshot("main_window_scaled", &snapshot(&mut overlay(), 1400, 850, 1.5, FRAMES));
+  }
+}
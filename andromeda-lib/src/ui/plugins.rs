@@ -0,0 +1,124 @@
+use andromeda_common::{config::get_andromeda_plugins_path, plugins::Compatibility};
+use egui::{Color32, RichText};
+use log::error;
+
+use crate::plugins::{PLUGINS, PluginStatus, set_plugin_enabled};
+
+const RED: Color32 = Color32::from_rgb(230, 80, 80);
+const GREEN: Color32 = Color32::from_rgb(110, 200, 110);
+
+struct PluginRow {
+  id: String,
+  name: String,
+  version: String,
+  new: bool,
+  enabled: bool,
+  /// Broken manifests can't be enabled
+  toggleable: bool,
+  status: PluginStatus,
+  compatibility: Option<Compatibility>
+}
+
+/// The plugin list of the main Andromeda window
+pub(super) fn show(ui: &mut egui::Ui) {
+  let rows: Vec<PluginRow> = match PLUGINS.lock() {
+    Ok(manager) => manager
+      .plugins()
+      .iter()
+      .map(|plugin| PluginRow {
+        id: plugin.id.clone(),
+        name: plugin
+          .manifest
+          .as_ref()
+          .map_or(plugin.id.clone(), |manifest| manifest.name.clone()),
+        version: plugin
+          .manifest
+          .as_ref()
+          .map(|manifest| manifest.version.clone())
+          .unwrap_or_default(),
+        new: plugin.new,
+        enabled: plugin.enabled,
+        toggleable: plugin.manifest.is_some(),
+        status: plugin.status.clone(),
+        compatibility: manager.compatibility(plugin)
+      })
+      .collect(),
+    Err(_) => {
+      ui.label("Plugins are unavailable");
+      return;
+    }
+  };
+
+  if rows.is_empty() {
+    let dir = get_andromeda_plugins_path().map_or("the plugins folder".to_string(), |dir| dir.display().to_string());
+    ui.label(format!(
+      "No plugins installed. Plugins go in their own folder in {dir}."
+    ));
+    return;
+  }
+
+  let mut toggled = None;
+  egui::Grid::new("plugins").num_columns(6).striped(true).show(ui, |ui| {
+    for heading in ["", "Name", "Id", "Version", "Status", "Game version"] {
+      ui.strong(heading);
+    }
+    ui.end_row();
+
+    for row in &rows {
+      let mut enabled = row.enabled;
+      if ui
+        .add_enabled(row.toggleable, egui::Checkbox::without_text(&mut enabled))
+        .changed()
+      {
+        toggled = Some((row.id.clone(), enabled));
+      }
+
+      ui.horizontal(|ui| {
+        ui.label(&row.name);
+        if row.new {
+          ui.label(RichText::new("New").small().strong().color(GREEN))
+            .on_hover_text("Installed since the last start");
+        }
+      });
+      ui.label(&row.id);
+      ui.label(&row.version);
+
+      match &row.status {
+        PluginStatus::Loaded if !row.enabled => {
+          ui.label("Loaded, unloads after a restart");
+        }
+        PluginStatus::Loaded => {
+          ui.colored_label(GREEN, "Loaded");
+        }
+        PluginStatus::Disabled => {
+          ui.weak("Disabled");
+        }
+        PluginStatus::SafeMode => {
+          ui.colored_label(RED, "Not loaded in safe mode");
+        }
+        PluginStatus::Failed(reason) => {
+          ui.colored_label(RED, format!("Failed: {reason}"));
+        }
+      }
+
+      match row.compatibility {
+        Some(Compatibility::AnyVersion) => ui.label("Any"),
+        Some(Compatibility::Compatible) => ui.colored_label(GREEN, "Compatible"),
+        Some(Compatibility::Incompatible) => ui.colored_label(RED, "Incompatible"),
+        None => ui.weak("Unknown")
+      };
+      ui.end_row();
+    }
+  });
+
+  if let Some((id, enabled)) = toggled &&
+    let Err(e) = set_plugin_enabled(&id, enabled)
+  {
+    error!(
+      "Failed to {} plugin '{}': {:#}",
+      if enabled { "enable" } else { "disable" },
+      id,
+      e
+    );
+  }
+}
@@ -0,0 +1,64 @@
+use egui::{Color32, RichText};
+use log::error;
+
+use crate::plugins::{PLUGINS, PluginStatus, leave_safe_mode};
+
+const BANNER_COLOR: Color32 = Color32::from_rgb(150, 40, 40);
+
+/// Banner across the top of the screen while Andromeda runs in safe mode
+pub(super) fn show(ctx: &egui::Context) {
+  let Some((unclean_exits, skipped)) = PLUGINS.lock().ok().and_then(|manager| {
+    let skipped: Vec<String> = manager
+      .plugins()
+      .iter()
+      .filter(|plugin| plugin.status == PluginStatus::SafeMode)
+      .map(|plugin| {
+        plugin
+          .manifest
+          .as_ref()
+          .map_or(plugin.id.clone(), |manifest| manifest.name.clone())
+      })
+      .collect();
+    manager.safe_mode().map(|unclean_exits| (unclean_exits, skipped))
+  }) else {
+    return;
+  };
+
+  let mut leave = false;
+  egui::Area::new(egui::Id::new("safe_mode_banner"))
+    .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 8.0))
+    .order(egui::Order::Foreground)
+    .show(ctx, |ui| {
+      egui::Frame::new()
+        .fill(BANNER_COLOR)
+        .inner_margin(10.0)
+        .corner_radius(4.0)
+        .show(ui, |ui| {
+          let reason = match unclean_exits {
+            0 => "after repeated crashes".to_string(),
+            count => format!("after the game crashed {count} times in a row")
+          };
+          ui.label(
+            RichText::new(format!("Andromeda started in safe mode {reason}"))
+              .strong()
+              .size(16.0)
+              .color(Color32::WHITE)
+          );
+          ui.label(
+            RichText::new(match skipped.is_empty() {
+              true => "Plugins and optional patches are off.".to_string(),
+              false => format!(
+                "Plugins and optional patches are off, these plugins were not loaded: {}",
+                skipped.join(", ")
+              )
+            })
+            .color(Color32::WHITE)
+          );
+          leave = ui.button("Leave safe mode").clicked();
+        });
+    });
+
+  if leave && let Err(e) = leave_safe_mode() {
+    error!("Failed to leave safe mode: {:#}", e);
+  }
+}
@@ -0,0 +1,167 @@
+use andromeda_common::keybindings::{Chord, canonical_key_name};
+use egui::{Color32, Event, Key};
+use log::error;
+
+use crate::{
+  keybindings::{KEYBINDINGS, save_keybindings},
+  ui::OverlayState
+};
+
+enum BindingChange {
+  Set(String, Option<Chord>),
+  Reset(String)
+}
+
+struct BindingRow {
+  id: String,
+  label: String,
+  chord: Option<Chord>,
+  is_default: bool
+}
+
+pub(super) fn show(ctx: &egui::Context, state: &mut OverlayState) {
+  if !state.settings_open {
+    state.recording = None;
+    return;
+  }
+
+  let mut open = true;
+  egui::Window::new("Andromeda Settings")
+    .open(&mut open)
+    .default_width(420.0)
+    .show(ctx, |ui| keybindings_page(ui, state));
+  state.settings_open = open;
+}
+
+fn keybindings_page(ui: &mut egui::Ui, state: &mut OverlayState) {
+  ui.heading("Keybindings");
+
+  let mut change = None;
+  if let Some(id) = state.recording.clone() {
+    match ui.input(|input| recorded_chord(&input.events)) {
+      Some(Some(chord)) => {
+        change = Some(BindingChange::Set(id, Some(chord)));
+        state.recording = None;
+      }
+      Some(None) => state.recording = None,
+      None => {}
+    }
+  }
+
+  let (rows, conflicts) = match KEYBINDINGS.lock() {
+    Ok(registry) => {
+      let rows: Vec<BindingRow> = registry
+        .actions()
+        .iter()
+        .map(|action| {
+          let chord = registry.binding(&action.id);
+          BindingRow {
+            id: action.id.clone(),
+            label: action.label.clone(),
+            is_default: chord == action.default_chord,
+            chord
+          }
+        })
+        .collect();
+      (rows, registry.conflicts())
+    }
+    Err(_) => {
+      ui.label("Keybindings are unavailable");
+      return;
+    }
+  };
+
+  egui::Grid::new("keybindings")
+    .num_columns(3)
+    .striped(true)
+    .show(ui, |ui| {
+      for row in &rows {
+        let conflict = conflicts.iter().find(|conflict| conflict.actions.contains(&row.id));
+        let label = ui.label(&row.label);
+        if let Some(conflict) = conflict {
+          let others: Vec<&str> = conflict
+            .actions
+            .iter()
+            .filter(|id| **id != row.id)
+            .map(String::as_str)
+            .collect();
+          label.on_hover_text(format!("{} is also bound to {}", conflict.chord, others.join(", ")));
+        }
+
+        let recording = state.recording.as_deref() == Some(row.id.as_str());
+        let text = match (&row.chord, recording) {
+          (_, true) => "Press a key...".to_string(),
+          (Some(chord), false) => chord.to_string(),
+          (None, false) => "Unbound".to_string()
+        };
+        let text = match conflict {
+          Some(_) => egui::RichText::new(text).color(Color32::from_rgb(230, 80, 80)),
+          None => egui::RichText::new(text)
+        };
+        if ui
+          .button(text)
+          .on_hover_text("Click, then press the new chord. Escape cancels.")
+          .clicked()
+        {
+          state.recording = Some(row.id.clone());
+        }
+
+        ui.horizontal(|ui| {
+          if ui
+            .add_enabled(row.chord.is_some(), egui::Button::new("Clear"))
+            .clicked()
+          {
+            change = Some(BindingChange::Set(row.id.clone(), None));
+          }
+          if ui.add_enabled(!row.is_default, egui::Button::new("Default")).clicked() {
+            change = Some(BindingChange::Reset(row.id.clone()));
+          }
+        });
+        ui.end_row();
+      }
+    });
+
+  if !conflicts.is_empty() {
+    ui.colored_label(
+      Color32::from_rgb(230, 80, 80),
+      "Actions sharing a chord all run when it is pressed"
+    );
+  }
+
+  let Some(change) = change else {
+    return;
+  };
+  if let Ok(mut registry) = KEYBINDINGS.lock() {
+    match change {
+      BindingChange::Set(id, chord) => registry.set_binding(&id, chord.as_ref()),
+      BindingChange::Reset(id) => registry.reset_binding(&id)
+    }
+  }
+  if let Err(e) = save_keybindings() {
+    error!("Failed to save keybindings: {:#}", e);
+  }
+}
+
+/// The chord pressed while recording, `Some(None)` if recording was cancelled with Escape
+fn recorded_chord(events: &[Event]) -> Option<Option<Chord>> {
+  events.iter().find_map(|event| match event {
+    Event::Key {
+      key,
+      pressed: true,
+      modifiers,
+      ..
+    } => {
+      if *key == Key::Escape && modifiers.is_none() {
+        return Some(None);
+      }
+      let key = canonical_key_name(key.name())?;
+      Some(Some(Chord {
+        ctrl: modifiers.ctrl,
+        shift: modifiers.shift,
+        alt: modifiers.alt,
+        key
+      }))
+    }
+    _ => None
+  })
+}
@@ -0,0 +1,271 @@
+//! One overlay drawn by several painters.
+//!
+//! The main swapchain runs the overlay with its input, mirrors paint the last frame it produced scaled to their own
+//! size. Every painter keeps its own copy of the overlay's textures, so before painting it gets the frame's texture
+//! updates if it has everything before them, and every texture in full otherwise, e.g. a mirror created later.
+
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  sync::Arc
+};
+
+use egui::{
+  FullOutput, ImageData, PlatformOutput, RawInput, TextureId, TexturesDelta,
+  epaint::{ClippedShape, ImageDelta}
+};
+
+use super::OverlayUi;
+
+/// The overlay shared by the main swapchain and its mirrors
+#[derive(Default)]
+pub struct SharedOverlay {
+  ui: OverlayUi,
+  frame: Option<Frame>,
+  /// Every texture of the overlay as one full image
+  textures: BTreeMap<TextureId, ImageDelta>,
+  /// Bumped by every frame that changes a texture
+  textures_version: u64,
+  /// The texture updates of the frame that made `textures_version`
+  last_textures_delta: TexturesDelta
+}
+
+struct Frame {
+  shapes: Vec<ClippedShape>,
+  pixels_per_point: f32,
+  /// In physical pixels
+  size: [u32; 2]
+}
+
+/// The overlay's textures a painter has
+#[derive(Default)]
+pub struct PainterTextures {
+  version: Option<u64>,
+  uploaded: BTreeSet<TextureId>
+}
+
+impl SharedOverlay {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Painters need the context to tessellate the output
+  pub fn context(&self) -> &egui::Context {
+    self.ui.context()
+  }
+
+  /// Runs a frame with the main swapchain's input, `size` being its size in pixels. The platform output goes back to
+  /// the main swapchain's window.
+  pub fn run(&mut self, raw_input: RawInput, size: [u32; 2]) -> PlatformOutput {
+    let output = self.ui.run(raw_input);
+    self.update_textures(output.textures_delta);
+    self.frame = Some(Frame {
+      shapes: output.shapes,
+      pixels_per_point: output.pixels_per_point,
+      size
+    });
+    output.platform_output
+  }
+
+  fn update_textures(&mut self, delta: TexturesDelta) {
+    if delta.is_empty() {
+      return;
+    }
+    for (id, image) in &delta.set {
+      match (image.pos, self.textures.get_mut(id)) {
+        (Some(pos), Some(texture)) => patch_texture(texture, pos, &image.image),
+        _ => {
+          self.textures.insert(*id, image.clone());
+        }
+      }
+    }
+    for id in &delta.free {
+      self.textures.remove(id);
+    }
+    self.textures_version += 1;
+    self.last_textures_delta = delta;
+  }
+
+  /// The last frame for a painter of `size` pixels, scaled to fit, with the texture updates `textures` is missing.
+  /// `None` before the first frame.
+  pub fn frame_for(&self, textures: &mut PainterTextures, size: [u32; 2]) -> Option<FullOutput> {
+    let frame = self.frame.as_ref()?;
+    let scale = |painter: u32, frame: u32| painter as f32 / frame.max(1) as f32;
+    let fit = scale(size[0], frame.size[0]).min(scale(size[1], frame.size[1]));
+
+    let textures_delta = match textures.version {
+      Some(version) if version == self.textures_version => TexturesDelta::default(),
+      Some(version) if version + 1 == self.textures_version => self.last_textures_delta.clone(),
+      _ => TexturesDelta {
+        set: self.textures.iter().map(|(id, image)| (*id, image.clone())).collect(),
+        free: textures
+          .uploaded
+          .iter()
+          .filter(|id| !self.textures.contains_key(id))
+          .copied()
+          .collect()
+      }
+    };
+    textures.version = Some(self.textures_version);
+    textures.uploaded = self.textures.keys().copied().collect();
+
+    Some(FullOutput {
+      textures_delta,
+      shapes: frame.shapes.clone(),
+      pixels_per_point: frame.pixels_per_point * fit,
+      ..Default::default()
+    })
+  }
+}
+
+/// Writes `patch` into the full `texture` at `pos`
+fn patch_texture(texture: &mut ImageDelta, pos: [usize; 2], patch: &ImageData) {
+  let (ImageData::Color(image), ImageData::Color(patch)) = (&mut texture.image, patch);
+  let image = Arc::make_mut(image);
+  let [x, y] = pos;
+  let [width, height] = patch.size;
+  if x + width > image.size[0] {
+    return;
+  }
+  for row in 0..height {
+    let start = (y + row) * image.size[0] + x;
+    if let Some(pixels) = image.pixels.get_mut(start..start + width) {
+      pixels.copy_from_slice(&patch.pixels[row * width..(row + 1) * width]);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use egui::{Color32, ColorImage, TextureOptions};
+
+  use super::*;
+  use crate::{plugins::show_test_plugins, ui::screen_input};
+
+  fn image(size: [usize; 2], color: Color32) -> ImageData {
+    ImageData::Color(Arc::new(ColorImage::new(size, vec![color; size[0] * size[1]])))
+  }
+
+  fn set(id: u64, image: ImageData, pos: Option<[usize; 2]>) -> TexturesDelta {
+    TexturesDelta {
+      set: vec![(
+        TextureId::Managed(id),
+        ImageDelta {
+          image,
+          options: TextureOptions::default(),
+          pos
+        }
+      )],
+      free: Vec::new()
+    }
+  }
+
+  /// An overlay that ran a frame of `size`
+  fn overlay(size: [u32; 2]) -> SharedOverlay {
+    show_test_plugins();
+    let mut overlay = SharedOverlay::new();
+    overlay.run(screen_input(size[0], size[1], 1.0), size);
+    overlay
+  }
+
+  #[test]
+  fn painters_that_saw_every_frame_get_only_its_updates() {
+    let mut overlay = overlay([800, 600]);
+    let mut painter = PainterTextures::default();
+    // The first frame brings the font texture
+    let first = overlay.frame_for(&mut painter, [800, 600]).unwrap();
+    assert!(!first.textures_delta.set.is_empty());
+    assert!(
+      overlay
+        .frame_for(&mut painter, [800, 600])
+        .unwrap()
+        .textures_delta
+        .is_empty()
+    );
+
+    let delta = set(100, image([2, 2], Color32::RED), None);
+    overlay.update_textures(delta.clone());
+    assert!(overlay.frame_for(&mut painter, [800, 600]).unwrap().textures_delta == delta);
+  }
+
+  #[test]
+  fn painters_that_missed_frames_get_every_texture() {
+    let mut overlay = overlay([800, 600]);
+    let mut painter = PainterTextures::default();
+    let _ = overlay.frame_for(&mut painter, [800, 600]);
+
+    overlay.update_textures(set(100, image([2, 2], Color32::RED), None));
+    overlay.update_textures(set(101, image([2, 2], Color32::BLUE), None));
+    overlay.update_textures(TexturesDelta {
+      set: Vec::new(),
+      free: vec![TextureId::Managed(100)]
+    });
+    let delta = overlay.frame_for(&mut painter, [800, 600]).unwrap().textures_delta;
+    let ids: Vec<TextureId> = delta.set.iter().map(|(id, _)| *id).collect();
+    assert_eq!(ids, overlay.textures.keys().copied().collect::<Vec<_>>());
+    assert!(ids.contains(&TextureId::Managed(101)));
+    assert!(delta.set.iter().all(|(_, image)| image.pos.is_none()));
+    // 100 came and went without the painter seeing it
+    assert!(delta.free.is_empty());
+
+    // A mirror created now starts from the same textures
+    let mut mirror = PainterTextures::default();
+    assert!(overlay.frame_for(&mut mirror, [400, 300]).unwrap().textures_delta == delta);
+  }
+
+  #[test]
+  fn full_updates_free_what_the_painter_has_left() {
+    let mut overlay = overlay([800, 600]);
+    overlay.update_textures(set(100, image([2, 2], Color32::RED), None));
+    let mut painter = PainterTextures::default();
+    let _ = overlay.frame_for(&mut painter, [800, 600]);
+
+    overlay.update_textures(TexturesDelta {
+      set: Vec::new(),
+      free: vec![TextureId::Managed(100)]
+    });
+    overlay.update_textures(set(101, image([2, 2], Color32::BLUE), None));
+    let delta = overlay.frame_for(&mut painter, [800, 600]).unwrap().textures_delta;
+    assert_eq!(delta.free, [TextureId::Managed(100)]);
+  }
+
+  #[test]
+  fn partial_updates_are_patched_into_the_full_texture() {
+    let mut overlay = SharedOverlay::new();
+    overlay.update_textures(set(100, image([3, 2], Color32::RED), None));
+    overlay.update_textures(set(100, image([2, 1], Color32::BLUE), Some([1, 1])));
+    overlay.update_textures(set(100, image([3, 1], Color32::GREEN), Some([1, 0])));
+
+    let ImageData::Color(texture) = &overlay.textures[&TextureId::Managed(100)].image;
+    assert_eq!(
+      texture.pixels,
+      [
+        Color32::RED,
+        Color32::RED,
+        Color32::RED,
+        Color32::RED,
+        Color32::BLUE,
+        Color32::BLUE
+      ]
+    );
+  }
+
+  #[test]
+  fn mirrors_get_the_frame_scaled_to_fit() {
+    let overlay = overlay([1600, 900]);
+    let mut painter = PainterTextures::default();
+    let frame = |painter: &mut PainterTextures, size| overlay.frame_for(painter, size).unwrap().pixels_per_point;
+    assert_eq!(frame(&mut painter, [1600, 900]), 1.0);
+    assert_eq!(frame(&mut painter, [800, 450]), 0.5);
+    assert_eq!(frame(&mut painter, [800, 900]), 0.5);
+    assert_eq!(frame(&mut painter, [3200, 1200]), 4.0 / 3.0);
+  }
+
+  #[test]
+  fn no_frame_before_the_first_run() {
+    assert!(
+      SharedOverlay::new()
+        .frame_for(&mut PainterTextures::default(), [800, 600])
+        .is_none()
+    );
+  }
+}
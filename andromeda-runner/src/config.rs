@@ -0,0 +1,359 @@
+//! `config`: reads and changes `andromeda_config.json` without editing it by hand, and checks it for mistakes.
+
+use std::{
+  fs, io,
+  path::{Path, PathBuf},
+  process::ExitCode
+};
+
+use andromeda_common::{
+  config::{
+    AndromedaConfig, CONFIG_FILE, Channel, andromeda_config::get_andromeda_config_path, read_andromeda_config,
+    write_andromeda_config
+  },
+  errors::{AndromedaError, ErrorContext},
+  integrity::parse_public_key,
+  keybindings::{Chord, find_conflicts},
+  updates::update_source
+};
+use clap::Subcommand;
+use serde_json::Value;
+
+use crate::{
+  doctor::Findings,
+  keys::{get_key, parse_value, remove_key, set_key, split_key}
+};
+
+#[derive(Subcommand)]
+pub(crate) enum ConfigCommand {
+  /// Prints a value, or the whole config without a key
+  Get {
+    /// e.g. `overlay.mirrorOverlay`, or `keybindings."andromeda.toggleOverlay"` for keys with dots
+    key: Option<String>
+  },
+  /// Changes a value, `null` puts it back to its default
+  Set {
+    key: String,
+    /// JSON, or a string if it isn't any
+    value: String
+  },
+  /// Checks the config for mistakes
+  Validate,
+  /// Replaces settings from older versions with what they stand for now
+  Migrate
+}
+
+pub(crate) fn run(command: &ConfigCommand) -> Result<ExitCode, AndromedaError> {
+  run_in(&config_dir()?, command)
+}
+
+fn run_in(config_dir: &Path, command: &ConfigCommand) -> Result<ExitCode, AndromedaError> {
+  match command {
+    ConfigCommand::Get { key } => {
+      let config = serde_json::to_value(load_config(config_dir)?)?;
+      let value = match key {
+        Some(key) => get_key(&config, &split_key(key).map_err(AndromedaError::config)?)
+          .ok_or_else(|| AndromedaError::config(format!("No config key {key}")))?,
+        None => &config
+      };
+      match value {
+        Value::String(value) => println!("{value}"),
+        value => println!("{}", serde_json::to_string_pretty(value)?)
+      }
+    }
+    ConfigCommand::Set { key, value } => {
+      set_value(config_dir, key, parse_value(value))?;
+      println!("Set {key}, it applies from the next start on");
+    }
+    ConfigCommand::Validate => {
+      let mut findings = Findings::default();
+      check_config(config_dir, &mut findings);
+      return Ok(findings.print());
+    }
+    ConfigCommand::Migrate => {
+      let mut config = read_andromeda_config(config_dir)?
+        .ok_or_else(|| AndromedaError::config(format!("No {CONFIG_FILE} to migrate")))?;
+      match config.migrate() {
+        true => {
+          write_andromeda_config(config_dir, &config)?;
+          println!("Migrated devBuild to the {:?} channel", config.channel());
+        }
+        false => println!("Nothing to migrate")
+      }
+    }
+  }
+  Ok(ExitCode::SUCCESS)
+}
+
+pub(crate) fn config_dir() -> Result<PathBuf, AndromedaError> {
+  get_andromeda_config_path().ok_or_else(|| AndromedaError::config("No config directory"))
+}
+
+/// The config, or the defaults the game starts out with if it never wrote one
+pub(crate) fn load_config(config_dir: &Path) -> Result<AndromedaConfig, AndromedaError> {
+  Ok(read_andromeda_config(config_dir)?.unwrap_or_default())
+}
+
+fn set_value(config_dir: &Path, key: &str, value: Value) -> Result<(), AndromedaError> {
+  let parts = split_key(key).map_err(AndromedaError::config)?;
+  let is_null = value.is_null();
+  let mut config = serde_json::to_value(load_config(config_dir)?)?;
+  if let Value::Object(object) = &mut config {
+    let known = match is_null {
+      true => remove_key(object, &parts),
+      false => {
+        set_key(object, &parts, value);
+        true
+      }
+    };
+    if !known {
+      return Err(AndromedaError::config(format!("No config key {key}")));
+    }
+  }
+  let config: AndromedaConfig =
+    serde_json::from_value(config).context(|| AndromedaError::config(format!("{key} can't be set to that")))?;
+  // Keys the config doesn't have are dropped when it is read, better to say so than to seem to have set them
+  if !is_null && get_key(&serde_json::to_value(&config)?, &parts).is_none() {
+    return Err(AndromedaError::config(format!("No config key {key}")));
+  }
+  write_andromeda_config(config_dir, &config)
+}
+
+/// Everything wrong with the config in `config_dir`, as `config validate` and `doctor` report it
+pub(crate) fn check_config(config_dir: &Path, findings: &mut Findings) {
+  let path = config_dir.join(CONFIG_FILE);
+  let contents = match fs::read(&path) {
+    Ok(contents) => contents,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+      return findings.warning(format!(
+        "No {}, the game writes one with the defaults on its first start",
+        path.display()
+      ));
+    }
+    Err(e) => return findings.error(format!("Can't read {}: {e}", path.display()))
+  };
+  let raw: Value = match serde_json::from_slice(&contents) {
+    Ok(raw) => raw,
+    Err(e) => return findings.error(format!("{} is not valid JSON: {e}", path.display()))
+  };
+  let config: AndromedaConfig = match serde_json::from_value(raw.clone()) {
+    Ok(config) => config,
+    Err(e) => return findings.error(format!("{} is not a valid config: {e}", path.display()))
+  };
+  findings.ok(format!("{} is a valid config", path.display()));
+
+  if raw.get("devBuild").is_some() {
+    findings.warning("devBuild is from before release channels, `config migrate` turns it into one");
+  }
+  if let Channel::Custom(dir) = config.channel() &&
+    !dir.is_dir()
+  {
+    findings.error(format!(
+      "The custom channel's directory {} doesn't exist",
+      dir.display()
+    ));
+  }
+  // Feeds on the web are left to the update check, but one on disk can be read right away
+  if let Some(feed) = &config.update_feed &&
+    !feed.starts_with("https://") &&
+    !feed.starts_with("http://") &&
+    let Err(e) = update_source(feed).feed()
+  {
+    findings.error(format!("{e:#}"));
+  }
+  for key in &config.trusted_keys {
+    if let Err(e) = parse_public_key(key) {
+      findings.error(format!("{e:#}"));
+    }
+  }
+
+  let mut chords = Vec::new();
+  for (action, chord) in config.keybindings.iter().filter(|(_, chord)| !chord.is_empty()) {
+    match chord.parse::<Chord>() {
+      Ok(chord) => chords.push((action.as_str(), chord)),
+      Err(e) => findings.error(format!("The keybinding of {action}: {e}"))
+    }
+  }
+  for conflict in find_conflicts(chords.iter().map(|(action, chord)| (*action, chord))) {
+    findings.warning(format!(
+      "{} is bound to {}",
+      conflict.chord,
+      conflict.actions.join(" and ")
+    ));
+  }
+
+  if let Ok(known) = serde_json::to_value(&config) {
+    for key in unknown_keys(&raw, &known) {
+      findings.warning(format!("Unknown key {key}, it is ignored"));
+    }
+  }
+}
+
+/// Keys in `raw` that didn't make it into `known`, the same config read and written back
+fn unknown_keys(raw: &Value, known: &Value) -> Vec<String> {
+  let (Value::Object(raw), Value::Object(known)) = (raw, known) else {
+    return Vec::new();
+  };
+  let mut unknown = Vec::new();
+  for (key, value) in raw.iter().filter(|(_, value)| !value.is_null()) {
+    match known.get(key) {
+      None => unknown.push(key.clone()),
+      Some(known) => unknown.extend(
+        unknown_keys(value, known)
+          .into_iter()
+          .map(|nested| format!("{key}.{nested}"))
+      )
+    }
+  }
+  unknown
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  fn write_raw(config_dir: &Path, raw: &Value) {
+    fs::write(config_dir.join(CONFIG_FILE), raw.to_string()).unwrap();
+  }
+
+  fn read_raw(config_dir: &Path) -> Value {
+    serde_json::from_slice(&fs::read(config_dir.join(CONFIG_FILE)).unwrap()).unwrap()
+  }
+
+  fn validate(config_dir: &Path) -> Vec<String> {
+    let mut findings = Findings::default();
+    check_config(config_dir, &mut findings);
+    findings.lines().collect()
+  }
+
+  #[test]
+  fn get_reads_the_defaults_without_a_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let get = |key: &str| ConfigCommand::Get {
+      key: Some(key.to_string())
+    };
+    assert_eq!(
+      run_in(dir.path(), &get("overlay.mirrorOverlay")).unwrap(),
+      ExitCode::SUCCESS
+    );
+    assert!(run_in(dir.path(), &get("overlay.nothing")).is_err());
+    assert!(!dir.path().join(CONFIG_FILE).exists());
+  }
+
+  #[test]
+  fn set_writes_the_value() {
+    let dir = tempfile::tempdir().unwrap();
+    let set = |key: &str, value: &str| ConfigCommand::Set {
+      key: key.to_string(),
+      value: value.to_string()
+    };
+    run_in(dir.path(), &set("overlay.mirrorOverlay", "true")).unwrap();
+    run_in(dir.path(), &set("channel", "beta")).unwrap();
+    run_in(
+      dir.path(),
+      &set(r#"keybindings."andromeda.toggleOverlay""#, "Shift+F12")
+    )
+    .unwrap();
+    let raw = read_raw(dir.path());
+    assert_eq!(raw["overlay"]["mirrorOverlay"], json!(true));
+    assert_eq!(raw["channel"], json!("beta"));
+    assert_eq!(raw["keybindings"]["andromeda.toggleOverlay"], json!("Shift+F12"));
+
+    // null puts the default back
+    run_in(dir.path(), &set("overlay.mirrorOverlay", "null")).unwrap();
+    assert_eq!(read_raw(dir.path())["overlay"]["mirrorOverlay"], json!(false));
+  }
+
+  #[test]
+  fn set_rejects_unknown_keys_and_bad_values() {
+    let dir = tempfile::tempdir().unwrap();
+    assert!(set_value(dir.path(), "overlay.nothing", json!(true)).is_err());
+    assert!(set_value(dir.path(), "safeModeAfterCrashes", json!("often")).is_err());
+    assert!(set_value(dir.path(), "overlay..mirrorOverlay", json!(true)).is_err());
+    assert!(!dir.path().join(CONFIG_FILE).exists());
+  }
+
+  #[test]
+  fn set_leaves_an_unreadable_config_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join(CONFIG_FILE), "{ not json").unwrap();
+    assert!(set_value(dir.path(), "overlay.mirrorOverlay", json!(true)).is_err());
+    assert_eq!(fs::read_to_string(dir.path().join(CONFIG_FILE)).unwrap(), "{ not json");
+  }
+
+  #[test]
+  fn validate_accepts_the_defaults() {
+    let dir = tempfile::tempdir().unwrap();
+    write_andromeda_config(dir.path(), &AndromedaConfig::default()).unwrap();
+    let lines = validate(dir.path());
+    assert!(lines.iter().all(|line| line.starts_with("ok: ")), "{lines:?}");
+  }
+
+  #[test]
+  fn validate_reports_a_missing_or_broken_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let lines = validate(dir.path());
+    assert!(lines[0].starts_with("warning: No "), "{lines:?}");
+
+    fs::write(dir.path().join(CONFIG_FILE), "{ not json").unwrap();
+    assert!(validate(dir.path())[0].contains("is not valid JSON"));
+
+    write_raw(dir.path(), &json!({ "latestVersion": 1 }));
+    assert!(validate(dir.path())[0].contains("is not a valid config"));
+    assert_eq!(run_in(dir.path(), &ConfigCommand::Validate).unwrap(), ExitCode::FAILURE);
+  }
+
+  #[test]
+  fn validate_reports_mistakes_in_a_valid_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut raw = serde_json::to_value(AndromedaConfig::default()).unwrap();
+    raw["devBuild"] = json!(true);
+    raw["channel"] = json!({ "custom": dir.path().join("missing") });
+    raw["trustedKeys"] = json!(["not hex"]);
+    raw["keybindings"] = json!({
+      "andromeda.toggleOverlay": "Ctrl+F12",
+      "andromeda.toggleConsole": "F12+Ctrl",
+      "andromeda.reloadPlugins": "Ctrl+Nothing"
+    });
+    raw["overlay"]["mirorOverlay"] = json!(true);
+    write_raw(dir.path(), &raw);
+
+    let lines = validate(dir.path());
+    let has = |prefix: &str, text: &str| lines.iter().any(|line| line.starts_with(prefix) && line.contains(text));
+    assert!(has("ok: ", "is a valid config"), "{lines:?}");
+    assert!(has("warning: ", "devBuild"), "{lines:?}");
+    assert!(has("error: ", "custom channel's directory"), "{lines:?}");
+    assert!(has("error: ", "andromeda.reloadPlugins"), "{lines:?}");
+    assert!(
+      has("warning: ", "andromeda.toggleConsole and andromeda.toggleOverlay"),
+      "{lines:?}"
+    );
+    assert!(has("warning: ", "Unknown key overlay.mirorOverlay"), "{lines:?}");
+    assert_eq!(
+      lines.iter().filter(|line| line.starts_with("error: ")).count(),
+      3,
+      "{lines:?}"
+    );
+  }
+
+  #[test]
+  fn migrate_replaces_dev_build() {
+    let dir = tempfile::tempdir().unwrap();
+    assert!(run_in(dir.path(), &ConfigCommand::Migrate).is_err());
+
+    let mut raw = serde_json::to_value(AndromedaConfig::default()).unwrap();
+    raw.as_object_mut().unwrap().remove("channel");
+    raw["devBuild"] = json!(true);
+    write_raw(dir.path(), &raw);
+    run_in(dir.path(), &ConfigCommand::Migrate).unwrap();
+    let raw = read_raw(dir.path());
+    assert_eq!(raw["channel"], json!("dev"));
+    assert!(raw.get("devBuild").is_none());
+
+    // Nothing left to do the second time
+    run_in(dir.path(), &ConfigCommand::Migrate).unwrap();
+    assert_eq!(read_raw(dir.path()), raw);
+  }
+}
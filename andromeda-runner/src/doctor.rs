@@ -0,0 +1,296 @@
+//! `doctor`: checks everything Andromeda needs to start and says what is wrong, without starting the game.
+
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  process::ExitCode
+};
+
+use andromeda_common::{
+  api::{Game, GameVersion, get_game, get_game_version, read_game_version},
+  config::{AndromedaConfig, andromeda_loader_path, read_andromeda_config},
+  errors::AndromedaError,
+  integrity::{allows_unverified, parse_public_key, sha256_file, verify_payload},
+  loader_store::PAYLOAD_FILE,
+  proxy::find_proxy_dlls
+};
+use clap::Args;
+
+use crate::{
+  ENTRY_FILE,
+  config::{check_config, config_dir}
+};
+
+#[derive(Args)]
+pub(crate) struct DoctorArgs {
+  /// A game's executable, to check Andromeda knows it and has a proxy DLL next to it
+  #[arg(long)]
+  game: Option<PathBuf>
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Severity {
+  Ok,
+  Warning,
+  Error
+}
+
+/// What the checks found, printed one per line
+#[derive(Default)]
+pub(crate) struct Findings(Vec<(Severity, String)>);
+
+impl Findings {
+  pub(crate) fn ok(&mut self, message: impl Into<String>) {
+    self.0.push((Severity::Ok, message.into()));
+  }
+
+  pub(crate) fn warning(&mut self, message: impl Into<String>) {
+    self.0.push((Severity::Warning, message.into()));
+  }
+
+  pub(crate) fn error(&mut self, message: impl Into<String>) {
+    self.0.push((Severity::Error, message.into()));
+  }
+
+  /// The findings as they are printed, e.g. `warning: No andromeda_config.json, ...`
+  pub(crate) fn lines(&self) -> impl Iterator<Item = String> {
+    self.0.iter().map(|(severity, message)| match severity {
+      Severity::Ok => format!("ok: {message}"),
+      Severity::Warning => format!("warning: {message}"),
+      Severity::Error => format!("error: {message}")
+    })
+  }
+
+  pub(crate) fn has_errors(&self) -> bool {
+    self.0.iter().any(|(severity, _)| *severity == Severity::Error)
+  }
+
+  /// Prints the findings, failing if there are errors
+  pub(crate) fn print(&self) -> ExitCode {
+    for line in self.lines() {
+      println!("{line}");
+    }
+    match self.has_errors() {
+      true => ExitCode::FAILURE,
+      false => ExitCode::SUCCESS
+    }
+  }
+}
+
+pub(crate) fn run(args: &DoctorArgs) -> Result<ExitCode, AndromedaError> {
+  Ok(check(&config_dir()?, args).print())
+}
+
+fn check(config_dir: &Path, args: &DoctorArgs) -> Findings {
+  let mut findings = Findings::default();
+  check_config_dir(config_dir, &mut findings);
+  check_config(config_dir, &mut findings);
+  // Whatever is wrong with it was reported above, the defaults are what the proxy would fall back to
+  let config = read_andromeda_config(config_dir).ok().flatten().unwrap_or_default();
+  check_payload(&andromeda_loader_path(config_dir, &config), &config, &mut findings);
+  if let Some(exe) = &args.game {
+    check_game(exe, &mut findings);
+    check_proxy_dlls(exe, &mut findings);
+  }
+  findings
+}
+
+fn check_config_dir(config_dir: &Path, findings: &mut Findings) {
+  let probe = config_dir.join(".doctor");
+  match fs::write(&probe, []) {
+    Ok(()) => {
+      let _ = fs::remove_file(&probe);
+      findings.ok(format!("Config directory {}", config_dir.display()));
+    }
+    Err(e) => findings.error(format!(
+      "Can't write to the config directory {}: {e}",
+      config_dir.display()
+    ))
+  }
+}
+
+fn check_payload(loader_dir: &Path, config: &AndromedaConfig, findings: &mut Findings) {
+  if !loader_dir.join(PAYLOAD_FILE).is_file() {
+    findings.error(format!(
+      "No {PAYLOAD_FILE} in {} for the {:?} channel",
+      loader_dir.display(),
+      config.channel()
+    ));
+    return;
+  }
+  // Keys that don't parse were reported with the config
+  let keys: Vec<_> = config
+    .trusted_keys
+    .iter()
+    .filter_map(|key| parse_public_key(key).ok())
+    .collect();
+  if allows_unverified(loader_dir, config.channel().is_local_build(), &keys) {
+    findings.warning(format!(
+      "{} is a local build without a manifest, it loads unverified",
+      loader_dir.display()
+    ));
+    return;
+  }
+  match verify_payload(loader_dir, PAYLOAD_FILE, &keys) {
+    Ok(manifest) if keys.is_empty() => findings.ok(format!("Payload {} matches its manifest", manifest.version)),
+    Ok(manifest) => findings.ok(format!(
+      "Payload {} matches its manifest, signed by a trusted key",
+      manifest.version
+    )),
+    Err(e) => findings.error(format!("{e:#}"))
+  }
+}
+
+fn check_game(exe: &Path, findings: &mut Findings) {
+  if !exe.is_file() {
+    findings.error(format!("No game at {}", exe.display()));
+    return;
+  }
+  let name = exe.file_name().unwrap_or_default().to_string_lossy();
+  let game = get_game(&name);
+  if game == Game::Unknown {
+    findings.error(format!("{name} is not a game Andromeda knows"));
+    return;
+  }
+  let Some(version) = read_game_version(&game, exe) else {
+    findings.warning(format!("Can't read the version of {name}, plugins may not load"));
+    return;
+  };
+  let version = version.trim();
+  match get_game_version(&game, version) {
+    GameVersion::Unknown => findings.warning(format!(
+      "{name} {version} is a version Andromeda doesn't know, plugins may not load"
+    )),
+    known => findings.ok(format!("{name} {version} is {known:?}"))
+  }
+}
+
+/// There has to be exactly one copy of the entry DLL in the game folder, unless the game is started by the runner
+fn check_proxy_dlls(exe: &Path, findings: &mut Findings) {
+  let Some(game_dir) = exe.parent().filter(|dir| dir.is_dir()) else {
+    return;
+  };
+  let proxies = match find_proxy_dlls(game_dir) {
+    Ok(proxies) => proxies,
+    Err(e) => return findings.error(format!("Can't read {}: {e}", game_dir.display()))
+  };
+  for proxy in proxies.iter().filter(|proxy| !proxy.andromeda) {
+    findings.ok(format!(
+      "{} is another mod's, Andromeda doesn't use it",
+      proxy.path.display()
+    ));
+  }
+  let andromeda: Vec<_> = proxies.iter().filter(|proxy| proxy.andromeda).collect();
+  match andromeda.as_slice() {
+    [] => findings.warning(format!(
+      "No proxy DLL in {}, add one with `andromeda-runner install` or start the game with `andromeda-runner launch`",
+      game_dir.display()
+    )),
+    [proxy] => {
+      findings.ok(format!("{} is Andromeda's proxy DLL", proxy.path.display()));
+      check_proxy_up_to_date(&proxy.path, findings);
+    }
+    _ => findings.error(format!(
+      "Andromeda's proxy DLL is in {} more than once: {}",
+      game_dir.display(),
+      andromeda
+        .iter()
+        .map(|proxy| proxy.path.file_name().unwrap_or_default().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(", ")
+    ))
+  }
+}
+
+/// Compares the installed proxy with the entry DLL shipped next to the runner, if there is one
+fn check_proxy_up_to_date(proxy: &Path, findings: &mut Findings) {
+  let Some(entry) = std::env::current_exe().ok().map(|exe| exe.with_file_name(ENTRY_FILE)) else {
+    return;
+  };
+  if let (Ok(installed), Ok(shipped)) = (sha256_file(proxy), sha256_file(&entry)) &&
+    installed != shipped
+  {
+    findings.warning(format!(
+      "{} differs from {}, `andromeda-runner install` updates it",
+      proxy.display(),
+      entry.display()
+    ));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use andromeda_common::{
+    config::{CONFIG_FILE, Channel, write_andromeda_config},
+    integrity::MANIFEST_FILE,
+    logging::files::ENTRY_LOG_BASENAME
+  };
+
+  use super::*;
+
+  fn doctor(config_dir: &Path, game: Option<PathBuf>) -> Vec<String> {
+    check(config_dir, &DoctorArgs { game }).lines().collect()
+  }
+
+  fn has(lines: &[String], prefix: &str, text: &str) -> bool {
+    lines.iter().any(|line| line.starts_with(prefix) && line.contains(text))
+  }
+
+  #[test]
+  fn reports_a_missing_payload() {
+    let dir = tempfile::tempdir().unwrap();
+    let lines = doctor(dir.path(), None);
+    assert!(has(&lines, "ok: ", "Config directory"), "{lines:?}");
+    assert!(has(&lines, "warning: ", CONFIG_FILE), "{lines:?}");
+    assert!(has(&lines, "error: ", &format!("No {PAYLOAD_FILE}")), "{lines:?}");
+  }
+
+  #[test]
+  fn accepts_a_local_build() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = AndromedaConfig::default();
+    config.set_channel(Channel::Dev);
+    write_andromeda_config(dir.path(), &config).unwrap();
+    let dev = dir.path().join("loader/dev");
+    fs::create_dir_all(&dev).unwrap();
+    fs::write(dev.join(PAYLOAD_FILE), "payload").unwrap();
+
+    let findings = check(dir.path(), &DoctorArgs { game: None });
+    let lines: Vec<_> = findings.lines().collect();
+    assert!(!findings.has_errors(), "{lines:?}");
+    assert!(has(&lines, "warning: ", "local build without a manifest"), "{lines:?}");
+
+    // Trusted keys have to sign local builds as well
+    // RFC 8032's first test key
+    config.trusted_keys = vec!["d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a".to_string()];
+    write_andromeda_config(dir.path(), &config).unwrap();
+    let findings = check(dir.path(), &DoctorArgs { game: None });
+    let lines: Vec<_> = findings.lines().collect();
+    assert!(has(&lines, "error: ", MANIFEST_FILE), "{lines:?}");
+  }
+
+  #[test]
+  fn checks_the_game_folder() {
+    let dir = tempfile::tempdir().unwrap();
+    let game_dir = dir.path().join("game");
+    fs::create_dir(&game_dir).unwrap();
+    let exe = game_dir.join("ffxiv_dx11.exe");
+    fs::write(&exe, "game").unwrap();
+
+    let lines = doctor(dir.path(), Some(exe.clone()));
+    assert!(has(&lines, "warning: ", "No proxy DLL"), "{lines:?}");
+
+    fs::write(game_dir.join("dinput8.dll"), format!("entry {ENTRY_LOG_BASENAME}")).unwrap();
+    fs::write(game_dir.join("dxgi.dll"), "another mod").unwrap();
+    let lines = doctor(dir.path(), Some(exe.clone()));
+    assert!(has(&lines, "ok: ", "dinput8.dll is Andromeda's proxy DLL"), "{lines:?}");
+    assert!(has(&lines, "ok: ", "dxgi.dll is another mod's"), "{lines:?}");
+
+    fs::write(game_dir.join("d3d11.dll"), format!("entry {ENTRY_LOG_BASENAME}")).unwrap();
+    let lines = doctor(dir.path(), Some(exe));
+    assert!(has(&lines, "error: ", "more than once"), "{lines:?}");
+
+    let lines = doctor(dir.path(), Some(game_dir.join("notepad.exe")));
+    assert!(has(&lines, "error: ", "No game at"), "{lines:?}");
+  }
+}
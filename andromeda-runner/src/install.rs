@@ -0,0 +1,87 @@
+//! `install` and `uninstall`: puts the entry DLL into a game folder as a proxy DLL, so the game starts Andromeda by
+//! itself, and takes it out again.
+
+use std::{
+  path::{Path, PathBuf},
+  process::ExitCode
+};
+
+use andromeda_common::{
+  errors::AndromedaError,
+  install::{default_search_roots, find_game_exe, install_proxy, search_game_exes, uninstall_proxy}
+};
+use clap::Args;
+
+use crate::ENTRY_FILE;
+
+#[derive(Args)]
+pub(crate) struct InstallArgs {
+  #[command(flatten)]
+  game: GameArgs,
+  /// The proxy name, `dinput8`, `dxgi` or `d3d11`. By default the one installed before, or else the first free one.
+  /// Another mod's DLL with this name is moved aside until the uninstall.
+  #[arg(long = "as", value_name = "NAME")]
+  name: Option<String>,
+  /// The entry DLL to install, `andromeda_entry.dll` next to the runner by default
+  #[arg(long)]
+  entry: Option<PathBuf>
+}
+
+#[derive(Args)]
+pub(crate) struct GameArgs {
+  /// The game's executable or install folder, searched for in the usual install folders by default
+  #[arg(long)]
+  game: Option<PathBuf>
+}
+
+pub(crate) fn install(args: &InstallArgs) -> Result<ExitCode, AndromedaError> {
+  let entry = match &args.entry {
+    Some(entry) => entry.clone(),
+    None => std::env::current_exe()?.with_file_name(ENTRY_FILE)
+  };
+  let game_dir = game_dir(&args.game)?;
+  let marker = install_proxy(&game_dir, &entry, args.name.as_deref())?;
+  println!("Installed Andromeda as {}", game_dir.join(&marker.proxy).display());
+  if let Some(displaced) = &marker.displaced {
+    println!("{displaced} of another mod was moved aside, the uninstall puts it back");
+  }
+  Ok(ExitCode::SUCCESS)
+}
+
+pub(crate) fn uninstall(args: &GameArgs) -> Result<ExitCode, AndromedaError> {
+  let game_dir = game_dir(args)?;
+  let marker = uninstall_proxy(&game_dir)?;
+  println!("Removed {}", game_dir.join(&marker.proxy).display());
+  if let Some(displaced) = &marker.displaced {
+    println!("Put back {displaced}");
+  }
+  Ok(ExitCode::SUCCESS)
+}
+
+/// The folder of the game's executable, found at `--game` or else in the usual install folders
+fn game_dir(args: &GameArgs) -> Result<PathBuf, AndromedaError> {
+  let exe = match &args.game {
+    Some(path) => find_game_exe(path)
+      .ok_or_else(|| AndromedaError::config(format!("No game Andromeda knows at {}", path.display())))?,
+    None => match search_game_exes(&default_search_roots()).as_slice() {
+      [] => {
+        return Err(AndromedaError::config(
+          "No game found in the usual install folders, pass it with --game"
+        ));
+      }
+      [exe] => exe.clone(),
+      exes => {
+        return Err(AndromedaError::config(format!(
+          "Found {} games, pick one with --game: {}",
+          exes.len(),
+          exes
+            .iter()
+            .map(|exe| exe.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+        )));
+      }
+    }
+  };
+  Ok(exe.parent().map_or_else(|| PathBuf::from("."), Path::to_path_buf))
+}
@@ -0,0 +1,64 @@
+//! Config keys like `overlay.mirrorOverlay`, the path to a value in `andromeda_config.json`. A part with dots in it is
+//! quoted, e.g. `keybindings."andromeda.toggleOverlay"`.
+
+use serde_json::{Map, Value};
+
+/// The parts of `key`
+pub(crate) fn split_key(key: &str) -> Result<Vec<String>, String> {
+  let mut parts = Vec::new();
+  let mut part = String::new();
+  let mut quoted = false;
+  for c in key.chars() {
+    match c {
+      '"' => quoted = !quoted,
+      '.' if !quoted => parts.push(std::mem::take(&mut part)),
+      c => part.push(c)
+    }
+  }
+  parts.push(part);
+  if quoted || parts.iter().any(String::is_empty) {
+    return Err(format!("'{key}' is not a config key"));
+  }
+  Ok(parts)
+}
+
+/// `value` as JSON, or as a string if it isn't any, so `--set channel=beta` needs no quotes
+pub(crate) fn parse_value(value: &str) -> Value {
+  serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))
+}
+
+pub(crate) fn get_key<'a>(value: &'a Value, key: &[String]) -> Option<&'a Value> {
+  key.iter().try_fold(value, |value, part| value.get(part))
+}
+
+/// Sets `key` in `object`, creating the objects on the way and replacing anything that is in the way
+pub(crate) fn set_key(object: &mut Map<String, Value>, key: &[String], value: Value) {
+  let Some((last, parents)) = key.split_last() else {
+    return;
+  };
+  let mut object = object;
+  for part in parents {
+    let entry = object.entry(part).or_insert_with(|| Value::Object(Map::new()));
+    if !entry.is_object() {
+      *entry = Value::Object(Map::new());
+    }
+    let Value::Object(nested) = entry else { unreachable!() };
+    object = nested;
+  }
+  object.insert(last.clone(), value);
+}
+
+/// Removes `key` from `object`, returning whether it was there
+pub(crate) fn remove_key(object: &mut Map<String, Value>, key: &[String]) -> bool {
+  let Some((last, parents)) = key.split_last() else {
+    return false;
+  };
+  let mut object = object;
+  for part in parents {
+    match object.get_mut(part) {
+      Some(Value::Object(nested)) => object = nested,
+      _ => return false
+    }
+  }
+  object.remove(last).is_some()
+}
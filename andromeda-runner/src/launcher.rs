@@ -0,0 +1,165 @@
+//! `launch` and `attach`: starting games with Andromeda injected, or injecting it into running ones.
+
+use std::{
+  path::{Path, PathBuf},
+  process::ExitCode,
+  thread,
+  time::{Duration, Instant}
+};
+
+use andromeda_common::{
+  config::andromeda_config::create_andromeda_config,
+  errors::AndromedaError,
+  launch::{
+    LaunchSession, StartupStatus, clean_up_launch_dir, remove_launch_session, take_startup_status, write_launch_session
+  },
+  loader_store::PAYLOAD_FILE
+};
+
+use crate::{
+  ENTRY_FILE, InjectionArgs,
+  config::config_dir,
+  overrides::{check_overrides, overrides_map},
+  process::{GameProcess, find_processes}
+};
+
+/// Sessions and statuses of games that never picked them up are removed after this long
+const STALE_SESSION_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+impl InjectionArgs {
+  fn entry(&self) -> Result<PathBuf, AndromedaError> {
+    let entry = match &self.entry {
+      Some(entry) => entry.clone(),
+      None => std::env::current_exe()?.with_file_name(ENTRY_FILE)
+    };
+    // LoadLibraryW in the game resolves a relative path against the game's directory, not ours
+    let entry = std::path::absolute(&entry)?;
+    if !entry.is_file() {
+      return Err(AndromedaError::config(format!("No entry DLL at {}", entry.display())));
+    }
+    Ok(entry)
+  }
+}
+
+pub(crate) fn launch(exe: &Path, args: &[String], injection: &InjectionArgs) -> Result<ExitCode, AndromedaError> {
+  let entry = injection.entry()?;
+  let config_dir = prepare(injection)?;
+  let game = GameProcess::spawn_suspended(exe, args)?;
+  println!("Started {} as process {}", exe.display(), game.pid);
+  // None of the game's code ran yet, so it can go without a trace if Andromeda can't go in
+  if let Err(e) = start_session(&game, &config_dir, injection, &entry).and_then(|()| game.resume()) {
+    game.terminate();
+    return Err(e);
+  }
+  let status = wait_for_startup(&game, &config_dir, injection.timeout)?;
+  Ok(report(&status))
+}
+
+pub(crate) fn attach(
+  pid: Option<u32>,
+  name: Option<&str>,
+  injection: &InjectionArgs
+) -> Result<ExitCode, AndromedaError> {
+  let pid = match (pid, name) {
+    (Some(pid), _) => pid,
+    (None, Some(name)) => find_game(name)?,
+    (None, None) => return Err(AndromedaError::config("Attaching needs --pid or --name"))
+  };
+  let entry = injection.entry()?;
+  let config_dir = prepare(injection)?;
+  let game = GameProcess::open(pid)?;
+  let running = game.modules()?.iter().any(|module| {
+    Path::new(module)
+      .file_name()
+      .is_some_and(|file| file.eq_ignore_ascii_case(PAYLOAD_FILE))
+  });
+  if running {
+    return Err(AndromedaError::config(format!(
+      "Andromeda is already running in process {pid}"
+    )));
+  }
+  println!("Attaching to process {pid}");
+  start_session(&game, &config_dir, injection, &entry)?;
+  let status = wait_for_startup(&game, &config_dir, injection.timeout)?;
+  Ok(report(&status))
+}
+
+fn find_game(name: &str) -> Result<u32, AndromedaError> {
+  match find_processes(name)?.as_slice() {
+    [] => Err(AndromedaError::config(format!("No process named {name}"))),
+    [pid] => Ok(*pid),
+    pids => Err(AndromedaError::config(format!(
+      "{} processes named {}, pick one with --pid: {:?}",
+      pids.len(),
+      name,
+      pids
+    )))
+  }
+}
+
+/// Checks the overrides and clears out what earlier launches left, returning the config directory
+fn prepare(injection: &InjectionArgs) -> Result<PathBuf, AndromedaError> {
+  let config_dir = config_dir()?;
+  // Overrides go over the file, so there has to be one
+  create_andromeda_config()?;
+  check_overrides(&config_dir, &overrides_map(&injection.overrides))?;
+  clean_up_launch_dir(&config_dir, STALE_SESSION_AGE);
+  Ok(config_dir)
+}
+
+/// Leaves the session for the game and injects the entry DLL, which picks it up
+fn start_session(
+  game: &GameProcess,
+  config_dir: &Path,
+  injection: &InjectionArgs,
+  entry: &Path
+) -> Result<(), AndromedaError> {
+  let session = LaunchSession {
+    process_created: game.creation_time()?,
+    overrides: overrides_map(&injection.overrides)
+  };
+  write_launch_session(config_dir, game.pid, &session)?;
+  let result = game.inject(entry);
+  if result.is_err() {
+    remove_launch_session(config_dir, game.pid);
+  }
+  result
+}
+
+/// Polls for the status the proxy writes once the payload started or failed to
+fn wait_for_startup(game: &GameProcess, config_dir: &Path, timeout: u64) -> Result<StartupStatus, AndromedaError> {
+  let deadline = Instant::now() + Duration::from_secs(timeout);
+  loop {
+    if let Some(status) = take_startup_status(config_dir, game.pid) {
+      return Ok(status);
+    }
+    if game.has_exited() {
+      remove_launch_session(config_dir, game.pid);
+      // It may have answered just before it went
+      return take_startup_status(config_dir, game.pid)
+        .ok_or_else(|| AndromedaError::config(format!("Process {} exited before Andromeda started", game.pid)));
+    }
+    if Instant::now() >= deadline {
+      return Err(AndromedaError::config(format!(
+        "Andromeda didn't report its start within {timeout}s, see its log"
+      )));
+    }
+    thread::sleep(POLL_INTERVAL);
+  }
+}
+
+fn report(status: &StartupStatus) -> ExitCode {
+  if !status.started {
+    let error = status.error.as_deref().unwrap_or("no reason given");
+    eprintln!("error: Andromeda failed to start: {error}");
+    return ExitCode::FAILURE;
+  }
+  let payload = status.payload.as_deref().unwrap_or("unknown payload");
+  let hooks = status.hooks.join(", ");
+  match status.safe_mode {
+    true => println!("Andromeda {payload} started in safe mode, hooked {hooks}"),
+    false => println!("Andromeda {payload} started, hooked {hooks}")
+  }
+  ExitCode::SUCCESS
+}
@@ -0,0 +1,151 @@
+//! `loader`: the payload versions installed in the loader store, and which one the proxy loads.
+
+use std::{path::Path, process::ExitCode};
+
+use andromeda_common::{
+  config::{AndromedaConfig, Channel},
+  errors::AndromedaError,
+  loader_store::LoaderStore
+};
+use clap::Subcommand;
+
+use crate::config::{config_dir, load_config};
+
+#[derive(Subcommand)]
+pub(crate) enum LoaderCommand {
+  /// Lists the installed versions, marking the current one with `*`
+  List,
+  /// Loads an installed version from the next start on
+  Use { version: String },
+  /// Goes back to the version before the current one, updates skip the current one from then on
+  Rollback
+}
+
+pub(crate) fn run(command: &LoaderCommand) -> Result<ExitCode, AndromedaError> {
+  run_in(&config_dir()?, command)
+}
+
+fn run_in(config_dir: &Path, command: &LoaderCommand) -> Result<ExitCode, AndromedaError> {
+  let store = LoaderStore::new(config_dir);
+  let mut config = load_config(config_dir)?;
+  match command {
+    LoaderCommand::List => {
+      let versions = store.versions()?;
+      if versions.is_empty() {
+        println!("No versions installed in {}", store.loader_dir().display());
+      }
+      for version in versions.iter().rev() {
+        let current = match version == config.latest_version() {
+          true => "*",
+          false => " "
+        };
+        match config.previous_version() == Some(version.as_str()) {
+          true => println!("{current} {version} (previous)"),
+          false => println!("{current} {version}")
+        }
+      }
+      println!("Channel: {}", describe_channel(&config));
+    }
+    LoaderCommand::Use { version } => {
+      store.make_current(&mut config, version)?;
+      println!("{version} loads from the next start on");
+      warn_local_build(&config);
+    }
+    LoaderCommand::Rollback => {
+      let version = store
+        .roll_back(&mut config)?
+        .ok_or_else(|| AndromedaError::config("No other installed version to roll back to"))?;
+      println!("Rolled back to {version}, it loads from the next start on");
+      warn_local_build(&config);
+    }
+  }
+  Ok(ExitCode::SUCCESS)
+}
+
+fn describe_channel(config: &AndromedaConfig) -> String {
+  match config.channel() {
+    Channel::Custom(dir) => format!("custom, loading {}", dir.display()),
+    Channel::Dev => "dev, loading loader/dev".to_string(),
+    channel => channel.feed_name().unwrap_or_default().to_string()
+  }
+}
+
+/// Local build channels ignore the current version, which is easy to miss after switching it
+fn warn_local_build(config: &AndromedaConfig) {
+  if config.channel().is_local_build() {
+    println!(
+      "note: the channel is {}, installed versions only load on stable and beta",
+      describe_channel(config)
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use andromeda_common::{config::read_andromeda_config, loader_store::PAYLOAD_FILE};
+
+  use super::*;
+
+  /// A config on the stable channel with `versions` installed, the last one current
+  fn store_with(config_dir: &Path, versions: &[&str]) {
+    let store = LoaderStore::new(config_dir);
+    let bundle = config_dir.join("bundle");
+    fs::create_dir_all(&bundle).unwrap();
+    let mut config = AndromedaConfig::default();
+    config.set_channel(Channel::Stable);
+    for version in versions {
+      fs::write(bundle.join(PAYLOAD_FILE), version).unwrap();
+      store.install(version, &bundle).unwrap();
+      store.make_current(&mut config, version).unwrap();
+    }
+  }
+
+  fn use_version(version: &str) -> LoaderCommand {
+    LoaderCommand::Use {
+      version: version.to_string()
+    }
+  }
+
+  fn current(config_dir: &Path) -> (String, Option<String>) {
+    let config = read_andromeda_config(config_dir).unwrap().unwrap();
+    (
+      config.latest_version().to_string(),
+      config.previous_version().map(str::to_string)
+    )
+  }
+
+  #[test]
+  fn use_switches_to_an_installed_version() {
+    let dir = tempfile::tempdir().unwrap();
+    store_with(dir.path(), &["1.0.0", "1.1.0", "1.2.0"]);
+    run_in(dir.path(), &use_version("1.0.0")).unwrap();
+    assert_eq!(current(dir.path()), ("1.0.0".to_string(), Some("1.2.0".to_string())));
+
+    assert!(run_in(dir.path(), &use_version("2.0.0")).is_err());
+    assert!(run_in(dir.path(), &use_version("../bundle")).is_err());
+    assert_eq!(current(dir.path()).0, "1.0.0");
+  }
+
+  #[test]
+  fn rollback_goes_back_to_the_previous_version() {
+    let dir = tempfile::tempdir().unwrap();
+    store_with(dir.path(), &["1.0.0", "1.2.0", "1.1.0"]);
+    run_in(dir.path(), &LoaderCommand::Rollback).unwrap();
+    assert_eq!(current(dir.path()), ("1.2.0".to_string(), None));
+
+    // Without a previous version the newest other one is next
+    run_in(dir.path(), &LoaderCommand::Rollback).unwrap();
+    assert_eq!(current(dir.path()), ("1.1.0".to_string(), None));
+  }
+
+  #[test]
+  fn rollback_needs_another_version() {
+    let dir = tempfile::tempdir().unwrap();
+    store_with(dir.path(), &["1.0.0"]);
+    assert!(run_in(dir.path(), &LoaderCommand::Rollback).is_err());
+    assert_eq!(current(dir.path()).0, "1.0.0");
+    assert_eq!(run_in(dir.path(), &LoaderCommand::List).unwrap(), ExitCode::SUCCESS);
+  }
+}
@@ -0,0 +1,93 @@
+//! `logs`: prints the newest log session of the payload or the proxy, and follows it as the game writes it.
+
+use std::{
+  fs::{self, File},
+  io::{self, Read, Seek, SeekFrom, Write},
+  path::{Path, PathBuf},
+  process::ExitCode,
+  thread,
+  time::Duration
+};
+
+use andromeda_common::{
+  config::get_andromeda_log_path,
+  errors::AndromedaError,
+  logging::files::{ENTRY_LOG_BASENAME, PAYLOAD_LOG_BASENAME, log_files}
+};
+use clap::Subcommand;
+
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Subcommand)]
+pub(crate) enum LogsCommand {
+  /// Prints the end of the newest log, the payload's unless `--entry` is given
+  Tail {
+    /// The proxy's log instead of the payload's
+    #[arg(long)]
+    entry: bool,
+    /// How many lines to print
+    #[arg(short = 'n', long, default_value_t = 50)]
+    lines: usize,
+    /// Keeps printing lines as they are written, moving on to the next session's log when the game restarts
+    #[arg(short, long)]
+    follow: bool
+  }
+}
+
+pub(crate) fn run(command: &LogsCommand) -> Result<ExitCode, AndromedaError> {
+  let LogsCommand::Tail { entry, lines, follow } = command;
+  let log_dir = get_andromeda_log_path().ok_or_else(|| AndromedaError::config("No log directory"))?;
+  let basename = match entry {
+    true => ENTRY_LOG_BASENAME,
+    false => PAYLOAD_LOG_BASENAME
+  };
+  let mut path = newest_log(&log_dir, basename)?
+    .ok_or_else(|| AndromedaError::config(format!("No {basename} logs in {}", log_dir.display())))?;
+
+  let contents = fs::read(&path)?;
+  // A line still being written is printed once it is complete
+  let mut offset = complete_len(&contents);
+  let text = String::from_utf8_lossy(&contents[..offset]);
+  let all: Vec<&str> = text.lines().collect();
+  for line in &all[all.len().saturating_sub(*lines)..] {
+    println!("{line}");
+  }
+  if !follow {
+    return Ok(ExitCode::SUCCESS);
+  }
+
+  loop {
+    thread::sleep(FOLLOW_INTERVAL);
+    // Old sessions get cleaned up, the newest log is looked for below then
+    offset = print_from(&path, offset).unwrap_or(offset);
+    if let Some(newest) = newest_log(&log_dir, basename)? &&
+      newest != path
+    {
+      println!("==> {} <==", newest.display());
+      path = newest;
+      offset = 0;
+    }
+  }
+}
+
+fn newest_log(log_dir: &Path, basename: &str) -> io::Result<Option<PathBuf>> {
+  Ok(log_files(log_dir, basename)?.pop())
+}
+
+/// Length of `bytes` up to and with the last line break
+fn complete_len(bytes: &[u8]) -> usize {
+  bytes.iter().rposition(|&byte| byte == b'\n').map_or(0, |end| end + 1)
+}
+
+/// Prints the complete lines written to `path` after `offset`, returning the offset after them
+fn print_from(path: &Path, offset: usize) -> io::Result<usize> {
+  let mut file = File::open(path)?;
+  file.seek(SeekFrom::Start(offset as u64))?;
+  let mut appended = Vec::new();
+  file.read_to_end(&mut appended)?;
+  let len = complete_len(&appended);
+  let mut stdout = io::stdout().lock();
+  stdout.write_all(String::from_utf8_lossy(&appended[..len]).as_bytes())?;
+  stdout.flush()?;
+  Ok(offset + len)
+}
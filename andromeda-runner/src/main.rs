@@ -1,51 +1,129 @@
-use std::ffi::CString;
-use std::ffi::c_void;
-use windows::Win32::Devices::HumanInterfaceDevice::DIRECTINPUT_VERSION;
-use windows::Win32::Devices::HumanInterfaceDevice::IDirectInput8W;
-use windows::Win32::Foundation::HINSTANCE;
-use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryA};
-use windows::core::GUID;
-use windows::core::HRESULT;
-use windows::core::IUnknown;
-use windows::core::Interface;
-use windows::core::PCSTR;
-
-type DirectInput8CreateFn = unsafe extern "system" fn(
-  hinst: HINSTANCE,
-  dw_version: u32,
-  riidltf: *const GUID,
-  ppv_out: *mut *mut c_void,
-  punk_outer: *mut IUnknown
-) -> HRESULT;
-
-fn main() {
-  // DLL name (null-terminated C string)
-  let dll_name = CString::new("dinput8.dll").unwrap();
-
-  unsafe {
-    let handle = LoadLibraryA(PCSTR(dll_name.as_ptr() as *const u8)).unwrap();
-
-    if handle.0.is_null() {
-      panic!("Failed to load DLL.");
-    }
+//! Starts games with Andromeda injected, or injects it into running ones, as an alternative to a proxy DLL in the
+//! game folder. The other commands install that proxy DLL or manage the config, plugins, payload versions and logs,
+//! and work on any platform.
 
-    let func_name = CString::new("DirectInput8Create").unwrap();
-    let proc = GetProcAddress(handle, PCSTR(func_name.as_ptr() as *const u8));
-    if proc.is_none() {
-      panic!("Failed to get DirectInput8Create");
-    }
+mod config;
+mod doctor;
+mod install;
+mod keys;
+#[cfg(windows)]
+mod launcher;
+mod loader;
+mod logs;
+// Only the parsing of `--set` is used where games can't be launched
+#[cfg_attr(not(windows), allow(dead_code))]
+mod overrides;
+mod plugins;
+#[cfg(windows)]
+mod process;
+
+use std::{path::PathBuf, process::ExitCode};
+
+use andromeda_common::errors::AndromedaError;
+use clap::{Args, Parser, Subcommand};
+use serde_json::Value;
+
+use crate::{
+  config::ConfigCommand,
+  doctor::DoctorArgs,
+  install::{GameArgs, InstallArgs},
+  loader::LoaderCommand,
+  logs::LogsCommand,
+  overrides::parse_override,
+  plugins::PluginsCommand
+};
+
+/// The entry DLL looked for next to the runner
+const ENTRY_FILE: &str = "andromeda_entry.dll";
 
-    // Cast to a callable function
-    let direct_input8_create: DirectInput8CreateFn = std::mem::transmute(proc);
+/// Starts games with Andromeda, no proxy DLL in the game folder needed
+#[derive(Parser)]
+#[command(name = "andromeda-runner", version)]
+struct Cli {
+  #[command(subcommand)]
+  command: Command
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// Starts a game with Andromeda injected
+  Launch {
+    /// The game's executable
+    exe: PathBuf,
+    /// Arguments for the game, after `--`
+    #[arg(last = true)]
+    args: Vec<String>,
+    #[command(flatten)]
+    injection: InjectionArgs
+  },
+  /// Injects Andromeda into a running game
+  Attach {
+    /// The game's process id
+    #[arg(long, conflicts_with = "name", required_unless_present = "name")]
+    pid: Option<u32>,
+    /// The game's executable, e.g. `ffxiv_dx11.exe`
+    #[arg(long)]
+    name: Option<String>,
+    #[command(flatten)]
+    injection: InjectionArgs
+  },
+  /// Reads and changes the config
+  #[command(subcommand)]
+  Config(ConfigCommand),
+  /// Lists, enables, disables and installs plugins
+  #[command(subcommand)]
+  Plugins(PluginsCommand),
+  /// Lists and switches the installed payload versions
+  #[command(subcommand)]
+  Loader(LoaderCommand),
+  /// Shows the logs
+  #[command(subcommand)]
+  Logs(LogsCommand),
+  /// Puts Andromeda into a game folder as a proxy DLL, so the game starts it by itself
+  Install(InstallArgs),
+  /// Takes the proxy DLL out of a game folder, putting back another mod's DLL it replaced
+  Uninstall(GameArgs),
+  /// Checks the setup for anything that would keep Andromeda from starting
+  Doctor(DoctorArgs)
+}
 
-    let mut di_ptr: *mut core::ffi::c_void = std::ptr::null_mut();
+#[derive(Args)]
+struct InjectionArgs {
+  /// The entry DLL to inject, `andromeda_entry.dll` next to the runner by default
+  #[arg(long)]
+  entry: Option<PathBuf>,
+  /// A config value for this session only, e.g. `--set channel=beta` or `--set overlay.mirrorOverlay=true`
+  #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_override)]
+  overrides: Vec<(Vec<String>, Value)>,
+  /// Seconds to wait for Andromeda to report how its start went
+  #[arg(long, default_value_t = 60)]
+  timeout: u64
+}
 
-    direct_input8_create(
-      HINSTANCE(std::ptr::null_mut()),
-      DIRECTINPUT_VERSION,
-      &IDirectInput8W::IID,
-      &mut di_ptr as *mut *mut _,
-      std::ptr::null_mut()
-    );
+fn main() -> ExitCode {
+  let cli = Cli::parse();
+  let result: Result<ExitCode, AndromedaError> = match &cli.command {
+    #[cfg(windows)]
+    Command::Launch { exe, args, injection } => launcher::launch(exe, args, injection),
+    #[cfg(windows)]
+    Command::Attach { pid, name, injection } => launcher::attach(*pid, name.as_deref(), injection),
+    #[cfg(not(windows))]
+    Command::Launch { .. } | Command::Attach { .. } => {
+      Err(AndromedaError::config("Launching and attaching need Windows"))
+    }
+    Command::Config(command) => config::run(command),
+    Command::Plugins(command) => plugins::run(command),
+    Command::Loader(command) => loader::run(command),
+    Command::Logs(command) => logs::run(command),
+    Command::Install(args) => install::install(args),
+    Command::Uninstall(args) => install::uninstall(args),
+    Command::Doctor(args) => doctor::run(args)
+  };
+  match result {
+    Ok(code) => code,
+    Err(e) => {
+      eprintln!("error: {e:#}");
+      ExitCode::FAILURE
+    }
   }
 }
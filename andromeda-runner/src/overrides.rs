@@ -0,0 +1,38 @@
+//! Config values set with `--set` for a single launch.
+
+use std::{fs, path::Path};
+
+use andromeda_common::{
+  config::{AndromedaConfig, CONFIG_FILE},
+  errors::{AndromedaError, ErrorContext},
+  launch::merge_overrides
+};
+use serde_json::{Map, Value};
+
+use crate::keys::{parse_value, set_key, split_key};
+
+/// Parses `key.path=value`, see [`parse_value`]
+pub(crate) fn parse_override(arg: &str) -> Result<(Vec<String>, Value), String> {
+  let (key, value) = arg.split_once('=').ok_or_else(|| format!("'{arg}' is not KEY=VALUE"))?;
+  Ok((split_key(key)?, parse_value(value)))
+}
+
+/// Nests the keys into objects like in `andromeda_config.json`, later values win
+pub(crate) fn overrides_map(overrides: &[(Vec<String>, Value)]) -> Map<String, Value> {
+  let mut map = Map::new();
+  for (key, value) in overrides {
+    set_key(&mut map, key, value.clone());
+  }
+  map
+}
+
+/// Fails if the config with `overrides` merged over it isn't valid, better here than once the game started
+pub(crate) fn check_overrides(config_dir: &Path, overrides: &Map<String, Value>) -> Result<(), AndromedaError> {
+  let path = config_dir.join(CONFIG_FILE);
+  let config = fs::read(&path).context(|| AndromedaError::config(format!("Can't read {}", path.display())))?;
+  let mut config: Value = serde_json::from_slice(&config)?;
+  merge_overrides(&mut config, overrides);
+  serde_json::from_value::<AndromedaConfig>(config)
+    .context(|| AndromedaError::config("The --set values don't make a valid config"))?;
+  Ok(())
+}
@@ -0,0 +1,374 @@
+//! `plugins`: lists the installed plugins, turns them on and off, and installs them from zip archives.
+
+use std::{
+  fs::{self, File},
+  io,
+  path::{Path, PathBuf},
+  process::ExitCode
+};
+
+use andromeda_common::{
+  config::{get_andromeda_plugins_path, write_andromeda_config},
+  errors::{AndromedaError, ErrorContext},
+  plugins::{PLUGIN_MANIFEST_NAME, PluginManifest, discover_plugins, read_manifest}
+};
+use clap::Subcommand;
+use zip::ZipArchive;
+
+use crate::config::{config_dir, load_config};
+
+/// Where an archive is unpacked before it replaces the plugin's folder, hidden so it isn't loaded as a plugin
+const STAGING_DIR: &str = ".installing";
+
+#[derive(Subcommand)]
+pub(crate) enum PluginsCommand {
+  /// Lists the installed plugins and whether they are enabled
+  List,
+  /// Loads a plugin from the next start on
+  Enable { id: String },
+  /// Stops loading a plugin from the next start on
+  Disable { id: String },
+  /// Installs a plugin from a zip archive, or updates it if it is installed. New plugins start out disabled.
+  Install { archive: PathBuf }
+}
+
+pub(crate) fn run(command: &PluginsCommand) -> Result<ExitCode, AndromedaError> {
+  let plugins_dir = get_andromeda_plugins_path().ok_or_else(|| AndromedaError::config("No plugins directory"))?;
+  run_in(&config_dir()?, &plugins_dir, command)
+}
+
+fn run_in(config_dir: &Path, plugins_dir: &Path, command: &PluginsCommand) -> Result<ExitCode, AndromedaError> {
+  match command {
+    PluginsCommand::List => list(config_dir, plugins_dir)?,
+    PluginsCommand::Enable { id } => {
+      set_enabled(config_dir, plugins_dir, id, true)?;
+      println!("Enabled {id}, it loads from the next start on");
+    }
+    PluginsCommand::Disable { id } => {
+      set_enabled(config_dir, plugins_dir, id, false)?;
+      println!("Disabled {id}, it stops loading from the next start on");
+    }
+    PluginsCommand::Install { archive } => {
+      let manifest = install(plugins_dir, archive)?;
+      let config = load_config(config_dir)?;
+      match config.is_plugin_enabled(&manifest.id) {
+        true => println!(
+          "Installed {} {}, it loads from the next start on",
+          manifest.id, manifest.version
+        ),
+        false => println!(
+          "Installed {} {}, enable it with `andromeda-runner plugins enable {}`",
+          manifest.id, manifest.version, manifest.id
+        )
+      }
+    }
+  }
+  Ok(ExitCode::SUCCESS)
+}
+
+fn list(config_dir: &Path, plugins_dir: &Path) -> Result<(), AndromedaError> {
+  let config = load_config(config_dir)?;
+  let plugins = discover_plugins(plugins_dir);
+  if plugins.is_empty() {
+    println!("No plugins in {}", plugins_dir.display());
+  }
+  for plugin in &plugins {
+    match &plugin.manifest {
+      Ok(manifest) => println!(
+        "{} {} [{}] {}",
+        manifest.id,
+        manifest.version,
+        match config.is_plugin_enabled(&manifest.id) {
+          true => "enabled",
+          false => "disabled"
+        },
+        manifest.name
+      ),
+      Err(reason) => println!("{} [broken] {}", plugin.id(), reason)
+    }
+  }
+  Ok(())
+}
+
+fn set_enabled(config_dir: &Path, plugins_dir: &Path, id: &str, enabled: bool) -> Result<(), AndromedaError> {
+  let plugins = discover_plugins(plugins_dir);
+  let plugin = plugins
+    .iter()
+    .find(|plugin| plugin.id() == id)
+    .ok_or_else(|| AndromedaError::plugin(format!("No plugin {id} in {}", plugins_dir.display())))?;
+  let manifest = match (&plugin.manifest, enabled) {
+    (Ok(manifest), _) => Some(manifest),
+    (Err(reason), true) => return Err(AndromedaError::plugin(format!("{id} can't be enabled: {reason}"))),
+    // A broken plugin can still be switched off, under its folder name
+    (Err(_), false) => None
+  };
+  let mut config = load_config(config_dir)?;
+  config.set_plugin_enabled(id, manifest.map_or(id, |manifest| &manifest.name), enabled);
+  // Decided on here, so the plugin manager doesn't announce it as new
+  config.mark_plugins_seen([id]);
+  write_andromeda_config(config_dir, &config)
+}
+
+/// Unpacks `archive` into the plugin folder named by its manifest's id, replacing what was there
+fn install(plugins_dir: &Path, archive: &Path) -> Result<PluginManifest, AndromedaError> {
+  let staging = plugins_dir.join(STAGING_DIR);
+  remove_dir(&staging)?;
+  let result = extract(archive, &staging)
+    .and_then(|()| plugin_root(archive, &staging))
+    .and_then(|root| {
+      let manifest = check_plugin(archive, &root)?;
+      replace_plugin(plugins_dir, &root, &manifest.id)?;
+      Ok(manifest)
+    });
+  let _ = fs::remove_dir_all(&staging);
+  result
+}
+
+fn extract(archive: &Path, to: &Path) -> Result<(), AndromedaError> {
+  let file = File::open(archive).context(|| AndromedaError::plugin(format!("Can't read {}", archive.display())))?;
+  let mut zip =
+    ZipArchive::new(file).context(|| AndromedaError::plugin(format!("{} is not a zip archive", archive.display())))?;
+  for i in 0..zip.len() {
+    let mut entry = zip
+      .by_index(i)
+      .context(|| AndromedaError::plugin(format!("Can't read {}", archive.display())))?;
+    // Names like `../x` would be written outside the plugin's folder
+    let Some(name) = entry.enclosed_name() else {
+      return Err(AndromedaError::plugin(format!(
+        "{} has a file outside its folder: {}",
+        archive.display(),
+        entry.name()
+      )));
+    };
+    let path = to.join(name);
+    if entry.is_dir() {
+      fs::create_dir_all(&path)?;
+      continue;
+    }
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    io::copy(&mut entry, &mut File::create(&path)?)?;
+  }
+  Ok(())
+}
+
+/// The folder holding the manifest, the archive's root or a single folder in it
+fn plugin_root(archive: &Path, unpacked: &Path) -> Result<PathBuf, AndromedaError> {
+  if unpacked.join(PLUGIN_MANIFEST_NAME).is_file() {
+    return Ok(unpacked.to_path_buf());
+  }
+  let dirs: Vec<PathBuf> = fs::read_dir(unpacked)?
+    .filter_map(|entry| entry.ok())
+    .map(|entry| entry.path())
+    .filter(|path| path.is_dir())
+    .collect();
+  match dirs.as_slice() {
+    [dir] if dir.join(PLUGIN_MANIFEST_NAME).is_file() => Ok(dir.clone()),
+    _ => Err(AndromedaError::plugin(format!(
+      "{} has no {PLUGIN_MANIFEST_NAME}",
+      archive.display()
+    )))
+  }
+}
+
+fn check_plugin(archive: &Path, root: &Path) -> Result<PluginManifest, AndromedaError> {
+  let manifest =
+    read_manifest(root).map_err(|reason| AndromedaError::plugin(format!("{}: {reason}", archive.display())))?;
+  // The id names the plugin's folder
+  let valid_id = !manifest.id.starts_with('.') &&
+    manifest
+      .id
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+  if !valid_id {
+    return Err(AndromedaError::plugin(format!(
+      "{}: '{}' can't be a plugin id, only letters, digits, '.', '-' and '_' can",
+      archive.display(),
+      manifest.id
+    )));
+  }
+  if !root.join(&manifest.entry).is_file() {
+    return Err(AndromedaError::plugin(format!(
+      "{}: the manifest names {}, which isn't in the archive",
+      archive.display(),
+      manifest.entry
+    )));
+  }
+  Ok(manifest)
+}
+
+/// Moves the unpacked plugin to `<plugins>/<id>`, putting the old one back if that fails
+fn replace_plugin(plugins_dir: &Path, root: &Path, id: &str) -> Result<(), AndromedaError> {
+  let target = plugins_dir.join(id);
+  let taken = discover_plugins(plugins_dir)
+    .into_iter()
+    .find(|plugin| plugin.dir != target && plugin.manifest.as_ref().is_ok_and(|manifest| manifest.id == id));
+  if let Some(plugin) = taken {
+    return Err(AndromedaError::plugin(format!(
+      "{} already has the id {id}, remove it first",
+      plugin.dir.display()
+    )));
+  }
+
+  let replaced = plugins_dir.join(format!(".{id}.replaced"));
+  remove_dir(&replaced)?;
+  if target.exists() {
+    fs::rename(&target, &replaced)
+      .context(|| AndromedaError::plugin(format!("Can't replace {}, is the game running?", target.display())))?;
+  }
+  if let Err(e) = fs::rename(root, &target) {
+    if replaced.exists() {
+      let _ = fs::rename(&replaced, &target);
+    }
+    return Err(AndromedaError::plugin(format!("Can't move the plugin to {}", target.display())).with_source(e));
+  }
+  let _ = fs::remove_dir_all(&replaced);
+  Ok(())
+}
+
+fn remove_dir(dir: &Path) -> Result<(), AndromedaError> {
+  match fs::remove_dir_all(dir) {
+    Err(e) if e.kind() != io::ErrorKind::NotFound => {
+      Err(AndromedaError::plugin(format!("Can't remove {}", dir.display())).with_source(e))
+    }
+    _ => Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use andromeda_common::config::read_andromeda_config;
+  use zip::{ZipWriter, write::SimpleFileOptions};
+
+  use super::*;
+
+  struct Dirs {
+    _temp: tempfile::TempDir,
+    config: PathBuf,
+    plugins: PathBuf
+  }
+
+  fn dirs() -> Dirs {
+    let temp = tempfile::tempdir().unwrap();
+    let config = temp.path().join("config");
+    let plugins = config.join("plugins");
+    fs::create_dir_all(&plugins).unwrap();
+    Dirs {
+      config,
+      plugins,
+      _temp: temp
+    }
+  }
+
+  fn manifest(id: &str, version: &str) -> String {
+    format!(r#"{{ "id": "{id}", "name": "The {id}", "version": "{version}", "entry": "{id}.dll" }}"#)
+  }
+
+  fn zip(path: &Path, files: &[(&str, &str)]) {
+    let mut zip = ZipWriter::new(File::create(path).unwrap());
+    for (name, contents) in files {
+      zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+      zip.write_all(contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+  }
+
+  fn run(dirs: &Dirs, command: PluginsCommand) -> Result<ExitCode, AndromedaError> {
+    run_in(&dirs.config, &dirs.plugins, &command)
+  }
+
+  fn install_archive(archive: &Path) -> PluginsCommand {
+    PluginsCommand::Install {
+      archive: archive.to_path_buf()
+    }
+  }
+
+  fn enable(id: &str) -> PluginsCommand {
+    PluginsCommand::Enable { id: id.to_string() }
+  }
+
+  fn disable(id: &str) -> PluginsCommand {
+    PluginsCommand::Disable { id: id.to_string() }
+  }
+
+  #[test]
+  fn install_unpacks_into_the_plugins_folder() {
+    let dirs = dirs();
+    let archive = dirs.config.join("hello.zip");
+    zip(
+      &archive,
+      &[
+        ("hello/plugin.json", &manifest("hello", "1.0.0")),
+        ("hello/hello.dll", "v1")
+      ]
+    );
+    run(&dirs, install_archive(&archive)).unwrap();
+    assert_eq!(fs::read_to_string(dirs.plugins.join("hello/hello.dll")).unwrap(), "v1");
+
+    // An update replaces the old files
+    zip(
+      &archive,
+      &[("plugin.json", &manifest("hello", "1.1.0")), ("hello.dll", "v2")]
+    );
+    run(&dirs, install_archive(&archive)).unwrap();
+    assert_eq!(fs::read_to_string(dirs.plugins.join("hello/hello.dll")).unwrap(), "v2");
+    assert_eq!(read_manifest(&dirs.plugins.join("hello")).unwrap().version, "1.1.0");
+    assert!(!dirs.plugins.join(STAGING_DIR).exists());
+    assert!(!dirs.plugins.join(".hello.replaced").exists());
+  }
+
+  #[test]
+  fn install_rejects_broken_archives() {
+    let dirs = dirs();
+    let archive = dirs.config.join("bad.zip");
+    let (evil, hidden, missing) = (
+      manifest("evil", "1.0.0"),
+      manifest(".hidden", "1.0.0"),
+      manifest("missing", "1.0.0")
+    );
+    let archives: [&[(&str, &str)]; 4] = [
+      &[("../evil.dll", "x"), ("plugin.json", &evil)],
+      &[("plugin.json", &hidden), (".hidden.dll", "x")],
+      &[("plugin.json", &missing)],
+      &[("readme.txt", "no manifest")]
+    ];
+    for files in archives {
+      zip(&archive, files);
+      assert!(run(&dirs, install_archive(&archive)).is_err());
+    }
+    assert!(!dirs.config.join("evil.dll").exists());
+    assert_eq!(fs::read_dir(&dirs.plugins).unwrap().count(), 0);
+  }
+
+  #[test]
+  fn enable_and_disable_update_the_config() {
+    let dirs = dirs();
+    let plugin = dirs.plugins.join("hello");
+    fs::create_dir(&plugin).unwrap();
+    fs::write(plugin.join(PLUGIN_MANIFEST_NAME), manifest("hello", "1.0.0")).unwrap();
+
+    run(&dirs, enable("hello")).unwrap();
+    let mut config = read_andromeda_config(&dirs.config).unwrap().unwrap();
+    assert!(config.is_plugin_enabled("hello"));
+    assert!(config.mark_plugins_seen(["hello"]).is_empty());
+
+    run(&dirs, disable("hello")).unwrap();
+    let config = read_andromeda_config(&dirs.config).unwrap().unwrap();
+    assert!(!config.is_plugin_enabled("hello"));
+
+    assert!(run(&dirs, enable("nothing")).is_err());
+  }
+
+  #[test]
+  fn broken_plugins_can_only_be_disabled() {
+    let dirs = dirs();
+    fs::create_dir(dirs.plugins.join("broken")).unwrap();
+    assert!(run(&dirs, enable("broken")).is_err());
+    run(&dirs, disable("broken")).unwrap();
+    let config = read_andromeda_config(&dirs.config).unwrap().unwrap();
+    assert!(!config.is_plugin_enabled("broken"));
+  }
+}
@@ -0,0 +1,316 @@
+//! Game processes the runner starts or attaches to, and loading the entry DLL into them.
+
+use std::{ffi::c_void, path::Path, time::Duration};
+
+use andromeda_common::{
+  errors::AndromedaError,
+  utils::win32::{process_creation_time, widestring}
+};
+use windows::{
+  Win32::{
+    Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0, WAIT_TIMEOUT},
+    System::{
+      Diagnostics::{
+        Debug::WriteProcessMemory,
+        ToolHelp::{
+          CreateToolhelp32Snapshot, MODULEENTRY32W, Module32FirstW, Module32NextW, PROCESSENTRY32W, Process32FirstW,
+          Process32NextW, TH32CS_SNAPMODULE, TH32CS_SNAPPROCESS
+        }
+      },
+      LibraryLoader::{GetModuleHandleW, GetProcAddress},
+      Memory::{MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_READWRITE, VirtualAllocEx, VirtualFreeEx},
+      Threading::{
+        CREATE_SUSPENDED, CreateProcessW, CreateRemoteThread, GetCurrentProcess, IsWow64Process,
+        LPTHREAD_START_ROUTINE, OpenProcess, PROCESS_CREATE_THREAD, PROCESS_INFORMATION, PROCESS_QUERY_INFORMATION,
+        PROCESS_SYNCHRONIZE, PROCESS_TERMINATE, PROCESS_VM_OPERATION, PROCESS_VM_READ, PROCESS_VM_WRITE, ResumeThread,
+        STARTUPINFOW, TerminateProcess, WaitForSingleObject
+      }
+    }
+  },
+  core::{BOOL, PCWSTR, PWSTR, s, w}
+};
+
+/// How long `LoadLibraryW` may take in the game, the entry DLL does its work on a thread of its own
+const LOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A handle closed when dropped
+struct OwnedHandle(HANDLE);
+
+impl Drop for OwnedHandle {
+  fn drop(&mut self) {
+    let _ = unsafe { CloseHandle(self.0) };
+  }
+}
+
+pub(crate) struct GameProcess {
+  pub pid: u32,
+  process: OwnedHandle,
+  /// The main thread of a game started suspended
+  main_thread: Option<OwnedHandle>
+}
+
+impl GameProcess {
+  /// Starts `exe` with `args` in its own directory, suspended before any of its code ran
+  pub fn spawn_suspended(exe: &Path, args: &[String]) -> Result<Self, AndromedaError> {
+    let exe_path = exe
+      .to_str()
+      .ok_or_else(|| AndromedaError::config(format!("{} is not a valid path", exe.display())))?;
+    let application = widestring(exe_path);
+    let command_line = std::iter::once(exe_path)
+      .chain(args.iter().map(String::as_str))
+      .map(quote_arg)
+      .collect::<Vec<_>>()
+      .join(" ");
+    // CreateProcessW may write to the command line
+    let mut command_line = widestring(command_line);
+    // Games load their data relative to where they are, not where they were started from
+    let directory = exe
+      .parent()
+      .and_then(Path::to_str)
+      .filter(|dir| !dir.is_empty())
+      .map(widestring);
+
+    let startup_info = STARTUPINFOW {
+      cb: size_of::<STARTUPINFOW>() as u32,
+      ..Default::default()
+    };
+    let mut process_info = PROCESS_INFORMATION::default();
+    unsafe {
+      CreateProcessW(
+        PCWSTR(application.as_ptr()),
+        Some(PWSTR(command_line.as_mut_ptr())),
+        None,
+        None,
+        false,
+        CREATE_SUSPENDED,
+        None,
+        directory.as_ref().map_or(PCWSTR::null(), |dir| PCWSTR(dir.as_ptr())),
+        &startup_info,
+        &mut process_info
+      )
+    }
+    .map_err(|e| AndromedaError::hresult(format!("Can't start {}", exe.display()), e.code()))?;
+
+    Ok(Self {
+      pid: process_info.dwProcessId,
+      process: OwnedHandle(process_info.hProcess),
+      main_thread: Some(OwnedHandle(process_info.hThread))
+    })
+  }
+
+  /// Opens the running process `pid`
+  pub fn open(pid: u32) -> Result<Self, AndromedaError> {
+    let access = PROCESS_CREATE_THREAD |
+      PROCESS_QUERY_INFORMATION |
+      PROCESS_SYNCHRONIZE |
+      PROCESS_TERMINATE |
+      PROCESS_VM_OPERATION |
+      PROCESS_VM_READ |
+      PROCESS_VM_WRITE;
+    let process = unsafe { OpenProcess(access, false, pid) }
+      .map_err(|e| AndromedaError::hresult(format!("Can't open process {pid}"), e.code()))?;
+    Ok(Self {
+      pid,
+      process: OwnedHandle(process),
+      main_thread: None
+    })
+  }
+
+  pub fn creation_time(&self) -> Result<u64, AndromedaError> {
+    process_creation_time(self.process.0)
+      .ok_or_else(|| AndromedaError::config(format!("Can't tell when process {} was created", self.pid)))
+  }
+
+  pub fn has_exited(&self) -> bool {
+    unsafe { WaitForSingleObject(self.process.0, 0) == WAIT_OBJECT_0 }
+  }
+
+  /// Lets a game started suspended run
+  pub fn resume(&self) -> Result<(), AndromedaError> {
+    if let Some(thread) = &self.main_thread &&
+      unsafe { ResumeThread(thread.0) } == u32::MAX
+    {
+      return Err(AndromedaError::hresult(
+        format!("Can't resume process {}", self.pid),
+        windows::core::Error::from_win32().code()
+      ));
+    }
+    Ok(())
+  }
+
+  pub fn terminate(&self) {
+    let _ = unsafe { TerminateProcess(self.process.0, 1) };
+  }
+
+  /// Paths of the modules loaded in the process
+  pub fn modules(&self) -> Result<Vec<String>, AndromedaError> {
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPMODULE, self.pid) }
+      .map_err(|e| AndromedaError::hresult(format!("Can't list the modules of process {}", self.pid), e.code()))?;
+    let snapshot = OwnedHandle(snapshot);
+    let mut entry = MODULEENTRY32W {
+      dwSize: size_of::<MODULEENTRY32W>() as u32,
+      ..Default::default()
+    };
+    let mut modules = Vec::new();
+    let mut next = unsafe { Module32FirstW(snapshot.0, &mut entry) };
+    while next.is_ok() {
+      modules.push(from_wide(&entry.szExePath));
+      next = unsafe { Module32NextW(snapshot.0, &mut entry) };
+    }
+    Ok(modules)
+  }
+
+  /// Loads `dll` into the process with `LoadLibraryW` on a thread of its own
+  pub fn inject(&self, dll: &Path) -> Result<(), AndromedaError> {
+    self.check_bitness()?;
+    let dll_path = dll
+      .to_str()
+      .ok_or_else(|| AndromedaError::config(format!("{} is not a valid path", dll.display())))?;
+    let wide = widestring(dll_path);
+    let size = size_of_val(wide.as_slice());
+
+    unsafe {
+      let remote = VirtualAllocEx(self.process.0, None, size, MEM_COMMIT | MEM_RESERVE, PAGE_READWRITE);
+      if remote.is_null() {
+        return Err(AndromedaError::hresult(
+          format!("Can't allocate memory in process {}", self.pid),
+          windows::core::Error::from_win32().code()
+        ));
+      }
+      let result = self.load_library(remote, &wide);
+      // Still in use if LoadLibraryW didn't return, a few bytes leaked beat a crashed game
+      if !matches!(result, Err(LoadError::TimedOut)) {
+        let _ = VirtualFreeEx(self.process.0, remote, 0, MEM_RELEASE);
+      }
+      result.map_err(|e| e.into_error(self.pid))?;
+    }
+
+    // The thread's exit code is only the lower half of the module's address, so ask for the module instead
+    let loaded = self
+      .modules()?
+      .iter()
+      .any(|module| Path::new(module).file_name() == dll.file_name());
+    if !loaded {
+      return Err(AndromedaError::config(format!(
+        "{} didn't load in process {}, see Andromeda's log",
+        dll.display(),
+        self.pid
+      )));
+    }
+    Ok(())
+  }
+
+  unsafe fn load_library(&self, remote: *mut c_void, path: &[u16]) -> Result<(), LoadError> {
+    unsafe {
+      WriteProcessMemory(
+        self.process.0,
+        remote,
+        path.as_ptr() as *const c_void,
+        size_of_val(path),
+        None
+      )
+      .map_err(LoadError::Win32)?;
+
+      // kernel32 is at the same address in every process of a session
+      let kernel32 = GetModuleHandleW(w!("kernel32.dll")).map_err(LoadError::Win32)?;
+      let load_library = GetProcAddress(kernel32, s!("LoadLibraryW")).ok_or_else(windows::core::Error::from_win32);
+      let load_library: LPTHREAD_START_ROUTINE = std::mem::transmute(load_library.map_err(LoadError::Win32)?);
+      let thread =
+        CreateRemoteThread(self.process.0, None, 0, load_library, Some(remote), 0, None).map_err(LoadError::Win32)?;
+      let thread = OwnedHandle(thread);
+      match WaitForSingleObject(thread.0, LOAD_TIMEOUT.as_millis() as u32) {
+        WAIT_OBJECT_0 => Ok(()),
+        WAIT_TIMEOUT => Err(LoadError::TimedOut),
+        _ => Err(LoadError::Win32(windows::core::Error::from_win32()))
+      }
+    }
+  }
+
+  /// The entry DLL is built for the runner's bitness, so the game has to match it
+  fn check_bitness(&self) -> Result<(), AndromedaError> {
+    let (mut game, mut runner) = (BOOL::default(), BOOL::default());
+    unsafe {
+      IsWow64Process(self.process.0, &mut game)
+        .and_then(|()| IsWow64Process(GetCurrentProcess(), &mut runner))
+        .map_err(|e| AndromedaError::hresult(format!("Can't tell the bitness of process {}", self.pid), e.code()))?;
+    }
+    if game != runner {
+      return Err(AndromedaError::config(format!(
+        "Process {} is {}-bit, the runner is {}-bit",
+        self.pid,
+        if game.as_bool() { 32 } else { 64 },
+        if runner.as_bool() { 32 } else { 64 }
+      )));
+    }
+    Ok(())
+  }
+}
+
+enum LoadError {
+  Win32(windows::core::Error),
+  TimedOut
+}
+
+impl LoadError {
+  fn into_error(self, pid: u32) -> AndromedaError {
+    match self {
+      LoadError::Win32(e) => AndromedaError::hresult(format!("Can't inject into process {pid}"), e.code()),
+      LoadError::TimedOut => AndromedaError::config(format!(
+        "LoadLibraryW didn't return in process {pid} within {}s",
+        LOAD_TIMEOUT.as_secs()
+      ))
+    }
+  }
+}
+
+/// Ids of the running processes whose executable is `name`, with or without `.exe`
+pub(crate) fn find_processes(name: &str) -> Result<Vec<u32>, AndromedaError> {
+  let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) }
+    .map_err(|e| AndromedaError::hresult("Can't list processes", e.code()))?;
+  let snapshot = OwnedHandle(snapshot);
+  let mut entry = PROCESSENTRY32W {
+    dwSize: size_of::<PROCESSENTRY32W>() as u32,
+    ..Default::default()
+  };
+  let mut pids = Vec::new();
+  let mut next = unsafe { Process32FirstW(snapshot.0, &mut entry) };
+  while next.is_ok() {
+    let exe = from_wide(&entry.szExeFile);
+    let stem = exe
+      .get(..exe.len().saturating_sub(4))
+      .filter(|_| exe.to_lowercase().ends_with(".exe"));
+    if exe.eq_ignore_ascii_case(name) || stem.is_some_and(|stem| stem.eq_ignore_ascii_case(name)) {
+      pids.push(entry.th32ProcessID);
+    }
+    next = unsafe { Process32NextW(snapshot.0, &mut entry) };
+  }
+  Ok(pids)
+}
+
+fn from_wide(buffer: &[u16]) -> String {
+  let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+  String::from_utf16_lossy(&buffer[..len])
+}
+
+/// Quotes `arg` so `CommandLineToArgvW` splits it back out as it is
+fn quote_arg(arg: &str) -> String {
+  if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+    return arg.to_string();
+  }
+  let mut quoted = String::from('"');
+  let mut backslashes = 0;
+  for c in arg.chars() {
+    if c == '\\' {
+      backslashes += 1;
+      continue;
+    }
+    // Backslashes only escape when a quote follows
+    let escaped = if c == '"' { backslashes * 2 + 1 } else { backslashes };
+    quoted.extend(std::iter::repeat_n('\\', escaped));
+    quoted.push(c);
+    backslashes = 0;
+  }
+  quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+  quoted.push('"');
+  quoted
+}